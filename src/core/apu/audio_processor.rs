@@ -0,0 +1,241 @@
+use crate::core::apu::dmc::Dmc;
+use crate::core::apu::filters::FilterChain;
+use crate::core::apu::frame_counter::{FrameClock, FrameCounter};
+use crate::core::apu::mixer;
+use crate::core::apu::noise::Noise;
+use crate::core::apu::pulse::{Pulse, PulseChannel};
+use crate::core::apu::resampler::Resampler;
+use crate::core::apu::triangle::Triangle;
+use crate::core::region::{Region, RegionTiming};
+use crate::core::state::snapshot::Snapshot;
+use crate::core::state::stream::{StateError, StateReader, StateWriter};
+
+pub const DEFAULT_SAMPLE_RATE: u32 = 44_100;
+
+// the 2A03's sound half. everything is clocked off CPU cycles via tick()
+pub struct AudioProcessor {
+    pub pulse_1: Pulse,
+    pub pulse_2: Pulse,
+    pub triangle: Triangle,
+    pub noise: Noise,
+    pub dmc: Dmc,
+    pub frame_counter: FrameCounter,
+    cycles: u64,
+    // CPU cycles since the last end_frame
+    frame_cycles: u32,
+    level: f32,
+    // from the cartridge, already on the mixer's scale
+    expansion_output: f32,
+    resampler: Resampler,
+    filters: FilterChain,
+    timing: &'static RegionTiming,
+}
+
+// what's between the channels and the samples that come out, which save states leave alone
+#[derive(Debug, Clone)]
+pub struct AudioPipeline {
+    frame_cycles: u32,
+    level: f32,
+    expansion_output: f32,
+    resampler: Resampler,
+    filters: FilterChain,
+}
+
+impl AudioProcessor {
+    pub fn new(region: Region) -> Self {
+        let timing = region.timing();
+
+        AudioProcessor {
+            pulse_1: Pulse::new(PulseChannel::One),
+            pulse_2: Pulse::new(PulseChannel::Two),
+            triangle: Triangle::default(),
+            noise: Noise::new(&timing.noise_periods),
+            dmc: Dmc::new(&timing.dmc_periods),
+            frame_counter: FrameCounter::new(timing),
+            cycles: 0,
+            frame_cycles: 0,
+            level: 0.0,
+            expansion_output: 0.0,
+            resampler: Resampler::new(timing.cpu_clock_rate(), DEFAULT_SAMPLE_RATE),
+            filters: FilterChain::new(DEFAULT_SAMPLE_RATE),
+            timing,
+        }
+    }
+
+    pub fn sample_rate(&self) -> u32 {
+        self.resampler.sample_rate()
+    }
+
+    // drops whatever hasn't been handed out yet
+    pub fn set_sample_rate(&mut self, sample_rate: u32) {
+        self.resampler.set_sample_rate(sample_rate);
+        self.filters = FilterChain::new(sample_rate);
+        self.frame_cycles = 0;
+        self.level = 0.0;
+    }
+
+    // whatever the cartridge's sound chip is putting out, mixed in from the next cycle on
+    pub fn set_expansion_output(&mut self, level: f32) {
+        self.expansion_output = level;
+    }
+
+    // the five channels through the DAC plus the cartridge's audio, before any filtering
+    pub fn output(&self) -> f32 {
+        mixer::mix(
+            self.pulse_1.output(),
+            self.pulse_2.output(),
+            self.triangle.output(),
+            self.noise.output(),
+            self.dmc.output(),
+        ) + self.expansion_output
+    }
+
+    // everything generated since the last call, filtered and at sample_rate()
+    pub fn end_frame(&mut self) -> Vec<f32> {
+        let mut samples = self.resampler.end_frame(self.frame_cycles);
+        self.frame_cycles = 0;
+
+        for sample in samples.iter_mut() {
+            *sample = self.filters.process(*sample);
+        }
+        samples
+    }
+
+    // for running frames that get thrown away (run-ahead) without them leaking into the real audio
+    pub fn pipeline(&self) -> AudioPipeline {
+        AudioPipeline {
+            frame_cycles: self.frame_cycles,
+            level: self.level,
+            expansion_output: self.expansion_output,
+            resampler: self.resampler.clone(),
+            filters: self.filters.clone(),
+        }
+    }
+
+    pub fn restore_pipeline(&mut self, pipeline: AudioPipeline) {
+        self.frame_cycles = pipeline.frame_cycles;
+        self.level = pipeline.level;
+        self.expansion_output = pipeline.expansion_output;
+        self.resampler = pipeline.resampler;
+        self.filters = pipeline.filters;
+    }
+
+    pub fn timing(&self) -> &'static RegionTiming {
+        self.timing
+    }
+
+    pub fn write_register(&mut self, addr: u16, data: u8) {
+        match addr {
+            0x4000..=0x4003 => self.pulse_1.write_register(addr, data),
+            0x4004..=0x4007 => self.pulse_2.write_register(addr, data),
+            0x4008..=0x400b => self.triangle.write_register(addr, data),
+            0x400c..=0x400f => self.noise.write_register(addr, data),
+            0x4010..=0x4013 => self.dmc.write_register(addr, data),
+            0x4015 => {
+                self.pulse_1.length_counter.set_enabled(data & 0b0001 != 0);
+                self.pulse_2.length_counter.set_enabled(data & 0b0010 != 0);
+                self.triangle.length_counter.set_enabled(data & 0b0100 != 0);
+                self.noise.length_counter.set_enabled(data & 0b1000 != 0);
+                self.dmc.set_enabled(data & 0b1_0000 != 0);
+            }
+            0x4017 => self.frame_counter.write(data, self.cycles % 2 == 1),
+            _ => {}
+        }
+    }
+
+    // $4015, reading it acknowledges the frame IRQ
+    pub fn read_status(&mut self) -> u8 {
+        let status = u8::from(self.pulse_1.length_counter.active())
+            | u8::from(self.pulse_2.length_counter.active()) << 1
+            | u8::from(self.triangle.length_counter.active()) << 2
+            | u8::from(self.noise.length_counter.active()) << 3
+            | u8::from(self.dmc.active()) << 4
+            | u8::from(self.frame_counter.irq_flag) << 6
+            | u8::from(self.dmc.irq_flag) << 7;
+
+        self.frame_counter.irq_flag = false;
+        status
+    }
+
+    pub fn irq(&self) -> bool {
+        self.frame_counter.irq_flag || self.dmc.irq_flag
+    }
+
+    // `read` is the CPU bus, for the DMC's sample fetches
+    pub fn tick<F: FnMut(u16) -> u8>(&mut self, cycles: u16, read: &mut F) {
+        for _ in 0..cycles {
+            self.clock(read);
+        }
+    }
+
+    // CPU cycles the DMC has stolen for sample fetches since last asked
+    pub fn take_stall_cycles(&mut self) -> u16 {
+        self.dmc.take_stall_cycles()
+    }
+
+    fn clock<F: FnMut(u16) -> u8>(&mut self, read: &mut F) {
+        let frame_clock = self.frame_counter.clock();
+        self.clock_frame(frame_clock);
+
+        self.triangle.clock_timer();
+        self.noise.clock_timer();
+        self.dmc.clock(read);
+
+        // the pulse timers run at half the CPU rate
+        if self.cycles % 2 == 1 {
+            self.pulse_1.clock_timer();
+            self.pulse_2.clock_timer();
+        }
+
+        // only changes go into the resampler, which keeps this cheap at 1.79MHz
+        let level = self.output();
+        if level != self.level {
+            self.resampler.add_delta(self.frame_cycles, level - self.level);
+            self.level = level;
+        }
+
+        self.frame_cycles += 1;
+        self.cycles += 1;
+    }
+
+    fn clock_frame(&mut self, frame_clock: FrameClock) {
+        if frame_clock.quarter {
+            self.pulse_1.clock_quarter_frame();
+            self.pulse_2.clock_quarter_frame();
+            self.triangle.clock_quarter_frame();
+            self.noise.clock_quarter_frame();
+        }
+
+        if frame_clock.half {
+            self.pulse_1.clock_half_frame();
+            self.pulse_2.clock_half_frame();
+            self.triangle.clock_half_frame();
+            self.noise.clock_half_frame();
+        }
+    }
+}
+
+// just the machine. the resampler and filters carry on from wherever they were, so loading a
+// state mid-session doesn't drop any audio that was already on its way out
+impl Snapshot for AudioProcessor {
+    fn save_state(&self, state: &mut StateWriter) {
+        self.pulse_1.save_state(state);
+        self.pulse_2.save_state(state);
+        self.triangle.save_state(state);
+        self.noise.save_state(state);
+        self.dmc.save_state(state);
+        self.frame_counter.save_state(state);
+        state.write_u64(self.cycles);
+    }
+
+    fn load_state(&mut self, state: &mut StateReader) -> Result<(), StateError> {
+        self.pulse_1.load_state(state)?;
+        self.pulse_2.load_state(state)?;
+        self.triangle.load_state(state)?;
+        self.noise.load_state(state)?;
+        self.dmc.load_state(state)?;
+        self.frame_counter.load_state(state)?;
+        self.cycles = state.read_u64()?;
+        Ok(())
+    }
+}
@@ -0,0 +1,188 @@
+use crate::core::state::snapshot::Snapshot;
+use crate::core::state::stream::{StateError, StateReader, StateWriter};
+
+// the delta modulation channel: 1-bit deltas streamed straight out of PRG space by DMA
+#[derive(Debug, Clone)]
+pub struct Dmc {
+    pub irq_enabled: bool,
+    pub looping: bool,
+    pub rate_index: u8,
+    timer: u16,
+    periods: &'static [u16; 16],
+
+    pub output_level: u8,
+    shift_register: u8,
+    bits_remaining: u8,
+    silence: bool,
+
+    sample_address: u16,
+    sample_length: u16,
+    current_address: u16,
+    bytes_remaining: u16,
+    sample_buffer: Option<u8>,
+
+    pub irq_flag: bool,
+    stall_cycles: u16,
+}
+
+// every sample fetch takes the bus away from the CPU for this long. really it's anywhere from
+// 1 to 4 depending on what the CPU was doing, 4 is the usual case
+const DMA_STALL_CYCLES: u16 = 4;
+
+impl Dmc {
+    pub fn new(periods: &'static [u16; 16]) -> Self {
+        Dmc {
+            irq_enabled: false,
+            looping: false,
+            rate_index: 0,
+            timer: 0,
+            periods,
+            output_level: 0,
+            shift_register: 0,
+            bits_remaining: 8,
+            silence: true,
+            sample_address: 0xc000,
+            sample_length: 1,
+            current_address: 0xc000,
+            bytes_remaining: 0,
+            sample_buffer: None,
+            irq_flag: false,
+            stall_cycles: 0,
+        }
+    }
+
+    // register 0-3, i.e. $4010-$4013
+    pub fn write_register(&mut self, register: u16, data: u8) {
+        match register & 0b11 {
+            0 => {
+                self.irq_enabled = data & 0x80 != 0;
+                self.looping = data & 0x40 != 0;
+                self.rate_index = data & 0x0f;
+                if !self.irq_enabled { self.irq_flag = false; }
+            }
+            // direct load, games use this for PCM by hammering it
+            1 => self.output_level = data & 0x7f,
+            2 => self.sample_address = 0xc000 | (u16::from(data) << 6),
+            _ => self.sample_length = (u16::from(data) << 4) | 1,
+        }
+    }
+
+    // bit 4 of $4015
+    pub fn set_enabled(&mut self, enabled: bool) {
+        self.irq_flag = false;
+
+        if !enabled {
+            self.bytes_remaining = 0;
+        } else if self.bytes_remaining == 0 {
+            self.restart();
+        }
+    }
+
+    pub fn active(&self) -> bool {
+        self.bytes_remaining > 0
+    }
+
+    fn restart(&mut self) {
+        self.current_address = self.sample_address;
+        self.bytes_remaining = self.sample_length;
+    }
+
+    // the CPU cycles the sample fetches have cost since the last time anyone asked
+    pub fn take_stall_cycles(&mut self) -> u16 {
+        let stall = self.stall_cycles;
+        self.stall_cycles = 0;
+        stall
+    }
+
+    // once per CPU cycle. `read` goes out on the CPU bus for sample bytes
+    pub fn clock<F: FnMut(u16) -> u8>(&mut self, read: &mut F) {
+        self.fetch_sample(read);
+
+        if self.timer > 0 {
+            self.timer -= 1;
+            return;
+        }
+        self.timer = self.periods[self.rate_index as usize] - 1;
+
+        if !self.silence {
+            if self.shift_register & 1 == 1 {
+                if self.output_level <= 125 { self.output_level += 2; }
+            } else if self.output_level >= 2 {
+                self.output_level -= 2;
+            }
+        }
+        self.shift_register >>= 1;
+
+        self.bits_remaining -= 1;
+        if self.bits_remaining == 0 {
+            self.bits_remaining = 8;
+            match self.sample_buffer.take() {
+                Some(sample) => {
+                    self.silence = false;
+                    self.shift_register = sample;
+                }
+                None => self.silence = true,
+            }
+        }
+    }
+
+    fn fetch_sample<F: FnMut(u16) -> u8>(&mut self, read: &mut F) {
+        if self.sample_buffer.is_some() || self.bytes_remaining == 0 { return; }
+
+        self.sample_buffer = Some(read(self.current_address));
+        self.stall_cycles += DMA_STALL_CYCLES;
+
+        // wraps around to $8000, not $0000
+        self.current_address = self.current_address.checked_add(1).unwrap_or(0x8000);
+        self.bytes_remaining -= 1;
+
+        if self.bytes_remaining == 0 {
+            if self.looping { self.restart(); }
+            else if self.irq_enabled { self.irq_flag = true; }
+        }
+    }
+
+    pub fn output(&self) -> u8 {
+        self.output_level
+    }
+}
+
+impl Snapshot for Dmc {
+    fn save_state(&self, state: &mut StateWriter) {
+        state.write_bool(self.irq_enabled);
+        state.write_bool(self.looping);
+        state.write_u8(self.rate_index);
+        state.write_u16(self.timer);
+        state.write_u8(self.output_level);
+        state.write_u8(self.shift_register);
+        state.write_u8(self.bits_remaining);
+        state.write_bool(self.silence);
+        state.write_u16(self.sample_address);
+        state.write_u16(self.sample_length);
+        state.write_u16(self.current_address);
+        state.write_u16(self.bytes_remaining);
+        state.write_option_u8(self.sample_buffer);
+        state.write_bool(self.irq_flag);
+        state.write_u16(self.stall_cycles);
+    }
+
+    fn load_state(&mut self, state: &mut StateReader) -> Result<(), StateError> {
+        self.irq_enabled = state.read_bool()?;
+        self.looping = state.read_bool()?;
+        self.rate_index = state.read_u8()? & 0x0f;
+        self.timer = state.read_u16()?;
+        self.output_level = state.read_u8()? & 0x7f;
+        self.shift_register = state.read_u8()?;
+        self.bits_remaining = state.read_u8()?;
+        if !(1..=8).contains(&self.bits_remaining) { return Err(StateError::Corrupt("DMC bit count out of range")); }
+        self.silence = state.read_bool()?;
+        self.sample_address = state.read_u16()?;
+        self.sample_length = state.read_u16()?;
+        self.current_address = state.read_u16()?;
+        self.bytes_remaining = state.read_u16()?;
+        self.sample_buffer = state.read_option_u8()?;
+        self.irq_flag = state.read_bool()?;
+        self.stall_cycles = state.read_u16()?;
+        Ok(())
+    }
+}
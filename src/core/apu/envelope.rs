@@ -0,0 +1,67 @@
+use crate::core::state::snapshot::Snapshot;
+use crate::core::state::stream::{StateError, StateReader, StateWriter};
+
+// volume envelope shared by pulse and noise: either a constant volume or a 15-to-0 sawtooth
+#[derive(Debug, Clone, Default)]
+pub struct Envelope {
+    pub start: bool,
+    pub looping: bool,
+    pub constant_volume: bool,
+    // doubles as the divider period when not constant
+    pub volume: u8,
+    divider: u8,
+    decay: u8,
+}
+
+impl Envelope {
+    // the --LC VVVV part of $4000/$4004/$400C
+    pub fn write_control(&mut self, data: u8) {
+        self.looping = data & 0b0010_0000 != 0;
+        self.constant_volume = data & 0b0001_0000 != 0;
+        self.volume = data & 0x0f;
+    }
+
+    // quarter frame
+    pub fn clock(&mut self) {
+        if self.start {
+            self.start = false;
+            self.decay = 15;
+            self.divider = self.volume;
+            return;
+        }
+
+        if self.divider > 0 {
+            self.divider -= 1;
+            return;
+        }
+
+        self.divider = self.volume;
+        if self.decay > 0 { self.decay -= 1; }
+        else if self.looping { self.decay = 15; }
+    }
+
+    pub fn output(&self) -> u8 {
+        if self.constant_volume { self.volume } else { self.decay }
+    }
+}
+
+impl Snapshot for Envelope {
+    fn save_state(&self, state: &mut StateWriter) {
+        state.write_bool(self.start);
+        state.write_bool(self.looping);
+        state.write_bool(self.constant_volume);
+        state.write_u8(self.volume);
+        state.write_u8(self.divider);
+        state.write_u8(self.decay);
+    }
+
+    fn load_state(&mut self, state: &mut StateReader) -> Result<(), StateError> {
+        self.start = state.read_bool()?;
+        self.looping = state.read_bool()?;
+        self.constant_volume = state.read_bool()?;
+        self.volume = state.read_u8()?;
+        self.divider = state.read_u8()?;
+        self.decay = state.read_u8()?;
+        Ok(())
+    }
+}
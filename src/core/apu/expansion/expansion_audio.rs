@@ -0,0 +1,17 @@
+use crate::core::state::snapshot::Snapshot;
+
+// a sound chip living on the cartridge (or the FDS RAM adapter). the cartridge's audio pin gets
+// mixed in with the 2A03's, so whatever comes out of output() is on the same scale as
+// mixer::mix, where a lone full-volume 2A03 pulse is about 0.15
+pub trait ExpansionAudio: Snapshot {
+    // addresses are the chip's own, whoever owns it decides which writes actually reach it
+    fn write_register(&mut self, addr: u16, data: u8);
+
+    // the few chips with readable registers, None when `addr` isn't one of them
+    fn read_register(&mut self, _addr: u16) -> Option<u8> { None }
+
+    // once per CPU cycle
+    fn clock(&mut self);
+
+    fn output(&self) -> f32;
+}
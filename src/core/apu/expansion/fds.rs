@@ -0,0 +1,272 @@
+use std::f32::consts::PI;
+use crate::core::apu::expansion::expansion_audio::ExpansionAudio;
+use crate::core::state::snapshot::Snapshot;
+use crate::core::state::stream::{StateError, StateReader, StateWriter};
+
+const LEVEL_SCALE: f32 = 0.0003;
+// $4089's master volume: 2/2, 2/3, 2/4 and 2/5
+const MASTER_VOLUME: [f32; 4] = [1.0, 2.0 / 3.0, 0.5, 0.4];
+// what the modulator does to its counter for each 3-bit table entry, None resets it
+const MOD_ADJUSTMENTS: [Option<i8>; 8] = [Some(0), Some(1), Some(2), Some(4), None, Some(-4), Some(-2), Some(-1)];
+
+#[derive(Debug, Clone, Default)]
+struct FdsEnvelope {
+    disabled: bool,
+    increase: bool,
+    speed: u8,
+    gain: u8,
+    timer: u32,
+}
+
+impl FdsEnvelope {
+    fn write(&mut self, data: u8) {
+        self.disabled = data & 0x80 != 0;
+        self.increase = data & 0x40 != 0;
+        self.speed = data & 0x3f;
+        if self.disabled { self.gain = self.speed; }
+        self.timer = 0;
+    }
+
+    fn clock(&mut self, master_speed: u8) {
+        if self.disabled { return; }
+
+        self.timer += 1;
+        if self.timer < 8 * (u32::from(self.speed) + 1) * u32::from(master_speed) { return; }
+        self.timer = 0;
+
+        if self.increase && self.gain < 32 { self.gain += 1; }
+        else if !self.increase && self.gain > 0 { self.gain -= 1; }
+    }
+}
+
+// the Famicom Disk System's RAM adapter: a 64-step 6-bit wavetable with a frequency modulator
+#[derive(Debug, Clone)]
+pub struct FdsAudio {
+    wave: [u8; 64],
+    wave_write: bool,
+    wave_halt: bool,
+    wave_frequency: u16,
+    wave_phase: u32,
+    volume: FdsEnvelope,
+    mod_envelope: FdsEnvelope,
+    envelopes_halted: bool,
+    envelope_speed: u8,
+    mod_table: [u8; 64],
+    mod_halt: bool,
+    mod_frequency: u16,
+    mod_phase: u32,
+    // 7-bit signed
+    mod_counter: i8,
+    master_volume: u8,
+    output_level: f32,
+    filter_alpha: f32,
+}
+
+impl Default for FdsAudio {
+    fn default() -> Self {
+        // the RAM adapter's output goes through a ~2kHz low pass, run here at the CPU rate
+        let rc = 1.0 / (2.0 * PI * 2000.0);
+        let dt = 1.0 / 1_789_773.0;
+
+        FdsAudio {
+            wave: [0; 64],
+            wave_write: false,
+            wave_halt: true,
+            wave_frequency: 0,
+            wave_phase: 0,
+            volume: FdsEnvelope::default(),
+            mod_envelope: FdsEnvelope::default(),
+            envelopes_halted: false,
+            envelope_speed: 0xe8,
+            mod_table: [0; 64],
+            mod_halt: true,
+            mod_frequency: 0,
+            mod_phase: 0,
+            mod_counter: 0,
+            master_volume: 0,
+            output_level: 0.0,
+            filter_alpha: dt / (rc + dt),
+        }
+    }
+}
+
+impl FdsAudio {
+    pub fn new() -> Self {
+        FdsAudio::default()
+    }
+
+    // how far the modulator bends the wave's pitch, straight off the wiki's description of the hardware
+    fn modulated_frequency(&self) -> u32 {
+        let pitch = i32::from(self.wave_frequency);
+        if self.mod_halt { return pitch as u32; }
+
+        let counter = i32::from(self.mod_counter);
+        let mut temp = counter * i32::from(self.mod_envelope.gain);
+        let remainder = temp & 0x0f;
+        temp >>= 4;
+        if remainder > 0 && temp & 0x80 == 0 {
+            temp += if counter < 0 { -1 } else { 2 };
+        }
+
+        if temp >= 192 { temp -= 256; } else if temp < -64 { temp += 256; }
+
+        temp *= pitch;
+        let remainder = temp & 0x3f;
+        temp >>= 6;
+        if remainder >= 32 { temp += 1; }
+
+        (pitch + temp).max(0) as u32
+    }
+
+    fn clock_modulator(&mut self) {
+        if self.mod_halt { return; }
+
+        let previous = self.mod_phase >> 16;
+        self.mod_phase = (self.mod_phase + u32::from(self.mod_frequency)) & 0x3f_ffff;
+        if self.mod_phase >> 16 == previous { return; }
+
+        let entry = self.mod_table[(previous & 0x3f) as usize];
+        self.mod_counter = match MOD_ADJUSTMENTS[entry as usize] {
+            // wraps around as a 7-bit value
+            Some(step) => ((self.mod_counter.wrapping_add(step)) << 1) >> 1,
+            None => 0,
+        };
+    }
+
+    fn set_mod_counter(&mut self, data: u8) {
+        self.mod_counter = ((data << 1) as i8) >> 1;
+    }
+}
+
+impl ExpansionAudio for FdsAudio {
+    fn write_register(&mut self, addr: u16, data: u8) {
+        match addr {
+            0x4040..=0x407f if self.wave_write => self.wave[(addr - 0x4040) as usize] = data & 0x3f,
+            0x4080 => self.volume.write(data),
+            0x4082 => self.wave_frequency = (self.wave_frequency & 0x0f00) | u16::from(data),
+            0x4083 => {
+                self.wave_frequency = (self.wave_frequency & 0x00ff) | (u16::from(data & 0x0f) << 8);
+                self.wave_halt = data & 0x80 != 0;
+                self.envelopes_halted = data & 0x40 != 0;
+                if self.wave_halt { self.wave_phase = 0; }
+            }
+            0x4084 => self.mod_envelope.write(data),
+            0x4085 => self.set_mod_counter(data),
+            0x4086 => self.mod_frequency = (self.mod_frequency & 0x0f00) | u16::from(data),
+            0x4087 => {
+                self.mod_frequency = (self.mod_frequency & 0x00ff) | (u16::from(data & 0x0f) << 8);
+                self.mod_halt = data & 0x80 != 0;
+            }
+            0x4088 => {
+                // only takes while the modulator's halted, each entry goes in twice
+                if !self.mod_halt { return; }
+
+                let position = ((self.mod_phase >> 16) & 0x3e) as usize;
+                self.mod_table[position] = data & 0b111;
+                self.mod_table[position + 1] = data & 0b111;
+                self.mod_phase = (self.mod_phase + 0x2_0000) & 0x3f_ffff;
+            }
+            0x4089 => {
+                self.wave_write = data & 0x80 != 0;
+                self.master_volume = data & 0b11;
+            }
+            0x408a => self.envelope_speed = data,
+            _ => {}
+        }
+    }
+
+    fn read_register(&mut self, addr: u16) -> Option<u8> {
+        match addr {
+            0x4040..=0x407f => Some(self.wave[(addr - 0x4040) as usize] | 0x40),
+            0x4090 => Some(self.volume.gain | 0x40),
+            0x4092 => Some(self.mod_envelope.gain | 0x40),
+            _ => None,
+        }
+    }
+
+    fn clock(&mut self) {
+        if !self.wave_halt && !self.envelopes_halted && self.envelope_speed != 0 {
+            self.volume.clock(self.envelope_speed);
+            self.mod_envelope.clock(self.envelope_speed);
+        }
+
+        self.clock_modulator();
+
+        if !self.wave_halt && !self.wave_write {
+            self.wave_phase = (self.wave_phase + self.modulated_frequency()) & 0x3f_ffff;
+        }
+
+        // the wave holds its last value while it's being written
+        let level = if self.wave_write { self.output_level } else {
+            let sample = self.wave[(self.wave_phase >> 16) as usize];
+            let gain = self.volume.gain.min(32);
+            f32::from(sample) * f32::from(gain) * MASTER_VOLUME[self.master_volume as usize] * LEVEL_SCALE
+        };
+
+        self.output_level += self.filter_alpha * (level - self.output_level);
+    }
+
+    fn output(&self) -> f32 {
+        self.output_level
+    }
+}
+
+impl Snapshot for FdsEnvelope {
+    fn save_state(&self, state: &mut StateWriter) {
+        state.write_bool(self.disabled);
+        state.write_bool(self.increase);
+        state.write_u8(self.speed);
+        state.write_u8(self.gain);
+        state.write_u32(self.timer);
+    }
+
+    fn load_state(&mut self, state: &mut StateReader) -> Result<(), StateError> {
+        self.disabled = state.read_bool()?;
+        self.increase = state.read_bool()?;
+        self.speed = state.read_u8()?;
+        self.gain = state.read_u8()?;
+        self.timer = state.read_u32()?;
+        Ok(())
+    }
+}
+
+impl Snapshot for FdsAudio {
+    fn save_state(&self, state: &mut StateWriter) {
+        state.write_bytes(&self.wave);
+        state.write_bool(self.wave_write);
+        state.write_bool(self.wave_halt);
+        state.write_u16(self.wave_frequency);
+        state.write_u32(self.wave_phase);
+        self.volume.save_state(state);
+        self.mod_envelope.save_state(state);
+        state.write_bool(self.envelopes_halted);
+        state.write_u8(self.envelope_speed);
+        state.write_bytes(&self.mod_table);
+        state.write_bool(self.mod_halt);
+        state.write_u16(self.mod_frequency);
+        state.write_u32(self.mod_phase);
+        state.write_u8(self.mod_counter as u8);
+        state.write_u8(self.master_volume);
+        state.write_f32(self.output_level);
+    }
+
+    fn load_state(&mut self, state: &mut StateReader) -> Result<(), StateError> {
+        state.read_bytes_into(&mut self.wave)?;
+        self.wave_write = state.read_bool()?;
+        self.wave_halt = state.read_bool()?;
+        self.wave_frequency = state.read_u16()?;
+        self.wave_phase = state.read_u32()?;
+        self.volume.load_state(state)?;
+        self.mod_envelope.load_state(state)?;
+        self.envelopes_halted = state.read_bool()?;
+        self.envelope_speed = state.read_u8()?;
+        state.read_bytes_into(&mut self.mod_table)?;
+        self.mod_halt = state.read_bool()?;
+        self.mod_frequency = state.read_u16()?;
+        self.mod_phase = state.read_u32()?;
+        self.mod_counter = state.read_u8()? as i8;
+        self.master_volume = state.read_u8()? & 0b11;
+        self.output_level = state.read_f32()?;
+        Ok(())
+    }
+}
@@ -0,0 +1,141 @@
+use crate::core::apu::expansion::expansion_audio::ExpansionAudio;
+use crate::core::apu::mixer;
+use crate::core::apu::pulse::{Pulse, PulseChannel};
+use crate::core::state::snapshot::Snapshot;
+use crate::core::state::stream::{StateError, StateReader, StateWriter};
+
+// the MMC5 runs its envelopes and length counters off its own 240Hz clock
+const FRAME_PERIOD: u16 = 7457;
+const PCM_SCALE: f32 = 0.0025;
+
+// Nintendo MMC5: two 2A03 pulses minus the sweep, and a raw 8-bit PCM register
+#[derive(Debug, Clone)]
+pub struct Mmc5Audio {
+    pulse_1: Pulse,
+    pulse_2: Pulse,
+    pcm: u8,
+    pcm_read_mode: bool,
+    pcm_irq_enabled: bool,
+    pcm_irq: bool,
+    frame_timer: u16,
+    cycles: u64,
+}
+
+impl Default for Mmc5Audio {
+    fn default() -> Self {
+        let mut pulse_1 = Pulse::new(PulseChannel::Two);
+        let mut pulse_2 = Pulse::new(PulseChannel::Two);
+        // no sweep unit: a negated zero shift keeps the sweep's overflow check from ever muting them
+        pulse_1.write_register(1, 0x08);
+        pulse_2.write_register(1, 0x08);
+
+        Mmc5Audio {
+            pulse_1,
+            pulse_2,
+            pcm: 0,
+            pcm_read_mode: false,
+            pcm_irq_enabled: false,
+            pcm_irq: false,
+            frame_timer: 0,
+            cycles: 0,
+        }
+    }
+}
+
+impl Mmc5Audio {
+    pub fn new() -> Self {
+        Mmc5Audio::default()
+    }
+
+    pub fn irq(&self) -> bool {
+        self.pcm_irq && self.pcm_irq_enabled
+    }
+
+    // in read mode the PCM channel picks up whatever the CPU reads out of $8000-$BFFF
+    pub fn cpu_read(&mut self, addr: u16, data: u8) {
+        if !self.pcm_read_mode || !(0x8000..=0xbfff).contains(&addr) { return; }
+
+        if data == 0 { self.pcm_irq = true; } else { self.pcm = data; }
+    }
+}
+
+impl ExpansionAudio for Mmc5Audio {
+    fn write_register(&mut self, addr: u16, data: u8) {
+        match addr {
+            0x5000 | 0x5002 | 0x5003 => self.pulse_1.write_register(addr, data),
+            0x5004 | 0x5006 | 0x5007 => self.pulse_2.write_register(addr, data),
+            0x5010 => {
+                self.pcm_read_mode = data & 1 != 0;
+                self.pcm_irq_enabled = data & 0x80 != 0;
+            }
+            // a zero doesn't make it into the DAC
+            0x5011 if data != 0 && !self.pcm_read_mode => self.pcm = data,
+            0x5015 => {
+                self.pulse_1.length_counter.set_enabled(data & 0b01 != 0);
+                self.pulse_2.length_counter.set_enabled(data & 0b10 != 0);
+            }
+            _ => {}
+        }
+    }
+
+    fn read_register(&mut self, addr: u16) -> Option<u8> {
+        match addr {
+            0x5010 => {
+                let status = u8::from(self.irq()) << 7 | u8::from(self.pcm_read_mode);
+                self.pcm_irq = false;
+                Some(status)
+            }
+            0x5015 => Some(
+                u8::from(self.pulse_1.length_counter.active())
+                    | u8::from(self.pulse_2.length_counter.active()) << 1
+            ),
+            _ => None,
+        }
+    }
+
+    fn clock(&mut self) {
+        if self.cycles % 2 == 1 {
+            self.pulse_1.clock_timer();
+            self.pulse_2.clock_timer();
+        }
+        self.cycles += 1;
+
+        self.frame_timer += 1;
+        if self.frame_timer == FRAME_PERIOD {
+            self.frame_timer = 0;
+            for pulse in [&mut self.pulse_1, &mut self.pulse_2] {
+                pulse.clock_quarter_frame();
+                pulse.clock_half_frame();
+            }
+        }
+    }
+
+    fn output(&self) -> f32 {
+        mixer::mix(self.pulse_1.output(), self.pulse_2.output(), 0, 0, 0) + f32::from(self.pcm) * PCM_SCALE
+    }
+}
+
+impl Snapshot for Mmc5Audio {
+    fn save_state(&self, state: &mut StateWriter) {
+        self.pulse_1.save_state(state);
+        self.pulse_2.save_state(state);
+        state.write_u8(self.pcm);
+        state.write_bool(self.pcm_read_mode);
+        state.write_bool(self.pcm_irq_enabled);
+        state.write_bool(self.pcm_irq);
+        state.write_u16(self.frame_timer);
+        state.write_u64(self.cycles);
+    }
+
+    fn load_state(&mut self, state: &mut StateReader) -> Result<(), StateError> {
+        self.pulse_1.load_state(state)?;
+        self.pulse_2.load_state(state)?;
+        self.pcm = state.read_u8()?;
+        self.pcm_read_mode = state.read_bool()?;
+        self.pcm_irq_enabled = state.read_bool()?;
+        self.pcm_irq = state.read_bool()?;
+        self.frame_timer = state.read_u16()?;
+        self.cycles = state.read_u64()?;
+        Ok(())
+    }
+}
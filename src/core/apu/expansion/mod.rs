@@ -0,0 +1,7 @@
+pub mod expansion_audio;
+pub mod fds;
+pub mod mmc5;
+pub mod n163;
+pub mod sunsoft_5b;
+pub mod vrc6;
+pub mod vrc7;
\ No newline at end of file
@@ -0,0 +1,146 @@
+use crate::core::apu::expansion::expansion_audio::ExpansionAudio;
+use crate::core::state::snapshot::Snapshot;
+use crate::core::state::stream::{StateError, StateReader, StateWriter};
+
+const LEVEL_SCALE: f32 = 0.0025;
+// the chip spends 15 CPU cycles on each channel before moving to the next
+const CHANNEL_CYCLES: u8 = 15;
+
+// Namco 163: up to eight 4-bit wavetable channels, all of them defined in its 128 bytes of RAM.
+// the real thing cycles through the enabled channels one at a time, which comes out as an
+// average once the console's filtering has had a go at it, so that's what gets output
+#[derive(Debug, Clone)]
+pub struct N163Audio {
+    ram: [u8; 0x80],
+    address: u8,
+    auto_increment: bool,
+    enabled: bool,
+    cycles: u8,
+    // counts down from channel 7
+    channel: u8,
+    outputs: [i16; 8],
+}
+
+impl Default for N163Audio {
+    fn default() -> Self {
+        N163Audio {
+            ram: [0; 0x80],
+            address: 0,
+            auto_increment: false,
+            enabled: true,
+            cycles: 0,
+            channel: 7,
+            outputs: [0; 8],
+        }
+    }
+}
+
+impl N163Audio {
+    pub fn new() -> Self {
+        N163Audio::default()
+    }
+
+    // the mapper's $E000 bit 6 silences the lot
+    pub fn set_enabled(&mut self, enabled: bool) {
+        self.enabled = enabled;
+    }
+
+    fn active_channels(&self) -> u8 {
+        ((self.ram[0x7f] >> 4) & 0b111) + 1
+    }
+
+    fn advance_address(&mut self) {
+        if self.auto_increment { self.address = (self.address + 1) & 0x7f; }
+    }
+
+    fn update_channel(&mut self, channel: u8) {
+        let base = 0x40 + channel as usize * 8;
+        let registers = &self.ram[base..base + 8];
+
+        let frequency = u32::from(registers[0]) | u32::from(registers[2]) << 8 | u32::from(registers[4] & 0b11) << 16;
+        let mut phase = u32::from(registers[1]) | u32::from(registers[3]) << 8 | u32::from(registers[5]) << 16;
+        let length = 256 - u32::from(registers[4] & 0xfc);
+        let wave_address = u32::from(registers[6]);
+        let volume = i16::from(registers[7] & 0x0f);
+
+        phase = (phase + frequency) % (length << 16);
+
+        let sample_address = ((wave_address + (phase >> 16)) & 0xff) as usize;
+        let byte = self.ram[sample_address / 2];
+        let sample = if sample_address.is_multiple_of(2) { byte & 0x0f } else { byte >> 4 };
+
+        self.ram[base + 1] = phase as u8;
+        self.ram[base + 3] = (phase >> 8) as u8;
+        self.ram[base + 5] = (phase >> 16) as u8;
+        self.outputs[channel as usize] = (i16::from(sample) - 8) * volume;
+    }
+}
+
+impl ExpansionAudio for N163Audio {
+    fn write_register(&mut self, addr: u16, data: u8) {
+        match addr {
+            0x4800..=0x4fff => {
+                self.ram[self.address as usize] = data;
+                self.advance_address();
+            }
+            0xf800..=0xffff => {
+                self.address = data & 0x7f;
+                self.auto_increment = data & 0x80 != 0;
+            }
+            _ => {}
+        }
+    }
+
+    fn read_register(&mut self, addr: u16) -> Option<u8> {
+        if !(0x4800..=0x4fff).contains(&addr) { return None; }
+
+        let data = self.ram[self.address as usize];
+        self.advance_address();
+        Some(data)
+    }
+
+    fn clock(&mut self) {
+        if !self.enabled { return; }
+
+        self.cycles += 1;
+        if self.cycles < CHANNEL_CYCLES { return; }
+        self.cycles = 0;
+
+        self.update_channel(self.channel);
+
+        let lowest = 8 - self.active_channels();
+        self.channel = if self.channel <= lowest { 7 } else { self.channel - 1 };
+    }
+
+    fn output(&self) -> f32 {
+        if !self.enabled { return 0.0; }
+
+        let active = self.active_channels();
+        let sum: i16 = self.outputs[(8 - active) as usize..].iter().sum();
+        f32::from(sum) / f32::from(active) * LEVEL_SCALE
+    }
+}
+
+impl Snapshot for N163Audio {
+    fn save_state(&self, state: &mut StateWriter) {
+        state.write_bytes(&self.ram);
+        state.write_u8(self.address);
+        state.write_bool(self.auto_increment);
+        state.write_bool(self.enabled);
+        state.write_u8(self.cycles);
+        state.write_u8(self.channel);
+        for output in self.outputs { state.write_u16(output as u16); }
+    }
+
+    fn load_state(&mut self, state: &mut StateReader) -> Result<(), StateError> {
+        state.read_bytes_into(&mut self.ram)?;
+        self.address = state.read_u8()? & 0x7f;
+        self.auto_increment = state.read_bool()?;
+        self.enabled = state.read_bool()?;
+        self.cycles = state.read_u8()?;
+        if self.cycles >= CHANNEL_CYCLES { return Err(StateError::Corrupt("N163 channel clock out of range")); }
+        self.channel = state.read_u8()? & 0b111;
+        for output in self.outputs.iter_mut() { *output = state.read_u16()? as i16; }
+        Ok(())
+    }
+}
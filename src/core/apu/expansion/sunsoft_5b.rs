@@ -0,0 +1,276 @@
+use lazy_static::lazy_static;
+use crate::core::apu::expansion::expansion_audio::ExpansionAudio;
+use crate::core::state::snapshot::Snapshot;
+use crate::core::state::stream::{StateError, StateReader, StateWriter};
+
+// a full-volume 5B channel, a bit louder than a 2A03 pulse
+const CHANNEL_SCALE: f32 = 0.18;
+
+lazy_static! {
+    // the volume DAC is logarithmic, 1.5dB a step over the envelope's 32 levels
+    static ref VOLUME_TABLE: [f32; 32] = {
+        let mut table = [0.0; 32];
+        for (level, entry) in table.iter_mut().enumerate().skip(1) {
+            *entry = 10f32.powf(-((31 - level) as f32) * 1.5 / 20.0);
+        }
+        table
+    };
+}
+
+#[derive(Debug, Clone, Default)]
+struct Tone {
+    period: u16,
+    counter: u16,
+    output: bool,
+    tone_disabled: bool,
+    noise_disabled: bool,
+    // 0-15, or the envelope takes over
+    volume: u8,
+    use_envelope: bool,
+}
+
+impl Tone {
+    fn clock(&mut self) {
+        self.counter += 1;
+        if self.counter >= self.period.max(1) {
+            self.counter = 0;
+            self.output = !self.output;
+        }
+    }
+}
+
+#[derive(Debug, Clone, Default)]
+struct Envelope {
+    period: u16,
+    counter: u16,
+    step: u8,
+    attack: bool,
+    alternate: bool,
+    hold: bool,
+    continuing: bool,
+    holding: bool,
+}
+
+impl Envelope {
+    fn write_shape(&mut self, data: u8) {
+        self.continuing = data & 0b1000 != 0;
+        self.attack = data & 0b0100 != 0;
+        self.alternate = data & 0b0010 != 0;
+        self.hold = data & 0b0001 != 0;
+        self.step = 0;
+        self.counter = 0;
+        self.holding = false;
+    }
+
+    fn clock(&mut self) {
+        if self.holding { return; }
+
+        self.counter += 1;
+        if self.counter < self.period.max(1) { return; }
+        self.counter = 0;
+
+        if self.step < 31 {
+            self.step += 1;
+            return;
+        }
+
+        if !self.continuing {
+            self.holding = true;
+            self.attack = false;
+        } else if self.hold {
+            self.holding = true;
+            if self.alternate { self.attack = !self.attack; }
+        } else {
+            if self.alternate { self.attack = !self.attack; }
+            self.step = 0;
+        }
+    }
+
+    fn level(&self) -> u8 {
+        if self.attack { self.step } else { 31 - self.step }
+    }
+}
+
+// Sunsoft 5B, a YM2149F (an AY-3-8910 for all intents) inside the FME-7.
+// $C000 picks one of its 16 registers, $E000 writes it
+#[derive(Debug, Clone)]
+pub struct Sunsoft5bAudio {
+    register: u8,
+    tones: [Tone; 3],
+    envelope: Envelope,
+    noise_period: u8,
+    noise_counter: u8,
+    noise_shift: u32,
+    prescaler: u8,
+}
+
+impl Default for Sunsoft5bAudio {
+    fn default() -> Self {
+        Sunsoft5bAudio {
+            register: 0,
+            tones: Default::default(),
+            envelope: Envelope::default(),
+            noise_period: 0,
+            noise_counter: 0,
+            noise_shift: 1,
+            prescaler: 0,
+        }
+    }
+}
+
+impl Sunsoft5bAudio {
+    pub fn new() -> Self {
+        Sunsoft5bAudio::default()
+    }
+
+    fn write_data(&mut self, data: u8) {
+        match self.register {
+            0x00 | 0x02 | 0x04 => {
+                let tone = &mut self.tones[self.register as usize / 2];
+                tone.period = (tone.period & 0x0f00) | u16::from(data);
+            }
+            0x01 | 0x03 | 0x05 => {
+                let tone = &mut self.tones[self.register as usize / 2];
+                tone.period = (tone.period & 0x00ff) | (u16::from(data & 0x0f) << 8);
+            }
+            0x06 => self.noise_period = data & 0x1f,
+            0x07 => {
+                for (i, tone) in self.tones.iter_mut().enumerate() {
+                    tone.tone_disabled = data & (1 << i) != 0;
+                    tone.noise_disabled = data & (1 << (i + 3)) != 0;
+                }
+            }
+            0x08..=0x0a => {
+                let tone = &mut self.tones[self.register as usize - 0x08];
+                tone.use_envelope = data & 0x10 != 0;
+                tone.volume = data & 0x0f;
+            }
+            0x0b => self.envelope.period = (self.envelope.period & 0xff00) | u16::from(data),
+            0x0c => self.envelope.period = (self.envelope.period & 0x00ff) | (u16::from(data) << 8),
+            0x0d => self.envelope.write_shape(data),
+            // the I/O ports, nothing's connected to them
+            _ => {}
+        }
+    }
+
+    fn clock_noise(&mut self) {
+        self.noise_counter += 1;
+        if self.noise_counter < self.noise_period.max(1) { return; }
+        self.noise_counter = 0;
+
+        // 17-bit LFSR, taps on bits 0 and 3
+        let feedback = (self.noise_shift ^ (self.noise_shift >> 3)) & 1;
+        self.noise_shift = (self.noise_shift >> 1) | (feedback << 16);
+    }
+}
+
+impl ExpansionAudio for Sunsoft5bAudio {
+    fn write_register(&mut self, addr: u16, data: u8) {
+        match addr {
+            0xc000..=0xdfff => self.register = data & 0x0f,
+            0xe000..=0xffff => self.write_data(data),
+            _ => {}
+        }
+    }
+
+    fn clock(&mut self) {
+        // the envelope runs off an /8 of the CPU clock, the tones /16 and the noise /32
+        self.prescaler = self.prescaler.wrapping_add(1);
+        if !self.prescaler.is_multiple_of(8) { return; }
+
+        self.envelope.clock();
+        if !self.prescaler.is_multiple_of(16) { return; }
+
+        for tone in self.tones.iter_mut() {
+            tone.clock();
+        }
+        if self.prescaler.is_multiple_of(32) { self.clock_noise(); }
+    }
+
+    fn output(&self) -> f32 {
+        let noise = self.noise_shift & 1 != 0;
+
+        let level: f32 = self.tones.iter()
+            .filter(|tone| (tone.output || tone.tone_disabled) && (noise || tone.noise_disabled))
+            .map(|tone| {
+                let level = if tone.use_envelope { self.envelope.level() }
+                    else if tone.volume == 0 { 0 }
+                    else { tone.volume * 2 + 1 };
+                VOLUME_TABLE[level as usize]
+            })
+            .sum();
+
+        level * CHANNEL_SCALE
+    }
+}
+
+impl Snapshot for Tone {
+    fn save_state(&self, state: &mut StateWriter) {
+        state.write_u16(self.period);
+        state.write_u16(self.counter);
+        state.write_bool(self.output);
+        state.write_bool(self.tone_disabled);
+        state.write_bool(self.noise_disabled);
+        state.write_u8(self.volume);
+        state.write_bool(self.use_envelope);
+    }
+
+    fn load_state(&mut self, state: &mut StateReader) -> Result<(), StateError> {
+        self.period = state.read_u16()?;
+        self.counter = state.read_u16()?;
+        self.output = state.read_bool()?;
+        self.tone_disabled = state.read_bool()?;
+        self.noise_disabled = state.read_bool()?;
+        self.volume = state.read_u8()? & 0x0f;
+        self.use_envelope = state.read_bool()?;
+        Ok(())
+    }
+}
+
+impl Snapshot for Envelope {
+    fn save_state(&self, state: &mut StateWriter) {
+        state.write_u16(self.period);
+        state.write_u16(self.counter);
+        state.write_u8(self.step);
+        state.write_bool(self.attack);
+        state.write_bool(self.alternate);
+        state.write_bool(self.hold);
+        state.write_bool(self.continuing);
+        state.write_bool(self.holding);
+    }
+
+    fn load_state(&mut self, state: &mut StateReader) -> Result<(), StateError> {
+        self.period = state.read_u16()?;
+        self.counter = state.read_u16()?;
+        self.step = state.read_u8()?.min(31);
+        self.attack = state.read_bool()?;
+        self.alternate = state.read_bool()?;
+        self.hold = state.read_bool()?;
+        self.continuing = state.read_bool()?;
+        self.holding = state.read_bool()?;
+        Ok(())
+    }
+}
+
+impl Snapshot for Sunsoft5bAudio {
+    fn save_state(&self, state: &mut StateWriter) {
+        state.write_u8(self.register);
+        for tone in self.tones.iter() { tone.save_state(state); }
+        self.envelope.save_state(state);
+        state.write_u8(self.noise_period);
+        state.write_u8(self.noise_counter);
+        state.write_u32(self.noise_shift);
+        state.write_u8(self.prescaler);
+    }
+
+    fn load_state(&mut self, state: &mut StateReader) -> Result<(), StateError> {
+        self.register = state.read_u8()?;
+        for tone in self.tones.iter_mut() { tone.load_state(state)?; }
+        self.envelope.load_state(state)?;
+        self.noise_period = state.read_u8()?;
+        self.noise_counter = state.read_u8()?;
+        self.noise_shift = state.read_u32()?;
+        self.prescaler = state.read_u8()?;
+        Ok(())
+    }
+}
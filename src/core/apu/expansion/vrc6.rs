@@ -0,0 +1,209 @@
+use crate::core::apu::expansion::expansion_audio::ExpansionAudio;
+use crate::core::state::snapshot::Snapshot;
+use crate::core::state::stream::{StateError, StateReader, StateWriter};
+
+// one step of VRC6 output is about as loud as one step of a 2A03 pulse's volume
+const LEVEL_SCALE: f32 = 0.00996;
+
+#[derive(Debug, Clone, Default)]
+struct Vrc6Pulse {
+    volume: u8,
+    duty: u8,
+    // "digitized" mode, the duty is ignored and it just outputs the volume
+    constant: bool,
+    enabled: bool,
+    period: u16,
+    timer: u16,
+    step: u8,
+}
+
+impl Vrc6Pulse {
+    fn write_register(&mut self, register: u16, data: u8) {
+        match register {
+            0 => {
+                self.constant = data & 0x80 != 0;
+                self.duty = (data >> 4) & 0b111;
+                self.volume = data & 0x0f;
+            }
+            1 => self.period = (self.period & 0x0f00) | u16::from(data),
+            _ => {
+                self.period = (self.period & 0x00ff) | (u16::from(data & 0x0f) << 8);
+                self.enabled = data & 0x80 != 0;
+                if !self.enabled { self.step = 15; }
+            }
+        }
+    }
+
+    fn clock(&mut self, shift: u8) {
+        if !self.enabled { return; }
+
+        if self.timer == 0 {
+            self.timer = self.period >> shift;
+            self.step = self.step.wrapping_sub(1) & 0x0f;
+        } else {
+            self.timer -= 1;
+        }
+    }
+
+    fn output(&self) -> u8 {
+        if self.enabled && (self.constant || self.step <= self.duty) { self.volume } else { 0 }
+    }
+}
+
+#[derive(Debug, Clone, Default)]
+struct Vrc6Saw {
+    rate: u8,
+    enabled: bool,
+    period: u16,
+    timer: u16,
+    step: u8,
+    accumulator: u8,
+}
+
+impl Vrc6Saw {
+    fn write_register(&mut self, register: u16, data: u8) {
+        match register {
+            0 => self.rate = data & 0x3f,
+            1 => self.period = (self.period & 0x0f00) | u16::from(data),
+            _ => {
+                self.period = (self.period & 0x00ff) | (u16::from(data & 0x0f) << 8);
+                self.enabled = data & 0x80 != 0;
+                if !self.enabled {
+                    self.step = 0;
+                    self.accumulator = 0;
+                }
+            }
+        }
+    }
+
+    fn clock(&mut self, shift: u8) {
+        if !self.enabled { return; }
+
+        if self.timer > 0 {
+            self.timer -= 1;
+            return;
+        }
+        self.timer = self.period >> shift;
+
+        // the accumulator only moves on every other step, and resets after the 7th add
+        self.step += 1;
+        if self.step == 14 {
+            self.step = 0;
+            self.accumulator = 0;
+        } else if self.step.is_multiple_of(2) {
+            self.accumulator = self.accumulator.wrapping_add(self.rate);
+        }
+    }
+
+    fn output(&self) -> u8 {
+        self.accumulator >> 3
+    }
+}
+
+// Konami VRC6: two pulses with 8 duty settings and a sawtooth, registers at $9000-$B002
+#[derive(Debug, Clone, Default)]
+pub struct Vrc6Audio {
+    pulse_1: Vrc6Pulse,
+    pulse_2: Vrc6Pulse,
+    saw: Vrc6Saw,
+    halt: bool,
+    // $9003 can speed all three up by 16 or 256 times
+    period_shift: u8,
+}
+
+impl Vrc6Audio {
+    pub fn new() -> Self {
+        Vrc6Audio::default()
+    }
+}
+
+impl ExpansionAudio for Vrc6Audio {
+    fn write_register(&mut self, addr: u16, data: u8) {
+        match addr {
+            0x9003 => {
+                self.halt = data & 1 != 0;
+                self.period_shift = if data & 0b100 != 0 { 8 } else if data & 0b10 != 0 { 4 } else { 0 };
+            }
+            0x9000..=0x9002 => self.pulse_1.write_register(addr & 0b11, data),
+            0xa000..=0xa002 => self.pulse_2.write_register(addr & 0b11, data),
+            0xb000..=0xb002 => self.saw.write_register(addr & 0b11, data),
+            _ => {}
+        }
+    }
+
+    fn clock(&mut self) {
+        if self.halt { return; }
+
+        self.pulse_1.clock(self.period_shift);
+        self.pulse_2.clock(self.period_shift);
+        self.saw.clock(self.period_shift);
+    }
+
+    fn output(&self) -> f32 {
+        let level = self.pulse_1.output() + self.pulse_2.output() + self.saw.output();
+        f32::from(level) * LEVEL_SCALE
+    }
+}
+
+impl Snapshot for Vrc6Pulse {
+    fn save_state(&self, state: &mut StateWriter) {
+        state.write_u8(self.volume);
+        state.write_u8(self.duty);
+        state.write_bool(self.constant);
+        state.write_bool(self.enabled);
+        state.write_u16(self.period);
+        state.write_u16(self.timer);
+        state.write_u8(self.step);
+    }
+
+    fn load_state(&mut self, state: &mut StateReader) -> Result<(), StateError> {
+        self.volume = state.read_u8()?;
+        self.duty = state.read_u8()?;
+        self.constant = state.read_bool()?;
+        self.enabled = state.read_bool()?;
+        self.period = state.read_u16()?;
+        self.timer = state.read_u16()?;
+        self.step = state.read_u8()?;
+        Ok(())
+    }
+}
+
+impl Snapshot for Vrc6Saw {
+    fn save_state(&self, state: &mut StateWriter) {
+        state.write_u8(self.rate);
+        state.write_bool(self.enabled);
+        state.write_u16(self.period);
+        state.write_u16(self.timer);
+        state.write_u8(self.step);
+        state.write_u8(self.accumulator);
+    }
+
+    fn load_state(&mut self, state: &mut StateReader) -> Result<(), StateError> {
+        self.rate = state.read_u8()?;
+        self.enabled = state.read_bool()?;
+        self.period = state.read_u16()?;
+        self.timer = state.read_u16()?;
+        self.step = state.read_u8()? % 14;
+        self.accumulator = state.read_u8()?;
+        Ok(())
+    }
+}
+
+impl Snapshot for Vrc6Audio {
+    fn save_state(&self, state: &mut StateWriter) {
+        self.pulse_1.save_state(state);
+        self.pulse_2.save_state(state);
+        self.saw.save_state(state);
+        state.write_bool(self.halt);
+        state.write_u8(self.period_shift);
+    }
+
+    fn load_state(&mut self, state: &mut StateReader) -> Result<(), StateError> {
+        self.pulse_1.load_state(state)?;
+        self.pulse_2.load_state(state)?;
+        self.saw.load_state(state)?;
+        self.halt = state.read_bool()?;
+        self.period_shift = state.read_u8()?;
+        Ok(())
+    }
+}
@@ -0,0 +1,415 @@
+use std::f32::consts::PI;
+use crate::core::apu::expansion::expansion_audio::ExpansionAudio;
+use crate::core::state::snapshot::Snapshot;
+use crate::core::state::stream::{StateError, StateReader, StateWriter};
+
+const CHANNEL_SCALE: f32 = 0.12;
+// the OPLL runs off a 3.58MHz crystal and puts out a sample every 72 of its clocks
+const SAMPLE_RATE: f32 = 3_579_545.0 / 72.0;
+const CPU_CYCLES_PER_SAMPLE: u8 = 36;
+// how many waveform cycles a full-scale modulator output shifts the carrier's phase by
+const MODULATION_DEPTH: f32 = 2.0;
+// the envelope can attenuate by 48dB before the operator's considered silent
+const MAX_ATTENUATION: f32 = 48.0;
+
+const MULTIPLIERS: [f32; 16] = [0.5, 1.0, 2.0, 3.0, 4.0, 5.0, 6.0, 7.0, 8.0, 9.0, 10.0, 10.0, 12.0, 12.0, 15.0, 15.0];
+// dB at block 7, by the top four bits of the F-number
+const KEY_SCALE_LEVELS: [f32; 16] = [
+    0.0, 18.0, 24.0, 27.75, 30.0, 32.25, 33.75, 35.25, 36.0, 37.5, 38.25, 39.0, 39.75, 40.5, 41.25, 42.0,
+];
+const KEY_SCALE_MULTIPLIERS: [f32; 4] = [0.0, 0.25, 0.5, 1.0];
+
+// the 15 instruments baked into the VRC7's ROM, instrument 0 is the user-defined one at $00-$07
+const PATCHES: [[u8; 8]; 15] = [
+    [0x03, 0x21, 0x05, 0x06, 0xe8, 0x81, 0x42, 0x27],
+    [0x13, 0x41, 0x14, 0x0d, 0xd8, 0xf6, 0x23, 0x12],
+    [0x11, 0x11, 0x08, 0x08, 0xfa, 0xb2, 0x20, 0x12],
+    [0x31, 0x61, 0x0c, 0x07, 0xa8, 0x64, 0x61, 0x27],
+    [0x32, 0x21, 0x1e, 0x06, 0xe1, 0x76, 0x01, 0x28],
+    [0x02, 0x01, 0x06, 0x00, 0xa3, 0xe2, 0xf4, 0xf4],
+    [0x21, 0x61, 0x1d, 0x07, 0x82, 0x81, 0x11, 0x07],
+    [0x23, 0x21, 0x22, 0x17, 0xa2, 0x72, 0x01, 0x17],
+    [0x35, 0x11, 0x25, 0x00, 0x40, 0x73, 0x72, 0x01],
+    [0xb5, 0x01, 0x0f, 0x0f, 0xa8, 0xa5, 0x51, 0x02],
+    [0x17, 0xc1, 0x24, 0x07, 0xf8, 0xf8, 0x22, 0x12],
+    [0x71, 0x23, 0x11, 0x06, 0x65, 0x74, 0x18, 0x16],
+    [0x01, 0x02, 0xd3, 0x05, 0xc9, 0x95, 0x03, 0x02],
+    [0x61, 0x63, 0x0c, 0x00, 0x94, 0xc0, 0x33, 0xf6],
+    [0x21, 0x72, 0x0d, 0x00, 0xc1, 0xd5, 0x56, 0x06],
+];
+
+#[derive(Debug, Copy, Clone, Eq, PartialEq, Default)]
+enum EnvelopeState {
+    Attack,
+    Decay,
+    Sustain,
+    #[default]
+    Release,
+}
+
+// one half of a patch, decoded
+#[derive(Debug, Copy, Clone, Default)]
+struct OperatorPatch {
+    tremolo: bool,
+    vibrato: bool,
+    sustained: bool,
+    key_scale_rate: bool,
+    multiplier: f32,
+    key_scale_level: u8,
+    rectified: bool,
+    attack_rate: u8,
+    decay_rate: u8,
+    sustain_level: u8,
+    release_rate: u8,
+}
+
+#[derive(Debug, Copy, Clone, Default)]
+struct Patch {
+    modulator: OperatorPatch,
+    carrier: OperatorPatch,
+    // modulator only
+    total_level: u8,
+    feedback: u8,
+}
+
+impl Patch {
+    fn decode(raw: &[u8; 8]) -> Self {
+        let operator = |flags: u8, levels: u8, rectified: bool, rates: u8, release: u8| OperatorPatch {
+            tremolo: flags & 0x80 != 0,
+            vibrato: flags & 0x40 != 0,
+            sustained: flags & 0x20 != 0,
+            key_scale_rate: flags & 0x10 != 0,
+            multiplier: MULTIPLIERS[(flags & 0x0f) as usize],
+            key_scale_level: levels >> 6,
+            rectified,
+            attack_rate: rates >> 4,
+            decay_rate: rates & 0x0f,
+            sustain_level: release >> 4,
+            release_rate: release & 0x0f,
+        };
+
+        Patch {
+            modulator: operator(raw[0], raw[2], raw[3] & 0x08 != 0, raw[4], raw[6]),
+            carrier: operator(raw[1], raw[3], raw[3] & 0x10 != 0, raw[5], raw[7]),
+            total_level: raw[2] & 0x3f,
+            feedback: raw[3] & 0b111,
+        }
+    }
+}
+
+#[derive(Debug, Copy, Clone, Default)]
+struct Operator {
+    // in waveform cycles, 0.0..1.0
+    phase: f32,
+    state: EnvelopeState,
+    // dB
+    attenuation: f32,
+}
+
+impl Operator {
+    fn key_on(&mut self) {
+        self.phase = 0.0;
+        self.state = EnvelopeState::Attack;
+    }
+
+    fn key_off(&mut self) {
+        self.state = EnvelopeState::Release;
+    }
+
+    // seconds for a full-depth decay at a 0-63 rate, halving every four steps
+    fn decay_step(rate: u8) -> f32 {
+        if rate < 4 { return 0.0; }
+
+        let seconds = 39.28 / 2f32.powf(f32::from(rate - 4) / 4.0);
+        MAX_ATTENUATION / (seconds * SAMPLE_RATE)
+    }
+
+    fn clock_envelope(&mut self, patch: &OperatorPatch, key_scale: u8, channel_sustain: bool) {
+        let effective = |rate: u8| if rate == 0 { 0 } else { (rate * 4 + key_scale).min(63) };
+
+        match self.state {
+            EnvelopeState::Attack => {
+                let rate = effective(patch.attack_rate);
+                if rate >= 60 {
+                    self.attenuation = 0.0;
+                } else if rate >= 4 {
+                    // exponential, and a good deal quicker than the decays
+                    let samples = 39.28 / 14.0 / 2f32.powf(f32::from(rate - 4) / 4.0) * SAMPLE_RATE;
+                    self.attenuation *= (0.1f32 / MAX_ATTENUATION).powf(1.0 / samples.max(1.0));
+                }
+
+                if self.attenuation < 0.1 {
+                    self.attenuation = 0.0;
+                    self.state = EnvelopeState::Decay;
+                }
+            }
+            EnvelopeState::Decay => {
+                self.attenuation += Operator::decay_step(effective(patch.decay_rate));
+
+                let sustain_level = f32::from(patch.sustain_level) * 3.0;
+                if self.attenuation >= sustain_level {
+                    self.attenuation = sustain_level;
+                    self.state = EnvelopeState::Sustain;
+                }
+            }
+            EnvelopeState::Sustain => {
+                // percussive instruments keep fading even with the key down
+                if !patch.sustained {
+                    self.attenuation += Operator::decay_step(effective(patch.release_rate));
+                }
+            }
+            EnvelopeState::Release => {
+                let rate = if channel_sustain { 5 }
+                    else if patch.sustained { patch.release_rate }
+                    else { 7 };
+                self.attenuation += Operator::decay_step(effective(rate));
+            }
+        }
+
+        self.attenuation = self.attenuation.min(MAX_ATTENUATION);
+    }
+
+    // -1.0..=1.0, `modulation` in waveform cycles
+    fn output(&self, modulation: f32, extra_attenuation: f32, rectified: bool) -> f32 {
+        let attenuation = self.attenuation + extra_attenuation;
+        if attenuation >= MAX_ATTENUATION { return 0.0; }
+
+        let wave = (2.0 * PI * (self.phase + modulation)).sin();
+        let wave = if rectified { wave.max(0.0) } else { wave };
+        wave * 10f32.powf(-attenuation / 20.0)
+    }
+}
+
+#[derive(Debug, Copy, Clone, Default)]
+struct Channel {
+    f_number: u16,
+    block: u8,
+    key_on: bool,
+    sustain: bool,
+    instrument: u8,
+    volume: u8,
+    modulator: Operator,
+    carrier: Operator,
+    feedback_history: [f32; 2],
+    output: f32,
+}
+
+impl Channel {
+    fn set_key(&mut self, key_on: bool) {
+        if key_on && !self.key_on {
+            self.modulator.key_on();
+            self.carrier.key_on();
+        } else if !key_on && self.key_on {
+            self.modulator.key_off();
+            self.carrier.key_off();
+        }
+        self.key_on = key_on;
+    }
+
+    fn frequency(&self) -> f32 {
+        f32::from(self.f_number) * SAMPLE_RATE * 2f32.powi(i32::from(self.block) - 1) / 262_144.0
+    }
+
+    fn key_scale_level(&self, level: u8) -> f32 {
+        let attenuation = KEY_SCALE_LEVELS[(self.f_number >> 5) as usize] - 6.0 * f32::from(7 - self.block);
+        attenuation.max(0.0) * KEY_SCALE_MULTIPLIERS[level as usize]
+    }
+
+    fn key_scale_rate(&self, enabled: bool) -> u8 {
+        let key_code = (self.block << 1) | (self.f_number >> 8) as u8;
+        if enabled { key_code } else { key_code >> 2 }
+    }
+
+    fn clock(&mut self, patch: &Patch, tremolo: f32, vibrato: f32) {
+        let frequency = self.frequency();
+
+        for (operator, settings) in [(&mut self.modulator, &patch.modulator), (&mut self.carrier, &patch.carrier)] {
+            let pitch = if settings.vibrato { 1.0 + vibrato } else { 1.0 };
+            operator.phase = (operator.phase + frequency * settings.multiplier * pitch / SAMPLE_RATE).fract();
+        }
+
+        let modulator_scale = self.key_scale_rate(patch.modulator.key_scale_rate);
+        let carrier_scale = self.key_scale_rate(patch.carrier.key_scale_rate);
+        self.modulator.clock_envelope(&patch.modulator, modulator_scale, self.sustain);
+        self.carrier.clock_envelope(&patch.carrier, carrier_scale, self.sustain);
+
+        let feedback = if patch.feedback == 0 { 0.0 } else {
+            (self.feedback_history[0] + self.feedback_history[1]) / 2.0 * 2f32.powi(i32::from(patch.feedback) - 6)
+        };
+        let modulator = self.modulator.output(
+            feedback,
+            f32::from(patch.total_level) * 0.75
+                + self.key_scale_level(patch.modulator.key_scale_level)
+                + if patch.modulator.tremolo { tremolo } else { 0.0 },
+            patch.modulator.rectified,
+        );
+        self.feedback_history = [self.feedback_history[1], modulator];
+
+        self.output = self.carrier.output(
+            modulator * MODULATION_DEPTH,
+            f32::from(self.volume) * 3.0
+                + self.key_scale_level(patch.carrier.key_scale_level)
+                + if patch.carrier.tremolo { tremolo } else { 0.0 },
+            patch.carrier.rectified,
+        );
+    }
+}
+
+// Konami VRC7, a cut-down YM2413 (OPLL): six two-operator FM channels and no rhythm section.
+// $9010 picks a register, $9030 writes it
+#[derive(Debug, Clone, Default)]
+pub struct Vrc7Audio {
+    register: u8,
+    custom_patch: [u8; 8],
+    channels: [Channel; 6],
+    cycles: u8,
+    // sample count, drives the tremolo (3.7Hz) and vibrato (6.4Hz) LFOs
+    lfo_clock: u32,
+}
+
+impl Vrc7Audio {
+    pub fn new() -> Self {
+        Vrc7Audio::default()
+    }
+
+    fn patch(&self, instrument: u8) -> Patch {
+        if instrument == 0 { Patch::decode(&self.custom_patch) }
+        else { Patch::decode(&PATCHES[instrument as usize - 1]) }
+    }
+
+    fn write_data(&mut self, data: u8) {
+        let register = self.register;
+        let channel = (register & 0x0f) as usize;
+
+        match register {
+            0x00..=0x07 => self.custom_patch[register as usize] = data,
+            0x10..=0x15 => {
+                let channel = &mut self.channels[channel];
+                channel.f_number = (channel.f_number & 0x100) | u16::from(data);
+            }
+            0x20..=0x25 => {
+                let channel = &mut self.channels[channel];
+                channel.f_number = (channel.f_number & 0xff) | (u16::from(data & 1) << 8);
+                channel.block = (data >> 1) & 0b111;
+                channel.sustain = data & 0x20 != 0;
+                channel.set_key(data & 0x10 != 0);
+            }
+            0x30..=0x35 => {
+                let channel = &mut self.channels[channel];
+                channel.instrument = data >> 4;
+                channel.volume = data & 0x0f;
+            }
+            _ => {}
+        }
+    }
+
+    fn generate_sample(&mut self) {
+        self.lfo_clock = self.lfo_clock.wrapping_add(1);
+        let time = self.lfo_clock as f32 / SAMPLE_RATE;
+        // 4.8dB of tremolo, about 14 cents of vibrato either way
+        let tremolo = 2.4 * (1.0 + (2.0 * PI * 3.7 * time).sin());
+        let vibrato = 0.008 * (2.0 * PI * 6.4 * time).sin();
+
+        for i in 0..self.channels.len() {
+            let patch = self.patch(self.channels[i].instrument);
+            self.channels[i].clock(&patch, tremolo, vibrato);
+        }
+    }
+}
+
+impl ExpansionAudio for Vrc7Audio {
+    fn write_register(&mut self, addr: u16, data: u8) {
+        match addr {
+            0x9010 => self.register = data,
+            0x9030 => self.write_data(data),
+            _ => {}
+        }
+    }
+
+    fn clock(&mut self) {
+        self.cycles += 1;
+        if self.cycles < CPU_CYCLES_PER_SAMPLE { return; }
+        self.cycles = 0;
+
+        self.generate_sample();
+    }
+
+    fn output(&self) -> f32 {
+        self.channels.iter().map(|channel| channel.output).sum::<f32>() * CHANNEL_SCALE
+    }
+}
+
+impl Snapshot for Operator {
+    fn save_state(&self, state: &mut StateWriter) {
+        state.write_f32(self.phase);
+        state.write_u8(match self.state {
+            EnvelopeState::Attack => 0,
+            EnvelopeState::Decay => 1,
+            EnvelopeState::Sustain => 2,
+            EnvelopeState::Release => 3,
+        });
+        state.write_f32(self.attenuation);
+    }
+
+    fn load_state(&mut self, state: &mut StateReader) -> Result<(), StateError> {
+        self.phase = state.read_f32()?;
+        self.state = match state.read_u8()? {
+            0 => EnvelopeState::Attack,
+            1 => EnvelopeState::Decay,
+            2 => EnvelopeState::Sustain,
+            3 => EnvelopeState::Release,
+            _ => return Err(StateError::Corrupt("unknown VRC7 envelope state")),
+        };
+        self.attenuation = state.read_f32()?;
+        Ok(())
+    }
+}
+
+impl Snapshot for Channel {
+    fn save_state(&self, state: &mut StateWriter) {
+        state.write_u16(self.f_number);
+        state.write_u8(self.block);
+        state.write_bool(self.key_on);
+        state.write_bool(self.sustain);
+        state.write_u8(self.instrument);
+        state.write_u8(self.volume);
+        self.modulator.save_state(state);
+        self.carrier.save_state(state);
+        for sample in self.feedback_history { state.write_f32(sample); }
+        state.write_f32(self.output);
+    }
+
+    fn load_state(&mut self, state: &mut StateReader) -> Result<(), StateError> {
+        self.f_number = state.read_u16()? & 0x1ff;
+        self.block = state.read_u8()? & 0b111;
+        self.key_on = state.read_bool()?;
+        self.sustain = state.read_bool()?;
+        self.instrument = state.read_u8()? & 0x0f;
+        self.volume = state.read_u8()? & 0x0f;
+        self.modulator.load_state(state)?;
+        self.carrier.load_state(state)?;
+        for sample in self.feedback_history.iter_mut() { *sample = state.read_f32()?; }
+        self.output = state.read_f32()?;
+        Ok(())
+    }
+}
+
+impl Snapshot for Vrc7Audio {
+    fn save_state(&self, state: &mut StateWriter) {
+        state.write_u8(self.register);
+        state.write_bytes(&self.custom_patch);
+        for channel in self.channels.iter() { channel.save_state(state); }
+        state.write_u8(self.cycles);
+        state.write_u32(self.lfo_clock);
+    }
+
+    fn load_state(&mut self, state: &mut StateReader) -> Result<(), StateError> {
+        self.register = state.read_u8()?;
+        state.read_bytes_into(&mut self.custom_patch)?;
+        for channel in self.channels.iter_mut() { channel.load_state(state)?; }
+        self.cycles = state.read_u8()?;
+        if self.cycles >= CPU_CYCLES_PER_SAMPLE { return Err(StateError::Corrupt("VRC7 sample clock out of range")); }
+        self.lfo_clock = state.read_u32()?;
+        Ok(())
+    }
+}
@@ -0,0 +1,69 @@
+use std::f32::consts::PI;
+
+// first-order filters, run at the output sample rate
+#[derive(Debug, Clone)]
+pub struct HighPass {
+    alpha: f32,
+    previous_input: f32,
+    previous_output: f32,
+}
+
+impl HighPass {
+    pub fn new(cutoff: f32, sample_rate: u32) -> Self {
+        let rc = 1.0 / (2.0 * PI * cutoff);
+        let dt = 1.0 / sample_rate as f32;
+
+        HighPass { alpha: rc / (rc + dt), previous_input: 0.0, previous_output: 0.0 }
+    }
+
+    pub fn process(&mut self, input: f32) -> f32 {
+        let output = self.alpha * (self.previous_output + input - self.previous_input);
+        self.previous_input = input;
+        self.previous_output = output;
+        output
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct LowPass {
+    alpha: f32,
+    previous_output: f32,
+}
+
+impl LowPass {
+    pub fn new(cutoff: f32, sample_rate: u32) -> Self {
+        let rc = 1.0 / (2.0 * PI * cutoff);
+        let dt = 1.0 / sample_rate as f32;
+
+        LowPass { alpha: dt / (rc + dt), previous_output: 0.0 }
+    }
+
+    pub fn process(&mut self, input: f32) -> f32 {
+        self.previous_output += self.alpha * (input - self.previous_output);
+        self.previous_output
+    }
+}
+
+// what sits between the 2A03 and the RCA jack on a front-loader
+#[derive(Debug, Clone)]
+pub struct FilterChain {
+    high_pass_90: HighPass,
+    high_pass_440: HighPass,
+    low_pass_14k: LowPass,
+}
+
+impl FilterChain {
+    pub fn new(sample_rate: u32) -> Self {
+        FilterChain {
+            high_pass_90: HighPass::new(90.0, sample_rate),
+            high_pass_440: HighPass::new(440.0, sample_rate),
+            low_pass_14k: LowPass::new(14_000.0, sample_rate),
+        }
+    }
+
+    pub fn process(&mut self, input: f32) -> f32 {
+        let sample = self.high_pass_90.process(input);
+        let sample = self.high_pass_440.process(sample);
+        self.low_pass_14k.process(sample)
+    }
+}
@@ -0,0 +1,146 @@
+use crate::core::region::RegionTiming;
+use crate::core::state::snapshot::Snapshot;
+use crate::core::state::stream::{StateError, StateReader, StateWriter};
+
+#[derive(Debug, Copy, Clone, Default, Eq, PartialEq)]
+pub struct FrameClock {
+    pub quarter: bool,
+    pub half: bool,
+}
+
+#[derive(Debug, Copy, Clone, Default, Eq, PartialEq)]
+pub enum FrameCounterMode {
+    #[default]
+    FourStep,
+    FiveStep,
+}
+
+// the APU's own little sequencer that clocks envelopes, sweeps and length counters,
+// and in 4-step mode raises the frame IRQ at the end of every sequence
+#[derive(Debug, Clone)]
+pub struct FrameCounter {
+    cycle: u32,
+    pub mode: FrameCounterMode,
+    pub irq_inhibit: bool,
+    pub irq_flag: bool,
+    // $4017 writes only land 3 or 4 CPU cycles later
+    pending_write: Option<(u8, u8)>,
+    timing: &'static RegionTiming,
+}
+
+impl FrameCounter {
+    pub fn new(timing: &'static RegionTiming) -> Self {
+        FrameCounter {
+            cycle: 0,
+            mode: FrameCounterMode::FourStep,
+            irq_inhibit: false,
+            irq_flag: false,
+            pending_write: None,
+            timing,
+        }
+    }
+
+    // $4017. odd_cycle is whether the write lands between two APU cycles, which costs the extra delay cycle
+    pub fn write(&mut self, data: u8, odd_cycle: bool) {
+        self.irq_inhibit = data & 0x40 != 0;
+        if self.irq_inhibit { self.irq_flag = false; }
+
+        let delay = if odd_cycle { 4 } else { 3 };
+        self.pending_write = Some((data, delay));
+    }
+
+    // once per CPU cycle
+    pub fn clock(&mut self) -> FrameClock {
+        if let Some((data, delay)) = self.pending_write {
+            if delay > 1 {
+                self.pending_write = Some((data, delay - 1));
+            } else {
+                self.pending_write = None;
+                return self.reset(data);
+            }
+        }
+
+        self.cycle += 1;
+
+        match self.mode {
+            FrameCounterMode::FourStep => self.clock_four_step(),
+            FrameCounterMode::FiveStep => self.clock_five_step(),
+        }
+    }
+
+    fn reset(&mut self, data: u8) -> FrameClock {
+        self.cycle = 0;
+
+        // switching to 5-step clocks everything once right away
+        if data & 0x80 != 0 {
+            self.mode = FrameCounterMode::FiveStep;
+            FrameClock { quarter: true, half: true }
+        } else {
+            self.mode = FrameCounterMode::FourStep;
+            FrameClock::default()
+        }
+    }
+
+    fn clock_four_step(&mut self) -> FrameClock {
+        let steps = &self.timing.frame_counter_4_step;
+        let last = steps[3];
+
+        // the IRQ flag gets set three cycles running around the last step
+        if (last - 1 ..= last + 1).contains(&self.cycle) && !self.irq_inhibit {
+            self.irq_flag = true;
+        }
+
+        let clock = match self.cycle {
+            c if c == steps[0] || c == steps[2] => FrameClock { quarter: true, half: false },
+            c if c == steps[1] || c == last => FrameClock { quarter: true, half: true },
+            _ => FrameClock::default(),
+        };
+
+        if self.cycle == last + 1 { self.cycle = 0; }
+        clock
+    }
+
+    fn clock_five_step(&mut self) -> FrameClock {
+        let steps = &self.timing.frame_counter_5_step;
+
+        // step 4 (steps[3]) does nothing at all in this mode
+        let clock = match self.cycle {
+            c if c == steps[0] || c == steps[2] => FrameClock { quarter: true, half: false },
+            c if c == steps[1] || c == steps[4] => FrameClock { quarter: true, half: true },
+            _ => FrameClock::default(),
+        };
+
+        if self.cycle == steps[4] + 1 { self.cycle = 0; }
+        clock
+    }
+}
+
+impl Snapshot for FrameCounter {
+    fn save_state(&self, state: &mut StateWriter) {
+        state.write_u32(self.cycle);
+        state.write_bool(self.mode == FrameCounterMode::FiveStep);
+        state.write_bool(self.irq_inhibit);
+        state.write_bool(self.irq_flag);
+        let (data, delay) = self.pending_write.unwrap_or((0, 0));
+        state.write_u8(data);
+        state.write_u8(delay);
+    }
+
+    fn load_state(&mut self, state: &mut StateReader) -> Result<(), StateError> {
+        self.cycle = state.read_u32()?;
+        self.mode = if state.read_bool()? { FrameCounterMode::FiveStep } else { FrameCounterMode::FourStep };
+        // the sequence only wraps on the cycle after its last step, anything past that would never get there
+        let last = match self.mode {
+            FrameCounterMode::FourStep => self.timing.frame_counter_4_step[3],
+            FrameCounterMode::FiveStep => self.timing.frame_counter_5_step[4],
+        };
+        if self.cycle > last { return Err(StateError::Corrupt("frame counter is past the end of its sequence")); }
+        self.irq_inhibit = state.read_bool()?;
+        self.irq_flag = state.read_bool()?;
+        let data = state.read_u8()?;
+        let delay = state.read_u8()?;
+        // a delay of 0 never gets queued, so it stands for nothing pending
+        self.pending_write = (delay > 0).then_some((data, delay));
+        Ok(())
+    }
+}
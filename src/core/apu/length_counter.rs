@@ -0,0 +1,51 @@
+use crate::core::state::snapshot::Snapshot;
+use crate::core::state::stream::{StateError, StateReader, StateWriter};
+
+const LENGTH_TABLE: [u8; 32] = [
+    10, 254, 20, 2, 40, 4, 80, 6, 160, 8, 60, 10, 14, 12, 26, 14,
+    12, 16, 24, 18, 48, 20, 96, 22, 192, 24, 72, 26, 16, 28, 32, 30,
+];
+
+// silences a channel after a set number of half frames, unless halted
+#[derive(Debug, Clone, Default)]
+pub struct LengthCounter {
+    pub counter: u8,
+    pub halt: bool,
+    enabled: bool,
+}
+
+impl LengthCounter {
+    // $4015, turning a channel off zeroes its counter straight away
+    pub fn set_enabled(&mut self, enabled: bool) {
+        self.enabled = enabled;
+        if !enabled { self.counter = 0; }
+    }
+
+    // the top 5 bits of the channel's last register
+    pub fn load(&mut self, index: u8) {
+        if self.enabled { self.counter = LENGTH_TABLE[(index & 0x1f) as usize]; }
+    }
+
+    pub fn clock(&mut self) {
+        if !self.halt && self.counter > 0 { self.counter -= 1; }
+    }
+
+    pub fn active(&self) -> bool {
+        self.counter > 0
+    }
+}
+
+impl Snapshot for LengthCounter {
+    fn save_state(&self, state: &mut StateWriter) {
+        state.write_u8(self.counter);
+        state.write_bool(self.halt);
+        state.write_bool(self.enabled);
+    }
+
+    fn load_state(&mut self, state: &mut StateReader) -> Result<(), StateError> {
+        self.counter = state.read_u8()?;
+        self.halt = state.read_bool()?;
+        self.enabled = state.read_bool()?;
+        Ok(())
+    }
+}
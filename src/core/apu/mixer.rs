@@ -0,0 +1,27 @@
+use lazy_static::lazy_static;
+
+lazy_static! {
+    // the 2A03's DAC isn't linear, these are the usual lookup-table approximations of it
+    static ref PULSE_TABLE: [f32; 31] = {
+        let mut table = [0.0; 31];
+        for (n, entry) in table.iter_mut().enumerate().skip(1) {
+            *entry = 95.52 / (8128.0 / n as f32 + 100.0);
+        }
+        table
+    };
+
+    static ref TND_TABLE: [f32; 203] = {
+        let mut table = [0.0; 203];
+        for (n, entry) in table.iter_mut().enumerate().skip(1) {
+            *entry = 163.67 / (24329.0 / n as f32 + 100.0);
+        }
+        table
+    };
+}
+
+// channel levels in, roughly 0.0..=1.0 out
+pub fn mix(pulse_1: u8, pulse_2: u8, triangle: u8, noise: u8, dmc: u8) -> f32 {
+    let pulse = PULSE_TABLE[(pulse_1 + pulse_2) as usize];
+    let tnd = TND_TABLE[3 * triangle as usize + 2 * noise as usize + dmc as usize];
+    pulse + tnd
+}
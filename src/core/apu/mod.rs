@@ -0,0 +1,12 @@
+pub mod audio_processor;
+pub mod dmc;
+pub mod envelope;
+pub mod expansion;
+pub mod filters;
+pub mod frame_counter;
+pub mod length_counter;
+pub mod mixer;
+pub mod noise;
+pub mod pulse;
+pub mod resampler;
+pub mod triangle;
\ No newline at end of file
@@ -0,0 +1,97 @@
+use crate::core::apu::envelope::Envelope;
+use crate::core::apu::length_counter::LengthCounter;
+use crate::core::state::snapshot::Snapshot;
+use crate::core::state::stream::{StateError, StateReader, StateWriter};
+
+#[derive(Debug, Clone)]
+pub struct Noise {
+    // "short" mode taps bit 6 instead of bit 1, giving a 93-step metallic loop
+    pub short_mode: bool,
+    pub period_index: u8,
+    timer: u16,
+    shift_register: u16,
+    periods: &'static [u16; 16],
+    pub envelope: Envelope,
+    pub length_counter: LengthCounter,
+}
+
+impl Noise {
+    pub fn new(periods: &'static [u16; 16]) -> Self {
+        Noise {
+            short_mode: false,
+            period_index: 0,
+            timer: 0,
+            shift_register: 1,
+            periods,
+            envelope: Envelope::default(),
+            length_counter: LengthCounter::default(),
+        }
+    }
+
+    // register 0-3, i.e. $400C-$400F ($400D doesn't do anything)
+    pub fn write_register(&mut self, register: u16, data: u8) {
+        match register & 0b11 {
+            0 => {
+                self.length_counter.halt = data & 0b0010_0000 != 0;
+                self.envelope.write_control(data);
+            }
+            2 => {
+                self.short_mode = data & 0x80 != 0;
+                self.period_index = data & 0x0f;
+            }
+            3 => {
+                self.length_counter.load(data >> 3);
+                self.envelope.start = true;
+            }
+            _ => {}
+        }
+    }
+
+    // the period tables are in CPU cycles, so this gets clocked every CPU cycle
+    pub fn clock_timer(&mut self) {
+        if self.timer > 0 {
+            self.timer -= 1;
+            return;
+        }
+
+        self.timer = self.periods[self.period_index as usize] - 1;
+
+        let tap = if self.short_mode { 6 } else { 1 };
+        let feedback = (self.shift_register ^ (self.shift_register >> tap)) & 1;
+        self.shift_register = (self.shift_register >> 1) | (feedback << 14);
+    }
+
+    pub fn clock_quarter_frame(&mut self) {
+        self.envelope.clock();
+    }
+
+    pub fn clock_half_frame(&mut self) {
+        self.length_counter.clock();
+    }
+
+    pub fn output(&self) -> u8 {
+        if self.shift_register & 1 == 1 || !self.length_counter.active() { return 0; }
+
+        self.envelope.output()
+    }
+}
+
+impl Snapshot for Noise {
+    fn save_state(&self, state: &mut StateWriter) {
+        state.write_bool(self.short_mode);
+        state.write_u8(self.period_index);
+        state.write_u16(self.timer);
+        state.write_u16(self.shift_register);
+        self.envelope.save_state(state);
+        self.length_counter.save_state(state);
+    }
+
+    fn load_state(&mut self, state: &mut StateReader) -> Result<(), StateError> {
+        self.short_mode = state.read_bool()?;
+        self.period_index = state.read_u8()? & 0x0f;
+        self.timer = state.read_u16()?;
+        self.shift_register = state.read_u16()?;
+        self.envelope.load_state(state)?;
+        self.length_counter.load_state(state)
+    }
+}
@@ -0,0 +1,168 @@
+use crate::core::apu::envelope::Envelope;
+use crate::core::apu::length_counter::LengthCounter;
+use crate::core::state::snapshot::Snapshot;
+use crate::core::state::stream::{StateError, StateReader, StateWriter};
+
+const DUTY_TABLE: [[u8; 8]; 4] = [
+    [0, 1, 0, 0, 0, 0, 0, 0],
+    [0, 1, 1, 0, 0, 0, 0, 0],
+    [0, 1, 1, 1, 1, 0, 0, 0],
+    [1, 0, 0, 1, 1, 1, 1, 1],
+];
+
+// the two pulses only differ in how the sweep negates
+#[derive(Debug, Copy, Clone, Eq, PartialEq)]
+pub enum PulseChannel {
+    One,
+    Two,
+}
+
+#[derive(Debug, Clone, Default)]
+pub struct Sweep {
+    pub enabled: bool,
+    pub period: u8,
+    pub negate: bool,
+    pub shift: u8,
+    reload: bool,
+    divider: u8,
+}
+
+#[derive(Debug, Clone)]
+pub struct Pulse {
+    channel: PulseChannel,
+    pub duty: u8,
+    duty_position: u8,
+    pub timer_period: u16,
+    timer: u16,
+    pub envelope: Envelope,
+    pub sweep: Sweep,
+    pub length_counter: LengthCounter,
+}
+
+impl Pulse {
+    pub fn new(channel: PulseChannel) -> Self {
+        Pulse {
+            channel,
+            duty: 0,
+            duty_position: 0,
+            timer_period: 0,
+            timer: 0,
+            envelope: Envelope::default(),
+            sweep: Sweep::default(),
+            length_counter: LengthCounter::default(),
+        }
+    }
+
+    // register 0-3, i.e. $4000-$4003 or $4004-$4007
+    pub fn write_register(&mut self, register: u16, data: u8) {
+        match register & 0b11 {
+            0 => {
+                self.duty = data >> 6;
+                self.length_counter.halt = data & 0b0010_0000 != 0;
+                self.envelope.write_control(data);
+            }
+            1 => {
+                self.sweep.enabled = data & 0x80 != 0;
+                self.sweep.period = (data >> 4) & 0b111;
+                self.sweep.negate = data & 0b1000 != 0;
+                self.sweep.shift = data & 0b111;
+                self.sweep.reload = true;
+            }
+            2 => self.timer_period = (self.timer_period & 0x0700) | u16::from(data),
+            _ => {
+                self.timer_period = (self.timer_period & 0x00ff) | (u16::from(data & 0b111) << 8);
+                self.length_counter.load(data >> 3);
+                self.envelope.start = true;
+                self.duty_position = 0;
+            }
+        }
+    }
+
+    // once per APU cycle (every other CPU cycle)
+    pub fn clock_timer(&mut self) {
+        if self.timer == 0 {
+            self.timer = self.timer_period;
+            self.duty_position = (self.duty_position + 1) % 8;
+        } else {
+            self.timer -= 1;
+        }
+    }
+
+    pub fn clock_quarter_frame(&mut self) {
+        self.envelope.clock();
+    }
+
+    pub fn clock_half_frame(&mut self) {
+        self.length_counter.clock();
+
+        let target = self.sweep_target();
+        if self.sweep.divider == 0 && self.sweep.enabled && self.sweep.shift != 0 && !self.sweep_muting(target) {
+            self.timer_period = target;
+        }
+
+        if self.sweep.divider == 0 || self.sweep.reload {
+            self.sweep.divider = self.sweep.period;
+            self.sweep.reload = false;
+        } else {
+            self.sweep.divider -= 1;
+        }
+    }
+
+    // worked out all the time, not just when the sweep is enabled, since it can mute the channel either way
+    fn sweep_target(&self) -> u16 {
+        let change = self.timer_period >> self.sweep.shift;
+        if !self.sweep.negate { return self.timer_period + change; }
+
+        match self.channel {
+            // pulse 1 adds the ones' complement, so it lands one lower
+            PulseChannel::One => self.timer_period.saturating_sub(change + 1),
+            PulseChannel::Two => self.timer_period.saturating_sub(change),
+        }
+    }
+
+    fn sweep_muting(&self, target: u16) -> bool {
+        self.timer_period < 8 || target > 0x7ff
+    }
+
+    pub fn output(&self) -> u8 {
+        if self.sweep_muting(self.sweep_target())
+            || !self.length_counter.active()
+            || DUTY_TABLE[self.duty as usize][self.duty_position as usize] == 0 {
+            return 0;
+        }
+
+        self.envelope.output()
+    }
+}
+
+impl Snapshot for Pulse {
+    fn save_state(&self, state: &mut StateWriter) {
+        state.write_u8(self.duty);
+        state.write_u8(self.duty_position);
+        state.write_u16(self.timer_period);
+        state.write_u16(self.timer);
+        self.envelope.save_state(state);
+        state.write_bool(self.sweep.enabled);
+        state.write_u8(self.sweep.period);
+        state.write_bool(self.sweep.negate);
+        state.write_u8(self.sweep.shift);
+        state.write_bool(self.sweep.reload);
+        state.write_u8(self.sweep.divider);
+        self.length_counter.save_state(state);
+    }
+
+    fn load_state(&mut self, state: &mut StateReader) -> Result<(), StateError> {
+        self.duty = state.read_u8()? & 0b11;
+        self.duty_position = state.read_u8()? % 8;
+        self.timer_period = state.read_u16()?;
+        self.timer = state.read_u16()?;
+        self.envelope.load_state(state)?;
+        self.sweep.enabled = state.read_bool()?;
+        self.sweep.period = state.read_u8()?;
+        self.sweep.negate = state.read_bool()?;
+        self.sweep.shift = state.read_u8()?;
+        self.sweep.reload = state.read_bool()?;
+        self.sweep.divider = state.read_u8()?;
+        self.length_counter.load_state(state)
+    }
+}
@@ -0,0 +1,108 @@
+use lazy_static::lazy_static;
+use std::f64::consts::PI;
+
+// band-limited step synthesis: rather than point-sampling the APU at 1.79MHz, every change in
+// the output gets drawn into the output buffer as a windowed-sinc step, so nothing above the
+// host's Nyquist frequency makes it through to alias
+const KERNEL_WIDTH: usize = 16;
+const KERNEL_PHASES: usize = 64;
+// fraction of the output sample rate, a bit under Nyquist so the window's rolloff fits
+const CUTOFF: f64 = 0.45;
+
+lazy_static! {
+    static ref KERNEL: [[f32; KERNEL_WIDTH]; KERNEL_PHASES] = {
+        let mut kernel = [[0.0; KERNEL_WIDTH]; KERNEL_PHASES];
+
+        for (phase, taps) in kernel.iter_mut().enumerate() {
+            let offset = phase as f64 / KERNEL_PHASES as f64;
+            let mut sum = 0.0;
+            let mut raw = [0.0f64; KERNEL_WIDTH];
+
+            for (i, tap) in raw.iter_mut().enumerate() {
+                let x = i as f64 - offset - (KERNEL_WIDTH / 2) as f64;
+                let sinc = if x == 0.0 { 1.0 } else { (2.0 * PI * CUTOFF * x).sin() / (2.0 * PI * CUTOFF * x) };
+
+                // blackman window over the kernel's span
+                let w = (x + (KERNEL_WIDTH / 2) as f64) / KERNEL_WIDTH as f64;
+                let window = 0.42 - 0.5 * (2.0 * PI * w).cos() + 0.08 * (4.0 * PI * w).cos();
+
+                *tap = sinc * window.max(0.0);
+                sum += *tap;
+            }
+
+            // each step has to add up to exactly its delta
+            for (tap, value) in taps.iter_mut().zip(raw.iter()) {
+                *tap = (*value / sum) as f32;
+            }
+        }
+
+        kernel
+    };
+}
+
+#[derive(Debug, Clone)]
+pub struct Resampler {
+    clock_rate: f64,
+    sample_rate: u32,
+    // output samples per input clock
+    ratio: f64,
+    // where clock 0 of the current frame falls, in output samples
+    start: f64,
+    deltas: Vec<f32>,
+    level: f32,
+}
+
+impl Resampler {
+    pub fn new(clock_rate: f64, sample_rate: u32) -> Self {
+        Resampler {
+            clock_rate,
+            sample_rate,
+            ratio: f64::from(sample_rate) / clock_rate,
+            start: 0.0,
+            deltas: vec![0.0; KERNEL_WIDTH],
+            level: 0.0,
+        }
+    }
+
+    pub fn sample_rate(&self) -> u32 {
+        self.sample_rate
+    }
+
+    pub fn set_sample_rate(&mut self, sample_rate: u32) {
+        *self = Resampler::new(self.clock_rate, sample_rate);
+    }
+
+    // the input jumped by `delta` at `clock` input clocks into the frame
+    pub fn add_delta(&mut self, clock: u32, delta: f32) {
+        let position = self.start + f64::from(clock) * self.ratio;
+        let index = position as usize;
+        let phase = ((position - index as f64) * KERNEL_PHASES as f64) as usize;
+
+        if self.deltas.len() < index + KERNEL_WIDTH {
+            self.deltas.resize(index + KERNEL_WIDTH, 0.0);
+        }
+
+        for (slot, tap) in self.deltas[index..].iter_mut().zip(KERNEL[phase.min(KERNEL_PHASES - 1)].iter()) {
+            *slot += delta * tap;
+        }
+    }
+
+    // finishes off `clocks` worth of input and hands back every output sample that's complete
+    pub fn end_frame(&mut self, clocks: u32) -> Vec<f32> {
+        let end = self.start + f64::from(clocks) * self.ratio;
+        let count = end as usize;
+
+        if self.deltas.len() < count + KERNEL_WIDTH {
+            self.deltas.resize(count + KERNEL_WIDTH, 0.0);
+        }
+
+        let mut samples = Vec::with_capacity(count);
+        for delta in self.deltas.drain(..count) {
+            self.level += delta;
+            samples.push(self.level);
+        }
+
+        self.start = end - count as f64;
+        samples
+    }
+}
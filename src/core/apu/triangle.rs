@@ -0,0 +1,94 @@
+use crate::core::apu::length_counter::LengthCounter;
+use crate::core::state::snapshot::Snapshot;
+use crate::core::state::stream::{StateError, StateReader, StateWriter};
+
+const TRIANGLE_SEQUENCE: [u8; 32] = [
+    15, 14, 13, 12, 11, 10, 9, 8, 7, 6, 5, 4, 3, 2, 1, 0,
+    0, 1, 2, 3, 4, 5, 6, 7, 8, 9, 10, 11, 12, 13, 14, 15,
+];
+
+#[derive(Debug, Clone, Default)]
+pub struct Triangle {
+    // also halts the length counter
+    pub control: bool,
+    pub linear_reload_value: u8,
+    linear_counter: u8,
+    linear_reload: bool,
+    pub timer_period: u16,
+    timer: u16,
+    sequence_position: u8,
+    pub length_counter: LengthCounter,
+}
+
+impl Triangle {
+    // register 0-3, i.e. $4008-$400B ($4009 doesn't do anything)
+    pub fn write_register(&mut self, register: u16, data: u8) {
+        match register & 0b11 {
+            0 => {
+                self.control = data & 0x80 != 0;
+                self.length_counter.halt = self.control;
+                self.linear_reload_value = data & 0x7f;
+            }
+            2 => self.timer_period = (self.timer_period & 0x0700) | u16::from(data),
+            3 => {
+                self.timer_period = (self.timer_period & 0x00ff) | (u16::from(data & 0b111) << 8);
+                self.length_counter.load(data >> 3);
+                self.linear_reload = true;
+            }
+            _ => {}
+        }
+    }
+
+    // unlike the others this one runs at the full CPU rate
+    pub fn clock_timer(&mut self) {
+        if self.timer > 0 {
+            self.timer -= 1;
+            return;
+        }
+
+        self.timer = self.timer_period;
+        if self.linear_counter > 0 && self.length_counter.active() {
+            self.sequence_position = (self.sequence_position + 1) % 32;
+        }
+    }
+
+    pub fn clock_quarter_frame(&mut self) {
+        if self.linear_reload { self.linear_counter = self.linear_reload_value; }
+        else if self.linear_counter > 0 { self.linear_counter -= 1; }
+
+        if !self.control { self.linear_reload = false; }
+    }
+
+    pub fn clock_half_frame(&mut self) {
+        self.length_counter.clock();
+    }
+
+    // stopping the sequencer just freezes it wherever it was, it doesn't go to 0
+    pub fn output(&self) -> u8 {
+        TRIANGLE_SEQUENCE[self.sequence_position as usize]
+    }
+}
+
+impl Snapshot for Triangle {
+    fn save_state(&self, state: &mut StateWriter) {
+        state.write_bool(self.control);
+        state.write_u8(self.linear_reload_value);
+        state.write_u8(self.linear_counter);
+        state.write_bool(self.linear_reload);
+        state.write_u16(self.timer_period);
+        state.write_u16(self.timer);
+        state.write_u8(self.sequence_position);
+        self.length_counter.save_state(state);
+    }
+
+    fn load_state(&mut self, state: &mut StateReader) -> Result<(), StateError> {
+        self.control = state.read_bool()?;
+        self.linear_reload_value = state.read_u8()?;
+        self.linear_counter = state.read_u8()?;
+        self.linear_reload = state.read_bool()?;
+        self.timer_period = state.read_u16()?;
+        self.timer = state.read_u16()?;
+        self.sequence_position = state.read_u8()? % 32;
+        self.length_counter.load_state(state)
+    }
+}
@@ -0,0 +1,55 @@
+// everything the CPU can see on its address bus
+pub trait Bus {
+    fn mem_read(&mut self, addr: u16) -> u8;
+    fn mem_write(&mut self, addr: u16, data: u8);
+
+    // what a read would give, without anything a read sets off. for debuggers and trace logs
+    fn peek(&self, addr: u16) -> u8;
+
+    // called after every instruction with the number of CPU cycles it took
+    fn tick(&mut self, _cycles: u16) {}
+
+    // edge triggered, so reading it acknowledges it
+    fn poll_nmi(&mut self) -> bool { false }
+
+    // level triggered, stays up until whoever raised it gets acknowledged
+    fn poll_irq(&self) -> bool { false }
+
+    // cycles the CPU has to sit out (OAM DMA and friends), reading it clears it
+    fn take_stall_cycles(&mut self) -> u16 { 0 }
+
+    // scanline and dot, for trace logs. None when there's no PPU on the bus
+    fn ppu_position(&self) -> Option<(u16, u16)> { None }
+}
+
+// plain 64K of RAM, handy for testing the CPU on its own
+pub struct FlatMemory {
+    memory: Box<[u8; 0x10000]>,
+}
+
+impl Default for FlatMemory {
+    fn default() -> Self {
+        FlatMemory { memory: Box::new([0x0; 0x10000]) }
+    }
+}
+
+impl FlatMemory {
+    pub fn load(&mut self, start: u16, data: &[u8]) {
+        let start = start as usize;
+        self.memory[start .. (start + data.len())].copy_from_slice(data);
+    }
+}
+
+impl Bus for FlatMemory {
+    fn mem_read(&mut self, addr: u16) -> u8 {
+        self.memory[addr as usize]
+    }
+
+    fn mem_write(&mut self, addr: u16, data: u8) {
+        self.memory[addr as usize] = data;
+    }
+
+    fn peek(&self, addr: u16) -> u8 {
+        self.memory[addr as usize]
+    }
+}
@@ -0,0 +1,109 @@
+use crate::core::cartridge::mappers::{axrom::AxRom, cnrom::CnRom, fme7::Fme7, mmc1::Mmc1, mmc5::Mmc5, namco163::Namco163};
+use crate::core::cartridge::mappers::{nrom::NRom, uxrom::UxRom, vrc6::Vrc6, vrc7::Vrc7};
+use crate::core::cartridge::rom::{CartridgeError, Mirroring};
+use crate::core::state::snapshot::Snapshot;
+use crate::core::state::stream::{StateError, StateReader, StateWriter};
+
+// whatever the board has soldered onto it. the CPU side sees $4020-$FFFF, the PPU side $0000-$1FFF.
+// its snapshot covers the registers and any RAM on the board, the ROM is whatever was loaded
+pub trait Mapper: Snapshot {
+    // None means nothing drove the bus, so the caller should fall back to open bus
+    fn cpu_read(&mut self, addr: u16) -> Option<u8> { self.cpu_peek(addr) }
+    // the same without anything a read sets off, registers that only do that give None
+    fn cpu_peek(&self, addr: u16) -> Option<u8>;
+    fn cpu_write(&mut self, addr: u16, data: u8);
+
+    fn ppu_read(&mut self, addr: u16) -> u8;
+    fn ppu_write(&mut self, addr: u16, data: u8);
+
+    fn mirroring(&self) -> Mirroring;
+
+    fn irq(&self) -> bool { false }
+
+    // CPU cycles that went by, for mappers with their own counters
+    fn tick(&mut self, _cycles: u16) {}
+
+    // once per rendered scanline, around where the PPU starts fetching sprites
+    fn scanline(&mut self) {}
+
+    // whatever sound chip is on the board, on the same scale as the 2A03's mixer output
+    fn audio_output(&self) -> f32 { 0.0 }
+}
+
+// the raw pieces of an iNES file that the mappers slice up however they want
+pub struct CartridgeData {
+    pub prg_rom: Vec<u8>,
+    pub chr: Vec<u8>,
+    pub chr_is_ram: bool,
+    pub prg_ram: Vec<u8>,
+    pub mirroring: Mirroring,
+}
+
+impl CartridgeData {
+    // reads out of a list of banks with wraparound, since carts love having fewer banks than the register allows
+    pub fn prg_byte(&self, bank: usize, bank_size: usize, offset: usize) -> u8 {
+        let banks = (self.prg_rom.len() / bank_size).max(1);
+        self.prg_rom[(bank % banks) * bank_size + offset % bank_size]
+    }
+
+    pub fn chr_index(&self, bank: usize, bank_size: usize, offset: usize) -> usize {
+        let banks = (self.chr.len() / bank_size).max(1);
+        (bank % banks) * bank_size + offset % bank_size
+    }
+
+    pub fn chr_read(&self, bank: usize, bank_size: usize, offset: usize) -> u8 {
+        self.chr[self.chr_index(bank, bank_size, offset)]
+    }
+
+    pub fn chr_write(&mut self, bank: usize, bank_size: usize, offset: usize, data: u8) {
+        if !self.chr_is_ram { return; }
+
+        let index = self.chr_index(bank, bank_size, offset);
+        self.chr[index] = data;
+    }
+
+    pub fn prg_ram_read(&self, addr: u16) -> Option<u8> {
+        if self.prg_ram.is_empty() { return None; }
+
+        Some(self.prg_ram[(addr as usize - 0x6000) % self.prg_ram.len()])
+    }
+
+    pub fn prg_ram_write(&mut self, addr: u16, data: u8) {
+        if self.prg_ram.is_empty() { return; }
+
+        let len = self.prg_ram.len();
+        self.prg_ram[(addr as usize - 0x6000) % len] = data;
+    }
+}
+
+// what every board has in common: its RAM, and CHR too when that's RAM
+impl Snapshot for CartridgeData {
+    fn save_state(&self, state: &mut StateWriter) {
+        state.write_bytes(&self.prg_ram);
+        if self.chr_is_ram { state.write_bytes(&self.chr); }
+        self.mirroring.save_state(state);
+    }
+
+    fn load_state(&mut self, state: &mut StateReader) -> Result<(), StateError> {
+        state.read_bytes_into(&mut self.prg_ram)?;
+        if self.chr_is_ram { state.read_bytes_into(&mut self.chr)?; }
+        self.mirroring.load_state(state)
+    }
+}
+
+pub fn create_mapper(id: u16, data: CartridgeData) -> Result<Box<dyn Mapper>, CartridgeError> {
+    match id {
+        0 => Ok(Box::new(NRom::new(data))),
+        1 => Ok(Box::new(Mmc1::new(data))),
+        2 => Ok(Box::new(UxRom::new(data))),
+        3 => Ok(Box::new(CnRom::new(data))),
+        5 => Ok(Box::new(Mmc5::new(data))),
+        7 => Ok(Box::new(AxRom::new(data))),
+        19 => Ok(Box::new(Namco163::new(data))),
+        24 => Ok(Box::new(Vrc6::new(data, false))),
+        26 => Ok(Box::new(Vrc6::new(data, true))),
+        69 => Ok(Box::new(Fme7::new(data))),
+        85 => Ok(Box::new(Vrc7::new(data))),
+        _ => Err(CartridgeError::UnsupportedMapper(id)),
+    }
+}
@@ -0,0 +1,60 @@
+use crate::core::cartridge::mapper::{CartridgeData, Mapper};
+use crate::core::cartridge::rom::Mirroring;
+use crate::core::state::snapshot::Snapshot;
+use crate::core::state::stream::{StateError, StateReader, StateWriter};
+
+// mapper 7, 32K PRG banks and a register picking which nametable everything mirrors to
+pub struct AxRom {
+    data: CartridgeData,
+    prg_bank: usize,
+    mirroring: Mirroring,
+}
+
+impl AxRom {
+    pub fn new(data: CartridgeData) -> Self {
+        AxRom { data, prg_bank: 0, mirroring: Mirroring::SingleScreenLower }
+    }
+}
+
+impl Mapper for AxRom {
+    fn cpu_peek(&self, addr: u16) -> Option<u8> {
+        match addr {
+            0x8000..=0xffff => Some(self.data.prg_byte(self.prg_bank, 0x8000, addr as usize)),
+            _ => None,
+        }
+    }
+
+    fn cpu_write(&mut self, addr: u16, data: u8) {
+        if addr < 0x8000 { return; }
+
+        self.prg_bank = (data & 0b0111) as usize;
+        self.mirroring = if data & 0b1_0000 == 0 { Mirroring::SingleScreenLower }
+            else { Mirroring::SingleScreenUpper };
+    }
+
+    fn ppu_read(&mut self, addr: u16) -> u8 {
+        self.data.chr_read(0, 0x2000, addr as usize)
+    }
+
+    fn ppu_write(&mut self, addr: u16, data: u8) {
+        self.data.chr_write(0, 0x2000, addr as usize, data);
+    }
+
+    fn mirroring(&self) -> Mirroring {
+        self.mirroring
+    }
+}
+
+impl Snapshot for AxRom {
+    fn save_state(&self, state: &mut StateWriter) {
+        self.data.save_state(state);
+        state.write_usize(self.prg_bank);
+        self.mirroring.save_state(state);
+    }
+
+    fn load_state(&mut self, state: &mut StateReader) -> Result<(), StateError> {
+        self.data.load_state(state)?;
+        self.prg_bank = state.read_usize()?;
+        self.mirroring.load_state(state)
+    }
+}
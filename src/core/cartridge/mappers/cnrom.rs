@@ -0,0 +1,59 @@
+use crate::core::cartridge::mapper::{CartridgeData, Mapper};
+use crate::core::cartridge::rom::Mirroring;
+use crate::core::state::snapshot::Snapshot;
+use crate::core::state::stream::{StateError, StateReader, StateWriter};
+
+// mapper 3, fixed PRG and a switchable 8K of CHR
+pub struct CnRom {
+    data: CartridgeData,
+    chr_bank: usize,
+}
+
+impl CnRom {
+    pub fn new(data: CartridgeData) -> Self {
+        CnRom { data, chr_bank: 0 }
+    }
+}
+
+impl Mapper for CnRom {
+    fn cpu_peek(&self, addr: u16) -> Option<u8> {
+        match addr {
+            0x6000..=0x7fff => self.data.prg_ram_read(addr),
+            0x8000..=0xffff => Some(self.data.prg_byte(0, 0x8000.min(self.data.prg_rom.len()), addr as usize - 0x8000)),
+            _ => None,
+        }
+    }
+
+    fn cpu_write(&mut self, addr: u16, data: u8) {
+        match addr {
+            0x6000..=0x7fff => self.data.prg_ram_write(addr, data),
+            0x8000..=0xffff => self.chr_bank = data as usize,
+            _ => {}
+        }
+    }
+
+    fn ppu_read(&mut self, addr: u16) -> u8 {
+        self.data.chr_read(self.chr_bank, 0x2000, addr as usize)
+    }
+
+    fn ppu_write(&mut self, addr: u16, data: u8) {
+        self.data.chr_write(self.chr_bank, 0x2000, addr as usize, data);
+    }
+
+    fn mirroring(&self) -> Mirroring {
+        self.data.mirroring
+    }
+}
+
+impl Snapshot for CnRom {
+    fn save_state(&self, state: &mut StateWriter) {
+        self.data.save_state(state);
+        state.write_usize(self.chr_bank);
+    }
+
+    fn load_state(&mut self, state: &mut StateReader) -> Result<(), StateError> {
+        self.data.load_state(state)?;
+        self.chr_bank = state.read_usize()?;
+        Ok(())
+    }
+}
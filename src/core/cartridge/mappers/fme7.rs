@@ -0,0 +1,158 @@
+use crate::core::apu::expansion::expansion_audio::ExpansionAudio;
+use crate::core::apu::expansion::sunsoft_5b::Sunsoft5bAudio;
+use crate::core::cartridge::mapper::{CartridgeData, Mapper};
+use crate::core::cartridge::rom::Mirroring;
+use crate::core::state::snapshot::Snapshot;
+use crate::core::state::stream::{StateError, StateReader, StateWriter};
+
+// mapper 69, Sunsoft FME-7 (and the 5B, which is the same thing plus a sound chip).
+// $8000 picks a command, $A000 is its parameter
+pub struct Fme7 {
+    data: CartridgeData,
+    command: u8,
+    chr_banks: [usize; 8],
+    prg_banks: [usize; 3],
+    // what's at $6000: a ROM bank, or RAM if the flag's set
+    low_bank: usize,
+    low_bank_ram: bool,
+    prg_ram_enabled: bool,
+    mirroring: Mirroring,
+    irq_enabled: bool,
+    irq_counter_enabled: bool,
+    irq_counter: u16,
+    irq_pending: bool,
+    audio: Sunsoft5bAudio,
+}
+
+impl Fme7 {
+    pub fn new(data: CartridgeData) -> Self {
+        Fme7 {
+            mirroring: data.mirroring,
+            data,
+            command: 0,
+            chr_banks: [0; 8],
+            prg_banks: [0; 3],
+            low_bank: 0,
+            low_bank_ram: false,
+            prg_ram_enabled: false,
+            irq_enabled: false,
+            irq_counter_enabled: false,
+            irq_counter: 0,
+            irq_pending: false,
+            audio: Sunsoft5bAudio::new(),
+        }
+    }
+
+    fn write_parameter(&mut self, data: u8) {
+        match self.command {
+            0x0..=0x7 => self.chr_banks[self.command as usize] = data as usize,
+            0x8 => {
+                self.low_bank = (data & 0x3f) as usize;
+                self.low_bank_ram = data & 0x40 != 0;
+                self.prg_ram_enabled = data & 0x80 != 0;
+            }
+            0x9..=0xb => self.prg_banks[self.command as usize - 0x9] = (data & 0x3f) as usize,
+            0xc => self.mirroring = Mirroring::from_control(data),
+            0xd => {
+                self.irq_enabled = data & 0x01 != 0;
+                self.irq_counter_enabled = data & 0x80 != 0;
+                self.irq_pending = false;
+            }
+            0xe => self.irq_counter = (self.irq_counter & 0xff00) | u16::from(data),
+            _ => self.irq_counter = (self.irq_counter & 0x00ff) | (u16::from(data) << 8),
+        }
+    }
+}
+
+impl Mapper for Fme7 {
+    fn cpu_peek(&self, addr: u16) -> Option<u8> {
+        let last_bank = (self.data.prg_rom.len() / 0x2000).saturating_sub(1);
+
+        match addr {
+            0x6000..=0x7fff if self.low_bank_ram => {
+                if self.prg_ram_enabled { self.data.prg_ram_read(addr) } else { None }
+            }
+            0x6000..=0x7fff => Some(self.data.prg_byte(self.low_bank, 0x2000, addr as usize)),
+            0x8000..=0xdfff => {
+                let bank = self.prg_banks[(addr as usize - 0x8000) / 0x2000];
+                Some(self.data.prg_byte(bank, 0x2000, addr as usize))
+            }
+            0xe000..=0xffff => Some(self.data.prg_byte(last_bank, 0x2000, addr as usize)),
+            _ => None,
+        }
+    }
+
+    fn cpu_write(&mut self, addr: u16, data: u8) {
+        match addr {
+            0x6000..=0x7fff if self.low_bank_ram && self.prg_ram_enabled => self.data.prg_ram_write(addr, data),
+            0x8000..=0x9fff => self.command = data & 0x0f,
+            0xa000..=0xbfff => self.write_parameter(data),
+            0xc000..=0xffff => self.audio.write_register(addr, data),
+            _ => {}
+        }
+    }
+
+    fn ppu_read(&mut self, addr: u16) -> u8 {
+        self.data.chr_read(self.chr_banks[(addr >> 10) as usize & 7], 0x400, addr as usize)
+    }
+
+    fn ppu_write(&mut self, addr: u16, data: u8) {
+        self.data.chr_write(self.chr_banks[(addr >> 10) as usize & 7], 0x400, addr as usize, data);
+    }
+
+    fn mirroring(&self) -> Mirroring {
+        self.mirroring
+    }
+
+    fn irq(&self) -> bool {
+        self.irq_pending
+    }
+
+    fn tick(&mut self, cycles: u16) {
+        for _ in 0..cycles {
+            self.audio.clock();
+
+            if !self.irq_counter_enabled { continue; }
+            self.irq_counter = self.irq_counter.wrapping_sub(1);
+            if self.irq_counter == 0xffff && self.irq_enabled { self.irq_pending = true; }
+        }
+    }
+
+    fn audio_output(&self) -> f32 {
+        self.audio.output()
+    }
+}
+
+impl Snapshot for Fme7 {
+    fn save_state(&self, state: &mut StateWriter) {
+        self.data.save_state(state);
+        state.write_u8(self.command);
+        for bank in self.chr_banks { state.write_usize(bank); }
+        for bank in self.prg_banks { state.write_usize(bank); }
+        state.write_usize(self.low_bank);
+        state.write_bool(self.low_bank_ram);
+        state.write_bool(self.prg_ram_enabled);
+        self.mirroring.save_state(state);
+        state.write_bool(self.irq_enabled);
+        state.write_bool(self.irq_counter_enabled);
+        state.write_u16(self.irq_counter);
+        state.write_bool(self.irq_pending);
+        self.audio.save_state(state);
+    }
+
+    fn load_state(&mut self, state: &mut StateReader) -> Result<(), StateError> {
+        self.data.load_state(state)?;
+        self.command = state.read_u8()?;
+        for bank in self.chr_banks.iter_mut() { *bank = state.read_usize()?; }
+        for bank in self.prg_banks.iter_mut() { *bank = state.read_usize()?; }
+        self.low_bank = state.read_usize()?;
+        self.low_bank_ram = state.read_bool()?;
+        self.prg_ram_enabled = state.read_bool()?;
+        self.mirroring.load_state(state)?;
+        self.irq_enabled = state.read_bool()?;
+        self.irq_counter_enabled = state.read_bool()?;
+        self.irq_counter = state.read_u16()?;
+        self.irq_pending = state.read_bool()?;
+        self.audio.load_state(state)
+    }
+}
@@ -0,0 +1,133 @@
+use crate::core::cartridge::mapper::{CartridgeData, Mapper};
+use crate::core::cartridge::rom::Mirroring;
+use crate::core::state::snapshot::Snapshot;
+use crate::core::state::stream::{StateError, StateReader, StateWriter};
+
+// mapper 1. everything goes through a 5-bit serial shift register, written one bit at a time
+pub struct Mmc1 {
+    data: CartridgeData,
+    shift_register: u8,
+    control: u8,
+    chr_bank_0: u8,
+    chr_bank_1: u8,
+    prg_bank: u8,
+}
+
+impl Mmc1 {
+    pub fn new(data: CartridgeData) -> Self {
+        Mmc1 {
+            data,
+            shift_register: 0x10,
+            control: 0x0c,
+            chr_bank_0: 0,
+            chr_bank_1: 0,
+            prg_bank: 0,
+        }
+    }
+
+    fn write_register(&mut self, addr: u16, value: u8) {
+        match addr {
+            0x8000..=0x9fff => self.control = value,
+            0xa000..=0xbfff => self.chr_bank_0 = value,
+            0xc000..=0xdfff => self.chr_bank_1 = value,
+            _ => self.prg_bank = value & 0x0f,
+        }
+    }
+
+    // SUROM and friends use the CHR register's bit 4 to pick which 256K of PRG is visible
+    fn prg_outer_bank(&self) -> usize {
+        if self.data.prg_rom.len() > 0x40000 { self.chr_bank_0 as usize & 0x10 } else { 0 }
+    }
+
+    fn prg_bank_for(&self, addr: u16) -> usize {
+        let bank = self.prg_bank as usize;
+        let last_bank = (self.data.prg_rom.len().min(0x40000) / 0x4000).saturating_sub(1);
+
+        let bank = match (self.control >> 2) & 0b11 {
+            0 | 1 => (bank & !1) | usize::from(addr >= 0xc000),
+            2 => if addr < 0xc000 { 0 } else { bank },
+            _ => if addr < 0xc000 { bank } else { last_bank },
+        };
+
+        self.prg_outer_bank() | bank
+    }
+
+    fn chr_bank_for(&self, addr: u16) -> usize {
+        if self.control & 0b1_0000 == 0 {
+            return (self.chr_bank_0 as usize & !1) | usize::from(addr >= 0x1000);
+        }
+
+        if addr < 0x1000 { self.chr_bank_0 as usize } else { self.chr_bank_1 as usize }
+    }
+}
+
+impl Mapper for Mmc1 {
+    fn cpu_peek(&self, addr: u16) -> Option<u8> {
+        match addr {
+            0x6000..=0x7fff => self.data.prg_ram_read(addr),
+            0x8000..=0xffff => Some(self.data.prg_byte(self.prg_bank_for(addr), 0x4000, addr as usize)),
+            _ => None,
+        }
+    }
+
+    fn cpu_write(&mut self, addr: u16, data: u8) {
+        match addr {
+            0x6000..=0x7fff => self.data.prg_ram_write(addr, data),
+            0x8000..=0xffff => {
+                if data & 0x80 != 0 {
+                    self.shift_register = 0x10;
+                    self.control |= 0x0c;
+                    return;
+                }
+
+                // the marker bit falling out the bottom means this was the fifth write
+                let full = self.shift_register & 1 == 1;
+                self.shift_register = (self.shift_register >> 1) | ((data & 1) << 4);
+
+                if full {
+                    self.write_register(addr, self.shift_register);
+                    self.shift_register = 0x10;
+                }
+            }
+            _ => {}
+        }
+    }
+
+    fn ppu_read(&mut self, addr: u16) -> u8 {
+        self.data.chr_read(self.chr_bank_for(addr), 0x1000, addr as usize)
+    }
+
+    fn ppu_write(&mut self, addr: u16, data: u8) {
+        self.data.chr_write(self.chr_bank_for(addr), 0x1000, addr as usize, data);
+    }
+
+    fn mirroring(&self) -> Mirroring {
+        match self.control & 0b11 {
+            0 => Mirroring::SingleScreenLower,
+            1 => Mirroring::SingleScreenUpper,
+            2 => Mirroring::Vertical,
+            _ => Mirroring::Horizontal,
+        }
+    }
+}
+
+impl Snapshot for Mmc1 {
+    fn save_state(&self, state: &mut StateWriter) {
+        self.data.save_state(state);
+        state.write_u8(self.shift_register);
+        state.write_u8(self.control);
+        state.write_u8(self.chr_bank_0);
+        state.write_u8(self.chr_bank_1);
+        state.write_u8(self.prg_bank);
+    }
+
+    fn load_state(&mut self, state: &mut StateReader) -> Result<(), StateError> {
+        self.data.load_state(state)?;
+        self.shift_register = state.read_u8()?;
+        self.control = state.read_u8()?;
+        self.chr_bank_0 = state.read_u8()?;
+        self.chr_bank_1 = state.read_u8()?;
+        self.prg_bank = state.read_u8()?;
+        Ok(())
+    }
+}
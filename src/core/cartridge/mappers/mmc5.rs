@@ -0,0 +1,307 @@
+use crate::core::apu::expansion::expansion_audio::ExpansionAudio;
+use crate::core::apu::expansion::mmc5::Mmc5Audio;
+use crate::core::cartridge::mapper::{CartridgeData, Mapper};
+use crate::core::cartridge::rom::Mirroring;
+use crate::core::state::snapshot::Snapshot;
+use crate::core::state::stream::{StateError, StateReader, StateWriter};
+
+// more CPU cycles than a scanline takes without the PPU saying anything means it's stopped rendering
+const IN_FRAME_TIMEOUT: u16 = 300;
+
+// mapper 5, Nintendo MMC5. covers the PRG/CHR banking, the scanline IRQ, the multiplier, ExRAM as
+// plain RAM and the sound. the nametable mapping only gets as far as what Mirroring can express,
+// and since the PPU doesn't say whether it's fetching sprites or background the CHR set that was
+// written last is the one in use (fine for anything that sticks to 8x8 sprites)
+pub struct Mmc5 {
+    data: CartridgeData,
+    prg_mode: u8,
+    chr_mode: u8,
+    prg_ram_protect: [u8; 2],
+    exram_mode: u8,
+    exram: [u8; 0x400],
+    mirroring: Mirroring,
+    // $5113-$5117
+    prg_banks: [u8; 5],
+    // $5120-$512B
+    chr_banks: [usize; 12],
+    chr_upper: usize,
+    using_chr_set_b: bool,
+    irq_compare: u8,
+    irq_enabled: bool,
+    irq_pending: bool,
+    in_frame: bool,
+    scanline: u8,
+    cycles_since_scanline: u16,
+    multiplicand: u8,
+    multiplier: u8,
+    audio: Mmc5Audio,
+}
+
+impl Mmc5 {
+    pub fn new(data: CartridgeData) -> Self {
+        Mmc5 {
+            mirroring: data.mirroring,
+            data,
+            prg_mode: 3,
+            chr_mode: 0,
+            prg_ram_protect: [0; 2],
+            exram_mode: 0,
+            exram: [0; 0x400],
+            prg_banks: [0, 0, 0, 0, 0xff],
+            chr_banks: [0; 12],
+            chr_upper: 0,
+            using_chr_set_b: false,
+            irq_compare: 0,
+            irq_enabled: false,
+            irq_pending: false,
+            in_frame: false,
+            scanline: 0,
+            cycles_since_scanline: 0,
+            multiplicand: 0xff,
+            multiplier: 0xff,
+            audio: Mmc5Audio::new(),
+        }
+    }
+
+    fn prg_ram_writable(&self) -> bool {
+        self.prg_ram_protect == [0b10, 0b01]
+    }
+
+    // which of $5113-$5117 covers `addr`, and how big a bank it's counting in
+    fn prg_register_for(&self, addr: u16) -> (usize, usize) {
+        let quarter = (addr as usize - 0x8000) / 0x2000;
+
+        match self.prg_mode {
+            0 => (4, 0x8000),
+            1 => (if quarter < 2 { 2 } else { 4 }, 0x4000),
+            2 => match quarter {
+                0 | 1 => (2, 0x4000),
+                2 => (3, 0x2000),
+                _ => (4, 0x2000),
+            },
+            _ => (1 + quarter, 0x2000),
+        }
+    }
+
+    // bit 7 picks ROM over RAM, and $5117 is always ROM
+    fn prg_rom_selected(&self, register: usize) -> bool {
+        register == 4 || self.prg_banks[register] & 0x80 != 0
+    }
+
+    // in 8K units, the low bits of the register get ignored for the bigger bank sizes
+    fn prg_ram_bank(&self, register: usize, size: usize, addr: u16) -> u8 {
+        let bank = self.prg_banks[register] as usize & !(size / 0x2000 - 1);
+        (bank + (addr as usize & (size - 1)) / 0x2000) as u8
+    }
+
+    fn prg_ram_index(&self, bank: u8, addr: u16) -> Option<usize> {
+        if self.data.prg_ram.is_empty() { return None; }
+
+        let index = (bank as usize & 0x07) * 0x2000 + (addr as usize & 0x1fff);
+        Some(index % self.data.prg_ram.len())
+    }
+
+    fn read_prg(&self, addr: u16) -> Option<u8> {
+        let (register, size) = self.prg_register_for(addr);
+
+        if self.prg_rom_selected(register) {
+            // bank numbers are always in 8K units, whatever the mode
+            let bank = (self.prg_banks[register] & 0x7f) as usize * 0x2000 / size;
+            Some(self.data.prg_byte(bank, size, addr as usize))
+        } else {
+            let bank = self.prg_ram_bank(register, size, addr);
+            self.prg_ram_index(bank, addr).map(|index| self.data.prg_ram[index])
+        }
+    }
+
+    fn write_prg_ram(&mut self, addr: u16, data: u8) {
+        if !self.prg_ram_writable() { return; }
+
+        let index = if addr < 0x8000 { self.prg_ram_index(self.prg_banks[0], addr) } else {
+            let (register, size) = self.prg_register_for(addr);
+            if self.prg_rom_selected(register) { return; }
+
+            self.prg_ram_index(self.prg_ram_bank(register, size, addr), addr)
+        };
+
+        if let Some(index) = index { self.data.prg_ram[index] = data; }
+    }
+
+    fn chr_bank_for(&self, addr: u16) -> (usize, usize) {
+        let slot = (addr >> 10) as usize & 7;
+
+        // set B only has four registers, and they cover both halves of the pattern tables
+        let (register, size) = match self.chr_mode {
+            0 => (7, 0x2000),
+            1 => (3 + (slot / 4) * 4, 0x1000),
+            2 => (1 + (slot / 2) * 2, 0x800),
+            _ => (slot, 0x400),
+        };
+        let register = if self.using_chr_set_b { 8 + (register & 0b11) } else { register };
+
+        (self.chr_banks[register], size)
+    }
+
+    fn write_nametable_mapping(&mut self, data: u8) {
+        let tables = [data & 0b11, (data >> 2) & 0b11, (data >> 4) & 0b11, (data >> 6) & 0b11];
+        self.mirroring = match tables {
+            [0, 0, 0, 0] => Mirroring::SingleScreenLower,
+            [1, 1, 1, 1] => Mirroring::SingleScreenUpper,
+            [0, 0, 1, 1] => Mirroring::Horizontal,
+            _ => Mirroring::Vertical,
+        };
+    }
+}
+
+impl Mapper for Mmc5 {
+    fn cpu_read(&mut self, addr: u16) -> Option<u8> {
+        let data = match addr {
+            0x5010 | 0x5015 => self.audio.read_register(addr),
+            // reading the status acknowledges the IRQ
+            0x5204 => {
+                let status = self.cpu_peek(addr);
+                self.irq_pending = false;
+                status
+            }
+            _ => self.cpu_peek(addr),
+        };
+
+        if let Some(data) = data { self.audio.cpu_read(addr, data); }
+        data
+    }
+
+    fn cpu_peek(&self, addr: u16) -> Option<u8> {
+        match addr {
+            0x5204 => Some(u8::from(self.irq_pending) << 7 | u8::from(self.in_frame) << 6),
+            0x5205 => Some((u16::from(self.multiplicand) * u16::from(self.multiplier)) as u8),
+            0x5206 => Some(((u16::from(self.multiplicand) * u16::from(self.multiplier)) >> 8) as u8),
+            0x5c00..=0x5fff if self.exram_mode >= 2 => Some(self.exram[addr as usize - 0x5c00]),
+            0x6000..=0x7fff => {
+                self.prg_ram_index(self.prg_banks[0], addr).map(|index| self.data.prg_ram[index])
+            }
+            0x8000..=0xffff => self.read_prg(addr),
+            _ => None,
+        }
+    }
+
+    fn cpu_write(&mut self, addr: u16, data: u8) {
+        match addr {
+            0x5000..=0x5015 => self.audio.write_register(addr, data),
+            0x5100 => self.prg_mode = data & 0b11,
+            0x5101 => self.chr_mode = data & 0b11,
+            0x5102 => self.prg_ram_protect[0] = data & 0b11,
+            0x5103 => self.prg_ram_protect[1] = data & 0b11,
+            0x5104 => self.exram_mode = data & 0b11,
+            0x5105 => self.write_nametable_mapping(data),
+            0x5113..=0x5117 => self.prg_banks[addr as usize - 0x5113] = data,
+            0x5120..=0x512b => {
+                let register = addr as usize - 0x5120;
+                self.chr_banks[register] = data as usize | self.chr_upper << 8;
+                self.using_chr_set_b = register >= 8;
+            }
+            0x5130 => self.chr_upper = (data & 0b11) as usize,
+            0x5203 => self.irq_compare = data,
+            0x5204 => self.irq_enabled = data & 0x80 != 0,
+            0x5205 => self.multiplicand = data,
+            0x5206 => self.multiplier = data,
+            // read-only in mode 3
+            0x5c00..=0x5fff if self.exram_mode != 3 => self.exram[addr as usize - 0x5c00] = data,
+            0x6000..=0xdfff => self.write_prg_ram(addr, data),
+            _ => {}
+        }
+    }
+
+    fn ppu_read(&mut self, addr: u16) -> u8 {
+        let (bank, size) = self.chr_bank_for(addr);
+        self.data.chr_read(bank, size, addr as usize)
+    }
+
+    fn ppu_write(&mut self, addr: u16, data: u8) {
+        let (bank, size) = self.chr_bank_for(addr);
+        self.data.chr_write(bank, size, addr as usize, data);
+    }
+
+    fn mirroring(&self) -> Mirroring {
+        self.mirroring
+    }
+
+    fn irq(&self) -> bool {
+        (self.irq_pending && self.irq_enabled) || self.audio.irq()
+    }
+
+    fn tick(&mut self, cycles: u16) {
+        for _ in 0..cycles {
+            self.audio.clock();
+        }
+
+        self.cycles_since_scanline = self.cycles_since_scanline.saturating_add(cycles);
+        if self.cycles_since_scanline > IN_FRAME_TIMEOUT { self.in_frame = false; }
+    }
+
+    // the first one after vblank is the pre-render line, which starts the count
+    fn scanline(&mut self) {
+        self.cycles_since_scanline = 0;
+
+        if !self.in_frame {
+            self.in_frame = true;
+            self.scanline = 0;
+            self.irq_pending = false;
+            return;
+        }
+
+        self.scanline = self.scanline.wrapping_add(1);
+        if self.scanline == self.irq_compare { self.irq_pending = true; }
+    }
+
+    fn audio_output(&self) -> f32 {
+        self.audio.output()
+    }
+}
+
+impl Snapshot for Mmc5 {
+    fn save_state(&self, state: &mut StateWriter) {
+        self.data.save_state(state);
+        state.write_u8(self.prg_mode);
+        state.write_u8(self.chr_mode);
+        state.write_bytes(&self.prg_ram_protect);
+        state.write_u8(self.exram_mode);
+        state.write_bytes(&self.exram);
+        self.mirroring.save_state(state);
+        state.write_bytes(&self.prg_banks);
+        for bank in self.chr_banks { state.write_usize(bank); }
+        state.write_usize(self.chr_upper);
+        state.write_bool(self.using_chr_set_b);
+        state.write_u8(self.irq_compare);
+        state.write_bool(self.irq_enabled);
+        state.write_bool(self.irq_pending);
+        state.write_bool(self.in_frame);
+        state.write_u8(self.scanline);
+        state.write_u16(self.cycles_since_scanline);
+        state.write_u8(self.multiplicand);
+        state.write_u8(self.multiplier);
+        self.audio.save_state(state);
+    }
+
+    fn load_state(&mut self, state: &mut StateReader) -> Result<(), StateError> {
+        self.data.load_state(state)?;
+        self.prg_mode = state.read_u8()?;
+        self.chr_mode = state.read_u8()?;
+        state.read_bytes_into(&mut self.prg_ram_protect)?;
+        self.exram_mode = state.read_u8()?;
+        state.read_bytes_into(&mut self.exram)?;
+        self.mirroring.load_state(state)?;
+        state.read_bytes_into(&mut self.prg_banks)?;
+        for bank in self.chr_banks.iter_mut() { *bank = state.read_usize()?; }
+        self.chr_upper = state.read_usize()?;
+        self.using_chr_set_b = state.read_bool()?;
+        self.irq_compare = state.read_u8()?;
+        self.irq_enabled = state.read_bool()?;
+        self.irq_pending = state.read_bool()?;
+        self.in_frame = state.read_bool()?;
+        self.scanline = state.read_u8()?;
+        self.cycles_since_scanline = state.read_u16()?;
+        self.multiplicand = state.read_u8()?;
+        self.multiplier = state.read_u8()?;
+        self.audio.load_state(state)
+    }
+}
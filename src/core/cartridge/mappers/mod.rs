@@ -0,0 +1,11 @@
+pub mod axrom;
+pub mod cnrom;
+pub mod fme7;
+pub mod mmc1;
+pub mod mmc5;
+pub mod namco163;
+pub mod nrom;
+pub mod uxrom;
+pub mod vrc6;
+pub mod vrc7;
+pub mod vrc_irq;
\ No newline at end of file
@@ -0,0 +1,140 @@
+use crate::core::apu::expansion::expansion_audio::ExpansionAudio;
+use crate::core::apu::expansion::n163::N163Audio;
+use crate::core::cartridge::mapper::{CartridgeData, Mapper};
+use crate::core::cartridge::rom::Mirroring;
+use crate::core::state::snapshot::Snapshot;
+use crate::core::state::stream::{StateError, StateReader, StateWriter};
+
+// mapper 19, Namco 163. the chip can also page the console's nametable RAM into the pattern
+// tables and pick nametables itself, neither of which is wired up here: CHR banks always come out
+// of CHR, and the nametables follow the header
+pub struct Namco163 {
+    data: CartridgeData,
+    prg_banks: [usize; 3],
+    chr_banks: [usize; 8],
+    irq_counter: u16,
+    irq_enabled: bool,
+    irq_pending: bool,
+    audio: N163Audio,
+}
+
+impl Namco163 {
+    pub fn new(data: CartridgeData) -> Self {
+        Namco163 {
+            data,
+            prg_banks: [0; 3],
+            chr_banks: [0; 8],
+            irq_counter: 0,
+            irq_enabled: false,
+            irq_pending: false,
+            audio: N163Audio::new(),
+        }
+    }
+}
+
+impl Mapper for Namco163 {
+    // sound RAM reads can bump the address along
+    fn cpu_read(&mut self, addr: u16) -> Option<u8> {
+        match addr {
+            0x4800..=0x4fff => self.audio.read_register(addr),
+            _ => self.cpu_peek(addr),
+        }
+    }
+
+    fn cpu_peek(&self, addr: u16) -> Option<u8> {
+        let last_bank = (self.data.prg_rom.len() / 0x2000).saturating_sub(1);
+
+        match addr {
+            0x5000..=0x57ff => Some(self.irq_counter as u8),
+            0x5800..=0x5fff => Some((self.irq_counter >> 8) as u8 | u8::from(self.irq_enabled) << 7),
+            0x6000..=0x7fff => self.data.prg_ram_read(addr),
+            0x8000..=0xdfff => {
+                let bank = self.prg_banks[(addr as usize - 0x8000) / 0x2000];
+                Some(self.data.prg_byte(bank, 0x2000, addr as usize))
+            }
+            0xe000..=0xffff => Some(self.data.prg_byte(last_bank, 0x2000, addr as usize)),
+            _ => None,
+        }
+    }
+
+    fn cpu_write(&mut self, addr: u16, data: u8) {
+        match addr {
+            0x4800..=0x4fff => self.audio.write_register(addr, data),
+            0x5000..=0x57ff => {
+                self.irq_counter = (self.irq_counter & 0x7f00) | u16::from(data);
+                self.irq_pending = false;
+            }
+            0x5800..=0x5fff => {
+                self.irq_counter = (self.irq_counter & 0x00ff) | (u16::from(data & 0x7f) << 8);
+                self.irq_enabled = data & 0x80 != 0;
+                self.irq_pending = false;
+            }
+            0x6000..=0x7fff => self.data.prg_ram_write(addr, data),
+            0x8000..=0xbfff => self.chr_banks[(addr as usize - 0x8000) / 0x800] = data as usize,
+            // nametable selects
+            0xc000..=0xdfff => {}
+            0xe000..=0xe7ff => {
+                self.prg_banks[0] = (data & 0x3f) as usize;
+                self.audio.set_enabled(data & 0x40 == 0);
+            }
+            0xe800..=0xefff => self.prg_banks[1] = (data & 0x3f) as usize,
+            0xf000..=0xf7ff => self.prg_banks[2] = (data & 0x3f) as usize,
+            0xf800..=0xffff => self.audio.write_register(addr, data),
+            _ => {}
+        }
+    }
+
+    fn ppu_read(&mut self, addr: u16) -> u8 {
+        self.data.chr_read(self.chr_banks[(addr >> 10) as usize & 7], 0x400, addr as usize)
+    }
+
+    fn ppu_write(&mut self, addr: u16, data: u8) {
+        self.data.chr_write(self.chr_banks[(addr >> 10) as usize & 7], 0x400, addr as usize, data);
+    }
+
+    fn mirroring(&self) -> Mirroring {
+        self.data.mirroring
+    }
+
+    fn irq(&self) -> bool {
+        self.irq_pending
+    }
+
+    fn tick(&mut self, cycles: u16) {
+        for _ in 0..cycles {
+            self.audio.clock();
+
+            // counts up to $7FFF and sits there
+            if self.irq_enabled && self.irq_counter < 0x7fff {
+                self.irq_counter += 1;
+                if self.irq_counter == 0x7fff { self.irq_pending = true; }
+            }
+        }
+    }
+
+    fn audio_output(&self) -> f32 {
+        self.audio.output()
+    }
+}
+
+impl Snapshot for Namco163 {
+    fn save_state(&self, state: &mut StateWriter) {
+        self.data.save_state(state);
+        for bank in self.prg_banks { state.write_usize(bank); }
+        for bank in self.chr_banks { state.write_usize(bank); }
+        state.write_u16(self.irq_counter);
+        state.write_bool(self.irq_enabled);
+        state.write_bool(self.irq_pending);
+        self.audio.save_state(state);
+    }
+
+    fn load_state(&mut self, state: &mut StateReader) -> Result<(), StateError> {
+        self.data.load_state(state)?;
+        for bank in self.prg_banks.iter_mut() { *bank = state.read_usize()?; }
+        for bank in self.chr_banks.iter_mut() { *bank = state.read_usize()?; }
+        self.irq_counter = state.read_u16()?;
+        self.irq_enabled = state.read_bool()?;
+        self.irq_pending = state.read_bool()?;
+        self.audio.load_state(state)
+    }
+}
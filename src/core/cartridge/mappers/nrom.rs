@@ -0,0 +1,51 @@
+use crate::core::cartridge::mapper::{CartridgeData, Mapper};
+use crate::core::cartridge::rom::Mirroring;
+use crate::core::state::snapshot::Snapshot;
+use crate::core::state::stream::{StateError, StateReader, StateWriter};
+
+// mapper 0, no banking at all. 16K carts just show up twice
+pub struct NRom {
+    data: CartridgeData,
+}
+
+impl NRom {
+    pub fn new(data: CartridgeData) -> Self {
+        NRom { data }
+    }
+}
+
+impl Mapper for NRom {
+    fn cpu_peek(&self, addr: u16) -> Option<u8> {
+        match addr {
+            0x6000..=0x7fff => self.data.prg_ram_read(addr),
+            0x8000..=0xffff => Some(self.data.prg_byte(0, 0x8000.min(self.data.prg_rom.len()), addr as usize - 0x8000)),
+            _ => None,
+        }
+    }
+
+    fn cpu_write(&mut self, addr: u16, data: u8) {
+        if let 0x6000..=0x7fff = addr { self.data.prg_ram_write(addr, data); }
+    }
+
+    fn ppu_read(&mut self, addr: u16) -> u8 {
+        self.data.chr_read(0, 0x2000, addr as usize)
+    }
+
+    fn ppu_write(&mut self, addr: u16, data: u8) {
+        self.data.chr_write(0, 0x2000, addr as usize, data);
+    }
+
+    fn mirroring(&self) -> Mirroring {
+        self.data.mirroring
+    }
+}
+
+impl Snapshot for NRom {
+    fn save_state(&self, state: &mut StateWriter) {
+        self.data.save_state(state);
+    }
+
+    fn load_state(&mut self, state: &mut StateReader) -> Result<(), StateError> {
+        self.data.load_state(state)
+    }
+}
@@ -0,0 +1,62 @@
+use crate::core::cartridge::mapper::{CartridgeData, Mapper};
+use crate::core::cartridge::rom::Mirroring;
+use crate::core::state::snapshot::Snapshot;
+use crate::core::state::stream::{StateError, StateReader, StateWriter};
+
+// mapper 2, switchable 16K at $8000 and the last bank stuck at $C000
+pub struct UxRom {
+    data: CartridgeData,
+    prg_bank: usize,
+}
+
+impl UxRom {
+    pub fn new(data: CartridgeData) -> Self {
+        UxRom { data, prg_bank: 0 }
+    }
+}
+
+impl Mapper for UxRom {
+    fn cpu_peek(&self, addr: u16) -> Option<u8> {
+        let last_bank = (self.data.prg_rom.len() / 0x4000).saturating_sub(1);
+
+        match addr {
+            0x6000..=0x7fff => self.data.prg_ram_read(addr),
+            0x8000..=0xbfff => Some(self.data.prg_byte(self.prg_bank, 0x4000, addr as usize)),
+            0xc000..=0xffff => Some(self.data.prg_byte(last_bank, 0x4000, addr as usize)),
+            _ => None,
+        }
+    }
+
+    fn cpu_write(&mut self, addr: u16, data: u8) {
+        match addr {
+            0x6000..=0x7fff => self.data.prg_ram_write(addr, data),
+            0x8000..=0xffff => self.prg_bank = data as usize,
+            _ => {}
+        }
+    }
+
+    fn ppu_read(&mut self, addr: u16) -> u8 {
+        self.data.chr_read(0, 0x2000, addr as usize)
+    }
+
+    fn ppu_write(&mut self, addr: u16, data: u8) {
+        self.data.chr_write(0, 0x2000, addr as usize, data);
+    }
+
+    fn mirroring(&self) -> Mirroring {
+        self.data.mirroring
+    }
+}
+
+impl Snapshot for UxRom {
+    fn save_state(&self, state: &mut StateWriter) {
+        self.data.save_state(state);
+        state.write_usize(self.prg_bank);
+    }
+
+    fn load_state(&mut self, state: &mut StateReader) -> Result<(), StateError> {
+        self.data.load_state(state)?;
+        self.prg_bank = state.read_usize()?;
+        Ok(())
+    }
+}
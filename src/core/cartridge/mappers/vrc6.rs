@@ -0,0 +1,134 @@
+use crate::core::apu::expansion::expansion_audio::ExpansionAudio;
+use crate::core::apu::expansion::vrc6::Vrc6Audio;
+use crate::core::cartridge::mapper::{CartridgeData, Mapper};
+use crate::core::cartridge::mappers::vrc_irq::VrcIrq;
+use crate::core::cartridge::rom::Mirroring;
+use crate::core::state::snapshot::Snapshot;
+use crate::core::state::stream::{StateError, StateReader, StateWriter};
+
+// mappers 24 and 26, Konami VRC6. 26 has the two low address lines swapped round
+pub struct Vrc6 {
+    data: CartridgeData,
+    swapped_lines: bool,
+    prg_bank_16k: usize,
+    prg_bank_8k: usize,
+    chr_banks: [usize; 8],
+    mirroring: Mirroring,
+    prg_ram_enabled: bool,
+    irq: VrcIrq,
+    audio: Vrc6Audio,
+}
+
+impl Vrc6 {
+    pub fn new(data: CartridgeData, swapped_lines: bool) -> Self {
+        Vrc6 {
+            mirroring: data.mirroring,
+            data,
+            swapped_lines,
+            prg_bank_16k: 0,
+            prg_bank_8k: 0,
+            chr_banks: [0; 8],
+            prg_ram_enabled: false,
+            irq: VrcIrq::default(),
+            audio: Vrc6Audio::new(),
+        }
+    }
+
+    // everything decodes as $x000-$x003
+    fn register(&self, addr: u16) -> u16 {
+        let register = addr & 0xf003;
+        if !self.swapped_lines { return register; }
+
+        (register & 0xf000) | ((register & 1) << 1) | ((register & 2) >> 1)
+    }
+}
+
+impl Mapper for Vrc6 {
+    fn cpu_peek(&self, addr: u16) -> Option<u8> {
+        let last_bank = (self.data.prg_rom.len() / 0x2000).saturating_sub(1);
+
+        match addr {
+            0x6000..=0x7fff if self.prg_ram_enabled => self.data.prg_ram_read(addr),
+            0x8000..=0xbfff => Some(self.data.prg_byte(self.prg_bank_16k, 0x4000, addr as usize)),
+            0xc000..=0xdfff => Some(self.data.prg_byte(self.prg_bank_8k, 0x2000, addr as usize)),
+            0xe000..=0xffff => Some(self.data.prg_byte(last_bank, 0x2000, addr as usize)),
+            _ => None,
+        }
+    }
+
+    fn cpu_write(&mut self, addr: u16, data: u8) {
+        if (0x6000..=0x7fff).contains(&addr) {
+            if self.prg_ram_enabled { self.data.prg_ram_write(addr, data); }
+            return;
+        }
+
+        match self.register(addr) {
+            0x8000..=0x8003 => self.prg_bank_16k = (data & 0x0f) as usize,
+            register @ (0x9000..=0x9003 | 0xa000..=0xa002 | 0xb000..=0xb002) => {
+                self.audio.write_register(register, data);
+            }
+            0xb003 => {
+                self.mirroring = Mirroring::from_control(data >> 2);
+                self.prg_ram_enabled = data & 0x80 != 0;
+            }
+            0xc000..=0xc003 => self.prg_bank_8k = (data & 0x1f) as usize,
+            register @ 0xd000..=0xd003 => self.chr_banks[(register & 0b11) as usize] = data as usize,
+            register @ 0xe000..=0xe003 => self.chr_banks[4 + (register & 0b11) as usize] = data as usize,
+            0xf000 => self.irq.write_latch(data),
+            0xf001 => self.irq.write_control(data),
+            0xf002 => self.irq.acknowledge(),
+            _ => {}
+        }
+    }
+
+    fn ppu_read(&mut self, addr: u16) -> u8 {
+        self.data.chr_read(self.chr_banks[(addr >> 10) as usize & 7], 0x400, addr as usize)
+    }
+
+    fn ppu_write(&mut self, addr: u16, data: u8) {
+        self.data.chr_write(self.chr_banks[(addr >> 10) as usize & 7], 0x400, addr as usize, data);
+    }
+
+    fn mirroring(&self) -> Mirroring {
+        self.mirroring
+    }
+
+    fn irq(&self) -> bool {
+        self.irq.pending
+    }
+
+    fn tick(&mut self, cycles: u16) {
+        for _ in 0..cycles {
+            self.irq.clock();
+            self.audio.clock();
+        }
+    }
+
+    fn audio_output(&self) -> f32 {
+        self.audio.output()
+    }
+}
+
+impl Snapshot for Vrc6 {
+    fn save_state(&self, state: &mut StateWriter) {
+        self.data.save_state(state);
+        state.write_usize(self.prg_bank_16k);
+        state.write_usize(self.prg_bank_8k);
+        for bank in self.chr_banks { state.write_usize(bank); }
+        self.mirroring.save_state(state);
+        state.write_bool(self.prg_ram_enabled);
+        self.irq.save_state(state);
+        self.audio.save_state(state);
+    }
+
+    fn load_state(&mut self, state: &mut StateReader) -> Result<(), StateError> {
+        self.data.load_state(state)?;
+        self.prg_bank_16k = state.read_usize()?;
+        self.prg_bank_8k = state.read_usize()?;
+        for bank in self.chr_banks.iter_mut() { *bank = state.read_usize()?; }
+        self.mirroring.load_state(state)?;
+        self.prg_ram_enabled = state.read_bool()?;
+        self.irq.load_state(state)?;
+        self.audio.load_state(state)
+    }
+}
@@ -0,0 +1,134 @@
+use crate::core::apu::expansion::expansion_audio::ExpansionAudio;
+use crate::core::apu::expansion::vrc7::Vrc7Audio;
+use crate::core::cartridge::mapper::{CartridgeData, Mapper};
+use crate::core::cartridge::mappers::vrc_irq::VrcIrq;
+use crate::core::cartridge::rom::Mirroring;
+use crate::core::state::snapshot::Snapshot;
+use crate::core::state::stream::{StateError, StateReader, StateWriter};
+
+// mapper 85, Konami VRC7. the two board variants put the second register of each pair on A3 or A4
+pub struct Vrc7 {
+    data: CartridgeData,
+    prg_banks: [usize; 3],
+    chr_banks: [usize; 8],
+    mirroring: Mirroring,
+    prg_ram_enabled: bool,
+    audio_silenced: bool,
+    irq: VrcIrq,
+    audio: Vrc7Audio,
+}
+
+impl Vrc7 {
+    pub fn new(data: CartridgeData) -> Self {
+        Vrc7 {
+            mirroring: data.mirroring,
+            data,
+            prg_banks: [0; 3],
+            chr_banks: [0; 8],
+            prg_ram_enabled: false,
+            audio_silenced: false,
+            irq: VrcIrq::default(),
+            audio: Vrc7Audio::new(),
+        }
+    }
+}
+
+impl Mapper for Vrc7 {
+    fn cpu_peek(&self, addr: u16) -> Option<u8> {
+        let last_bank = (self.data.prg_rom.len() / 0x2000).saturating_sub(1);
+
+        match addr {
+            0x6000..=0x7fff if self.prg_ram_enabled => self.data.prg_ram_read(addr),
+            0x8000..=0xdfff => {
+                let bank = self.prg_banks[(addr as usize - 0x8000) / 0x2000];
+                Some(self.data.prg_byte(bank, 0x2000, addr as usize))
+            }
+            0xe000..=0xffff => Some(self.data.prg_byte(last_bank, 0x2000, addr as usize)),
+            _ => None,
+        }
+    }
+
+    fn cpu_write(&mut self, addr: u16, data: u8) {
+        if (0x6000..=0x7fff).contains(&addr) {
+            if self.prg_ram_enabled { self.data.prg_ram_write(addr, data); }
+            return;
+        }
+
+        // the sound chip's two ports need A5 as well
+        if matches!(addr & 0xf030, 0x9010 | 0x9030) {
+            self.audio.write_register(addr & 0xf030, data);
+            return;
+        }
+
+        let second = addr & 0x18 != 0;
+        match (addr & 0xf000, second) {
+            (0x8000, false) => self.prg_banks[0] = (data & 0x3f) as usize,
+            (0x8000, true) => self.prg_banks[1] = (data & 0x3f) as usize,
+            (0x9000, false) => self.prg_banks[2] = (data & 0x3f) as usize,
+            (0xa000..=0xd000, _) => {
+                let index = ((addr as usize & 0xf000) - 0xa000) / 0x1000 * 2 + usize::from(second);
+                self.chr_banks[index] = data as usize;
+            }
+            (0xe000, false) => {
+                self.mirroring = Mirroring::from_control(data);
+                self.audio_silenced = data & 0x40 != 0;
+                self.prg_ram_enabled = data & 0x80 != 0;
+            }
+            (0xe000, true) => self.irq.write_latch(data),
+            (0xf000, false) => self.irq.write_control(data),
+            (0xf000, true) => self.irq.acknowledge(),
+            _ => {}
+        }
+    }
+
+    fn ppu_read(&mut self, addr: u16) -> u8 {
+        self.data.chr_read(self.chr_banks[(addr >> 10) as usize & 7], 0x400, addr as usize)
+    }
+
+    fn ppu_write(&mut self, addr: u16, data: u8) {
+        self.data.chr_write(self.chr_banks[(addr >> 10) as usize & 7], 0x400, addr as usize, data);
+    }
+
+    fn mirroring(&self) -> Mirroring {
+        self.mirroring
+    }
+
+    fn irq(&self) -> bool {
+        self.irq.pending
+    }
+
+    fn tick(&mut self, cycles: u16) {
+        for _ in 0..cycles {
+            self.irq.clock();
+            self.audio.clock();
+        }
+    }
+
+    fn audio_output(&self) -> f32 {
+        if self.audio_silenced { 0.0 } else { self.audio.output() }
+    }
+}
+
+impl Snapshot for Vrc7 {
+    fn save_state(&self, state: &mut StateWriter) {
+        self.data.save_state(state);
+        for bank in self.prg_banks { state.write_usize(bank); }
+        for bank in self.chr_banks { state.write_usize(bank); }
+        self.mirroring.save_state(state);
+        state.write_bool(self.prg_ram_enabled);
+        state.write_bool(self.audio_silenced);
+        self.irq.save_state(state);
+        self.audio.save_state(state);
+    }
+
+    fn load_state(&mut self, state: &mut StateReader) -> Result<(), StateError> {
+        self.data.load_state(state)?;
+        for bank in self.prg_banks.iter_mut() { *bank = state.read_usize()?; }
+        for bank in self.chr_banks.iter_mut() { *bank = state.read_usize()?; }
+        self.mirroring.load_state(state)?;
+        self.prg_ram_enabled = state.read_bool()?;
+        self.audio_silenced = state.read_bool()?;
+        self.irq.load_state(state)?;
+        self.audio.load_state(state)
+    }
+}
@@ -0,0 +1,78 @@
+use crate::core::state::snapshot::Snapshot;
+use crate::core::state::stream::{StateError, StateReader, StateWriter};
+
+// the IRQ counter the VRC4, VRC6 and VRC7 share. it counts up to $FF, either every CPU cycle or
+// once a scanline, where a "scanline" is just a prescaler going round every 113.67 CPU cycles
+#[derive(Debug, Clone, Default)]
+pub struct VrcIrq {
+    latch: u8,
+    counter: u8,
+    prescaler: i16,
+    enabled: bool,
+    enable_after_ack: bool,
+    cycle_mode: bool,
+    pub pending: bool,
+}
+
+impl VrcIrq {
+    pub fn write_latch(&mut self, data: u8) {
+        self.latch = data;
+    }
+
+    pub fn write_control(&mut self, data: u8) {
+        self.enable_after_ack = data & 0b001 != 0;
+        self.enabled = data & 0b010 != 0;
+        self.cycle_mode = data & 0b100 != 0;
+        self.pending = false;
+
+        if self.enabled {
+            self.counter = self.latch;
+            self.prescaler = 341;
+        }
+    }
+
+    pub fn acknowledge(&mut self) {
+        self.pending = false;
+        self.enabled = self.enable_after_ack;
+    }
+
+    pub fn clock(&mut self) {
+        if !self.enabled { return; }
+
+        if !self.cycle_mode {
+            self.prescaler -= 3;
+            if self.prescaler > 0 { return; }
+            self.prescaler += 341;
+        }
+
+        if self.counter == 0xff {
+            self.counter = self.latch;
+            self.pending = true;
+        } else {
+            self.counter += 1;
+        }
+    }
+}
+
+impl Snapshot for VrcIrq {
+    fn save_state(&self, state: &mut StateWriter) {
+        state.write_u8(self.latch);
+        state.write_u8(self.counter);
+        state.write_u16(self.prescaler as u16);
+        state.write_bool(self.enabled);
+        state.write_bool(self.enable_after_ack);
+        state.write_bool(self.cycle_mode);
+        state.write_bool(self.pending);
+    }
+
+    fn load_state(&mut self, state: &mut StateReader) -> Result<(), StateError> {
+        self.latch = state.read_u8()?;
+        self.counter = state.read_u8()?;
+        self.prescaler = state.read_u16()? as i16;
+        self.enabled = state.read_bool()?;
+        self.enable_after_ack = state.read_bool()?;
+        self.cycle_mode = state.read_bool()?;
+        self.pending = state.read_bool()?;
+        Ok(())
+    }
+}
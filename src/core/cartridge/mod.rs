@@ -0,0 +1,3 @@
+pub mod mapper;
+pub mod mappers;
+pub mod rom;
\ No newline at end of file
@@ -0,0 +1,196 @@
+use std::fmt;
+use std::fs;
+use std::path::Path;
+use crate::core::cartridge::mapper::{create_mapper, CartridgeData, Mapper};
+use crate::core::checksum::{crc32, md5};
+use crate::core::region::Region;
+use crate::core::state::snapshot::Snapshot;
+use crate::core::state::stream::{StateError, StateReader, StateWriter};
+
+const NES_TAG: [u8; 4] = [0x4e, 0x45, 0x53, 0x1a];
+const PRG_ROM_PAGE_SIZE: usize = 0x4000;
+const CHR_ROM_PAGE_SIZE: usize = 0x2000;
+
+#[derive(Debug, Copy, Clone, Eq, PartialEq)]
+pub enum Mirroring {
+    Horizontal,
+    Vertical,
+    FourScreen,
+    SingleScreenLower,
+    SingleScreenUpper,
+}
+
+impl Mirroring {
+    // where a $2000-$2FFF address lands in the PPU's 4K of nametable space
+    pub fn nametable_offset(&self, addr: u16) -> usize {
+        let table = ((addr as usize) >> 10) & 0b11;
+        let offset = addr as usize & 0x3ff;
+
+        let physical = match self {
+            Mirroring::Horizontal => table >> 1,
+            Mirroring::Vertical => table & 1,
+            Mirroring::FourScreen => table,
+            Mirroring::SingleScreenLower => 0,
+            Mirroring::SingleScreenUpper => 1,
+        };
+
+        physical * 0x400 + offset
+    }
+
+    // the vertical, horizontal, lower, upper order that the Konami and Sunsoft boards use
+    pub fn from_control(bits: u8) -> Mirroring {
+        match bits & 0b11 {
+            0 => Mirroring::Vertical,
+            1 => Mirroring::Horizontal,
+            2 => Mirroring::SingleScreenLower,
+            _ => Mirroring::SingleScreenUpper,
+        }
+    }
+}
+
+#[derive(Debug)]
+pub enum CartridgeError {
+    Io(std::io::Error),
+    NotINes,
+    NoPrgRom,
+    Truncated { expected: usize, found: usize },
+    UnsupportedMapper(u16),
+}
+
+impl fmt::Display for CartridgeError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            CartridgeError::Io(e) => write!(f, "couldn't read ROM: {e}"),
+            CartridgeError::NotINes => write!(f, "not an iNES file"),
+            CartridgeError::NoPrgRom => write!(f, "ROM header says there's no PRG-ROM"),
+            CartridgeError::Truncated { expected, found } => write!(
+                f, "ROM is truncated, header says {expected} bytes but file has {found}"
+            ),
+            CartridgeError::UnsupportedMapper(id) => write!(f, "mapper {id} isn't supported"),
+        }
+    }
+}
+
+impl std::error::Error for CartridgeError {}
+
+impl From<std::io::Error> for CartridgeError {
+    fn from(e: std::io::Error) -> Self {
+        CartridgeError::Io(e)
+    }
+}
+
+pub struct Cartridge {
+    pub mapper_id: u16,
+    pub battery: bool,
+    // only set when the header actually says something (NES 2.0 mostly)
+    pub region: Option<Region>,
+    // CRC-32 of the PRG and CHR ROM, header left out, so save states can tell which game they're for
+    pub crc32: u32,
+    // MD5 of the same, which is how FCEUX movies name their ROM
+    pub md5: [u8; 16],
+    pub mapper: Box<dyn Mapper>,
+}
+
+impl Cartridge {
+    pub fn from_ines(raw: &[u8]) -> Result<Cartridge, CartridgeError> {
+        if raw.len() < 16 || raw[0..4] != NES_TAG { return Err(CartridgeError::NotINes); }
+
+        let nes2 = raw[7] & 0b0000_1100 == 0b0000_1000;
+
+        let mut mapper_id = u16::from((raw[7] & 0b1111_0000) | (raw[6] >> 4));
+        let mut prg_rom_size = raw[4] as usize * PRG_ROM_PAGE_SIZE;
+        let mut chr_rom_size = raw[5] as usize * CHR_ROM_PAGE_SIZE;
+        let mut prg_ram_size = 0x2000;
+        let mut chr_ram_size = 0x2000;
+
+        if nes2 {
+            mapper_id |= u16::from(raw[8] & 0x0f) << 8;
+            prg_rom_size += ((raw[9] & 0x0f) as usize) << 8 << 14;
+            chr_rom_size += ((raw[9] >> 4) as usize) << 8 << 13;
+
+            // shift counts, 0 means none. volatile and battery-backed both count
+            let shift_size = |shift: u8| if shift == 0 { 0 } else { 64usize << shift };
+            prg_ram_size = shift_size(raw[10] & 0x0f) + shift_size(raw[10] >> 4);
+            chr_ram_size = shift_size(raw[11] & 0x0f) + shift_size(raw[11] >> 4);
+        }
+
+        // there'd be nothing for the CPU to run
+        if prg_rom_size == 0 { return Err(CartridgeError::NoPrgRom); }
+
+        let four_screen = raw[6] & 0b1000 != 0;
+        let vertical_mirroring = raw[6] & 0b1 != 0;
+        let mirroring = match (four_screen, vertical_mirroring) {
+            (true, _) => Mirroring::FourScreen,
+            (false, true) => Mirroring::Vertical,
+            (false, false) => Mirroring::Horizontal,
+        };
+
+        let region = if nes2 {
+            match raw[12] & 0b11 {
+                0 => Some(Region::Ntsc),
+                1 => Some(Region::Pal),
+                3 => Some(Region::Dendy),
+                _ => None,
+            }
+        } else if raw[9] & 1 == 1 { Some(Region::Pal) }
+        else { None };
+
+        let skip_trainer = raw[6] & 0b100 != 0;
+        let prg_rom_start = 16 + if skip_trainer { 512 } else { 0 };
+        let chr_rom_start = prg_rom_start + prg_rom_size;
+        let expected = chr_rom_start + chr_rom_size;
+        if raw.len() < expected {
+            return Err(CartridgeError::Truncated { expected, found: raw.len() });
+        }
+
+        let chr_is_ram = chr_rom_size == 0;
+        let chr = if chr_is_ram { vec![0; chr_ram_size.max(CHR_ROM_PAGE_SIZE)] }
+            else { raw[chr_rom_start .. expected].to_vec() };
+
+        let data = CartridgeData {
+            prg_rom: raw[prg_rom_start .. chr_rom_start].to_vec(),
+            chr,
+            chr_is_ram,
+            prg_ram: vec![0; prg_ram_size],
+            mirroring,
+        };
+
+        Ok(Cartridge {
+            mapper_id,
+            battery: raw[6] & 0b10 != 0,
+            region,
+            crc32: crc32(&raw[prg_rom_start .. expected]),
+            md5: md5(&raw[prg_rom_start .. expected]),
+            mapper: create_mapper(mapper_id, data)?,
+        })
+    }
+
+    pub fn load<P: AsRef<Path>>(path: P) -> Result<Cartridge, CartridgeError> {
+        let raw = fs::read(path)?;
+        Cartridge::from_ines(&raw)
+    }
+}
+
+impl Snapshot for Mirroring {
+    fn save_state(&self, state: &mut StateWriter) {
+        state.write_u8(match self {
+            Mirroring::Horizontal => 0,
+            Mirroring::Vertical => 1,
+            Mirroring::FourScreen => 2,
+            Mirroring::SingleScreenLower => 3,
+            Mirroring::SingleScreenUpper => 4,
+        });
+    }
+
+    fn load_state(&mut self, state: &mut StateReader) -> Result<(), StateError> {
+        *self = match state.read_u8()? {
+            0 => Mirroring::Horizontal,
+            1 => Mirroring::Vertical,
+            2 => Mirroring::FourScreen,
+            3 => Mirroring::SingleScreenLower,
+            4 => Mirroring::SingleScreenUpper,
+            _ => return Err(StateError::Corrupt("unknown mirroring")),
+        };
+        Ok(())
+    }
+}
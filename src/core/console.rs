@@ -0,0 +1,624 @@
+use std::fs::{self, File};
+use std::io::{self, BufWriter};
+use std::path::Path;
+use crate::core::cartridge::rom::{Cartridge, CartridgeError};
+use crate::core::checksum::md5;
+use crate::core::cpu::processor::Processor;
+use crate::core::export::wav::{sample_to_i16, WavRecorder};
+use crate::core::input::four_player::{FourPlayerAdapter, FourPlayerMode};
+use crate::core::input::input_device::{InputDevice, Unplugged};
+use crate::core::input::joypad::{Buttons, Joypad};
+use crate::core::movie::file::{Movie, MovieError, MovieFrame, COMMAND_RESET};
+use crate::core::nes_bus::NesBus;
+use crate::core::ppu::palette::Palette;
+use crate::core::ppu::picture_processor::{SCREEN_HEIGHT, SCREEN_WIDTH};
+use crate::core::region::Region;
+use crate::core::state::header::StateHeader;
+use crate::core::state::rewind::RewindBuffer;
+use crate::core::state::snapshot::Snapshot;
+use crate::core::state::stream::{StateError, StateReader, StateWriter};
+
+// everything that came out of the machine over one frame
+pub struct Frame {
+    pub number: u64,
+    // RGB24, SCREEN_WIDTH * SCREEN_HEIGHT * 3 bytes
+    pub pixels: Vec<u8>,
+    // mono, at the console's sample_rate()
+    pub samples: Vec<f32>,
+}
+
+impl Frame {
+    pub fn samples_i16(&self) -> Vec<i16> {
+        self.samples.iter().map(|sample| sample_to_i16(*sample)).collect()
+    }
+}
+
+// audio being written out to a WAV as frames get stepped
+struct Recording {
+    recorder: WavRecorder<BufWriter<File>>,
+    // None records until stop_recording
+    frames_left: Option<u64>,
+    // a failed write stops the recording, stop_recording reports it
+    error: Option<io::Error>,
+}
+
+// where a new movie recording starts
+#[derive(Debug, Copy, Clone, Eq, PartialEq)]
+pub enum MovieStart {
+    // only from a console that hasn't stepped a frame yet
+    PowerOn,
+    // from wherever the console is now, with a save state in the movie
+    SaveState,
+}
+
+// how many frames ahead to show, and the console to run them on when it isn't this one
+struct RunAhead {
+    frames: u32,
+    second_instance: Option<Box<Console>>,
+}
+
+// what's in a port, as far as keeping a second instance's ports the same goes
+#[derive(Copy, Clone, Eq, PartialEq)]
+enum PortKind {
+    Joypad,
+    FourPlayer(FourPlayerMode),
+    Other,
+}
+
+enum MovieSession {
+    Recording { movie: Movie, start_frame: u64, reset_pending: bool },
+    Playing { movie: Movie, next: usize },
+}
+
+// the whole machine, no window attached. drive it with the step_* functions
+pub struct Console {
+    pub cpu: Processor<NesBus>,
+    palette: Palette,
+    frame_number: u64,
+    recording: Option<Recording>,
+    rewind: Option<RewindBuffer>,
+    movie: Option<MovieSession>,
+    run_ahead: Option<RunAhead>,
+}
+
+impl Console {
+    // goes with whatever region the header asks for, NTSC if it doesn't say
+    pub fn new(cartridge: Cartridge) -> Self {
+        let region = cartridge.region.unwrap_or_default();
+        Console::with_region(cartridge, region)
+    }
+
+    pub fn with_region(cartridge: Cartridge, region: Region) -> Self {
+        let mut cpu = Processor::with_bus(NesBus::new(cartridge, region));
+        cpu.region = region;
+        cpu.reset();
+
+        Console {
+            cpu,
+            palette: Palette::default(),
+            frame_number: 0,
+            recording: None,
+            rewind: None,
+            movie: None,
+            run_ahead: None,
+        }
+    }
+
+    pub fn load_rom<P: AsRef<Path>>(path: P) -> Result<Self, CartridgeError> {
+        Ok(Console::new(Cartridge::load(path)?))
+    }
+
+    pub fn region(&self) -> Region {
+        self.cpu.region
+    }
+
+    pub fn set_palette(&mut self, palette: Palette) {
+        self.palette = palette;
+    }
+
+    pub fn palette(&self) -> &Palette {
+        &self.palette
+    }
+
+    pub fn sample_rate(&self) -> u32 {
+        self.cpu.bus.apu.sample_rate()
+    }
+
+    // set this before start_recording, a WAV that's already going keeps the rate it started with
+    pub fn set_sample_rate(&mut self, sample_rate: u32) {
+        self.cpu.bus.apu.set_sample_rate(sample_rate);
+    }
+
+    pub fn frame_number(&self) -> u64 {
+        self.frame_number
+    }
+
+    // `port` 0 is $4016, 1 is $4017
+    pub fn connect(&mut self, port: usize, device: Box<dyn InputDevice>) {
+        self.cpu.bus.ports[port] = device;
+    }
+
+    // the device on `port`, if it's a `T`
+    pub fn device<T: InputDevice>(&self, port: usize) -> Option<&T> {
+        let device: &dyn std::any::Any = &*self.cpu.bus.ports[port];
+        device.downcast_ref::<T>()
+    }
+
+    pub fn device_mut<T: InputDevice>(&mut self, port: usize) -> Option<&mut T> {
+        let device: &mut dyn std::any::Any = &mut *self.cpu.bus.ports[port];
+        device.downcast_mut::<T>()
+    }
+
+    pub fn connect_expansion(&mut self, device: Box<dyn InputDevice>) {
+        self.cpu.bus.expansion = device;
+    }
+
+    // the device on the expansion port, if it's a `T`
+    pub fn expansion_device_mut<T: InputDevice>(&mut self) -> Option<&mut T> {
+        let device: &mut dyn std::any::Any = &mut *self.cpu.bus.expansion;
+        device.downcast_mut::<T>()
+    }
+
+    // plugs a four-player adapter into both ports
+    pub fn connect_four_player(&mut self, mode: FourPlayerMode) {
+        self.connect(0, Box::new(FourPlayerAdapter::new(mode, 0)));
+        self.connect(1, Box::new(FourPlayerAdapter::new(mode, 1)));
+    }
+
+    // what `player` (0-3) has held down, until it's set again. players 3 and 4 need a
+    // four-player adapter, and nothing happens for a port with something other than a pad in it
+    pub fn set_buttons(&mut self, player: usize, buttons: Buttons) {
+        let port = player % 2;
+
+        if let Some(adapter) = self.device_mut::<FourPlayerAdapter>(port) {
+            adapter.set_buttons(player / 2, buttons);
+        } else if let Some(joypad) = self.device_mut::<Joypad>(port) {
+            if player < 2 { joypad.set_buttons(buttons); }
+        }
+    }
+
+    // what `player` is holding, None if there's no pad for them
+    pub fn buttons(&self, player: usize) -> Option<Buttons> {
+        let port = player % 2;
+
+        if let Some(adapter) = self.device::<FourPlayerAdapter>(port) {
+            Some(adapter.buttons(player / 2))
+        } else {
+            self.device::<Joypad>(port).filter(|_| player < 2).map(|joypad| joypad.buttons())
+        }
+    }
+
+    // records the audio of every frame stepped from here on, either `frames` of them or
+    // until stop_recording. the file isn't a valid WAV until stop_recording is called
+    pub fn start_recording<P: AsRef<Path>>(&mut self, path: P, frames: Option<u64>) -> io::Result<()> {
+        self.stop_recording()?;
+
+        self.recording = Some(Recording {
+            recorder: WavRecorder::create(path, self.sample_rate())?,
+            frames_left: frames,
+            error: None,
+        });
+        Ok(())
+    }
+
+    // false once the frame limit's been hit, even before stop_recording
+    pub fn is_recording(&self) -> bool {
+        self.recording.as_ref().is_some_and(|recording| {
+            recording.error.is_none() && recording.frames_left != Some(0)
+        })
+    }
+
+    pub fn stop_recording(&mut self) -> io::Result<()> {
+        let Some(recording) = self.recording.take() else { return Ok(()) };
+        if let Some(error) = recording.error {
+            return Err(error);
+        }
+
+        recording.recorder.finish()?;
+        Ok(())
+    }
+
+    // the whole machine: CPU, RAM, PPU, APU and the cartridge's registers and RAM. what's plugged
+    // into the ports and the audio already on its way out aren't part of it
+    pub fn save_state(&self) -> Vec<u8> {
+        let mut state = StateWriter::new();
+        StateHeader {
+            rom_crc32: self.cpu.bus.cartridge.crc32,
+            region: self.region(),
+            frame_number: self.frame_number,
+        }.write(&mut state);
+        self.cpu.save_state(&mut state);
+        state.into_bytes()
+    }
+
+    // a state for another ROM or region gets turned away before anything's touched, and one
+    // that turns out to be broken partway through leaves the machine as it was
+    pub fn load_state(&mut self, data: &[u8]) -> Result<(), StateError> {
+        let mut state = StateReader::new(data);
+        let header = StateHeader::read(&mut state)?;
+        header.check(self.cpu.bus.cartridge.crc32, self.region())?;
+
+        self.load_machine_state(&mut state)?;
+        self.frame_number = header.frame_number;
+        self.restart_rewind();
+
+        // going back while recording a movie is a rerecord: what came after gets thrown away
+        if let Some(MovieSession::Recording { movie, start_frame, .. }) = self.movie.as_mut() {
+            if let Some(kept) = self.frame_number.checked_sub(*start_frame) {
+                movie.frames.truncate(kept as usize);
+                movie.rerecord_count += 1;
+            }
+        }
+        Ok(())
+    }
+
+    // everything after the header. failing partway through puts the machine back how it was
+    fn load_machine_state(&mut self, state: &mut StateReader) -> Result<(), StateError> {
+        let mut backup = StateWriter::new();
+        self.cpu.save_state(&mut backup);
+
+        let loaded = self.cpu.load_state(state).and_then(|_| {
+            if state.is_empty() { Ok(()) } else { Err(StateError::Corrupt("trailing data")) }
+        });
+        if let Err(error) = loaded {
+            let backup = backup.into_bytes();
+            self.cpu.load_state(&mut StateReader::new(&backup)).expect("state that was just saved loads");
+            return Err(error);
+        }
+        Ok(())
+    }
+
+    pub fn save_state_file<P: AsRef<Path>>(&self, path: P) -> io::Result<()> {
+        fs::write(path, self.save_state())
+    }
+
+    pub fn load_state_file<P: AsRef<Path>>(&mut self, path: P) -> Result<(), StateError> {
+        let data = fs::read(path)?;
+        self.load_state(&data)
+    }
+
+    // keeps a snapshot every `interval` frames, delta compressed, and the pads' input in between,
+    // in at most `budget` bytes. the oldest history goes first once that fills up
+    pub fn enable_rewind(&mut self, interval: u32, budget: usize) {
+        self.rewind = Some(RewindBuffer::new(interval, budget));
+        self.restart_rewind();
+    }
+
+    pub fn disable_rewind(&mut self) {
+        self.rewind = None;
+    }
+
+    pub fn rewind_buffer(&self) -> Option<&RewindBuffer> {
+        self.rewind.as_ref()
+    }
+
+    // goes back one frame by loading the last snapshot before it and re-running the recorded
+    // input from there. the frame that comes back is silent, and None means there's no history
+    // left to go back into, or it wouldn't load and the console hasn't moved. only standard pads
+    // get replayed, anything else plugged in stays put
+    pub fn step_back(&mut self) -> Option<Frame> {
+        let target = self.frame_number.checked_sub(1)?;
+        let point = self.rewind.as_mut()?.rewind_to(target)?;
+
+        let mut state = StateReader::new(&point.state);
+        let loaded = StateHeader::read(&mut state).and_then(|_| self.load_machine_state(&mut state));
+        if loaded.is_err() {
+            // the machine's as it was, but the history it was going back into is gone
+            self.restart_rewind();
+            return None;
+        }
+        self.frame_number = point.frame;
+
+        let live_input = self.input();
+        for input in point.inputs {
+            self.set_input(input);
+            self.run_frame(|_| {});
+        }
+        self.set_input(live_input);
+        // nothing that was made while re-running is worth hearing
+        self.cpu.bus.apu.end_frame();
+
+        Some(Frame { number: self.frame_number, pixels: self.framebuffer(), samples: Vec::new() })
+    }
+
+    // history from before a jump (a reset, a loaded state) can't be re-run into the present
+    fn restart_rewind(&mut self) {
+        if self.rewind.is_none() { return; }
+
+        let state = self.save_state();
+        if let Some(rewind) = self.rewind.as_mut() {
+            rewind.clear();
+            rewind.push_snapshot(self.frame_number, state);
+        }
+    }
+
+    // the four players' buttons as bytes, the way the rewind buffer keeps them
+    fn input(&self) -> [u8; 4] {
+        std::array::from_fn(|player| self.buttons(player).map_or(0, |buttons| buttons.to_byte()))
+    }
+
+    fn set_input(&mut self, input: [u8; 4]) {
+        for (player, byte) in input.into_iter().enumerate() {
+            self.set_buttons(player, Buttons::from_byte(byte));
+        }
+    }
+
+    // hides `frames` frames of the game's own input lag: every frame also runs that many further
+    // ahead on the same input and shows where that got to, then goes back. the sound is still
+    // the real frame's. 0 turns it off
+    pub fn enable_run_ahead(&mut self, frames: u32) {
+        self.run_ahead = (frames > 0).then_some(RunAhead { frames, second_instance: None });
+    }
+
+    // the same, but running ahead on another console made from `cartridge`, which has to be the
+    // same game. this one never gets a state loaded into it, for games where that glitches
+    pub fn enable_run_ahead_second_instance(&mut self, frames: u32, cartridge: Cartridge) -> Result<(), StateError> {
+        if cartridge.crc32 != self.cpu.bus.cartridge.crc32 {
+            return Err(StateError::RomMismatch { expected: self.cpu.bus.cartridge.crc32, found: cartridge.crc32 });
+        }
+
+        let second = Console::with_region(cartridge, self.region());
+        self.run_ahead = (frames > 0).then(|| RunAhead { frames, second_instance: Some(Box::new(second)) });
+        Ok(())
+    }
+
+    pub fn disable_run_ahead(&mut self) {
+        self.run_ahead = None;
+    }
+
+    pub fn run_ahead_frames(&self) -> u32 {
+        self.run_ahead.as_ref().map_or(0, |run_ahead| run_ahead.frames)
+    }
+
+    // the picture from `frames` frames on, holding the current input, leaving the machine as it was.
+    // nothing here goes through the movie, rewind or WAV recording, they only see the real frames,
+    // and the APU's resampler and filters get put back so the sound carries on from the real one
+    fn run_ahead_pixels(&mut self, run_ahead: &mut RunAhead) -> Vec<u8> {
+        let Some(second) = run_ahead.second_instance.as_deref_mut() else {
+            let mut backup = StateWriter::new();
+            self.cpu.save_state(&mut backup);
+            let pipeline = self.cpu.bus.apu.pipeline();
+            let frame_number = self.frame_number;
+
+            for _ in 0..run_ahead.frames { self.run_frame(|_| {}); }
+            let pixels = self.framebuffer();
+
+            let backup = backup.into_bytes();
+            self.cpu.load_state(&mut StateReader::new(&backup)).expect("state that was just saved loads");
+            self.cpu.bus.apu.restore_pipeline(pipeline);
+            self.frame_number = frame_number;
+            return pixels;
+        };
+
+        for port in 0..2 {
+            let kind = self.port_kind(port);
+            if second.port_kind(port) == kind { continue; }
+            match kind {
+                PortKind::Joypad => second.connect(port, Box::new(Joypad::default())),
+                PortKind::FourPlayer(mode) => second.connect_four_player(mode),
+                PortKind::Other => second.connect(port, Box::new(Unplugged)),
+            }
+        }
+
+        second.load_state(&self.save_state()).expect("the same game's state loads");
+        second.set_input(self.input());
+        for _ in 0..run_ahead.frames { second.run_frame(|_| {}); }
+        self.pixels(second.cpu.bus.ppu.framebuffer())
+    }
+
+    fn port_kind(&self, port: usize) -> PortKind {
+        if self.device::<Joypad>(port).is_some() { return PortKind::Joypad; }
+        match self.device::<FourPlayerAdapter>(port) {
+            Some(adapter) => PortKind::FourPlayer(adapter.mode()),
+            None => PortKind::Other,
+        }
+    }
+
+    // records the pads from the next frame on, until stop_movie
+    pub fn start_movie_recording(&mut self, start: MovieStart) -> Result<(), MovieError> {
+        let mut movie = Movie::new(self.region());
+        movie.rom_md5 = Some(self.cpu.bus.cartridge.md5);
+        movie.guid = new_guid();
+
+        let adapters = [0, 1].map(|port| self.device::<FourPlayerAdapter>(port).map(|adapter| adapter.mode()));
+        movie.four_score = adapters.iter().all(|mode| *mode == Some(FourPlayerMode::FourScore));
+        movie.ports = [0, 1].map(|port| self.device::<Joypad>(port).is_some());
+
+        match start {
+            MovieStart::PowerOn if self.frame_number != 0 => return Err(MovieError::NotAtPowerOn),
+            MovieStart::PowerOn => {}
+            MovieStart::SaveState => movie.save_state = Some(self.save_state()),
+        }
+
+        self.movie = Some(MovieSession::Recording { movie, start_frame: self.frame_number, reset_pending: false });
+        Ok(())
+    }
+
+    // plays `movie` back from the next frame, replacing whatever's in the ports with what it was
+    // recorded with. the host's own input is ignored until it runs out
+    pub fn play_movie(&mut self, movie: Movie) -> Result<(), MovieError> {
+        if movie.region != self.region() {
+            return Err(MovieError::Unsupported(format!("a {:?} console", movie.region)));
+        }
+        if movie.frames.iter().skip(1).any(MovieFrame::power) {
+            return Err(MovieError::Unsupported("power cycling".into()));
+        }
+
+        match &movie.save_state {
+            Some(state) => self.load_state(state)?,
+            None if self.frame_number != 0 => return Err(MovieError::NotAtPowerOn),
+            None => {}
+        }
+
+        if movie.four_score {
+            self.connect_four_player(FourPlayerMode::FourScore);
+        } else {
+            for (port, pad) in movie.ports.iter().enumerate() {
+                let device: Box<dyn InputDevice> = if *pad { Box::new(Joypad::default()) } else { Box::new(Unplugged) };
+                self.connect(port, device);
+            }
+        }
+
+        self.movie = Some(MovieSession::Playing { movie, next: 0 });
+        Ok(())
+    }
+
+    pub fn is_recording_movie(&self) -> bool {
+        matches!(self.movie, Some(MovieSession::Recording { .. }))
+    }
+
+    pub fn is_playing_movie(&self) -> bool {
+        matches!(self.movie, Some(MovieSession::Playing { .. }))
+    }
+
+    // stops recording or playing, handing back the movie
+    pub fn stop_movie(&mut self) -> Option<Movie> {
+        match self.movie.take()? {
+            MovieSession::Recording { movie, .. } | MovieSession::Playing { movie, .. } => Some(movie),
+        }
+    }
+
+    // the next frame's input, from the movie being played or into the one being recorded
+    fn movie_frame(&mut self) {
+        match self.movie.as_mut() {
+            Some(MovieSession::Playing { movie, next }) => {
+                let Some(&frame) = movie.frames.get(*next) else {
+                    self.movie = None;
+                    return;
+                };
+                let players: Vec<usize> = (0..4).filter(|player| movie.has_pad(*player)).collect();
+                *next += 1;
+                if *next == movie.frames.len() { self.movie = None; }
+
+                if frame.reset() { self.reset_machine(); }
+                for player in players {
+                    self.set_buttons(player, frame.pads[player]);
+                }
+            }
+            Some(MovieSession::Recording { .. }) => {
+                let pads = std::array::from_fn(|player| self.buttons(player).unwrap_or_default());
+                if let Some(MovieSession::Recording { movie, reset_pending, .. }) = self.movie.as_mut() {
+                    let commands = if *reset_pending { COMMAND_RESET } else { 0 };
+                    movie.frames.push(MovieFrame { commands, pads });
+                    *reset_pending = false;
+                }
+            }
+            None => {}
+        }
+    }
+
+    // the reset button, RAM and the cartridge keep their contents
+    pub fn reset(&mut self) {
+        if let Some(MovieSession::Recording { reset_pending, .. }) = self.movie.as_mut() {
+            *reset_pending = true;
+        }
+        self.reset_machine();
+    }
+
+    fn reset_machine(&mut self) {
+        self.cpu.bus.ppu.reset();
+        self.cpu.reset();
+        self.restart_rewind();
+    }
+
+    // returns how many CPU cycles it took, interrupts and DMA stalls included
+    pub fn step_instruction(&mut self) -> u64 {
+        let start = self.cpu.cycles;
+        self.cpu.step();
+        self.cpu.cycles - start
+    }
+
+    // runs whole instructions until at least `cycles` have gone by, returns how many actually did
+    pub fn run_cycles(&mut self, cycles: u64) -> u64 {
+        let start = self.cpu.cycles;
+        while self.cpu.cycles - start < cycles {
+            self.step_instruction();
+        }
+        self.cpu.cycles - start
+    }
+
+    // runs up to the start of the next vblank, which is when the picture is done
+    pub fn step_frame(&mut self) -> Frame {
+        self.step_frame_with_callback(|_| {})
+    }
+
+    // same as step_frame, calling back before every instruction
+    pub fn step_frame_with_callback<F: FnMut(&mut Processor<NesBus>)>(&mut self, callback: F) -> Frame {
+        self.movie_frame();
+
+        let input = self.rewind.is_some().then(|| self.input());
+        if let (Some(rewind), Some(input)) = (self.rewind.as_mut(), input) {
+            rewind.push_input(self.frame_number + 1, input);
+        }
+
+        let mut frame = self.run_frame(callback);
+
+        if self.rewind.as_ref().is_some_and(|rewind| rewind.wants_snapshot(self.frame_number)) {
+            let state = self.save_state();
+            if let Some(rewind) = self.rewind.as_mut() { rewind.push_snapshot(self.frame_number, state); }
+        }
+
+        self.record(&frame.samples);
+
+        if let Some(mut run_ahead) = self.run_ahead.take() {
+            frame.pixels = self.run_ahead_pixels(&mut run_ahead);
+            self.run_ahead = Some(run_ahead);
+        }
+        frame
+    }
+
+    // runs a frame for nothing but what it does to the machine: no movie, rewind, WAV recording or
+    // run-ahead, and the sound's thrown away. for going back over frames that were already shown
+    pub fn step_frame_silently(&mut self) {
+        self.run_frame(|_| {});
+    }
+
+    fn run_frame<F: FnMut(&mut Processor<NesBus>)>(&mut self, mut callback: F) -> Frame {
+        self.cpu.bus.ppu.frame_complete = false;
+        while !self.cpu.bus.ppu.frame_complete {
+            callback(&mut self.cpu);
+            self.step_instruction();
+        }
+
+        self.frame_number += 1;
+
+        Frame {
+            number: self.frame_number,
+            pixels: self.framebuffer(),
+            samples: self.cpu.bus.apu.end_frame(),
+        }
+    }
+
+    fn record(&mut self, samples: &[f32]) {
+        if !self.is_recording() { return; }
+        let Some(recording) = self.recording.as_mut() else { return };
+
+        if let Err(error) = recording.recorder.write_samples(samples) {
+            recording.error = Some(error);
+        }
+        if let Some(frames_left) = recording.frames_left.as_mut() {
+            *frames_left -= 1;
+        }
+    }
+
+    pub fn framebuffer(&self) -> Vec<u8> {
+        self.pixels(self.cpu.bus.ppu.framebuffer())
+    }
+
+    fn pixels(&self, framebuffer: &[u16]) -> Vec<u8> {
+        let mut pixels = Vec::with_capacity(SCREEN_WIDTH * SCREEN_HEIGHT * 3);
+        for entry in framebuffer {
+            pixels.extend_from_slice(&self.palette.entry(*entry));
+        }
+        pixels
+    }
+}
+
+// FCEUX wants every movie to have one. nothing needs it to be more than unlikely to repeat
+fn new_guid() -> String {
+    let nanos = std::time::SystemTime::now()
+        .duration_since(std::time::UNIX_EPOCH)
+        .map_or(0, |time| time.as_nanos());
+    let hex: String = md5(&nanos.to_le_bytes()).iter().map(|byte| format!("{byte:02X}")).collect();
+    format!("{}-{}-{}-{}-{}", &hex[0..8], &hex[8..12], &hex[12..16], &hex[16..20], &hex[20..32])
+}
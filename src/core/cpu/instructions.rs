@@ -1,6 +1,8 @@
 use bit_struct::u1;
 use lazy_static::lazy_static;
+use crate::core::bus::Bus;
 use crate::core::cpu::processor::{AddressingMode, Processor};
+use crate::core::cpu::status_flags::CPUStatusFlags;
 use std::sync::Arc;
 
 #[derive(Debug, Copy, Clone, Eq, PartialEq)]
@@ -34,7 +36,7 @@ impl OpCode {
 
 lazy_static! {
     pub static ref CPU_OPCODES: Arc<[OpCode]> = Arc::from([
-        /* ADC -  Add with Carry */
+        /* ADC - Add with Carry */
         OpCode::new(0x69, ProcessorAction::ADC, 2, 2, AddressingMode::Immediate),
         OpCode::new(0x65, ProcessorAction::ADC, 2, 3, AddressingMode::ZeroPage),
         OpCode::new(0x75, ProcessorAction::ADC, 2, 4, AddressingMode::ZeroPage_X),
@@ -47,7 +49,7 @@ lazy_static! {
         /* AND - Logical AND */
         OpCode::new(0x29, ProcessorAction::AND, 2, 2, AddressingMode::Immediate),
         OpCode::new(0x25, ProcessorAction::AND, 2, 3, AddressingMode::ZeroPage),
-        OpCode::new(0x23, ProcessorAction::AND, 2, 4, AddressingMode::ZeroPage_X),
+        OpCode::new(0x35, ProcessorAction::AND, 2, 4, AddressingMode::ZeroPage_X),
         OpCode::new(0x2d, ProcessorAction::AND, 3, 4, AddressingMode::Absolute),
         OpCode::new(0x3d, ProcessorAction::AND, 3, 4, AddressingMode::Absolute_X),
         OpCode::new(0x39, ProcessorAction::AND, 3, 4, AddressingMode::Absolute_Y),
@@ -74,12 +76,97 @@ lazy_static! {
         OpCode::new(0x24, ProcessorAction::BIT, 2, 3, AddressingMode::ZeroPage),
         OpCode::new(0x2c, ProcessorAction::BIT, 3, 4, AddressingMode::Absolute),
 
+        /* BMI - Branch if Minus */
+        OpCode::new(0x30, ProcessorAction::BMI, 2, 2, AddressingMode::NoneAddressing),
+
+        /* BNE - Branch if Not Equal */
+        OpCode::new(0xd0, ProcessorAction::BNE, 2, 2, AddressingMode::NoneAddressing),
+
+        /* BPL - Branch if Positive */
+        OpCode::new(0x10, ProcessorAction::BPL, 2, 2, AddressingMode::NoneAddressing),
+
         /* BRK - Force Interrupt */
         OpCode::new(0x00, ProcessorAction::BRK, 1, 7, AddressingMode::NoneAddressing),
 
+        /* BVC - Branch if Overflow Clear */
+        OpCode::new(0x50, ProcessorAction::BVC, 2, 2, AddressingMode::NoneAddressing),
+
+        /* BVS - Branch if Overflow Set */
+        OpCode::new(0x70, ProcessorAction::BVS, 2, 2, AddressingMode::NoneAddressing),
+
+        /* CLC - Clear Carry Flag */
+        OpCode::new(0x18, ProcessorAction::CLC, 1, 2, AddressingMode::NoneAddressing),
+
+        /* CLD - Clear Decimal Mode */
+        OpCode::new(0xd8, ProcessorAction::CLD, 1, 2, AddressingMode::NoneAddressing),
+
+        /* CLI - Clear Interrupt Disable */
+        OpCode::new(0x58, ProcessorAction::CLI, 1, 2, AddressingMode::NoneAddressing),
+
+        /* CLV - Clear Overflow Flag */
+        OpCode::new(0xb8, ProcessorAction::CLV, 1, 2, AddressingMode::NoneAddressing),
+
+        /* CMP - Compare */
+        OpCode::new(0xc9, ProcessorAction::CMP, 2, 2, AddressingMode::Immediate),
+        OpCode::new(0xc5, ProcessorAction::CMP, 2, 3, AddressingMode::ZeroPage),
+        OpCode::new(0xd5, ProcessorAction::CMP, 2, 4, AddressingMode::ZeroPage_X),
+        OpCode::new(0xcd, ProcessorAction::CMP, 3, 4, AddressingMode::Absolute),
+        OpCode::new(0xdd, ProcessorAction::CMP, 3, 4, AddressingMode::Absolute_X),
+        OpCode::new(0xd9, ProcessorAction::CMP, 3, 4, AddressingMode::Absolute_Y),
+        OpCode::new(0xc1, ProcessorAction::CMP, 2, 6, AddressingMode::Indirect_X),
+        OpCode::new(0xd1, ProcessorAction::CMP, 2, 5, AddressingMode::Indirect_Y),
+
+        /* CPX - Compare X Register */
+        OpCode::new(0xe0, ProcessorAction::CPX, 2, 2, AddressingMode::Immediate),
+        OpCode::new(0xe4, ProcessorAction::CPX, 2, 3, AddressingMode::ZeroPage),
+        OpCode::new(0xec, ProcessorAction::CPX, 3, 4, AddressingMode::Absolute),
+
+        /* CPY - Compare Y Register */
+        OpCode::new(0xc0, ProcessorAction::CPY, 2, 2, AddressingMode::Immediate),
+        OpCode::new(0xc4, ProcessorAction::CPY, 2, 3, AddressingMode::ZeroPage),
+        OpCode::new(0xcc, ProcessorAction::CPY, 3, 4, AddressingMode::Absolute),
+
+        /* DEC - Decrement Memory */
+        OpCode::new(0xc6, ProcessorAction::DEC, 2, 5, AddressingMode::ZeroPage),
+        OpCode::new(0xd6, ProcessorAction::DEC, 2, 6, AddressingMode::ZeroPage_X),
+        OpCode::new(0xce, ProcessorAction::DEC, 3, 6, AddressingMode::Absolute),
+        OpCode::new(0xde, ProcessorAction::DEC, 3, 7, AddressingMode::Absolute_X),
+
+        /* DEX - Decrement X */
+        OpCode::new(0xca, ProcessorAction::DEX, 1, 2, AddressingMode::NoneAddressing),
+
+        /* DEY - Decrement Y */
+        OpCode::new(0x88, ProcessorAction::DEY, 1, 2, AddressingMode::NoneAddressing),
+
+        /* EOR - Exclusive OR */
+        OpCode::new(0x49, ProcessorAction::EOR, 2, 2, AddressingMode::Immediate),
+        OpCode::new(0x45, ProcessorAction::EOR, 2, 3, AddressingMode::ZeroPage),
+        OpCode::new(0x55, ProcessorAction::EOR, 2, 4, AddressingMode::ZeroPage_X),
+        OpCode::new(0x4d, ProcessorAction::EOR, 3, 4, AddressingMode::Absolute),
+        OpCode::new(0x5d, ProcessorAction::EOR, 3, 4, AddressingMode::Absolute_X),
+        OpCode::new(0x59, ProcessorAction::EOR, 3, 4, AddressingMode::Absolute_Y),
+        OpCode::new(0x41, ProcessorAction::EOR, 2, 6, AddressingMode::Indirect_X),
+        OpCode::new(0x51, ProcessorAction::EOR, 2, 5, AddressingMode::Indirect_Y),
+
+        /* INC - Increment Memory */
+        OpCode::new(0xe6, ProcessorAction::INC, 2, 5, AddressingMode::ZeroPage),
+        OpCode::new(0xf6, ProcessorAction::INC, 2, 6, AddressingMode::ZeroPage_X),
+        OpCode::new(0xee, ProcessorAction::INC, 3, 6, AddressingMode::Absolute),
+        OpCode::new(0xfe, ProcessorAction::INC, 3, 7, AddressingMode::Absolute_X),
+
         /* INX - Increment X */
         OpCode::new(0xe8, ProcessorAction::INX, 1, 2, AddressingMode::NoneAddressing),
 
+        /* INY - Increment Y */
+        OpCode::new(0xc8, ProcessorAction::INY, 1, 2, AddressingMode::NoneAddressing),
+
+        /* JMP - Jump */
+        OpCode::new(0x4c, ProcessorAction::JMP, 3, 3, AddressingMode::Absolute),
+        OpCode::new(0x6c, ProcessorAction::JMP, 3, 5, AddressingMode::Indirect),
+
+        /* JSR - Jump to Subroutine */
+        OpCode::new(0x20, ProcessorAction::JSR, 3, 6, AddressingMode::Absolute),
+
         /* LDA - Load Accumulator */
         OpCode::new(0xa9, ProcessorAction::LDA, 2, 2, AddressingMode::Immediate),
         OpCode::new(0xa5, ProcessorAction::LDA, 2, 3, AddressingMode::ZeroPage),
@@ -88,7 +175,73 @@ lazy_static! {
         OpCode::new(0xbd, ProcessorAction::LDA, 3, 4, AddressingMode::Absolute_X),
         OpCode::new(0xb9, ProcessorAction::LDA, 3, 4, AddressingMode::Absolute_Y),
         OpCode::new(0xa1, ProcessorAction::LDA, 2, 6, AddressingMode::Indirect_X),
-        OpCode::new(0xb1, ProcessorAction::LDA, 3, 4, AddressingMode::Indirect_Y),
+        OpCode::new(0xb1, ProcessorAction::LDA, 2, 5, AddressingMode::Indirect_Y),
+
+        /* LDX - Load X Register */
+        OpCode::new(0xa2, ProcessorAction::LDX, 2, 2, AddressingMode::Immediate),
+        OpCode::new(0xa6, ProcessorAction::LDX, 2, 3, AddressingMode::ZeroPage),
+        OpCode::new(0xb6, ProcessorAction::LDX, 2, 4, AddressingMode::ZeroPage_Y),
+        OpCode::new(0xae, ProcessorAction::LDX, 3, 4, AddressingMode::Absolute),
+        OpCode::new(0xbe, ProcessorAction::LDX, 3, 4, AddressingMode::Absolute_Y),
+
+        /* LDY - Load Y Register */
+        OpCode::new(0xa0, ProcessorAction::LDY, 2, 2, AddressingMode::Immediate),
+        OpCode::new(0xa4, ProcessorAction::LDY, 2, 3, AddressingMode::ZeroPage),
+        OpCode::new(0xb4, ProcessorAction::LDY, 2, 4, AddressingMode::ZeroPage_X),
+        OpCode::new(0xac, ProcessorAction::LDY, 3, 4, AddressingMode::Absolute),
+        OpCode::new(0xbc, ProcessorAction::LDY, 3, 4, AddressingMode::Absolute_X),
+
+        /* LSR - Logical Shift Right */
+        OpCode::new(0x4a, ProcessorAction::LSR, 1, 2, AddressingMode::NoneAddressing),
+        OpCode::new(0x46, ProcessorAction::LSR, 2, 5, AddressingMode::ZeroPage),
+        OpCode::new(0x56, ProcessorAction::LSR, 2, 6, AddressingMode::ZeroPage_X),
+        OpCode::new(0x4e, ProcessorAction::LSR, 3, 6, AddressingMode::Absolute),
+        OpCode::new(0x5e, ProcessorAction::LSR, 3, 7, AddressingMode::Absolute_X),
+
+        /* NOP - No Operation */
+        OpCode::new(0xea, ProcessorAction::NOP, 1, 2, AddressingMode::NoneAddressing),
+
+        /* ORA - Logical Inclusive OR */
+        OpCode::new(0x09, ProcessorAction::ORA, 2, 2, AddressingMode::Immediate),
+        OpCode::new(0x05, ProcessorAction::ORA, 2, 3, AddressingMode::ZeroPage),
+        OpCode::new(0x15, ProcessorAction::ORA, 2, 4, AddressingMode::ZeroPage_X),
+        OpCode::new(0x0d, ProcessorAction::ORA, 3, 4, AddressingMode::Absolute),
+        OpCode::new(0x1d, ProcessorAction::ORA, 3, 4, AddressingMode::Absolute_X),
+        OpCode::new(0x19, ProcessorAction::ORA, 3, 4, AddressingMode::Absolute_Y),
+        OpCode::new(0x01, ProcessorAction::ORA, 2, 6, AddressingMode::Indirect_X),
+        OpCode::new(0x11, ProcessorAction::ORA, 2, 5, AddressingMode::Indirect_Y),
+
+        /* PHA - Push Accumulator */
+        OpCode::new(0x48, ProcessorAction::PHA, 1, 3, AddressingMode::NoneAddressing),
+
+        /* PHP - Push Processor Status */
+        OpCode::new(0x08, ProcessorAction::PHP, 1, 3, AddressingMode::NoneAddressing),
+
+        /* PLA - Pull Accumulator */
+        OpCode::new(0x68, ProcessorAction::PLA, 1, 4, AddressingMode::NoneAddressing),
+
+        /* PLP - Pull Processor Status */
+        OpCode::new(0x28, ProcessorAction::PLP, 1, 4, AddressingMode::NoneAddressing),
+
+        /* ROL - Rotate Left */
+        OpCode::new(0x2a, ProcessorAction::ROL, 1, 2, AddressingMode::NoneAddressing),
+        OpCode::new(0x26, ProcessorAction::ROL, 2, 5, AddressingMode::ZeroPage),
+        OpCode::new(0x36, ProcessorAction::ROL, 2, 6, AddressingMode::ZeroPage_X),
+        OpCode::new(0x2e, ProcessorAction::ROL, 3, 6, AddressingMode::Absolute),
+        OpCode::new(0x3e, ProcessorAction::ROL, 3, 7, AddressingMode::Absolute_X),
+
+        /* ROR - Rotate Right */
+        OpCode::new(0x6a, ProcessorAction::ROR, 1, 2, AddressingMode::NoneAddressing),
+        OpCode::new(0x66, ProcessorAction::ROR, 2, 5, AddressingMode::ZeroPage),
+        OpCode::new(0x76, ProcessorAction::ROR, 2, 6, AddressingMode::ZeroPage_X),
+        OpCode::new(0x6e, ProcessorAction::ROR, 3, 6, AddressingMode::Absolute),
+        OpCode::new(0x7e, ProcessorAction::ROR, 3, 7, AddressingMode::Absolute_X),
+
+        /* RTI - Return from Interrupt */
+        OpCode::new(0x40, ProcessorAction::RTI, 1, 6, AddressingMode::NoneAddressing),
+
+        /* RTS - Return from Subroutine */
+        OpCode::new(0x60, ProcessorAction::RTS, 1, 6, AddressingMode::NoneAddressing),
 
         /* SBC - Subtract with Carry */
         OpCode::new(0xe9, ProcessorAction::SBC, 2, 2, AddressingMode::Immediate),
@@ -100,6 +253,15 @@ lazy_static! {
         OpCode::new(0xe1, ProcessorAction::SBC, 2, 6, AddressingMode::Indirect_X),
         OpCode::new(0xf1, ProcessorAction::SBC, 2, 5, AddressingMode::Indirect_Y),
 
+        /* SEC - Set Carry Flag */
+        OpCode::new(0x38, ProcessorAction::SEC, 1, 2, AddressingMode::NoneAddressing),
+
+        /* SED - Set Decimal Flag */
+        OpCode::new(0xf8, ProcessorAction::SED, 1, 2, AddressingMode::NoneAddressing),
+
+        /* SEI - Set Interrupt Disable */
+        OpCode::new(0x78, ProcessorAction::SEI, 1, 2, AddressingMode::NoneAddressing),
+
         /* STA - Store Accumulator in Memory */
         OpCode::new(0x85, ProcessorAction::STA, 2, 3, AddressingMode::ZeroPage),
         OpCode::new(0x95, ProcessorAction::STA, 2, 4, AddressingMode::ZeroPage_X),
@@ -109,16 +271,49 @@ lazy_static! {
         OpCode::new(0x81, ProcessorAction::STA, 2, 6, AddressingMode::Indirect_X),
         OpCode::new(0x91, ProcessorAction::STA, 2, 6, AddressingMode::Indirect_Y),
 
+        /* STX - Store X Register */
+        OpCode::new(0x86, ProcessorAction::STX, 2, 3, AddressingMode::ZeroPage),
+        OpCode::new(0x96, ProcessorAction::STX, 2, 4, AddressingMode::ZeroPage_Y),
+        OpCode::new(0x8e, ProcessorAction::STX, 3, 4, AddressingMode::Absolute),
+
+        /* STY - Store Y Register */
+        OpCode::new(0x84, ProcessorAction::STY, 2, 3, AddressingMode::ZeroPage),
+        OpCode::new(0x94, ProcessorAction::STY, 2, 4, AddressingMode::ZeroPage_X),
+        OpCode::new(0x8c, ProcessorAction::STY, 3, 4, AddressingMode::Absolute),
+
         /* TAX - Transfer Accumulator to X */
         OpCode::new(0xaa, ProcessorAction::TAX, 1, 2, AddressingMode::NoneAddressing),
+
+        /* TAY - Transfer Accumulator to Y */
+        OpCode::new(0xa8, ProcessorAction::TAY, 1, 2, AddressingMode::NoneAddressing),
+
+        /* TSX - Transfer Stack Pointer to X */
+        OpCode::new(0xba, ProcessorAction::TSX, 1, 2, AddressingMode::NoneAddressing),
+
+        /* TXA - Transfer X to Accumulator */
+        OpCode::new(0x8a, ProcessorAction::TXA, 1, 2, AddressingMode::NoneAddressing),
+
+        /* TXS - Transfer X to Stack Pointer */
+        OpCode::new(0x9a, ProcessorAction::TXS, 1, 2, AddressingMode::NoneAddressing),
+
+        /* TYA - Transfer Y to Accumulator */
+        OpCode::new(0x98, ProcessorAction::TYA, 1, 2, AddressingMode::NoneAddressing),
     ]);
 }
 
-fn advance_program_counter(cpu: &mut Processor, opcode: &OpCode) {
-    cpu.program_counter += (opcode.bytes - 1) as u16;
+fn advance_program_counter<B: Bus>(cpu: &mut Processor<B>, opcode: &OpCode) {
+    cpu.program_counter = cpu.program_counter.wrapping_add(u16::from(opcode.bytes - 1));
 }
 
-fn register_a_add(cpu: &mut Processor, data: u8) {
+// for the read instructions, where indexing across a page costs an extra cycle
+fn read_operand<B: Bus>(cpu: &mut Processor<B>, opcode: &OpCode) -> u8 {
+    let (addr, page_crossed) = cpu.get_absolute_address(&opcode.mode, cpu.program_counter);
+    if page_crossed { cpu.cycles += 1; }
+
+    cpu.mem_read(addr)
+}
+
+fn register_a_add<B: Bus>(cpu: &mut Processor<B>, data: u8) {
     let sum = cpu.register_a as u16
         + data as u16
         + cpu.status.carry().get_raw() as u16;
@@ -133,110 +328,296 @@ fn register_a_add(cpu: &mut Processor, data: u8) {
     cpu.register_a = result;
 }
 
-pub fn adc(cpu: &mut Processor, opcode: &OpCode) {
-    let addr = cpu.get_operand_address(&opcode.mode);
-    let addend = cpu.mem_read(addr);
+fn compare<B: Bus>(cpu: &mut Processor<B>, opcode: &OpCode, register: u8) {
+    let operand = read_operand(cpu, opcode);
 
-    register_a_add(cpu, addend);
-    cpu.status.zero_negative_flags(cpu.register_a);
-
-    advance_program_counter(cpu, opcode);
-}
-
-pub fn and(cpu: &mut Processor, opcode: &OpCode) {
-    let addr = cpu.get_operand_address(&opcode.mode);
-    let operand = cpu.mem_read(addr);
+    if register >= operand { cpu.status.carry().set(u1!(1)); }
+    else { cpu.status.carry().set(u1!(0)); }
 
-    cpu.register_a &= operand;
-    cpu.status.zero_negative_flags(cpu.register_a);
+    cpu.status.zero_negative_flags(register.wrapping_sub(operand));
 
     advance_program_counter(cpu, opcode);
 }
 
-pub fn asl(cpu: &mut Processor, opcode: &OpCode) {
-    // check if we're just doing this on the accumulator
+// runs a read-modify-write op on either the accumulator or memory, returning the result
+fn modify<B: Bus, F: Fn(&mut Processor<B>, u8) -> u8>(cpu: &mut Processor<B>, opcode: &OpCode, op: F) -> u8 {
     if opcode.mode == AddressingMode::NoneAddressing {
-        asl_accumulator(cpu, opcode);
-        return;
+        let result = op(cpu, cpu.register_a);
+        cpu.register_a = result;
+        cpu.status.zero_negative_flags(result);
+        return result;
     }
 
     let addr = cpu.get_operand_address(&opcode.mode);
-    let mut value = cpu.mem_read(addr);
+    let value = cpu.mem_read(addr);
+    let result = op(cpu, value);
+    cpu.mem_write(addr, result);
+    cpu.status.zero_negative_flags(result);
+
+    advance_program_counter(cpu, opcode);
+    result
+}
 
-    if value >> 7 == 1 { cpu.status.carry().set(u1!(1)) }
-    else { cpu.status.carry().set(u1!(0)) }
+fn set_carry<B: Bus>(cpu: &mut Processor<B>, carry: bool) {
+    if carry { cpu.status.carry().set(u1!(1)); }
+    else { cpu.status.carry().set(u1!(0)); }
+}
 
-    value <<= 1;
-    cpu.mem_write(addr, value);
+pub fn adc<B: Bus>(cpu: &mut Processor<B>, opcode: &OpCode) {
+    let addend = read_operand(cpu, opcode);
 
-    cpu.status.zero_negative_flags(value);
+    register_a_add(cpu, addend);
+    cpu.status.zero_negative_flags(cpu.register_a);
 
     advance_program_counter(cpu, opcode);
 }
 
-fn asl_accumulator(cpu: &mut Processor, opcode: &OpCode) {
-    if cpu.register_a >> 7 == 1 { cpu.status.carry().set(u1!(1)) }
-    else { cpu.status.carry().set(u1!(0)) }
-
-    cpu.register_a <<= 1;
+pub fn and<B: Bus>(cpu: &mut Processor<B>, opcode: &OpCode) {
+    let operand = read_operand(cpu, opcode);
 
+    cpu.register_a &= operand;
     cpu.status.zero_negative_flags(cpu.register_a);
 
     advance_program_counter(cpu, opcode);
 }
 
-pub fn bcc(cpu: &mut Processor) {
+pub fn asl<B: Bus>(cpu: &mut Processor<B>, opcode: &OpCode) {
+    modify(cpu, opcode, |cpu, value| {
+        set_carry(cpu, value >> 7 == 1);
+        value << 1
+    });
+}
+
+pub fn bcc<B: Bus>(cpu: &mut Processor<B>) {
     let carry = cpu.status.carry().get_raw();
     cpu.branch(carry == 0);
 }
 
-pub fn bcs(cpu: &mut Processor) {
+pub fn bcs<B: Bus>(cpu: &mut Processor<B>) {
     let carry = cpu.status.carry().get_raw();
     cpu.branch(carry == 1);
 }
 
-pub fn beq(cpu: &mut Processor) {
+pub fn beq<B: Bus>(cpu: &mut Processor<B>) {
     let zero = cpu.status.zero().get_raw();
     cpu.branch(zero == 1);
 }
 
-pub fn bit(cpu: &mut Processor, opcode: &OpCode) {
+pub fn bit<B: Bus>(cpu: &mut Processor<B>, opcode: &OpCode) {
     let addr = cpu.get_operand_address(&opcode.mode);
     let operand = cpu.mem_read(addr);
 
-    let result = cpu.register_a & operand;
+    // only Z comes from the AND, N and V are bits 7 and 6 of memory, and A stays as it was
+    if cpu.register_a & operand == 0 { cpu.status.zero().set(u1!(1)); }
+    else { cpu.status.zero().set(u1!(0)); }
 
-    if result & 0b0100_0000 == 0b0100_0000 { cpu.status.overflow().set(u1!(1)); }
+    if operand & 0b0100_0000 == 0b0100_0000 { cpu.status.overflow().set(u1!(1)); }
     else { cpu.status.overflow().set(u1!(0)); }
 
-    if result & 0b1000_0000 == 0b1000_0000 { cpu.status.negative().set(u1!(1)); }
+    if operand & 0b1000_0000 == 0b1000_0000 { cpu.status.negative().set(u1!(1)); }
     else { cpu.status.negative().set(u1!(0)); }
 
-    cpu.register_a = result;
+    advance_program_counter(cpu, opcode);
+}
+
+pub fn bmi<B: Bus>(cpu: &mut Processor<B>) {
+    let negative = cpu.status.negative().get_raw();
+    cpu.branch(negative == 1);
+}
+
+pub fn bne<B: Bus>(cpu: &mut Processor<B>) {
+    let zero = cpu.status.zero().get_raw();
+    cpu.branch(zero == 0);
+}
+
+pub fn bpl<B: Bus>(cpu: &mut Processor<B>) {
+    let negative = cpu.status.negative().get_raw();
+    cpu.branch(negative == 0);
+}
+
+pub fn brk<B: Bus>(cpu: &mut Processor<B>) {
+    // BRK has a padding byte after it that gets skipped over on return
+    cpu.program_counter = cpu.program_counter.wrapping_add(1);
+    cpu.interrupt(0xfffe, true);
+}
+
+pub fn bvc<B: Bus>(cpu: &mut Processor<B>) {
+    let overflow = cpu.status.overflow().get_raw();
+    cpu.branch(overflow == 0);
+}
+
+pub fn bvs<B: Bus>(cpu: &mut Processor<B>) {
+    let overflow = cpu.status.overflow().get_raw();
+    cpu.branch(overflow == 1);
+}
+
+pub fn clc<B: Bus>(cpu: &mut Processor<B>) {
+    cpu.status.carry().set(u1!(0));
+}
+
+pub fn cld<B: Bus>(cpu: &mut Processor<B>) {
+    cpu.status.decimal().set(u1!(0));
+}
+
+pub fn cli<B: Bus>(cpu: &mut Processor<B>) {
+    cpu.status.interrupt_disable().set(u1!(0));
+}
+
+pub fn clv<B: Bus>(cpu: &mut Processor<B>) {
+    cpu.status.overflow().set(u1!(0));
+}
+
+pub fn cmp<B: Bus>(cpu: &mut Processor<B>, opcode: &OpCode) {
+    compare(cpu, opcode, cpu.register_a);
+}
+
+pub fn cpx<B: Bus>(cpu: &mut Processor<B>, opcode: &OpCode) {
+    compare(cpu, opcode, cpu.register_x);
+}
+
+pub fn cpy<B: Bus>(cpu: &mut Processor<B>, opcode: &OpCode) {
+    compare(cpu, opcode, cpu.register_y);
+}
+
+pub fn dec<B: Bus>(cpu: &mut Processor<B>, opcode: &OpCode) {
+    modify(cpu, opcode, |_, value| value.wrapping_sub(1));
+}
+
+pub fn dex<B: Bus>(cpu: &mut Processor<B>) {
+    cpu.register_x = cpu.register_x.wrapping_sub(1);
+    cpu.status.zero_negative_flags(cpu.register_x);
+}
+
+pub fn dey<B: Bus>(cpu: &mut Processor<B>) {
+    cpu.register_y = cpu.register_y.wrapping_sub(1);
+    cpu.status.zero_negative_flags(cpu.register_y);
+}
 
+pub fn eor<B: Bus>(cpu: &mut Processor<B>, opcode: &OpCode) {
+    let operand = read_operand(cpu, opcode);
+
+    cpu.register_a ^= operand;
     cpu.status.zero_negative_flags(cpu.register_a);
 
     advance_program_counter(cpu, opcode);
 }
 
-pub fn inx(cpu: &mut Processor) {
+pub fn inc<B: Bus>(cpu: &mut Processor<B>, opcode: &OpCode) {
+    modify(cpu, opcode, |_, value| value.wrapping_add(1));
+}
+
+pub fn inx<B: Bus>(cpu: &mut Processor<B>) {
     let result = cpu.register_x.overflowing_add(1);
     cpu.register_x = result.0;
     cpu.status.zero_negative_flags(cpu.register_x);
 }
 
-pub fn lda(cpu: &mut Processor, opcode: &OpCode) {
-    let addr = cpu.get_operand_address(&opcode.mode);
-    let value = cpu.mem_read(addr);
+pub fn iny<B: Bus>(cpu: &mut Processor<B>) {
+    cpu.register_y = cpu.register_y.wrapping_add(1);
+    cpu.status.zero_negative_flags(cpu.register_y);
+}
+
+pub fn jmp<B: Bus>(cpu: &mut Processor<B>, opcode: &OpCode) {
+    cpu.program_counter = cpu.get_operand_address(&opcode.mode);
+}
+
+pub fn jsr<B: Bus>(cpu: &mut Processor<B>, opcode: &OpCode) {
+    let target = cpu.get_operand_address(&opcode.mode);
+
+    // pushes the address of the last byte of the JSR, RTS adds the 1 back
+    cpu.stack_push_u16(cpu.program_counter.wrapping_add(1));
+    cpu.program_counter = target;
+}
+
+pub fn lda<B: Bus>(cpu: &mut Processor<B>, opcode: &OpCode) {
+    let value = read_operand(cpu, opcode);
     cpu.register_a = value;
     cpu.status.zero_negative_flags(cpu.register_a);
 
     advance_program_counter(cpu, opcode);
 }
 
-pub fn sbc(cpu: &mut Processor, opcode: &OpCode) {
-    let addr = cpu.get_operand_address(&opcode.mode);
-    let addend = cpu.mem_read(addr);
+pub fn ldx<B: Bus>(cpu: &mut Processor<B>, opcode: &OpCode) {
+    cpu.register_x = read_operand(cpu, opcode);
+    cpu.status.zero_negative_flags(cpu.register_x);
+
+    advance_program_counter(cpu, opcode);
+}
+
+pub fn ldy<B: Bus>(cpu: &mut Processor<B>, opcode: &OpCode) {
+    cpu.register_y = read_operand(cpu, opcode);
+    cpu.status.zero_negative_flags(cpu.register_y);
+
+    advance_program_counter(cpu, opcode);
+}
+
+pub fn lsr<B: Bus>(cpu: &mut Processor<B>, opcode: &OpCode) {
+    modify(cpu, opcode, |cpu, value| {
+        set_carry(cpu, value & 1 == 1);
+        value >> 1
+    });
+}
+
+pub fn nop<B: Bus>(cpu: &mut Processor<B>, opcode: &OpCode) {
+    advance_program_counter(cpu, opcode);
+}
+
+pub fn ora<B: Bus>(cpu: &mut Processor<B>, opcode: &OpCode) {
+    let operand = read_operand(cpu, opcode);
+
+    cpu.register_a |= operand;
+    cpu.status.zero_negative_flags(cpu.register_a);
+
+    advance_program_counter(cpu, opcode);
+}
+
+pub fn pha<B: Bus>(cpu: &mut Processor<B>) {
+    cpu.stack_push(cpu.register_a);
+}
+
+pub fn php<B: Bus>(cpu: &mut Processor<B>) {
+    // B and the unused bit always read back as set when pushed by PHP
+    cpu.stack_push(cpu.status.raw() | 0b0011_0000);
+}
+
+pub fn pla<B: Bus>(cpu: &mut Processor<B>) {
+    cpu.register_a = cpu.stack_pop();
+    cpu.status.zero_negative_flags(cpu.register_a);
+}
+
+pub fn plp<B: Bus>(cpu: &mut Processor<B>) {
+    let flags = cpu.stack_pop();
+    cpu.status = CPUStatusFlags::from_stack(flags);
+}
+
+pub fn rol<B: Bus>(cpu: &mut Processor<B>, opcode: &OpCode) {
+    modify(cpu, opcode, |cpu, value| {
+        let carry_in = cpu.status.carry().get_raw();
+        set_carry(cpu, value >> 7 == 1);
+        (value << 1) | carry_in
+    });
+}
+
+pub fn ror<B: Bus>(cpu: &mut Processor<B>, opcode: &OpCode) {
+    modify(cpu, opcode, |cpu, value| {
+        let carry_in = cpu.status.carry().get_raw();
+        set_carry(cpu, value & 1 == 1);
+        (value >> 1) | (carry_in << 7)
+    });
+}
+
+pub fn rti<B: Bus>(cpu: &mut Processor<B>) {
+    let flags = cpu.stack_pop();
+    cpu.status = CPUStatusFlags::from_stack(flags);
+    cpu.program_counter = cpu.stack_pop_u16();
+}
+
+pub fn rts<B: Bus>(cpu: &mut Processor<B>) {
+    cpu.program_counter = cpu.stack_pop_u16().wrapping_add(1);
+}
+
+pub fn sbc<B: Bus>(cpu: &mut Processor<B>, opcode: &OpCode) {
+    let addend = read_operand(cpu, opcode);
                                                     /* handle NOT-ing the carry flag here */
     register_a_add(cpu, ((addend as i8).wrapping_neg().wrapping_sub(1)) as u8);
     cpu.status.zero_negative_flags(cpu.register_a);
@@ -244,14 +625,64 @@ pub fn sbc(cpu: &mut Processor, opcode: &OpCode) {
     advance_program_counter(cpu, opcode);
 }
 
-pub fn sta(cpu: &mut Processor, opcode: &OpCode) {
+pub fn sec<B: Bus>(cpu: &mut Processor<B>) {
+    cpu.status.carry().set(u1!(1));
+}
+
+pub fn sed<B: Bus>(cpu: &mut Processor<B>) {
+    cpu.status.decimal().set(u1!(1));
+}
+
+pub fn sei<B: Bus>(cpu: &mut Processor<B>) {
+    cpu.status.interrupt_disable().set(u1!(1));
+}
+
+pub fn sta<B: Bus>(cpu: &mut Processor<B>, opcode: &OpCode) {
     let addr = cpu.get_operand_address(&opcode.mode);
     cpu.mem_write(addr, cpu.register_a);
 
-    cpu.program_counter += u16::from(opcode.bytes - 1);
+    advance_program_counter(cpu, opcode);
+}
+
+pub fn stx<B: Bus>(cpu: &mut Processor<B>, opcode: &OpCode) {
+    let addr = cpu.get_operand_address(&opcode.mode);
+    cpu.mem_write(addr, cpu.register_x);
+
+    advance_program_counter(cpu, opcode);
+}
+
+pub fn sty<B: Bus>(cpu: &mut Processor<B>, opcode: &OpCode) {
+    let addr = cpu.get_operand_address(&opcode.mode);
+    cpu.mem_write(addr, cpu.register_y);
+
+    advance_program_counter(cpu, opcode);
 }
 
-pub fn tax(cpu: &mut Processor) {
+pub fn tax<B: Bus>(cpu: &mut Processor<B>) {
     cpu.register_x = cpu.register_a;
     cpu.status.zero_negative_flags(cpu.register_x);
 }
+
+pub fn tay<B: Bus>(cpu: &mut Processor<B>) {
+    cpu.register_y = cpu.register_a;
+    cpu.status.zero_negative_flags(cpu.register_y);
+}
+
+pub fn tsx<B: Bus>(cpu: &mut Processor<B>) {
+    cpu.register_x = cpu.register_s;
+    cpu.status.zero_negative_flags(cpu.register_x);
+}
+
+pub fn txa<B: Bus>(cpu: &mut Processor<B>) {
+    cpu.register_a = cpu.register_x;
+    cpu.status.zero_negative_flags(cpu.register_a);
+}
+
+pub fn txs<B: Bus>(cpu: &mut Processor<B>) {
+    cpu.register_s = cpu.register_x;
+}
+
+pub fn tya<B: Bus>(cpu: &mut Processor<B>) {
+    cpu.register_a = cpu.register_y;
+    cpu.status.zero_negative_flags(cpu.register_a);
+}
@@ -1,3 +1,4 @@
 pub mod processor;
 pub mod status_flags;
 pub mod instructions;
+pub mod trace;
\ No newline at end of file
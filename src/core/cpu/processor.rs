@@ -1,6 +1,33 @@
+use bit_struct::*;
+use lazy_static::lazy_static;
+use crate::core::bus::{Bus, FlatMemory};
 use crate::core::cpu::status_flags::CPUStatusFlags;
 use crate::core::cpu::instructions;
 use crate::core::cpu::instructions::{CPU_OPCODES, OpCode, ProcessorAction::*};
+use crate::core::cpu::trace;
+use crate::core::region::Region;
+use crate::core::state::snapshot::Snapshot;
+use crate::core::state::stream::{StateError, StateReader, StateWriter};
+
+const STACK: u16 = 0x0100;
+const STACK_RESET: u8 = 0xfd;
+
+const NMI_VECTOR: u16 = 0xfffa;
+const RESET_VECTOR: u16 = 0xfffc;
+const IRQ_VECTOR: u16 = 0xfffe;
+
+lazy_static! {
+    static ref OPCODE_LOOKUP: [Option<OpCode>; 256] = {
+        let mut lookup = [None; 256];
+        for opcode in CPU_OPCODES.iter() {
+            lookup[opcode.hex as usize] = Some(*opcode);
+        }
+        lookup
+    };
+}
+
+// gets each trace_line as it happens
+type Tracer = Box<dyn FnMut(&str)>;
 
 #[derive(Debug, Copy, Clone, Eq, PartialEq)]
 #[allow(non_camel_case_types)]
@@ -12,12 +39,13 @@ pub enum AddressingMode {
     Absolute,
     Absolute_X,
     Absolute_Y,
+    Indirect,
     Indirect_X,
     Indirect_Y,
     NoneAddressing,
 }
 
-pub struct Processor {
+pub struct Processor<B: Bus = FlatMemory> {
     pub register_a: u8,
     pub register_x: u8,
     pub register_y: u8,
@@ -25,11 +53,45 @@ pub struct Processor {
     pub register_p: u8,
     pub status: CPUStatusFlags,
     pub program_counter: u16,
-    memory: [u8; 0xffff]
+    pub cycles: u64,
+    pub region: Region,
+    pub bus: B,
+    // handed a nestest-style line before every instruction, when set
+    tracer: Option<Tracer>,
+}
+
+impl Default for Processor {
+    fn default() -> Self {
+        Processor::new()
+    }
 }
 
 impl Processor {
     pub fn new() -> Self {
+        Processor::with_bus(FlatMemory::default())
+    }
+
+    pub fn with_region(region: Region) -> Self {
+        Processor {
+            region,
+            ..Processor::new()
+        }
+    }
+
+    fn load(&mut self, program: Vec<u8>) {
+        self.bus.load(0x8000, &program[..]);
+        self.mem_write_u16(RESET_VECTOR, 0x8000);
+    }
+
+    pub fn load_and_run(&mut self, program: Vec<u8>) {
+        self.load(program);
+        self.reset();
+        self.run();
+    }
+}
+
+impl<B: Bus> Processor<B> {
+    pub fn with_bus(bus: B) -> Self {
         Processor {
             register_a: 0,
             register_x: 0,
@@ -38,139 +100,302 @@ impl Processor {
             register_p: 0,
             status: CPUStatusFlags::default(),
             program_counter: 0,
-            memory: [0x0; 0xffff],
+            cycles: 0,
+            region: Region::default(),
+            bus,
+            tracer: None,
         }
     }
 
-    pub fn mem_read(&self, addr: u16) -> u8 {
-        self.memory[addr as usize]
+    // logs every instruction from here on, see trace_line
+    pub fn set_tracer<F: FnMut(&str) + 'static>(&mut self, tracer: F) {
+        self.tracer = Some(Box::new(tracer));
+    }
+
+    pub fn clear_tracer(&mut self) {
+        self.tracer = None;
+    }
+
+    // the instruction at PC as a nestest.log line, without touching anything
+    pub fn trace_line(&self) -> String {
+        trace::trace_line(self, OPCODE_LOOKUP[self.bus.peek(self.program_counter) as usize])
+    }
+
+    pub fn clock_rate(&self) -> f64 {
+        self.region.timing().cpu_clock_rate()
+    }
+
+    pub fn mem_read(&mut self, addr: u16) -> u8 {
+        self.bus.mem_read(addr)
     }
 
     pub fn mem_write(&mut self, addr: u16, data: u8) {
-        self.memory[addr as usize] = data;
+        self.bus.mem_write(addr, data);
     }
 
-    pub fn mem_read_u16(&self, pos: u16) -> u16 {
+    pub fn mem_read_u16(&mut self, pos: u16) -> u16 {
         let lo = self.mem_read(pos);
-        let hi = self.mem_read(pos + 1);
+        let hi = self.mem_read(pos.wrapping_add(1));
         u16::from_le_bytes([lo, hi])
     }
 
     pub fn mem_write_u16(&mut self, pos: u16, data: u16) {
         let bytes: [u8; 2] = data.to_le_bytes();
         self.mem_write(pos, bytes[0]);
-        self.mem_write(pos + 1, bytes[1]);
+        self.mem_write(pos.wrapping_add(1), bytes[1]);
     }
 
-    fn load(&mut self, program: Vec<u8>) {
-        self.memory[0x8000 .. (0x8000 + program.len())].copy_from_slice(&program[..]);
-        self.mem_write_u16(0xFFFC, 0x8000);
+    pub fn stack_push(&mut self, data: u8) {
+        self.mem_write(STACK + u16::from(self.register_s), data);
+        self.register_s = self.register_s.wrapping_sub(1);
     }
 
-    pub fn load_and_run(&mut self, program: Vec<u8>) {
-        self.load(program);
-        self.reset();
-        self.run();
+    pub fn stack_pop(&mut self) -> u8 {
+        self.register_s = self.register_s.wrapping_add(1);
+        self.mem_read(STACK + u16::from(self.register_s))
+    }
+
+    pub fn stack_push_u16(&mut self, data: u16) {
+        let bytes: [u8; 2] = data.to_le_bytes();
+        self.stack_push(bytes[1]);
+        self.stack_push(bytes[0]);
+    }
+
+    pub fn stack_pop_u16(&mut self) -> u16 {
+        let lo = self.stack_pop();
+        let hi = self.stack_pop();
+        u16::from_le_bytes([lo, hi])
     }
 
     pub fn reset(&mut self) {
         self.register_a = 0;
         self.register_x = 0;
         self.register_y = 0;
-        self.register_s = 0;
+        self.register_s = STACK_RESET;
         self.register_p = 0;
         self.status = CPUStatusFlags::default();
+        self.status.interrupt_disable().set(u1!(1));
 
-        self.program_counter = self.mem_read_u16(0xFFFC);
+        // the reset sequence takes 7 cycles before the first instruction
+        self.cycles = 7;
+
+        self.program_counter = self.mem_read_u16(RESET_VECTOR);
     }
 
     pub fn branch(&mut self, condition: bool) {
+        let jump = self.mem_read(self.program_counter) as i8;
+        self.program_counter = self.program_counter.wrapping_add(1);
+
         if !condition { return; }
 
-        let jump = self.mem_read(self.program_counter) as i8;
-        let jump_addr = self.program_counter
-            .wrapping_add(1)
-            .wrapping_add(jump as u16);
+        let jump_addr = self.program_counter.wrapping_add(jump as u16);
+
+        // taken branches cost one extra cycle, two if they land on another page
+        self.cycles += 1;
+        if jump_addr & 0xff00 != self.program_counter & 0xff00 { self.cycles += 1; }
 
         self.program_counter = jump_addr;
     }
 
+    // pushes PC and P, then jumps through the vector. B only ends up set in the pushed copy for BRK/PHP
+    pub fn interrupt(&mut self, vector: u16, b_flag: bool) {
+        self.stack_push_u16(self.program_counter);
+
+        let mut flags = self.status;
+        flags.dummy_flag().set(u1!(1));
+        if b_flag { flags.b_flag().set(u1!(1)); }
+        else { flags.b_flag().set(u1!(0)); }
+        self.stack_push(flags.raw());
+
+        self.status.interrupt_disable().set(u1!(1));
+        self.program_counter = self.mem_read_u16(vector);
+    }
+
     pub fn run(&mut self) {
+        self.run_with_callback(|_| {});
+    }
 
+    // runs until a BRK, calling back before every instruction
+    pub fn run_with_callback<F: FnMut(&mut Processor<B>)>(&mut self, mut callback: F) {
+        loop {
+            callback(self);
+            if self.step().action == BRK { return; }
+        }
+    }
 
+    // executes one instruction (plus any interrupt/stall it leaves behind) and returns it
+    pub fn step(&mut self) -> OpCode {
+        if self.tracer.is_some() {
+            let line = self.trace_line();
+            if let Some(tracer) = self.tracer.as_mut() { tracer(&line); }
+        }
+
+        let start_cycles = self.cycles;
+
+        let next_byte = self.mem_read(self.program_counter);
+        self.program_counter = self.program_counter.wrapping_add(1);
+        let opcode: OpCode = OPCODE_LOOKUP[next_byte as usize]
+            .unwrap_or_else(|| panic!("invalid opcode: {next_byte:#04x}"));
+        self.cycles += u64::from(opcode.cycles);
+
+        self.execute(&opcode);
+
+        self.bus.tick((self.cycles - start_cycles) as u16);
+        self.run_stalls();
+        self.poll_interrupts();
+
+        opcode
+    }
+
+    // DMA (OAM copies, DMC sample fetches) takes the bus off the CPU for a while.
+    // time keeps going while it waits, which can kick off more DMA
+    fn run_stalls(&mut self) {
         loop {
-            let next_byte = self.mem_read(self.program_counter);
-            self.program_counter += 1;
-            let mut cpu_opcodes_iter = CPU_OPCODES.iter();
-            let opcode: &OpCode = cpu_opcodes_iter.find(|oc| oc.hex == next_byte)
-                .unwrap_or_else(|| panic!("invalid opcode: {next_byte:#04x}"));
-
-            match &opcode.action {
-                ADC => instructions::adc(self, opcode),
-                AND => instructions::and(self, opcode),
-                ASL => instructions::asl(self, opcode),
-                BCC => instructions::bcc(self),
-                BCS => instructions::bcs(self),
-                BEQ => instructions::beq(self),
-                BIT => instructions::bit(self, opcode),
-                BRK => return,
-                INX => instructions::inx(self),
-                LDA => instructions::lda(self, opcode),
-                SBC => instructions::sbc(self, opcode),
-                STA => instructions::sta(self, opcode),
-                TAX => instructions::tax(self),
-
-                _ => todo!("{:#?} (hex {:#04x}) not yet implemented", opcode.action, opcode.hex)
-            }
+            let stall = self.bus.take_stall_cycles();
+            if stall == 0 { return; }
+
+            self.cycles += u64::from(stall);
+            self.bus.tick(stall);
+        }
+    }
+
+    fn poll_interrupts(&mut self) {
+        let irq = self.bus.poll_irq() && self.status.interrupt_disable().get_raw() == 0;
+
+        let vector = if self.bus.poll_nmi() { NMI_VECTOR }
+            else if irq { IRQ_VECTOR }
+            else { return; };
+
+        self.interrupt(vector, false);
+        self.cycles += 7;
+        self.bus.tick(7);
+        self.run_stalls();
+    }
+
+    fn execute(&mut self, opcode: &OpCode) {
+        match &opcode.action {
+            ADC => instructions::adc(self, opcode),
+            AND => instructions::and(self, opcode),
+            ASL => instructions::asl(self, opcode),
+            BCC => instructions::bcc(self),
+            BCS => instructions::bcs(self),
+            BEQ => instructions::beq(self),
+            BIT => instructions::bit(self, opcode),
+            BMI => instructions::bmi(self),
+            BNE => instructions::bne(self),
+            BPL => instructions::bpl(self),
+            BRK => instructions::brk(self),
+            BVC => instructions::bvc(self),
+            BVS => instructions::bvs(self),
+            CLC => instructions::clc(self),
+            CLD => instructions::cld(self),
+            CLI => instructions::cli(self),
+            CLV => instructions::clv(self),
+            CMP => instructions::cmp(self, opcode),
+            CPX => instructions::cpx(self, opcode),
+            CPY => instructions::cpy(self, opcode),
+            DEC => instructions::dec(self, opcode),
+            DEX => instructions::dex(self),
+            DEY => instructions::dey(self),
+            EOR => instructions::eor(self, opcode),
+            INC => instructions::inc(self, opcode),
+            INX => instructions::inx(self),
+            INY => instructions::iny(self),
+            JMP => instructions::jmp(self, opcode),
+            JSR => instructions::jsr(self, opcode),
+            LDA => instructions::lda(self, opcode),
+            LDX => instructions::ldx(self, opcode),
+            LDY => instructions::ldy(self, opcode),
+            LSR => instructions::lsr(self, opcode),
+            NOP => instructions::nop(self, opcode),
+            ORA => instructions::ora(self, opcode),
+            PHA => instructions::pha(self),
+            PHP => instructions::php(self),
+            PLA => instructions::pla(self),
+            PLP => instructions::plp(self),
+            ROL => instructions::rol(self, opcode),
+            ROR => instructions::ror(self, opcode),
+            RTI => instructions::rti(self),
+            RTS => instructions::rts(self),
+            SBC => instructions::sbc(self, opcode),
+            SEC => instructions::sec(self),
+            SED => instructions::sed(self),
+            SEI => instructions::sei(self),
+            STA => instructions::sta(self, opcode),
+            STX => instructions::stx(self, opcode),
+            STY => instructions::sty(self, opcode),
+            TAX => instructions::tax(self),
+            TAY => instructions::tay(self),
+            TSX => instructions::tsx(self),
+            TXA => instructions::txa(self),
+            TXS => instructions::txs(self),
+            TYA => instructions::tya(self),
         }
     }
 
-    pub fn get_operand_address(&self, mode: &AddressingMode) -> u16 {
+    pub fn get_operand_address(&mut self, mode: &AddressingMode) -> u16 {
+        self.get_absolute_address(mode, self.program_counter).0
+    }
+
+    // works out the effective address for an operand sitting at `addr`, and whether indexing crossed a page
+    pub fn get_absolute_address(&mut self, mode: &AddressingMode, addr: u16) -> (u16, bool) {
         match mode {
-            AddressingMode::Immediate => self.program_counter,
+            AddressingMode::Immediate => (addr, false),
 
-            AddressingMode::ZeroPage => u16::from(self.mem_read(self.program_counter)),
+            AddressingMode::ZeroPage => (u16::from(self.mem_read(addr)), false),
 
-            AddressingMode::Absolute => self.mem_read_u16(self.program_counter),
+            AddressingMode::Absolute => (self.mem_read_u16(addr), false),
 
             AddressingMode::ZeroPage_X => {
-                let pos = self.mem_read(self.program_counter);
-                
-                u16::from(pos.wrapping_add(self.register_x))
+                let pos = self.mem_read(addr);
+
+                (u16::from(pos.wrapping_add(self.register_x)), false)
             }
             AddressingMode::ZeroPage_Y => {
-                let pos = self.mem_read(self.program_counter);
-                
-                u16::from(pos.wrapping_add(self.register_y))
+                let pos = self.mem_read(addr);
+
+                (u16::from(pos.wrapping_add(self.register_y)), false)
             }
 
             AddressingMode::Absolute_X => {
-                let base = self.mem_read_u16(self.program_counter);
-                
-                base.wrapping_add(u16::from(self.register_x))
+                let base = self.mem_read_u16(addr);
+                let result = base.wrapping_add(u16::from(self.register_x));
+
+                (result, page_crossed(base, result))
             }
             AddressingMode::Absolute_Y => {
-                let base = self.mem_read_u16(self.program_counter);
-                
-                base.wrapping_add(u16::from(self.register_y))
+                let base = self.mem_read_u16(addr);
+                let result = base.wrapping_add(u16::from(self.register_y));
+
+                (result, page_crossed(base, result))
+            }
+
+            AddressingMode::Indirect => {
+                let ptr = self.mem_read_u16(addr);
+
+                // the pointer never carries into the high byte, JMP ($10FF) reads $10FF and $1000
+                let lo = self.mem_read(ptr);
+                let hi = self.mem_read((ptr & 0xff00) | (ptr.wrapping_add(1) & 0x00ff));
+                (u16::from_le_bytes([lo, hi]), false)
             }
 
             AddressingMode::Indirect_X => {
-                let base = self.mem_read(self.program_counter);
+                let base = self.mem_read(addr);
 
                 let ptr: u8 = (base).wrapping_add(self.register_x);
                 let lo = self.mem_read(u16::from(ptr));
                 let hi = self.mem_read(u16::from(ptr.wrapping_add(1)));
-                u16::from_le_bytes([lo, hi])
+                (u16::from_le_bytes([lo, hi]), false)
             }
             AddressingMode::Indirect_Y => {
-                let base = self.mem_read(self.program_counter);
+                let base = self.mem_read(addr);
 
                 let lo = self.mem_read(u16::from(base));
                 let hi = self.mem_read(u16::from((base).wrapping_add(1)));
                 let deref_base = u16::from_le_bytes([lo, hi]);
-                
-                deref_base.wrapping_add(u16::from(self.register_y))
+                let result = deref_base.wrapping_add(u16::from(self.register_y));
+
+                (result, page_crossed(deref_base, result))
             }
 
             AddressingMode::NoneAddressing => {
@@ -178,4 +403,36 @@ impl Processor {
             }
         }
     }
-}
\ No newline at end of file
+}
+
+fn page_crossed(base: u16, result: u16) -> bool {
+    base & 0xff00 != result & 0xff00
+}
+
+// the interrupt lines live with whoever drives them (the PPU, APU and mapper), so they come along
+// with the bus
+impl<B: Bus + Snapshot> Snapshot for Processor<B> {
+    fn save_state(&self, state: &mut StateWriter) {
+        state.write_u8(self.register_a);
+        state.write_u8(self.register_x);
+        state.write_u8(self.register_y);
+        state.write_u8(self.register_s);
+        state.write_u8(self.register_p);
+        state.write_u8(self.status.raw());
+        state.write_u16(self.program_counter);
+        state.write_u64(self.cycles);
+        self.bus.save_state(state);
+    }
+
+    fn load_state(&mut self, state: &mut StateReader) -> Result<(), StateError> {
+        self.register_a = state.read_u8()?;
+        self.register_x = state.read_u8()?;
+        self.register_y = state.read_u8()?;
+        self.register_s = state.read_u8()?;
+        self.register_p = state.read_u8()?;
+        self.status = CPUStatusFlags::exact_from(state.read_u8()?);
+        self.program_counter = state.read_u16()?;
+        self.cycles = state.read_u64()?;
+        self.bus.load_state(state)
+    }
+}
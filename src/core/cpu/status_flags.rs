@@ -36,4 +36,12 @@ impl CPUStatusFlags {
         if target & 0b1000_0000 != 0 { self.negative().set(u1!(1)); }
         else { self.negative().set(u1!(0)); }
     }
-}
+
+    // what PLP/RTI do with a byte off the stack, B doesn't really exist and bit 5 is always on
+    pub fn from_stack(data: u8) -> Self {
+        let mut flags = CPUStatusFlags::exact_from(data);
+        flags.b_flag().set(u1!(0));
+        flags.dummy_flag().set(u1!(1));
+        flags
+    }
+}
\ No newline at end of file
@@ -0,0 +1,87 @@
+use crate::core::bus::Bus;
+use crate::core::cpu::instructions::OpCode;
+use crate::core::cpu::instructions::ProcessorAction::*;
+use crate::core::cpu::processor::{AddressingMode, Processor};
+
+// the instruction about to run at PC, the way nestest.log has it:
+// C000  4C F5 C5  JMP $C5F5                       A:00 X:00 Y:00 P:24 SP:FD PPU:  0, 21 CYC:7
+// operands come with the address they work out to and what's there now. only peeks, so tracing
+// can't change what runs
+pub fn trace_line<B: Bus>(cpu: &Processor<B>, opcode: Option<OpCode>) -> String {
+    let pc = cpu.program_counter;
+    let bytes = opcode.map_or(1, |opcode| opcode.bytes);
+    let raw: Vec<String> = (0..u16::from(bytes))
+        .map(|i| format!("{:02X}", cpu.bus.peek(pc.wrapping_add(i))))
+        .collect();
+    let disassembly = opcode.map_or("???".to_string(), |opcode| disassemble(cpu, &opcode));
+
+    // B isn't really a flag and bit 5 always reads back set, so the log never shows either
+    let p = cpu.status.raw() & !0x10 | 0x20;
+    let ppu = cpu.bus.ppu_position()
+        .map_or(String::new(), |(scanline, dot)| format!(" PPU:{scanline:>3},{dot:>3}"));
+
+    format!(
+        "{:<48}A:{:02X} X:{:02X} Y:{:02X} P:{p:02X} SP:{:02X}{ppu} CYC:{}",
+        format!("{pc:04X}  {:<8}  {disassembly}", raw.join(" ")),
+        cpu.register_a, cpu.register_x, cpu.register_y, cpu.register_s, cpu.cycles
+    )
+}
+
+fn disassemble<B: Bus>(cpu: &Processor<B>, opcode: &OpCode) -> String {
+    let name = format!("{:?}", opcode.action);
+    let operand = cpu.program_counter.wrapping_add(1);
+    let byte = cpu.bus.peek(operand);
+    let word = peek_u16(cpu, operand);
+    let peek = |addr: u16| cpu.bus.peek(addr);
+
+    match opcode.mode {
+        AddressingMode::Immediate => format!("{name} #${byte:02X}"),
+        AddressingMode::ZeroPage => format!("{name} ${byte:02X} = {:02X}", peek(u16::from(byte))),
+        AddressingMode::ZeroPage_X | AddressingMode::ZeroPage_Y => {
+            let (index, register) = if opcode.mode == AddressingMode::ZeroPage_X { (cpu.register_x, 'X') }
+                else { (cpu.register_y, 'Y') };
+            let addr = byte.wrapping_add(index);
+            format!("{name} ${byte:02X},{register} @ {addr:02X} = {:02X}", peek(u16::from(addr)))
+        }
+        AddressingMode::Absolute if matches!(opcode.action, JMP | JSR) => format!("{name} ${word:04X}"),
+        AddressingMode::Absolute => format!("{name} ${word:04X} = {:02X}", peek(word)),
+        AddressingMode::Absolute_X | AddressingMode::Absolute_Y => {
+            let (index, register) = if opcode.mode == AddressingMode::Absolute_X { (cpu.register_x, 'X') }
+                else { (cpu.register_y, 'Y') };
+            let addr = word.wrapping_add(u16::from(index));
+            format!("{name} ${word:04X},{register} @ {addr:04X} = {:02X}", peek(addr))
+        }
+        AddressingMode::Indirect => {
+            // the same page wrap JMP ($xxFF) really does
+            let hi = peek((word & 0xff00) | (word.wrapping_add(1) & 0x00ff));
+            format!("{name} (${word:04X}) = {:04X}", u16::from_le_bytes([peek(word), hi]))
+        }
+        AddressingMode::Indirect_X => {
+            let pointer = byte.wrapping_add(cpu.register_x);
+            let addr = peek_zero_page_u16(cpu, pointer);
+            format!("{name} (${byte:02X},X) @ {pointer:02X} = {addr:04X} = {:02X}", peek(addr))
+        }
+        AddressingMode::Indirect_Y => {
+            let base = peek_zero_page_u16(cpu, byte);
+            let addr = base.wrapping_add(u16::from(cpu.register_y));
+            format!("{name} (${byte:02X}),Y = {base:04X} @ {addr:04X} = {:02X}", peek(addr))
+        }
+        AddressingMode::NoneAddressing => match opcode.action {
+            BCC | BCS | BEQ | BMI | BNE | BPL | BVC | BVS => {
+                let target = operand.wrapping_add(1).wrapping_add(byte as i8 as u16);
+                format!("{name} ${target:04X}")
+            }
+            ASL | LSR | ROL | ROR => format!("{name} A"),
+            _ => name,
+        },
+    }
+}
+
+fn peek_u16<B: Bus>(cpu: &Processor<B>, addr: u16) -> u16 {
+    u16::from_le_bytes([cpu.bus.peek(addr), cpu.bus.peek(addr.wrapping_add(1))])
+}
+
+// pointers in zero page wrap around within it
+fn peek_zero_page_u16<B: Bus>(cpu: &Processor<B>, pointer: u8) -> u16 {
+    u16::from_le_bytes([cpu.bus.peek(u16::from(pointer)), cpu.bus.peek(u16::from(pointer.wrapping_add(1)))])
+}
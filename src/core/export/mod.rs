@@ -0,0 +1,2 @@
+pub mod png;
+pub mod wav;
\ No newline at end of file
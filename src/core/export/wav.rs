@@ -0,0 +1,96 @@
+use std::fs::{self, File};
+use std::io::{self, BufWriter, Seek, SeekFrom, Write};
+use std::path::Path;
+
+const HEADER_LEN: u32 = 44;
+
+// mono 16-bit PCM, samples in -1.0..=1.0 get clamped on the way in
+pub fn encode_wav(sample_rate: u32, samples: &[f32]) -> Vec<u8> {
+    let data_len = samples.len() as u32 * 2;
+
+    let mut wav = Vec::with_capacity((HEADER_LEN + data_len) as usize);
+    wav.extend_from_slice(&wav_header(sample_rate, data_len));
+    for sample in samples {
+        wav.extend_from_slice(&sample_to_i16(*sample).to_le_bytes());
+    }
+
+    wav
+}
+
+pub fn write_wav<P: AsRef<Path>>(path: P, sample_rate: u32, samples: &[f32]) -> io::Result<()> {
+    fs::write(path, encode_wav(sample_rate, samples))
+}
+
+pub fn sample_to_i16(sample: f32) -> i16 {
+    (sample.clamp(-1.0, 1.0) * f32::from(i16::MAX)) as i16
+}
+
+fn wav_header(sample_rate: u32, data_len: u32) -> [u8; HEADER_LEN as usize] {
+    let mut header = Vec::with_capacity(HEADER_LEN as usize);
+    header.extend_from_slice(b"RIFF");
+    header.extend_from_slice(&(HEADER_LEN - 8 + data_len).to_le_bytes());
+    header.extend_from_slice(b"WAVE");
+
+    header.extend_from_slice(b"fmt ");
+    header.extend_from_slice(&16u32.to_le_bytes());
+    header.extend_from_slice(&1u16.to_le_bytes()); // PCM
+    header.extend_from_slice(&1u16.to_le_bytes()); // channels
+    header.extend_from_slice(&sample_rate.to_le_bytes());
+    header.extend_from_slice(&(sample_rate * 2).to_le_bytes()); // byte rate
+    header.extend_from_slice(&2u16.to_le_bytes()); // block align
+    header.extend_from_slice(&16u16.to_le_bytes()); // bits per sample
+
+    header.extend_from_slice(b"data");
+    header.extend_from_slice(&data_len.to_le_bytes());
+
+    header.try_into().unwrap()
+}
+
+// streams samples out as they come instead of holding the whole recording in memory.
+// the header's lengths get filled in by finish(), until then the file claims to be empty
+pub struct WavRecorder<W: Write + Seek> {
+    writer: W,
+    sample_rate: u32,
+    data_len: u32,
+}
+
+impl WavRecorder<BufWriter<File>> {
+    pub fn create<P: AsRef<Path>>(path: P, sample_rate: u32) -> io::Result<Self> {
+        WavRecorder::new(BufWriter::new(File::create(path)?), sample_rate)
+    }
+}
+
+impl<W: Write + Seek> WavRecorder<W> {
+    pub fn new(mut writer: W, sample_rate: u32) -> io::Result<Self> {
+        writer.write_all(&wav_header(sample_rate, 0))?;
+        Ok(WavRecorder { writer, sample_rate, data_len: 0 })
+    }
+
+    pub fn sample_rate(&self) -> u32 {
+        self.sample_rate
+    }
+
+    pub fn samples_written(&self) -> u32 {
+        self.data_len / 2
+    }
+
+    pub fn write_samples(&mut self, samples: &[f32]) -> io::Result<()> {
+        let mut bytes = Vec::with_capacity(samples.len() * 2);
+        for sample in samples {
+            bytes.extend_from_slice(&sample_to_i16(*sample).to_le_bytes());
+        }
+
+        self.writer.write_all(&bytes)?;
+        self.data_len += bytes.len() as u32;
+        Ok(())
+    }
+
+    // patches up the header and hands the writer back
+    pub fn finish(mut self) -> io::Result<W> {
+        self.writer.seek(SeekFrom::Start(0))?;
+        self.writer.write_all(&wav_header(self.sample_rate, self.data_len))?;
+        self.writer.seek(SeekFrom::End(0))?;
+        self.writer.flush()?;
+        Ok(self.writer)
+    }
+}
@@ -0,0 +1,61 @@
+use crate::core::input::input_device::InputDevice;
+use crate::core::ppu::picture_processor::PictureProcessor;
+
+// roughly how far the knob turns on a real controller, left to right
+pub const VAUS_MIN_POSITION: u8 = 0x62;
+pub const VAUS_MAX_POSITION: u8 = 0xf2;
+
+// the Arkanoid paddle. the strobe latches the knob's position, which then comes out MSB first and
+// inverted. the NES one has it on bit 4 and the button on bit 3 of its port, the Famicom one
+// plugs into the expansion port with the button on bit 1 of $4016 and the knob on bit 1 of $4017
+#[derive(Debug, Clone)]
+pub struct ArkanoidVaus {
+    pub position: u8,
+    pub button: bool,
+    strobe: bool,
+    shift_register: u8,
+}
+
+impl Default for ArkanoidVaus {
+    fn default() -> Self {
+        ArkanoidVaus {
+            position: VAUS_MIN_POSITION + (VAUS_MAX_POSITION - VAUS_MIN_POSITION) / 2,
+            button: false,
+            strobe: false,
+            shift_register: 0,
+        }
+    }
+}
+
+impl ArkanoidVaus {
+    pub fn new() -> Self {
+        ArkanoidVaus::default()
+    }
+
+    fn knob_bit(&mut self) -> u8 {
+        if self.strobe { return !self.position >> 7; }
+
+        // zeroes shift in behind the last bit
+        let bit = self.shift_register >> 7;
+        self.shift_register <<= 1;
+        bit
+    }
+}
+
+impl InputDevice for ArkanoidVaus {
+    fn write(&mut self, data: u8) {
+        self.strobe = data & 1 != 0;
+        if self.strobe { self.shift_register = !self.position; }
+    }
+
+    fn read(&mut self, _ppu: &PictureProcessor) -> u8 {
+        self.knob_bit() << 4 | u8::from(self.button) << 3
+    }
+
+    fn read_expansion(&mut self, register: usize, _ppu: &PictureProcessor) -> u8 {
+        match register {
+            0 => u8::from(self.button) << 1,
+            _ => self.knob_bit() << 1,
+        }
+    }
+}
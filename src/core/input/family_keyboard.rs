@@ -0,0 +1,100 @@
+use crate::core::input::input_device::InputDevice;
+use crate::core::ppu::picture_processor::PictureProcessor;
+
+#[derive(Debug, Copy, Clone, Eq, PartialEq)]
+pub enum Key {
+    F1, F2, F3, F4, F5, F6, F7, F8,
+    Num1, Num2, Num3, Num4, Num5, Num6, Num7, Num8, Num9, Num0,
+    Minus, Caret, Yen, Stop,
+    Escape, Q, W, E, R, T, Y, U, I, O, P, At, LeftBracket, Return,
+    Control, A, S, D, F, G, H, J, K, L, Semicolon, Colon, RightBracket, Kana,
+    LeftShift, Z, X, C, V, B, N, M, Comma, Period, Slash, Underscore, RightShift,
+    Graph, Space,
+    ClearHome, Insert, Delete, Up, Down, Left, Right,
+}
+
+// nine rows of two four-key columns, the keys in the order they land on bits 1-4 of $4017
+const MATRIX: [[[Key; 4]; 2]; 9] = {
+    use Key::*;
+    [
+        [[RightBracket, LeftBracket, Return, F8], [Stop, Yen, RightShift, Kana]],
+        [[Semicolon, Colon, At, F7], [Caret, Minus, Slash, Underscore]],
+        [[K, L, O, F6], [Num0, P, Comma, Period]],
+        [[J, U, I, F5], [Num8, Num9, N, M]],
+        [[H, G, Y, F4], [Num6, Num7, V, B]],
+        [[D, R, T, F3], [Num4, Num5, C, F]],
+        [[A, S, W, F2], [Num3, E, Z, X]],
+        [[Control, Q, Escape, F1], [Num2, Num1, Graph, LeftShift]],
+        [[Left, Right, Up, ClearHome], [Insert, Delete, Space, Down]],
+    ]
+};
+
+// the Family BASIC keyboard, on the expansion port. $4016 bit 2 turns it on, bit 0 goes back to
+// the first row and bit 1 picks the column, with each 1 to 0 on it moving down a row. the current
+// half-row reads back on bits 1-4 of $4017, 0 for a key that's held
+#[derive(Debug, Clone, Default)]
+pub struct FamilyKeyboard {
+    pressed: [[u8; 2]; 9],
+    enabled: bool,
+    row: usize,
+    column: usize,
+}
+
+impl FamilyKeyboard {
+    pub fn new() -> Self {
+        FamilyKeyboard::default()
+    }
+
+    fn position(key: Key) -> (usize, usize, usize) {
+        for (row, columns) in MATRIX.iter().enumerate() {
+            for (column, keys) in columns.iter().enumerate() {
+                if let Some(bit) = keys.iter().position(|&k| k == key) {
+                    return (row, column, bit);
+                }
+            }
+        }
+        unreachable!("every key is in the matrix")
+    }
+
+    pub fn set_key(&mut self, key: Key, pressed: bool) {
+        let (row, column, bit) = FamilyKeyboard::position(key);
+        if pressed {
+            self.pressed[row][column] |= 1 << bit;
+        } else {
+            self.pressed[row][column] &= !(1 << bit);
+        }
+    }
+
+    pub fn is_pressed(&self, key: Key) -> bool {
+        let (row, column, bit) = FamilyKeyboard::position(key);
+        self.pressed[row][column] & 1 << bit != 0
+    }
+
+    pub fn release_all(&mut self) {
+        self.pressed = Default::default();
+    }
+}
+
+impl InputDevice for FamilyKeyboard {
+    fn write(&mut self, data: u8) {
+        self.enabled = data & 0b100 != 0;
+
+        let column = usize::from(data >> 1 & 1);
+        if self.column == 1 && column == 0 {
+            // past the last row there's nothing held
+            self.row = (self.row + 1).min(MATRIX.len());
+        }
+        self.column = column;
+
+        if data & 1 != 0 { self.row = 0; }
+    }
+
+    fn read(&mut self, _ppu: &PictureProcessor) -> u8 { 0 }
+
+    fn read_expansion(&mut self, register: usize, _ppu: &PictureProcessor) -> u8 {
+        if register == 0 || !self.enabled { return 0; }
+
+        let pressed = self.pressed.get(self.row).map_or(0, |row| row[self.column]);
+        !pressed << 1 & 0x1e
+    }
+}
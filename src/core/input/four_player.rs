@@ -0,0 +1,83 @@
+use crate::core::input::input_device::InputDevice;
+use crate::core::input::joypad::{Buttons, Joypad};
+use crate::core::ppu::picture_processor::PictureProcessor;
+
+#[derive(Debug, Copy, Clone, Eq, PartialEq)]
+pub enum FourPlayerMode {
+    // NES Four Score: the second pad's bits follow the first's on bit 0, then a signature byte
+    FourScore,
+    // the usual Famicom wiring, players 3 and 4 come in on bit 1 through the expansion port
+    Famicom,
+}
+
+// one port's worth of a four-player adapter: players 1 and 3 on $4016, 2 and 4 on $4017
+#[derive(Debug, Clone)]
+pub struct FourPlayerAdapter {
+    mode: FourPlayerMode,
+    pads: [Joypad; 2],
+    signature: u8,
+    strobe: bool,
+    shift_register: u32,
+    reads: u8,
+}
+
+impl FourPlayerAdapter {
+    // `port` 0 is $4016, 1 is $4017
+    pub fn new(mode: FourPlayerMode, port: usize) -> Self {
+        FourPlayerAdapter {
+            mode,
+            pads: Default::default(),
+            // read out LSB first, that's the 20th bit on $4016 and the 19th on $4017
+            signature: if port == 0 { 0x08 } else { 0x04 },
+            strobe: false,
+            shift_register: 0,
+            reads: 0,
+        }
+    }
+
+    pub fn mode(&self) -> FourPlayerMode {
+        self.mode
+    }
+
+    // `pad` 0 is player 1 or 2, 1 is player 3 or 4
+    pub fn set_buttons(&mut self, pad: usize, buttons: Buttons) {
+        self.pads[pad].set_buttons(buttons);
+        if self.strobe { self.reload(); }
+    }
+
+    pub fn buttons(&self, pad: usize) -> Buttons {
+        self.pads[pad].buttons()
+    }
+
+    fn reload(&mut self) {
+        self.shift_register = u32::from(self.pads[0].buttons().to_byte())
+            | u32::from(self.pads[1].buttons().to_byte()) << 8
+            | u32::from(self.signature) << 16;
+        self.reads = 0;
+    }
+}
+
+impl InputDevice for FourPlayerAdapter {
+    fn write(&mut self, data: u8) {
+        self.strobe = data & 1 != 0;
+        if self.strobe { self.reload(); }
+
+        for pad in self.pads.iter_mut() {
+            pad.write_strobe(data);
+        }
+    }
+
+    fn read(&mut self, _ppu: &PictureProcessor) -> u8 {
+        if self.mode == FourPlayerMode::Famicom {
+            return self.pads[0].read_bit() | self.pads[1].read_bit() << 1;
+        }
+
+        if self.strobe { return u8::from(self.pads[0].buttons().a); }
+        if self.reads >= 24 { return 1; }
+
+        let bit = (self.shift_register & 1) as u8;
+        self.shift_register >>= 1;
+        self.reads += 1;
+        bit
+    }
+}
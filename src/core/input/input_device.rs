@@ -0,0 +1,28 @@
+use std::any::Any;
+use crate::core::ppu::picture_processor::PictureProcessor;
+
+// anything that plugs into a controller port or the Famicom expansion port. reads only get to
+// drive the low five bits, the bus fills in the rest. Any lets the host get its own device back to
+// feed it input
+pub trait InputDevice: Any {
+    // every $4016 write. the ports only see the strobe in bit 0, the expansion port gets all three
+    fn write(&mut self, data: u8);
+
+    // a read of the port it's plugged into. the PPU's there for the light guns
+    fn read(&mut self, ppu: &PictureProcessor) -> u8;
+
+    // a read through the expansion port, `register` 0 for $4016 and 1 for $4017. it shares
+    // those with the ports, so the device has to stay off bit 0 of $4016 and $4017's
+    // is the second pad's. nothing there unless the device is wired for it
+    fn read_expansion(&mut self, _register: usize, _ppu: &PictureProcessor) -> u8 { 0 }
+}
+
+// an empty port, nothing pulls any of the lines low
+#[derive(Debug, Clone, Default)]
+pub struct Unplugged;
+
+impl InputDevice for Unplugged {
+    fn write(&mut self, _data: u8) {}
+
+    fn read(&mut self, _ppu: &PictureProcessor) -> u8 { 0 }
+}
@@ -0,0 +1,99 @@
+use crate::core::input::input_device::InputDevice;
+use crate::core::ppu::picture_processor::PictureProcessor;
+
+// what's held down on a standard controller, set by the host once a frame
+#[derive(Debug, Copy, Clone, Default, Eq, PartialEq)]
+pub struct Buttons {
+    pub a: bool,
+    pub b: bool,
+    pub select: bool,
+    pub start: bool,
+    pub up: bool,
+    pub down: bool,
+    pub left: bool,
+    pub right: bool,
+}
+
+impl Buttons {
+    // in the order the controller shifts them out, A in bit 0
+    pub fn to_byte(&self) -> u8 {
+        [self.a, self.b, self.select, self.start, self.up, self.down, self.left, self.right]
+            .iter()
+            .enumerate()
+            .fold(0, |byte, (bit, pressed)| byte | u8::from(*pressed) << bit)
+    }
+
+    pub fn from_byte(byte: u8) -> Self {
+        let pressed = |bit: u8| byte & (1 << bit) != 0;
+        Buttons {
+            a: pressed(0),
+            b: pressed(1),
+            select: pressed(2),
+            start: pressed(3),
+            up: pressed(4),
+            down: pressed(5),
+            left: pressed(6),
+            right: pressed(7),
+        }
+    }
+}
+
+// a standard controller: a 4021 shift register that gets loaded while the strobe's high
+#[derive(Debug, Clone, Default)]
+pub struct Joypad {
+    buttons: Buttons,
+    strobe: bool,
+    shift_register: u8,
+    // how many bits have been read out since the last reload
+    reads: u8,
+}
+
+impl Joypad {
+    pub fn set_buttons(&mut self, buttons: Buttons) {
+        self.buttons = buttons;
+        if self.strobe { self.reload(); }
+    }
+
+    pub fn buttons(&self) -> Buttons {
+        self.buttons
+    }
+
+    fn reload(&mut self) {
+        self.shift_register = self.buttons.to_byte();
+        self.reads = 0;
+    }
+
+    pub fn write_strobe(&mut self, data: u8) {
+        self.strobe = data & 1 != 0;
+        if self.strobe { self.reload(); }
+    }
+
+    pub fn read_bit(&mut self) -> u8 {
+        // while strobed it keeps reloading, so it's A over and over
+        if self.strobe { return u8::from(self.buttons.a); }
+
+        // an official controller shifts in 1s once the 8 buttons are out
+        if self.reads >= 8 { return 1; }
+
+        let bit = self.shift_register & 1;
+        self.shift_register >>= 1;
+        self.reads += 1;
+        bit
+    }
+}
+
+impl InputDevice for Joypad {
+    fn write(&mut self, data: u8) {
+        self.write_strobe(data);
+    }
+
+    // only bit 0 is driven
+    fn read(&mut self, _ppu: &PictureProcessor) -> u8 {
+        self.read_bit()
+    }
+
+    // a Famicom pad on the expansion port comes in as player 3, on bit 1 of $4016
+    fn read_expansion(&mut self, register: usize, _ppu: &PictureProcessor) -> u8 {
+        if register == 0 { self.read_bit() << 1 } else { 0 }
+    }
+}
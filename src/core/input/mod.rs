@@ -0,0 +1,8 @@
+pub mod arkanoid;
+pub mod family_keyboard;
+pub mod four_player;
+pub mod input_device;
+pub mod joypad;
+pub mod power_pad;
+pub mod snes_mouse;
+pub mod zapper;
\ No newline at end of file
@@ -0,0 +1,49 @@
+use crate::core::input::input_device::InputDevice;
+use crate::core::ppu::picture_processor::PictureProcessor;
+
+// which button goes out on each read, bit 3 gets all eight and bit 4 only has four
+const BIT_3_ORDER: [usize; 8] = [2, 1, 5, 9, 6, 10, 11, 7];
+const BIT_4_ORDER: [usize; 4] = [4, 3, 12, 8];
+
+// the Power Pad / Family Fun Fitness mat, two shift registers read out side by side
+#[derive(Debug, Clone, Default)]
+pub struct PowerPad {
+    // buttons 1-12 as printed on side B, index 0 being button 1
+    pub buttons: [bool; 12],
+    strobe: bool,
+    // a 1 is shifted in behind each, so the pad reads as all pressed once it runs out
+    bit_3: u16,
+    bit_4: u16,
+}
+
+impl PowerPad {
+    pub fn new() -> Self {
+        PowerPad::default()
+    }
+
+    fn reload(&mut self) {
+        let latch = |order: &[usize]| {
+            order.iter().enumerate().fold(0xffffu16 << order.len(), |bits, (i, &button)| {
+                bits | u16::from(self.buttons[button - 1]) << i
+            })
+        };
+        self.bit_3 = latch(&BIT_3_ORDER);
+        self.bit_4 = latch(&BIT_4_ORDER);
+    }
+}
+
+impl InputDevice for PowerPad {
+    fn write(&mut self, data: u8) {
+        self.strobe = data & 1 != 0;
+        if self.strobe { self.reload(); }
+    }
+
+    fn read(&mut self, _ppu: &PictureProcessor) -> u8 {
+        if self.strobe { self.reload(); }
+
+        let data = (self.bit_3 & 1) as u8 | ((self.bit_4 & 1) as u8) << 1;
+        self.bit_3 = self.bit_3 >> 1 | 0x8000;
+        self.bit_4 = self.bit_4 >> 1 | 0x8000;
+        data << 3
+    }
+}
@@ -0,0 +1,76 @@
+use crate::core::input::input_device::InputDevice;
+use crate::core::ppu::picture_processor::PictureProcessor;
+
+// the low nibble of the second byte, what software checks to tell it's a mouse
+const SIGNATURE: u32 = 0b0001;
+
+// a Super NES mouse on an NES port through an adapter. each strobe latches 32 bits, MSB first on
+// bit 0: a blank byte, the buttons with the sensitivity and signature, then the Y and X motion as
+// sign and magnitude
+#[derive(Debug, Clone, Default)]
+pub struct SnesMouse {
+    pub left: bool,
+    pub right: bool,
+    // motion since the last latch, down and right are positive
+    dx: i32,
+    dy: i32,
+    sensitivity: u8,
+    strobe: bool,
+    shift_register: u32,
+    reads: u8,
+}
+
+impl SnesMouse {
+    pub fn new() -> Self {
+        SnesMouse::default()
+    }
+
+    // adds up until the game next strobes
+    pub fn move_by(&mut self, dx: i32, dy: i32) {
+        self.dx += dx;
+        self.dy += dy;
+    }
+
+    // 0-2, cycled by clocking it while strobed
+    pub fn sensitivity(&self) -> u8 {
+        self.sensitivity
+    }
+
+    fn latch(&mut self) {
+        // anything past what fits in a report is dropped, like the real thing
+        let axis = |delta: i32| u32::from(delta < 0) << 7 | delta.unsigned_abs().min(0x7f);
+
+        self.shift_register = u32::from(self.right) << 23
+            | u32::from(self.left) << 22
+            | u32::from(self.sensitivity) << 20
+            | SIGNATURE << 16
+            | axis(self.dy) << 8
+            | axis(self.dx);
+        self.reads = 0;
+        self.dx = 0;
+        self.dy = 0;
+    }
+}
+
+impl InputDevice for SnesMouse {
+    fn write(&mut self, data: u8) {
+        let strobe = data & 1 != 0;
+        // the report's latched as the strobe drops, so the motion isn't lost while it's held
+        if self.strobe && !strobe { self.latch(); }
+        self.strobe = strobe;
+    }
+
+    fn read(&mut self, _ppu: &PictureProcessor) -> u8 {
+        if self.strobe {
+            self.sensitivity = (self.sensitivity + 1) % 3;
+            return 0;
+        }
+
+        if self.reads >= 32 { return 1; }
+
+        let bit = (self.shift_register >> 31) as u8;
+        self.shift_register <<= 1;
+        self.reads += 1;
+        bit
+    }
+}
@@ -0,0 +1,67 @@
+use crate::core::input::input_device::InputDevice;
+use crate::core::ppu::palette::Palette;
+use crate::core::ppu::picture_processor::{PictureProcessor, SCREEN_HEIGHT, SCREEN_WIDTH};
+
+// the photodiode only trips on something close to white
+const BRIGHTNESS_THRESHOLD: u8 = 0xc0;
+// and stays tripped for a while after the beam's gone past
+const LIGHT_SCANLINES: u16 = 20;
+// how far around the aim point the lens picks up light, in pixels
+const SENSE_RADIUS: usize = 2;
+
+// the NES Zapper, normally on port 2. bit 3 goes low while it sees light, bit 4 is the trigger
+pub struct Zapper {
+    pub trigger: bool,
+    // in screen pixels, None when it's pointed off screen
+    pub aim: Option<(usize, usize)>,
+    luminance: Vec<u8>,
+}
+
+impl Default for Zapper {
+    fn default() -> Self {
+        // brightness goes by the built-in palette, whatever the host ends up drawing with
+        let palette = Palette::default();
+        let luminance = (0..512u16)
+            .map(|entry| {
+                let [r, g, b] = palette.entry(entry);
+                ((u32::from(r) * 299 + u32::from(g) * 587 + u32::from(b) * 114) / 1000) as u8
+            })
+            .collect();
+
+        Zapper { trigger: false, aim: None, luminance }
+    }
+}
+
+impl Zapper {
+    pub fn new() -> Self {
+        Zapper::default()
+    }
+
+    // whether the beam's drawn something bright near the aim point recently enough
+    pub fn senses_light(&self, ppu: &PictureProcessor) -> bool {
+        let Some((x, y)) = self.aim else { return false };
+        if x >= SCREEN_WIDTH || y >= SCREEN_HEIGHT { return false; }
+
+        let scanline = ppu.scanline as usize;
+        let reached = scanline > y || (scanline == y && ppu.dot as usize > x);
+        if !reached || scanline > y + LIGHT_SCANLINES as usize { return false; }
+
+        let framebuffer = ppu.framebuffer();
+        let rows = y.saturating_sub(SENSE_RADIUS)..=(y + SENSE_RADIUS).min(SCREEN_HEIGHT - 1);
+        rows.into_iter().any(|row| {
+            let columns = x.saturating_sub(SENSE_RADIUS)..=(x + SENSE_RADIUS).min(SCREEN_WIDTH - 1);
+            columns.into_iter().any(|column| {
+                let entry = framebuffer[row * SCREEN_WIDTH + column];
+                self.luminance[entry as usize % self.luminance.len()] >= BRIGHTNESS_THRESHOLD
+            })
+        })
+    }
+}
+
+impl InputDevice for Zapper {
+    fn write(&mut self, _data: u8) {}
+
+    fn read(&mut self, ppu: &PictureProcessor) -> u8 {
+        u8::from(!self.senses_light(ppu)) << 3 | u8::from(self.trigger) << 4
+    }
+}
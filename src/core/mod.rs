@@ -1 +1,15 @@
-pub mod cpu;
\ No newline at end of file
+pub mod apu;
+pub mod bus;
+pub mod cartridge;
+pub mod checksum;
+pub mod console;
+pub mod cpu;
+pub mod export;
+pub mod input;
+pub mod movie;
+pub mod nes_bus;
+pub mod netplay;
+pub mod nsf;
+pub mod ppu;
+pub mod region;
+pub mod state;
\ No newline at end of file
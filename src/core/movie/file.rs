@@ -0,0 +1,291 @@
+use std::fmt;
+use std::fs;
+use std::path::Path;
+use crate::core::input::joypad::Buttons;
+use crate::core::region::Region;
+use crate::core::state::stream::StateError;
+
+const FM2_VERSION: u32 = 3;
+// what goes in emuVersion, FCEUX only shows it
+const EMU_VERSION: u32 = 22020;
+// FCEUX's numbers for what's in port0 and port1
+const PORT_NONE: u32 = 0;
+const PORT_GAMEPAD: u32 = 1;
+// how an FM2 spells a pad, bit 7 first
+const PAD_LETTERS: [u8; 8] = *b"RLDUTSBA";
+const BASE64: &[u8; 64] = b"ABCDEFGHIJKLMNOPQRSTUVWXYZabcdefghijklmnopqrstuvwxyz0123456789+/";
+
+// the command bits at the start of each input line
+pub const COMMAND_RESET: u8 = 1;
+pub const COMMAND_POWER: u8 = 2;
+
+#[derive(Debug)]
+pub enum MovieError {
+    Io(std::io::Error),
+    NotFm2,
+    // 1-based, like an editor would show it
+    Malformed { line: usize, reason: &'static str },
+    Unsupported(String),
+    // a movie without a save state has to start from a console that hasn't run a frame yet
+    NotAtPowerOn,
+    State(StateError),
+}
+
+impl fmt::Display for MovieError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            MovieError::Io(e) => write!(f, "couldn't read movie: {e}"),
+            MovieError::NotFm2 => write!(f, "not an FM2 movie"),
+            MovieError::Malformed { line, reason } => write!(f, "movie line {line}: {reason}"),
+            MovieError::Unsupported(what) => write!(f, "movie needs {what}, which isn't supported"),
+            MovieError::NotAtPowerOn => write!(f, "movie starts at power-on, but the console has already run"),
+            MovieError::State(e) => write!(f, "movie's save state: {e}"),
+        }
+    }
+}
+
+impl std::error::Error for MovieError {}
+
+impl From<std::io::Error> for MovieError {
+    fn from(e: std::io::Error) -> Self {
+        MovieError::Io(e)
+    }
+}
+
+impl From<StateError> for MovieError {
+    fn from(e: StateError) -> Self {
+        MovieError::State(e)
+    }
+}
+
+// one input line: any reset or power cycle before the frame runs, then what each player holds
+#[derive(Debug, Copy, Clone, Default, Eq, PartialEq)]
+pub struct MovieFrame {
+    pub commands: u8,
+    pub pads: [Buttons; 4],
+}
+
+impl MovieFrame {
+    pub fn reset(&self) -> bool { self.commands & COMMAND_RESET != 0 }
+    pub fn power(&self) -> bool { self.commands & COMMAND_POWER != 0 }
+}
+
+// an FCEUX-style input movie. only standard pads, with or without a Four Score, get recorded
+#[derive(Debug, Clone)]
+pub struct Movie {
+    pub rom_filename: String,
+    // FCEUX doesn't refuse to play on the wrong ROM, so nothing here does either
+    pub rom_md5: Option<[u8; 16]>,
+    pub guid: String,
+    pub region: Region,
+    pub rerecord_count: u32,
+    pub four_score: bool,
+    // whether there's a pad in each port, players 3 and 4 only exist with the Four Score
+    pub ports: [bool; 2],
+    pub comments: Vec<String>,
+    // where a movie that doesn't start at power-on starts from. this is one of our own save
+    // states rather than FCEUX's, so FCEUX can only play power-on movies from here
+    pub save_state: Option<Vec<u8>>,
+    pub frames: Vec<MovieFrame>,
+}
+
+impl Movie {
+    pub fn new(region: Region) -> Self {
+        Movie {
+            rom_filename: String::new(),
+            rom_md5: None,
+            guid: String::new(),
+            region,
+            rerecord_count: 0,
+            four_score: false,
+            ports: [true, true],
+            comments: Vec::new(),
+            save_state: None,
+            frames: Vec::new(),
+        }
+    }
+
+    pub fn len(&self) -> usize {
+        self.frames.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.frames.is_empty()
+    }
+
+    // whether `player` (0-3) has a pad in this movie
+    pub fn has_pad(&self, player: usize) -> bool {
+        if self.four_score { player < 4 } else { player < 2 && self.ports[player] }
+    }
+
+    pub fn load<P: AsRef<Path>>(path: P) -> Result<Movie, MovieError> {
+        let text = fs::read_to_string(path)?;
+        Movie::from_fm2(&text)
+    }
+
+    pub fn save<P: AsRef<Path>>(&self, path: P) -> Result<(), MovieError> {
+        fs::write(path, self.to_fm2())?;
+        Ok(())
+    }
+
+    pub fn from_fm2(text: &str) -> Result<Movie, MovieError> {
+        let mut lines = text.lines().enumerate().filter(|(_, line)| !line.trim().is_empty());
+        match lines.next() {
+            Some((_, line)) if line.starts_with("version ") => {}
+            _ => return Err(MovieError::NotFm2),
+        }
+
+        let mut movie = Movie::new(Region::Ntsc);
+        let mut version = None;
+
+        for (index, line) in text.lines().enumerate() {
+            let line = line.trim_end_matches('\r');
+            let malformed = |reason| MovieError::Malformed { line: index + 1, reason };
+
+            if line.starts_with('|') {
+                let frame = movie.parse_input(line).ok_or_else(|| malformed("bad input line"))?;
+                movie.frames.push(frame);
+                continue;
+            }
+            if line.trim().is_empty() { continue; }
+
+            let (key, value) = line.split_once(' ').unwrap_or((line, ""));
+            let number = || value.trim().parse::<u32>().map_err(|_| malformed("expected a number"));
+
+            match key {
+                "version" => version = Some(number()?),
+                "rerecordCount" => movie.rerecord_count = number()?,
+                "palFlag" => movie.region = if number()? != 0 { Region::Pal } else { Region::Ntsc },
+                "romFilename" => movie.rom_filename = value.to_string(),
+                "romChecksum" => {
+                    let digest = value.strip_prefix("base64:").and_then(base64_decode)
+                        .and_then(|bytes| <[u8; 16]>::try_from(bytes).ok())
+                        .ok_or_else(|| malformed("romChecksum isn't a base64 MD5"))?;
+                    movie.rom_md5 = Some(digest);
+                }
+                "guid" => movie.guid = value.to_string(),
+                "fourscore" => movie.four_score = number()? != 0,
+                "port0" | "port1" => {
+                    let port = usize::from(key == "port1");
+                    movie.ports[port] = match number()? {
+                        PORT_NONE => false,
+                        PORT_GAMEPAD => true,
+                        other => return Err(MovieError::Unsupported(format!("input device {other} in {key}"))),
+                    };
+                }
+                "port2" if number()? != 0 => return Err(MovieError::Unsupported("an expansion port device".into())),
+                "FDS" if number()? != 0 => return Err(MovieError::Unsupported("the Famicom Disk System".into())),
+                "binary" if number()? != 0 => return Err(MovieError::Unsupported("a binary input log".into())),
+                "comment" => movie.comments.push(value.to_string()),
+                "savestate" => {
+                    let state = value.strip_prefix("base64:").and_then(base64_decode)
+                        .ok_or_else(|| malformed("savestate isn't base64"))?;
+                    movie.save_state = Some(state);
+                }
+                // emuVersion, NewPPU, microphone, subtitle and the rest only matter to FCEUX
+                _ => {}
+            }
+        }
+
+        match version {
+            Some(FM2_VERSION) => Ok(movie),
+            Some(other) => Err(MovieError::Unsupported(format!("FM2 version {other}"))),
+            None => Err(MovieError::NotFm2),
+        }
+    }
+
+    // |commands|pad|pad|expansion|, or four pads in a row with the Four Score
+    fn parse_input(&self, line: &str) -> Option<MovieFrame> {
+        let fields: Vec<&str> = line.split('|').collect();
+        let pads = if self.four_score { 4 } else { 2 };
+        if fields.len() < pads + 3 { return None; }
+
+        let mut frame = MovieFrame { commands: fields[1].trim().parse().ok()?, ..MovieFrame::default() };
+        for (player, field) in fields[2..2 + pads].iter().enumerate() {
+            if field.is_empty() { continue; }
+            if field.len() != PAD_LETTERS.len() { return None; }
+
+            let byte = field.bytes().fold(0u8, |byte, letter| byte << 1 | u8::from(letter != b'.' && letter != b' '));
+            frame.pads[player] = Buttons::from_byte(byte);
+        }
+        Some(frame)
+    }
+
+    pub fn to_fm2(&self) -> String {
+        let mut out = String::new();
+        let mut line = |key: &str, value: &dyn fmt::Display| out.push_str(&format!("{key} {value}\n"));
+
+        line("version", &FM2_VERSION);
+        line("emuVersion", &EMU_VERSION);
+        line("rerecordCount", &self.rerecord_count);
+        line("palFlag", &u8::from(self.region == Region::Pal));
+        line("romFilename", &self.rom_filename);
+        if let Some(digest) = self.rom_md5 {
+            line("romChecksum", &format!("base64:{}", base64_encode(&digest)));
+        }
+        line("guid", &self.guid);
+        line("fourscore", &u8::from(self.four_score));
+        line("microphone", &0);
+        for (port, pad) in self.ports.iter().enumerate() {
+            let device = if *pad && !self.four_score { PORT_GAMEPAD } else { PORT_NONE };
+            line(&format!("port{port}"), &device);
+        }
+        line("port2", &0);
+        line("FDS", &0);
+        line("NewPPU", &0);
+        for comment in self.comments.iter() {
+            line("comment", comment);
+        }
+        if let Some(state) = &self.save_state {
+            line("savestate", &format!("base64:{}", base64_encode(state)));
+        }
+
+        for frame in self.frames.iter() {
+            out.push_str(&format!("|{}|", frame.commands));
+            let players = if self.four_score { 4 } else { 2 };
+            for (player, buttons) in frame.pads.iter().enumerate().take(players) {
+                if self.has_pad(player) {
+                    let byte = buttons.to_byte();
+                    out.extend(PAD_LETTERS.iter().enumerate().map(|(i, letter)| {
+                        if byte & (0x80 >> i) != 0 { char::from(*letter) } else { '.' }
+                    }));
+                }
+                out.push('|');
+            }
+            out.push_str("|\n");
+        }
+        out
+    }
+}
+
+fn base64_encode(data: &[u8]) -> String {
+    let mut out = String::with_capacity(data.len().div_ceil(3) * 4);
+    for chunk in data.chunks(3) {
+        let bits = chunk.iter().enumerate().fold(0u32, |bits, (i, byte)| bits | u32::from(*byte) << (16 - 8 * i));
+        for i in 0..4 {
+            if i <= chunk.len() {
+                out.push(char::from(BASE64[(bits >> (18 - 6 * i) & 0x3f) as usize]));
+            } else {
+                out.push('=');
+            }
+        }
+    }
+    out
+}
+
+fn base64_decode(text: &str) -> Option<Vec<u8>> {
+    let text = text.trim().trim_end_matches('=');
+    let mut out = Vec::with_capacity(text.len() * 3 / 4);
+    let (mut bits, mut count) = (0u32, 0);
+
+    for letter in text.bytes() {
+        let value = BASE64.iter().position(|&c| c == letter)? as u32;
+        bits = bits << 6 | value;
+        count += 6;
+        if count >= 8 {
+            count -= 8;
+            out.push((bits >> count) as u8);
+        }
+    }
+    Some(out)
+}
@@ -0,0 +1 @@
+pub mod file;
\ No newline at end of file
@@ -0,0 +1,186 @@
+use crate::core::apu::audio_processor::AudioProcessor;
+use crate::core::bus::Bus;
+use crate::core::cartridge::rom::Cartridge;
+use crate::core::input::input_device::{InputDevice, Unplugged};
+use crate::core::input::joypad::Joypad;
+use crate::core::ppu::picture_processor::PictureProcessor;
+use crate::core::region::{Region, RegionTiming};
+use crate::core::state::snapshot::Snapshot;
+use crate::core::state::stream::{StateError, StateReader, StateWriter};
+
+const RAM_MIRRORS_END: u16 = 0x1fff;
+const PPU_REGISTERS_MIRRORS_END: u16 = 0x3fff;
+
+// the CPU's view of the console: 2K of RAM, the PPU's registers, the APU and I/O block and the cartridge
+pub struct NesBus {
+    cpu_ram: [u8; 0x800],
+    pub ppu: PictureProcessor,
+    pub apu: AudioProcessor,
+    pub cartridge: Cartridge,
+    // $4016 and $4017, standard controllers unless the host plugs in something else
+    pub ports: [Box<dyn InputDevice>; 2],
+    // the Famicom's expansion port, wired into the same two registers
+    pub expansion: Box<dyn InputDevice>,
+    timing: &'static RegionTiming,
+    // last value that was on the data bus, what unmapped reads give back
+    open_bus: u8,
+    cycles: u64,
+    ppu_clock_remainder: u32,
+    stall_cycles: u16,
+}
+
+impl NesBus {
+    pub fn new(cartridge: Cartridge, region: Region) -> Self {
+        NesBus {
+            cpu_ram: [0; 0x800],
+            ppu: PictureProcessor::new(region),
+            apu: AudioProcessor::new(region),
+            cartridge,
+            ports: [Box::new(Joypad::default()), Box::new(Joypad::default())],
+            expansion: Box::new(Unplugged),
+            timing: region.timing(),
+            open_bus: 0,
+            cycles: 0,
+            ppu_clock_remainder: 0,
+            stall_cycles: 0,
+        }
+    }
+
+    pub fn cycles(&self) -> u64 {
+        self.cycles
+    }
+
+    fn oam_dma(&mut self, page: u8) {
+        let mut data = [0u8; 256];
+        let base = u16::from(page) << 8;
+        for (offset, byte) in data.iter_mut().enumerate() {
+            *byte = self.mem_read(base + offset as u16);
+        }
+        self.ppu.write_oam_dma(&data);
+
+        // 513 cycles, plus one more to line up if it started on an odd cycle
+        self.stall_cycles += 513 + (self.cycles & 1) as u16;
+    }
+}
+
+impl Bus for NesBus {
+    fn mem_read(&mut self, addr: u16) -> u8 {
+        let data = match addr {
+            0 ..= RAM_MIRRORS_END => Some(self.cpu_ram[(addr & 0x07ff) as usize]),
+            0x2000 ..= PPU_REGISTERS_MIRRORS_END => {
+                Some(self.ppu.read_register(addr, &mut *self.cartridge.mapper))
+            }
+            0x4015 => Some(self.apu.read_status()),
+            // the controllers only drive the low bits, the top three are whatever was last on the bus
+            0x4016 | 0x4017 => {
+                let register = addr as usize - 0x4016;
+                let data = self.ports[register].read(&self.ppu)
+                    | self.expansion.read_expansion(register, &self.ppu);
+                Some(self.open_bus & 0xe0 | data & 0x1f)
+            }
+            // the rest of the APU is write-only
+            0x4000 ..= 0x401f => None,
+            _ => self.cartridge.mapper.cpu_read(addr),
+        };
+
+        self.open_bus = data.unwrap_or(self.open_bus);
+        self.open_bus
+    }
+
+    fn mem_write(&mut self, addr: u16, data: u8) {
+        self.open_bus = data;
+
+        match addr {
+            0 ..= RAM_MIRRORS_END => self.cpu_ram[(addr & 0x07ff) as usize] = data,
+            0x2000 ..= PPU_REGISTERS_MIRRORS_END => {
+                self.ppu.write_register(addr, data, &mut *self.cartridge.mapper);
+            }
+            0x4014 => self.oam_dma(data),
+            0x4016 => {
+                for port in self.ports.iter_mut() {
+                    port.write(data);
+                }
+                self.expansion.write(data);
+            }
+            0x4000 ..= 0x4013 | 0x4015 | 0x4017 => self.apu.write_register(addr, data),
+            0x4000 ..= 0x401f => {}
+            _ => self.cartridge.mapper.cpu_write(addr, data),
+        }
+    }
+
+    fn peek(&self, addr: u16) -> u8 {
+        match addr {
+            0 ..= RAM_MIRRORS_END => self.cpu_ram[(addr & 0x07ff) as usize],
+            // every register in here either does something when it's read or can't be read at
+            // all, so like Nintendulator's logs they show as $FF
+            0x2000 ..= 0x401f => 0xff,
+            _ => self.cartridge.mapper.cpu_peek(addr).unwrap_or(self.open_bus),
+        }
+    }
+
+    fn ppu_position(&self) -> Option<(u16, u16)> {
+        Some((self.ppu.scanline, self.ppu.dot))
+    }
+
+    fn tick(&mut self, cycles: u16) {
+        self.cycles += u64::from(cycles);
+
+        // PAL runs 3.2 dots per CPU cycle, so carry the leftover between calls
+        let (cpu_divider, ppu_divider) = (self.timing.cpu_divider, self.timing.ppu_divider);
+        let clocks = u32::from(cycles) * cpu_divider + self.ppu_clock_remainder;
+        self.ppu_clock_remainder = clocks % ppu_divider;
+
+        self.ppu.tick(clocks / ppu_divider, &mut *self.cartridge.mapper);
+
+        // a cycle at a time, so any sound the cartridge makes lines up with the 2A03's.
+        // DMC samples always come out of $8000-$FFFF, so only the cartridge can answer
+        let mapper = &mut self.cartridge.mapper;
+        let open_bus = self.open_bus;
+        for _ in 0..cycles {
+            self.apu.tick(1, &mut |addr| mapper.cpu_read(addr).unwrap_or(open_bus));
+            mapper.tick(1);
+            self.apu.set_expansion_output(mapper.audio_output());
+        }
+        self.stall_cycles += self.apu.take_stall_cycles();
+    }
+
+    fn poll_nmi(&mut self) -> bool {
+        self.ppu.poll_nmi()
+    }
+
+    fn poll_irq(&self) -> bool {
+        self.cartridge.mapper.irq() || self.apu.irq()
+    }
+
+    fn take_stall_cycles(&mut self) -> u16 {
+        let stall = self.stall_cycles;
+        self.stall_cycles = 0;
+        stall
+    }
+}
+
+// the controllers and whatever's on the expansion port belong to the host, they're left alone
+impl Snapshot for NesBus {
+    fn save_state(&self, state: &mut StateWriter) {
+        state.write_bytes(&self.cpu_ram);
+        self.ppu.save_state(state);
+        self.apu.save_state(state);
+        self.cartridge.mapper.save_state(state);
+        state.write_u8(self.open_bus);
+        state.write_u64(self.cycles);
+        state.write_u32(self.ppu_clock_remainder);
+        state.write_u16(self.stall_cycles);
+    }
+
+    fn load_state(&mut self, state: &mut StateReader) -> Result<(), StateError> {
+        state.read_bytes_into(&mut self.cpu_ram)?;
+        self.ppu.load_state(state)?;
+        self.apu.load_state(state)?;
+        self.cartridge.mapper.load_state(state)?;
+        self.open_bus = state.read_u8()?;
+        self.cycles = state.read_u64()?;
+        self.ppu_clock_remainder = state.read_u32()?;
+        self.stall_cycles = state.read_u16()?;
+        Ok(())
+    }
+}
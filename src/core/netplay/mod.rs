@@ -0,0 +1,4 @@
+pub mod packet;
+pub mod session;
+pub mod transport;
+pub mod udp;
\ No newline at end of file
@@ -0,0 +1,50 @@
+use crate::core::state::stream::{StateError, StateReader, StateWriter};
+
+const MAGIC: [u8; 4] = *b"NESN";
+
+// everything one side ever tells the other, sent at least once a frame: its input from the
+// first frame the other side hasn't acknowledged on, and the state hashes of its latest confirmed
+// frames. losing one costs nothing, the next carries the same and more
+#[derive(Debug, Clone, Default, Eq, PartialEq)]
+pub struct Packet {
+    // how many frames of the receiver's input the sender has, with no gaps
+    pub ack: u64,
+    pub first_frame: u64,
+    // one controller byte a frame, from first_frame on
+    pub inputs: Vec<u8>,
+    // (frame, CRC-32 of the state at the end of it)
+    pub hashes: Vec<(u64, u32)>,
+}
+
+impl Packet {
+    pub fn encode(&self) -> Vec<u8> {
+        let mut out = StateWriter::new();
+        for byte in MAGIC { out.write_u8(byte); }
+        out.write_u64(self.ack);
+        out.write_u64(self.first_frame);
+        out.write_bytes(&self.inputs);
+        out.write_u8(self.hashes.len() as u8);
+        for (frame, hash) in self.hashes.iter() {
+            out.write_u64(*frame);
+            out.write_u32(*hash);
+        }
+        out.into_bytes()
+    }
+
+    pub fn decode(data: &[u8]) -> Result<Packet, StateError> {
+        let mut packet = StateReader::new(data);
+        for byte in MAGIC {
+            if packet.read_u8()? != byte { return Err(StateError::Corrupt("not a netplay packet")); }
+        }
+
+        let ack = packet.read_u64()?;
+        let first_frame = packet.read_u64()?;
+        let inputs = packet.read_bytes()?;
+        let hashes = (0..packet.read_u8()?)
+            .map(|_| Ok((packet.read_u64()?, packet.read_u32()?)))
+            .collect::<Result<_, StateError>>()?;
+
+        if !packet.is_empty() { return Err(StateError::Corrupt("trailing data")); }
+        Ok(Packet { ack, first_frame, inputs, hashes })
+    }
+}
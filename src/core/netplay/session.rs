@@ -0,0 +1,259 @@
+use std::collections::{BTreeMap, VecDeque};
+use std::fmt;
+use std::io;
+use crate::core::checksum::crc32;
+use crate::core::console::{Console, Frame};
+use crate::core::input::joypad::Buttons;
+use crate::core::netplay::packet::Packet;
+use crate::core::netplay::transport::Transport;
+
+// how far past the other player's last input a side runs on guesses before it waits for them
+pub const DEFAULT_MAX_PREDICTION: u64 = 8;
+// how many of the latest confirmed frames' hashes go out in each packet
+const HASHES_PER_PACKET: usize = 4;
+// hashes older than this many frames behind the newest confirmed one get forgotten
+const HASH_HISTORY: u64 = 120;
+
+#[derive(Debug)]
+pub enum NetplayError {
+    Io(io::Error),
+    // the two machines came out of `frame` different, on the same input
+    Desync { frame: u64 },
+}
+
+impl fmt::Display for NetplayError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            NetplayError::Io(e) => write!(f, "netplay connection failed: {e}"),
+            NetplayError::Desync { frame } => write!(f, "out of sync with the other player at frame {frame}"),
+        }
+    }
+}
+
+impl std::error::Error for NetplayError {}
+
+impl From<io::Error> for NetplayError {
+    fn from(e: io::Error) -> Self {
+        NetplayError::Io(e)
+    }
+}
+
+// two players over a Transport, each side on its own console and pad. every frame runs straight
+// away on a guess at the other player's input, whatever they held last, and when their real
+// input turns up different the session loads the state from before the wrong guess and runs
+// back up to the present. both consoles have to start out the same, at power-on say
+pub struct NetplaySession<T: Transport> {
+    transport: T,
+    // 0 or 1, the other player's on the other one
+    local_player: usize,
+    input_delay: u64,
+    max_prediction: u64,
+    // the next frame to run, counting from the start of the session
+    frame: u64,
+    // ours from the start, including the empty frames the input delay puts in front
+    local_inputs: Vec<u8>,
+    // theirs, as far as it's arrived with no gaps
+    remote_inputs: Vec<u8>,
+    // what each frame past the end of remote_inputs was run with for them
+    predictions: BTreeMap<u64, u8>,
+    // the state at the start of every frame that might still have to be run again
+    states: VecDeque<(u64, Vec<u8>)>,
+    // how much of our input they've said they have
+    peer_ack: u64,
+    // frames get hashed in order, once both inputs are known and they've run on them
+    next_hash: u64,
+    local_hashes: BTreeMap<u64, u32>,
+    remote_hashes: BTreeMap<u64, u32>,
+    desync: Option<u64>,
+    rollbacks: u64,
+}
+
+impl<T: Transport> NetplaySession<T> {
+    pub fn new(transport: T, local_player: usize) -> Self {
+        assert!(local_player < 2, "netplay is two players, 0 and 1");
+        NetplaySession {
+            transport,
+            local_player,
+            input_delay: 0,
+            max_prediction: DEFAULT_MAX_PREDICTION,
+            frame: 0,
+            local_inputs: Vec::new(),
+            remote_inputs: Vec::new(),
+            predictions: BTreeMap::new(),
+            states: VecDeque::new(),
+            peer_ack: 0,
+            next_hash: 0,
+            local_hashes: BTreeMap::new(),
+            remote_hashes: BTreeMap::new(),
+            desync: None,
+            rollbacks: 0,
+        }
+    }
+
+    // holds our own input back this many frames, so fewer guesses about theirs turn out wrong.
+    // meant to be set before the first frame
+    pub fn set_input_delay(&mut self, frames: u64) {
+        self.input_delay = frames;
+    }
+
+    pub fn set_max_prediction(&mut self, frames: u64) {
+        self.max_prediction = frames.max(1);
+    }
+
+    pub fn frame(&self) -> u64 {
+        self.frame
+    }
+
+    // how many frames of the other player's input have arrived
+    pub fn confirmed_frames(&self) -> u64 {
+        self.remote_inputs.len() as u64
+    }
+
+    // how many times a wrong guess meant going back
+    pub fn rollbacks(&self) -> u64 {
+        self.rollbacks
+    }
+
+    pub fn transport(&self) -> &T {
+        &self.transport
+    }
+
+    // takes in what the other side's sent, goes back over any frame that was run on a wrong guess,
+    // and tells them where we're at. advance_frame does this itself, it's for while waiting
+    pub fn poll(&mut self, console: &mut Console) -> Result<(), NetplayError> {
+        self.check_sync()?;
+
+        while let Some(data) = self.transport.receive()? {
+            // anything that doesn't decode isn't from the other side
+            if let Ok(packet) = Packet::decode(&data) { self.receive(packet); }
+        }
+        self.roll_back(console);
+        self.hash_confirmed(console);
+        self.send()?;
+
+        self.check_sync()
+    }
+
+    // runs the next frame with `buttons` on our pad and hands it over to be shown, or gives back
+    // None without running anything when we've got too far ahead of the other player
+    pub fn advance_frame(&mut self, console: &mut Console, buttons: Buttons) -> Result<Option<Frame>, NetplayError> {
+        self.poll(console)?;
+        if self.frame >= self.confirmed_frames() + self.max_prediction { return Ok(None); }
+
+        let target = (self.frame + self.input_delay) as usize;
+        if self.local_inputs.len() <= target {
+            self.local_inputs.resize(target, 0);
+            self.local_inputs.push(buttons.to_byte());
+            self.send()?;
+        }
+
+        self.prepare_frame(console);
+        Ok(Some(console.step_frame()))
+    }
+
+    fn check_sync(&self) -> Result<(), NetplayError> {
+        match self.desync {
+            Some(frame) => Err(NetplayError::Desync { frame }),
+            None => Ok(()),
+        }
+    }
+
+    fn receive(&mut self, packet: Packet) {
+        self.peer_ack = self.peer_ack.max(packet.ack);
+
+        // packets can come out of order, so only what carries on from what's already here counts
+        let have = self.confirmed_frames();
+        if packet.first_frame <= have {
+            let skip = (have - packet.first_frame) as usize;
+            self.remote_inputs.extend(packet.inputs.iter().skip(skip));
+        }
+
+        for (frame, hash) in packet.hashes {
+            self.compare_hash(frame, self.local_hashes.get(&frame).copied(), hash);
+            self.remote_hashes.insert(frame, hash);
+        }
+    }
+
+    fn send(&mut self) -> io::Result<()> {
+        let first_frame = self.peer_ack.min(self.local_inputs.len() as u64);
+        let packet = Packet {
+            ack: self.confirmed_frames(),
+            first_frame,
+            inputs: self.local_inputs[first_frame as usize..].to_vec(),
+            hashes: self.local_hashes.iter().rev().take(HASHES_PER_PACKET).map(|(frame, hash)| (*frame, *hash)).collect(),
+        };
+        self.transport.send(&packet.encode())
+    }
+
+    // keeps the state from before the next frame and sets up both pads for it
+    fn prepare_frame(&mut self, console: &mut Console) {
+        self.states.push_back((self.frame, console.save_state()));
+
+        let frame = self.frame as usize;
+        let local = self.local_inputs.get(frame).copied().unwrap_or(0);
+        let remote = match self.remote_inputs.get(frame) {
+            Some(input) => *input,
+            None => {
+                let guess = self.remote_inputs.last().copied().unwrap_or(0);
+                self.predictions.insert(self.frame, guess);
+                guess
+            }
+        };
+
+        console.set_buttons(self.local_player, Buttons::from_byte(local));
+        console.set_buttons(1 - self.local_player, Buttons::from_byte(remote));
+        self.frame += 1;
+    }
+
+    fn roll_back(&mut self, console: &mut Console) {
+        let confirmed = self.confirmed_frames();
+        let unconfirmed = self.predictions.split_off(&confirmed);
+        let wrong = self.predictions.iter()
+            .find(|(frame, guess)| self.remote_inputs[**frame as usize] != **guess)
+            .map(|(frame, _)| *frame);
+        self.predictions = unconfirmed;
+
+        let Some(wrong) = wrong else { return };
+        let Some(index) = self.states.iter().position(|(frame, _)| *frame == wrong) else { return };
+        let Some((_, state)) = self.states.split_off(index).pop_front() else { return };
+
+        // the frames in between were already shown, this only puts the machine right
+        let present = self.frame;
+        console.load_state(&state).expect("state that was just saved loads");
+        self.frame = wrong;
+        while self.frame < present {
+            self.prepare_frame(console);
+            console.step_frame_silently();
+        }
+        self.rollbacks += 1;
+    }
+
+    fn hash_confirmed(&mut self, console: &Console) {
+        let confirmed = self.confirmed_frames().min(self.frame);
+        while self.next_hash < confirmed {
+            // the end of a frame is the start of the next, or where the console is now
+            let end = self.next_hash + 1;
+            let hash = match self.states.iter().find(|(frame, _)| *frame == end) {
+                Some((_, state)) => crc32(state),
+                None => crc32(&console.save_state()),
+            };
+
+            self.compare_hash(self.next_hash, self.remote_hashes.get(&self.next_hash).copied(), hash);
+            self.local_hashes.insert(self.next_hash, hash);
+            self.next_hash = end;
+        }
+
+        // nothing before the first frame that isn't confirmed can be run again
+        let next_hash = self.next_hash;
+        self.states.retain(|(frame, _)| *frame >= next_hash);
+        let oldest = next_hash.saturating_sub(HASH_HISTORY);
+        self.local_hashes.retain(|frame, _| *frame >= oldest);
+        self.remote_hashes.retain(|frame, _| *frame >= oldest);
+    }
+
+    fn compare_hash(&mut self, frame: u64, other: Option<u32>, hash: u32) {
+        if other.is_some_and(|other| other != hash) && self.desync.is_none_or(|earliest| frame < earliest) {
+            self.desync = Some(frame);
+        }
+    }
+}
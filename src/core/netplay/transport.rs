@@ -0,0 +1,10 @@
+use std::io;
+
+// how a netplay session gets packets to and from the other player. datagrams: each send arrives
+// whole or not at all, maybe out of order, maybe twice. the session copes with all of that
+pub trait Transport {
+    fn send(&mut self, packet: &[u8]) -> io::Result<()>;
+
+    // never blocks, None once there's nothing waiting
+    fn receive(&mut self) -> io::Result<Option<Vec<u8>>>;
+}
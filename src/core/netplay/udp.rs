@@ -0,0 +1,51 @@
+use std::io;
+use std::net::{SocketAddr, ToSocketAddrs, UdpSocket};
+use crate::core::netplay::transport::Transport;
+
+// biggest packet worth expecting, the session's stay well under a normal MTU
+const MAX_PACKET: usize = 1500;
+
+// plain UDP to one peer. anything from anywhere else gets dropped by the socket
+pub struct UdpTransport {
+    socket: UdpSocket,
+}
+
+impl UdpTransport {
+    pub fn bind<A: ToSocketAddrs>(local: A, peer: SocketAddr) -> io::Result<Self> {
+        UdpTransport::new(UdpSocket::bind(local)?, peer)
+    }
+
+    pub fn new(socket: UdpSocket, peer: SocketAddr) -> io::Result<Self> {
+        socket.connect(peer)?;
+        socket.set_nonblocking(true)?;
+        Ok(UdpTransport { socket })
+    }
+
+    pub fn local_addr(&self) -> io::Result<SocketAddr> {
+        self.socket.local_addr()
+    }
+}
+
+impl Transport for UdpTransport {
+    fn send(&mut self, packet: &[u8]) -> io::Result<()> {
+        match self.socket.send(packet) {
+            // the peer isn't listening yet. it'll get the next one
+            Err(e) if e.kind() == io::ErrorKind::ConnectionRefused => Ok(()),
+            Err(e) => Err(e),
+            Ok(_) => Ok(()),
+        }
+    }
+
+    fn receive(&mut self) -> io::Result<Option<Vec<u8>>> {
+        let mut buffer = [0; MAX_PACKET];
+        loop {
+            match self.socket.recv(&mut buffer) {
+                Ok(len) => return Ok(Some(buffer[..len].to_vec())),
+                Err(e) if e.kind() == io::ErrorKind::WouldBlock => return Ok(None),
+                // an ICMP error from an earlier send, not a packet
+                Err(e) if e.kind() == io::ErrorKind::ConnectionRefused => continue,
+                Err(e) => return Err(e),
+            }
+        }
+    }
+}
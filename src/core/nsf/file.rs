@@ -0,0 +1,266 @@
+use std::fmt;
+use std::fs;
+use std::path::Path;
+use crate::core::region::Region;
+
+const NSF_TAG: [u8; 5] = *b"NESM\x1a";
+const NSFE_TAG: [u8; 4] = *b"NSFE";
+const NSF_HEADER_LEN: usize = 0x80;
+// what players assume when the header leaves the play rate at 0
+const NTSC_PLAY_SPEED: u16 = 16_639;
+const PAL_PLAY_SPEED: u16 = 19_997;
+
+#[derive(Debug)]
+pub enum NsfError {
+    Io(std::io::Error),
+    NotNsf,
+    Truncated,
+    // NSFe chunks with an uppercase first letter have to be understood to play the file
+    MissingChunk(&'static str),
+    UnknownChunk(String),
+}
+
+impl fmt::Display for NsfError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            NsfError::Io(e) => write!(f, "couldn't read NSF: {e}"),
+            NsfError::NotNsf => write!(f, "not an NSF or NSFe file"),
+            NsfError::Truncated => write!(f, "NSF is truncated"),
+            NsfError::MissingChunk(id) => write!(f, "NSFe has no {id} chunk"),
+            NsfError::UnknownChunk(id) => write!(f, "NSFe needs a {id} chunk, which isn't supported"),
+        }
+    }
+}
+
+impl std::error::Error for NsfError {}
+
+impl From<std::io::Error> for NsfError {
+    fn from(e: std::io::Error) -> Self {
+        NsfError::Io(e)
+    }
+}
+
+// the header's expansion byte
+#[derive(Debug, Copy, Clone, Default, Eq, PartialEq)]
+pub struct ExpansionChips(pub u8);
+
+impl ExpansionChips {
+    pub fn vrc6(&self) -> bool { self.0 & 0x01 != 0 }
+    pub fn vrc7(&self) -> bool { self.0 & 0x02 != 0 }
+    pub fn fds(&self) -> bool { self.0 & 0x04 != 0 }
+    pub fn mmc5(&self) -> bool { self.0 & 0x08 != 0 }
+    pub fn n163(&self) -> bool { self.0 & 0x10 != 0 }
+    pub fn sunsoft_5b(&self) -> bool { self.0 & 0x20 != 0 }
+}
+
+// the per-track bits only NSFe has, all in milliseconds
+#[derive(Debug, Clone, Default, Eq, PartialEq)]
+pub struct TrackInfo {
+    pub title: Option<String>,
+    pub length: Option<u32>,
+    pub fade: Option<u32>,
+}
+
+#[derive(Debug, Clone)]
+pub struct Nsf {
+    pub load_address: u16,
+    pub init_address: u16,
+    pub play_address: u16,
+    pub songs: u8,
+    // 0-based
+    pub starting_song: u8,
+    pub title: String,
+    pub artist: String,
+    pub copyright: String,
+    pub ripper: String,
+    // microseconds between PLAY calls
+    pub ntsc_speed: u16,
+    pub pal_speed: u16,
+    pub pal: bool,
+    pub dual_region: bool,
+    pub bank_init: [u8; 8],
+    pub expansion: ExpansionChips,
+    pub data: Vec<u8>,
+    // empty for plain NSFs
+    pub tracks: Vec<TrackInfo>,
+    // the order to play tracks in, if the NSFe has one
+    pub playlist: Vec<u8>,
+}
+
+impl Nsf {
+    pub fn from_bytes(raw: &[u8]) -> Result<Nsf, NsfError> {
+        if raw.starts_with(&NSF_TAG) { return Nsf::from_nsf(raw); }
+        if raw.starts_with(&NSFE_TAG) { return Nsf::from_nsfe(raw); }
+        Err(NsfError::NotNsf)
+    }
+
+    pub fn load<P: AsRef<Path>>(path: P) -> Result<Nsf, NsfError> {
+        let raw = fs::read(path)?;
+        Nsf::from_bytes(&raw)
+    }
+
+    fn empty() -> Nsf {
+        Nsf {
+            load_address: 0x8000,
+            init_address: 0x8000,
+            play_address: 0x8000,
+            songs: 1,
+            starting_song: 0,
+            title: String::new(),
+            artist: String::new(),
+            copyright: String::new(),
+            ripper: String::new(),
+            ntsc_speed: NTSC_PLAY_SPEED,
+            pal_speed: PAL_PLAY_SPEED,
+            pal: false,
+            dual_region: false,
+            bank_init: [0; 8],
+            expansion: ExpansionChips::default(),
+            data: Vec::new(),
+            tracks: Vec::new(),
+            playlist: Vec::new(),
+        }
+    }
+
+    fn from_nsf(raw: &[u8]) -> Result<Nsf, NsfError> {
+        if raw.len() < NSF_HEADER_LEN { return Err(NsfError::Truncated); }
+
+        let word = |offset: usize| u16::from_le_bytes([raw[offset], raw[offset + 1]]);
+        let mut nsf = Nsf::empty();
+
+        nsf.songs = raw[0x06];
+        nsf.starting_song = raw[0x07].saturating_sub(1);
+        nsf.load_address = word(0x08);
+        nsf.init_address = word(0x0a);
+        nsf.play_address = word(0x0c);
+        nsf.title = c_string(&raw[0x0e..0x2e]);
+        nsf.artist = c_string(&raw[0x2e..0x4e]);
+        nsf.copyright = c_string(&raw[0x4e..0x6e]);
+        nsf.set_speeds(word(0x6e), word(0x78));
+        nsf.bank_init.copy_from_slice(&raw[0x70..0x78]);
+        nsf.set_region_flags(raw[0x7a]);
+        nsf.expansion = ExpansionChips(raw[0x7b]);
+
+        // NSF2 can say where the program stops and metadata starts, 0 means it runs to the end
+        let data_len = usize::from(raw[0x7d]) | usize::from(raw[0x7e]) << 8 | usize::from(raw[0x7f]) << 16;
+        let end = if raw[0x05] >= 2 && data_len != 0 { (NSF_HEADER_LEN + data_len).min(raw.len()) } else { raw.len() };
+        nsf.data = raw[NSF_HEADER_LEN..end].to_vec();
+
+        Ok(nsf)
+    }
+
+    fn from_nsfe(raw: &[u8]) -> Result<Nsf, NsfError> {
+        let mut nsf = Nsf::empty();
+        let (mut info, mut data) = (false, false);
+        let mut fades = Vec::new();
+        let mut lengths = Vec::new();
+        let mut labels = Vec::new();
+
+        let mut offset = NSFE_TAG.len();
+        while offset + 8 <= raw.len() {
+            let len = u32::from_le_bytes(raw[offset..offset + 4].try_into().unwrap()) as usize;
+            let id = &raw[offset + 4..offset + 8];
+            let chunk = raw.get(offset + 8..offset + 8 + len).ok_or(NsfError::Truncated)?;
+            offset += 8 + len;
+
+            match id {
+                b"INFO" => {
+                    if chunk.len() < 9 { return Err(NsfError::Truncated); }
+
+                    let word = |offset: usize| u16::from_le_bytes([chunk[offset], chunk[offset + 1]]);
+                    nsf.load_address = word(0);
+                    nsf.init_address = word(2);
+                    nsf.play_address = word(4);
+                    nsf.set_region_flags(chunk[6]);
+                    nsf.expansion = ExpansionChips(chunk[7]);
+                    nsf.songs = chunk[8];
+                    nsf.starting_song = chunk.get(9).copied().unwrap_or(0);
+                    info = true;
+                }
+                b"DATA" => {
+                    nsf.data = chunk.to_vec();
+                    data = true;
+                }
+                b"BANK" => {
+                    let len = chunk.len().min(8);
+                    nsf.bank_init[..len].copy_from_slice(&chunk[..len]);
+                }
+                b"RATE" => {
+                    let word = |offset: usize| chunk.get(offset..offset + 2).map(|b| u16::from_le_bytes([b[0], b[1]]));
+                    nsf.set_speeds(word(0).unwrap_or(0), word(2).unwrap_or(0));
+                }
+                b"auth" => {
+                    let mut strings = chunk.split(|b| *b == 0).map(c_string);
+                    nsf.title = strings.next().unwrap_or_default();
+                    nsf.artist = strings.next().unwrap_or_default();
+                    nsf.copyright = strings.next().unwrap_or_default();
+                    nsf.ripper = strings.next().unwrap_or_default();
+                }
+                b"plst" => nsf.playlist = chunk.to_vec(),
+                b"time" => lengths = chunk.chunks_exact(4).map(milliseconds).collect(),
+                b"fade" => fades = chunk.chunks_exact(4).map(milliseconds).collect(),
+                b"tlbl" => labels = chunk.split(|b| *b == 0).map(c_string).collect(),
+                b"NEND" => break,
+                _ if id[0].is_ascii_uppercase() => {
+                    return Err(NsfError::UnknownChunk(String::from_utf8_lossy(id).into_owned()));
+                }
+                // anything lowercase is optional, skip it
+                _ => {}
+            }
+        }
+
+        if !info { return Err(NsfError::MissingChunk("INFO")); }
+        if !data { return Err(NsfError::MissingChunk("DATA")); }
+
+        nsf.tracks = (0..nsf.songs as usize)
+            .map(|track| TrackInfo {
+                title: labels.get(track).filter(|label| !label.is_empty()).cloned(),
+                length: lengths.get(track).copied().flatten(),
+                fade: fades.get(track).copied().flatten(),
+            })
+            .collect();
+
+        Ok(nsf)
+    }
+
+    fn set_speeds(&mut self, ntsc: u16, pal: u16) {
+        if ntsc != 0 { self.ntsc_speed = ntsc; }
+        if pal != 0 { self.pal_speed = pal; }
+    }
+
+    fn set_region_flags(&mut self, flags: u8) {
+        self.pal = flags & 0b01 != 0;
+        self.dual_region = flags & 0b10 != 0;
+    }
+
+    // dual-region tunes get NTSC unless asked otherwise
+    pub fn region(&self) -> Region {
+        if self.pal && !self.dual_region { Region::Pal } else { Region::Ntsc }
+    }
+
+    pub fn bankswitched(&self) -> bool {
+        self.bank_init.iter().any(|bank| *bank != 0)
+    }
+
+    pub fn track(&self, track: u8) -> TrackInfo {
+        self.tracks.get(track as usize).cloned().unwrap_or_default()
+    }
+
+    // microseconds between PLAY calls
+    pub fn play_speed(&self, region: Region) -> u16 {
+        match region {
+            Region::Ntsc => self.ntsc_speed,
+            Region::Pal | Region::Dendy => self.pal_speed,
+        }
+    }
+}
+
+fn c_string(raw: &[u8]) -> String {
+    let end = raw.iter().position(|b| *b == 0).unwrap_or(raw.len());
+    String::from_utf8_lossy(&raw[..end]).into_owned()
+}
+
+// negative means "use the default"
+fn milliseconds(raw: &[u8]) -> Option<u32> {
+    u32::try_from(i32::from_le_bytes(raw.try_into().unwrap())).ok()
+}
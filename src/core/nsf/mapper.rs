@@ -0,0 +1,200 @@
+use crate::core::apu::expansion::expansion_audio::ExpansionAudio;
+use crate::core::apu::expansion::fds::FdsAudio;
+use crate::core::apu::expansion::mmc5::Mmc5Audio;
+use crate::core::apu::expansion::n163::N163Audio;
+use crate::core::apu::expansion::sunsoft_5b::Sunsoft5bAudio;
+use crate::core::apu::expansion::vrc6::Vrc6Audio;
+use crate::core::apu::expansion::vrc7::Vrc7Audio;
+use crate::core::cartridge::mapper::Mapper;
+use crate::core::cartridge::rom::Mirroring;
+use crate::core::nsf::file::Nsf;
+use crate::core::state::snapshot::Snapshot;
+use crate::core::state::stream::{StateError, StateReader, StateWriter};
+
+// the player parks the CPU here between calls: JMP $4100. nothing else lives at $4100
+pub const IDLE_LOOP: u16 = 0x4100;
+const IDLE_CODE: [u8; 3] = [0x4c, 0x00, 0x41];
+const BANK_SIZE: usize = 0x1000;
+
+// the imaginary cartridge an NSF plays from: 4K banks at $8000-$FFFF switched through $5FF8-$5FFF,
+// 8K of RAM at $6000, and whichever sound chips the header asks for. FDS tunes get RAM all the way
+// from $6000 to $DFFF instead, with $5FF6-$5FFF copying banks into it
+pub struct NsfMapper {
+    // padded so that bank 0 starts at a 4K boundary
+    prg: Vec<u8>,
+    banks: [usize; 8],
+    bankswitched: bool,
+    fds: bool,
+    // $6000-$7FFF, or $6000-$FFFF for FDS
+    ram: Vec<u8>,
+    chr_ram: [u8; 0x2000],
+    multiplicand: u8,
+    multiplier: u8,
+    exram: [u8; 0x400],
+    chips: Vec<Box<dyn ExpansionAudio>>,
+    mmc5: bool,
+}
+
+impl NsfMapper {
+    pub fn new(nsf: &Nsf) -> Self {
+        let bankswitched = nsf.bankswitched();
+        let fds = nsf.expansion.fds();
+
+        // without bankswitching the data just goes where it says, as 8 banks covering $8000-$FFFF
+        let (prg, banks) = if bankswitched {
+            let padding = nsf.load_address as usize & 0x0fff;
+            let mut prg = vec![0; padding];
+            prg.extend_from_slice(&nsf.data);
+            (prg, nsf.bank_init.map(usize::from))
+        } else {
+            let mut prg = vec![0; 0x8000];
+            let start = (nsf.load_address as usize).saturating_sub(0x8000);
+            let len = nsf.data.len().min(0x8000 - start.min(0x8000));
+            prg[start..start + len].copy_from_slice(&nsf.data[..len]);
+            (prg, [0, 1, 2, 3, 4, 5, 6, 7])
+        };
+
+        let mut chips: Vec<Box<dyn ExpansionAudio>> = Vec::new();
+        if nsf.expansion.vrc6() { chips.push(Box::new(Vrc6Audio::new())); }
+        if nsf.expansion.vrc7() { chips.push(Box::new(Vrc7Audio::new())); }
+        if fds { chips.push(Box::new(FdsAudio::new())); }
+        if nsf.expansion.mmc5() { chips.push(Box::new(Mmc5Audio::new())); }
+        if nsf.expansion.n163() { chips.push(Box::new(N163Audio::new())); }
+        if nsf.expansion.sunsoft_5b() { chips.push(Box::new(Sunsoft5bAudio::new())); }
+
+        let mut mapper = NsfMapper {
+            prg,
+            banks,
+            bankswitched,
+            fds,
+            ram: vec![0; if fds { 0xa000 } else { 0x2000 }],
+            chr_ram: [0; 0x2000],
+            multiplicand: 0xff,
+            multiplier: 0xff,
+            exram: [0; 0x400],
+            chips,
+            mmc5: nsf.expansion.mmc5(),
+        };
+
+        if fds {
+            // FDS tunes get their ROM copied into RAM up front, $6000/$7000 from the last two banks
+            let load = nsf.load_address as usize;
+            if bankswitched {
+                mapper.copy_bank(0, banks[6]);
+                mapper.copy_bank(1, banks[7]);
+                for (slot, bank) in banks.iter().enumerate() {
+                    mapper.copy_bank(slot + 2, *bank);
+                }
+            } else if load >= 0x6000 {
+                let start = load - 0x6000;
+                let len = nsf.data.len().min(mapper.ram.len() - start.min(mapper.ram.len()));
+                mapper.ram[start..start + len].copy_from_slice(&nsf.data[..len]);
+            }
+        }
+
+        mapper
+    }
+
+    // FDS only: bank into 4K slot `slot` of the RAM at $6000
+    fn copy_bank(&mut self, slot: usize, bank: usize) {
+        let start = bank * BANK_SIZE;
+        for offset in 0..BANK_SIZE {
+            self.ram[slot * BANK_SIZE + offset] = self.prg.get(start + offset).copied().unwrap_or(0);
+        }
+    }
+
+    fn prg_read(&self, addr: u16) -> u8 {
+        let slot = (addr as usize - 0x8000) / BANK_SIZE;
+        let index = self.banks[slot] * BANK_SIZE + (addr as usize & 0x0fff);
+        self.prg.get(index).copied().unwrap_or(0)
+    }
+}
+
+impl Mapper for NsfMapper {
+    fn cpu_read(&mut self, addr: u16) -> Option<u8> {
+        if let Some(data) = self.chips.iter_mut().find_map(|chip| chip.read_register(addr)) {
+            return Some(data);
+        }
+        self.cpu_peek(addr)
+    }
+
+    fn cpu_peek(&self, addr: u16) -> Option<u8> {
+        match addr {
+            IDLE_LOOP..=0x4102 => Some(IDLE_CODE[(addr - IDLE_LOOP) as usize]),
+            0x5205 if self.mmc5 => Some((u16::from(self.multiplicand) * u16::from(self.multiplier)) as u8),
+            0x5206 if self.mmc5 => Some(((u16::from(self.multiplicand) * u16::from(self.multiplier)) >> 8) as u8),
+            0x5c00..=0x5ff5 if self.mmc5 => Some(self.exram[addr as usize - 0x5c00]),
+            0x6000..=0xffff if self.fds => Some(self.ram[addr as usize - 0x6000]),
+            0x6000..=0x7fff => Some(self.ram[addr as usize - 0x6000]),
+            0x8000..=0xffff => Some(self.prg_read(addr)),
+            _ => None,
+        }
+    }
+
+    fn cpu_write(&mut self, addr: u16, data: u8) {
+        for chip in self.chips.iter_mut() {
+            chip.write_register(addr, data);
+        }
+
+        match addr {
+            0x5205 if self.mmc5 => self.multiplicand = data,
+            0x5206 if self.mmc5 => self.multiplier = data,
+            0x5c00..=0x5ff5 if self.mmc5 => self.exram[addr as usize - 0x5c00] = data,
+            0x5ff6..=0x5fff if self.fds && self.bankswitched => {
+                // $5FF6/$5FF7 are $6000/$7000, the rest follow on from $8000
+                self.copy_bank(addr as usize - 0x5ff6, data as usize);
+            }
+            0x5ff8..=0x5fff if self.bankswitched => self.banks[addr as usize - 0x5ff8] = data as usize,
+            0x6000..=0xdfff if self.fds => self.ram[addr as usize - 0x6000] = data,
+            0x6000..=0x7fff => self.ram[addr as usize - 0x6000] = data,
+            _ => {}
+        }
+    }
+
+    fn ppu_read(&mut self, addr: u16) -> u8 {
+        self.chr_ram[addr as usize & 0x1fff]
+    }
+
+    fn ppu_write(&mut self, addr: u16, data: u8) {
+        self.chr_ram[addr as usize & 0x1fff] = data;
+    }
+
+    fn mirroring(&self) -> Mirroring {
+        Mirroring::Horizontal
+    }
+
+    fn tick(&mut self, cycles: u16) {
+        for _ in 0..cycles {
+            for chip in self.chips.iter_mut() {
+                chip.clock();
+            }
+        }
+    }
+
+    fn audio_output(&self) -> f32 {
+        self.chips.iter().map(|chip| chip.output()).sum()
+    }
+}
+
+impl Snapshot for NsfMapper {
+    fn save_state(&self, state: &mut StateWriter) {
+        for bank in self.banks { state.write_usize(bank); }
+        state.write_bytes(&self.ram);
+        state.write_bytes(&self.chr_ram);
+        state.write_u8(self.multiplicand);
+        state.write_u8(self.multiplier);
+        state.write_bytes(&self.exram);
+        for chip in self.chips.iter() { chip.save_state(state); }
+    }
+
+    fn load_state(&mut self, state: &mut StateReader) -> Result<(), StateError> {
+        for bank in self.banks.iter_mut() { *bank = state.read_usize()?; }
+        state.read_bytes_into(&mut self.ram)?;
+        state.read_bytes_into(&mut self.chr_ram)?;
+        self.multiplicand = state.read_u8()?;
+        self.multiplier = state.read_u8()?;
+        state.read_bytes_into(&mut self.exram)?;
+        for chip in self.chips.iter_mut() { chip.load_state(state)?; }
+        Ok(())
+    }
+}
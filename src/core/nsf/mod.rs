@@ -0,0 +1,3 @@
+pub mod file;
+pub mod mapper;
+pub mod player;
\ No newline at end of file
@@ -0,0 +1,161 @@
+use crate::core::apu::audio_processor::DEFAULT_SAMPLE_RATE;
+use crate::core::cartridge::rom::Cartridge;
+use crate::core::checksum::{crc32, md5};
+use crate::core::cpu::processor::Processor;
+use crate::core::nes_bus::NesBus;
+use crate::core::nsf::file::Nsf;
+use crate::core::nsf::mapper::{NsfMapper, IDLE_LOOP};
+use crate::core::region::Region;
+
+// what a track gets when the file doesn't say, in milliseconds
+pub const DEFAULT_TRACK_LENGTH: u32 = 150_000;
+pub const DEFAULT_FADE: u32 = 8_000;
+// how much gets run between handing samples back, about a frame's worth
+const CHUNK_CYCLES: u64 = 29_781;
+// mapper 31 is the iNES number for NSF-style banking, the closest thing there is
+const NSF_MAPPER_ID: u16 = 31;
+
+// runs an NSF the way a hardware player would: INIT once with the track number in A, then PLAY
+// at the rate the header asks for, with the CPU sitting in an idle loop in between
+pub struct NsfPlayer {
+    pub cpu: Processor<NesBus>,
+    nsf: Nsf,
+    region: Region,
+    sample_rate: u32,
+    track: u8,
+    // in CPU cycles
+    play_period: f64,
+    next_play: f64,
+}
+
+impl NsfPlayer {
+    pub fn new(nsf: Nsf) -> Self {
+        let region = nsf.region();
+        NsfPlayer::with_region(nsf, region)
+    }
+
+    pub fn with_region(nsf: Nsf, region: Region) -> Self {
+        let track = nsf.starting_song;
+        let mut player = NsfPlayer {
+            cpu: NsfPlayer::build_cpu(&nsf, region),
+            nsf,
+            region,
+            sample_rate: DEFAULT_SAMPLE_RATE,
+            track,
+            play_period: 0.0,
+            next_play: 0.0,
+        };
+        player.start_track(track);
+        player
+    }
+
+    fn build_cpu(nsf: &Nsf, region: Region) -> Processor<NesBus> {
+        let cartridge = Cartridge {
+            mapper_id: NSF_MAPPER_ID,
+            battery: false,
+            region: Some(region),
+            crc32: crc32(&nsf.data),
+            md5: md5(&nsf.data),
+            mapper: Box::new(NsfMapper::new(nsf)),
+        };
+
+        let mut cpu = Processor::with_bus(NesBus::new(cartridge, region));
+        cpu.region = region;
+        cpu
+    }
+
+    pub fn nsf(&self) -> &Nsf {
+        &self.nsf
+    }
+
+    pub fn region(&self) -> Region {
+        self.region
+    }
+
+    pub fn track(&self) -> u8 {
+        self.track
+    }
+
+    pub fn sample_rate(&self) -> u32 {
+        self.sample_rate
+    }
+
+    // takes effect from the next start_track
+    pub fn set_sample_rate(&mut self, sample_rate: u32) {
+        self.sample_rate = sample_rate;
+    }
+
+    // 0-based. everything gets powered back up, so tracks can't leak into each other
+    pub fn start_track(&mut self, track: u8) {
+        self.track = track;
+        self.cpu = NsfPlayer::build_cpu(&self.nsf, self.region);
+        self.cpu.bus.apu.set_sample_rate(self.sample_rate);
+        self.cpu.reset();
+
+        for addr in 0x4000..=0x4013 {
+            self.cpu.mem_write(addr, 0x00);
+        }
+        self.cpu.mem_write(0x4015, 0x0f);
+        self.cpu.mem_write(0x4017, 0x40);
+
+        self.cpu.register_a = track;
+        self.cpu.register_x = u8::from(self.region != Region::Ntsc);
+        self.cpu.register_y = 0;
+        self.call(self.nsf.init_address);
+
+        let speed = f64::from(self.nsf.play_speed(self.region));
+        self.play_period = speed * self.cpu.clock_rate() / 1_000_000.0;
+        self.next_play = self.cpu.cycles as f64;
+    }
+
+    // a JSR from the idle loop, so the RTS lands back in it
+    fn call(&mut self, addr: u16) {
+        self.cpu.program_counter = IDLE_LOOP;
+        self.cpu.stack_push_u16(IDLE_LOOP.wrapping_sub(1));
+        self.cpu.program_counter = addr;
+    }
+
+    fn idle(&self) -> bool {
+        self.cpu.program_counter == IDLE_LOOP
+    }
+
+    // runs about a frame and hands back the audio, at sample_rate()
+    pub fn step_frame(&mut self) -> Vec<f32> {
+        let end = self.cpu.cycles + CHUNK_CYCLES;
+        while self.cpu.cycles < end {
+            // a PLAY that's still going when the next one's due just makes it late
+            if self.idle() && self.cpu.cycles as f64 >= self.next_play {
+                self.call(self.nsf.play_address);
+                self.next_play += self.play_period;
+            }
+            self.cpu.step();
+        }
+
+        self.cpu.bus.apu.end_frame()
+    }
+
+    // `length_ms` of the current track, fading out over the last `fade_ms`
+    pub fn render(&mut self, length_ms: u32, fade_ms: u32) -> Vec<f32> {
+        let total = (u64::from(length_ms) * u64::from(self.sample_rate) / 1000) as usize;
+        let fade = ((u64::from(fade_ms) * u64::from(self.sample_rate) / 1000) as usize).min(total);
+
+        let mut samples = Vec::with_capacity(total);
+        while samples.len() < total {
+            samples.extend(self.step_frame());
+        }
+        samples.truncate(total);
+
+        let fade_start = total - fade;
+        for (i, sample) in samples[fade_start..].iter_mut().enumerate() {
+            *sample *= 1.0 - i as f32 / fade as f32;
+        }
+        samples
+    }
+
+    // plays `track` from the start for as long as the file says, or the defaults if it doesn't
+    pub fn render_track(&mut self, track: u8) -> Vec<f32> {
+        let info = self.nsf.track(track);
+        self.start_track(track);
+        self.render(info.length.unwrap_or(DEFAULT_TRACK_LENGTH), info.fade.unwrap_or(DEFAULT_FADE))
+    }
+}
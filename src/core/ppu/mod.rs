@@ -0,0 +1,3 @@
+pub mod palette;
+pub mod picture_processor;
+pub mod registers;
\ No newline at end of file
@@ -0,0 +1,154 @@
+use std::fmt;
+use std::fs;
+use std::path::Path;
+use crate::core::ppu::registers::PPUMask;
+
+pub type Rgb = [u8; 3];
+
+// 64 base colours, then the same 64 for each of the 7 emphasis combinations
+pub const PALETTE_ENTRIES: usize = 64 * 8;
+
+// how much the non-emphasized channels get dimmed when we have to make up the emphasis colours ourselves
+const EMPHASIS_ATTENUATION: f32 = 0.816_328;
+
+const DEFAULT_COLORS: [Rgb; 64] = [
+    [0x80, 0x80, 0x80], [0x00, 0x3d, 0xa6], [0x00, 0x12, 0xb0], [0x44, 0x00, 0x96],
+    [0xa1, 0x00, 0x5e], [0xc7, 0x00, 0x28], [0xba, 0x06, 0x00], [0x8c, 0x17, 0x00],
+    [0x5c, 0x2f, 0x00], [0x10, 0x45, 0x00], [0x05, 0x4a, 0x00], [0x00, 0x47, 0x2e],
+    [0x00, 0x41, 0x66], [0x00, 0x00, 0x00], [0x05, 0x05, 0x05], [0x05, 0x05, 0x05],
+
+    [0xc7, 0xc7, 0xc7], [0x00, 0x77, 0xff], [0x21, 0x55, 0xff], [0x82, 0x37, 0xfa],
+    [0xeb, 0x2f, 0xb5], [0xff, 0x29, 0x50], [0xff, 0x22, 0x00], [0xd6, 0x32, 0x00],
+    [0xc4, 0x62, 0x00], [0x35, 0x80, 0x00], [0x05, 0x8f, 0x00], [0x00, 0x8a, 0x55],
+    [0x00, 0x99, 0xcc], [0x21, 0x21, 0x21], [0x09, 0x09, 0x09], [0x09, 0x09, 0x09],
+
+    [0xff, 0xff, 0xff], [0x0f, 0xd7, 0xff], [0x69, 0xa2, 0xff], [0xd4, 0x80, 0xff],
+    [0xff, 0x45, 0xf3], [0xff, 0x61, 0x8b], [0xff, 0x88, 0x33], [0xff, 0x9c, 0x12],
+    [0xfa, 0xbc, 0x20], [0x9f, 0xe3, 0x0e], [0x2b, 0xf0, 0x35], [0x0c, 0xf0, 0xa4],
+    [0x05, 0xfb, 0xff], [0x5e, 0x5e, 0x5e], [0x0d, 0x0d, 0x0d], [0x0d, 0x0d, 0x0d],
+
+    [0xff, 0xff, 0xff], [0xa6, 0xfc, 0xff], [0xb3, 0xec, 0xff], [0xda, 0xab, 0xeb],
+    [0xff, 0xa8, 0xf9], [0xff, 0xab, 0xb3], [0xff, 0xd2, 0xb0], [0xff, 0xef, 0xa6],
+    [0xff, 0xf7, 0x9c], [0xd7, 0xe8, 0x95], [0xa6, 0xed, 0xaf], [0xa2, 0xf2, 0xda],
+    [0x99, 0xff, 0xfc], [0xdd, 0xdd, 0xdd], [0x11, 0x11, 0x11], [0x11, 0x11, 0x11],
+];
+
+#[derive(Debug)]
+pub enum PaletteError {
+    Io(std::io::Error),
+    InvalidLength(usize),
+}
+
+impl fmt::Display for PaletteError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            PaletteError::Io(e) => write!(f, "couldn't read palette: {e}"),
+            PaletteError::InvalidLength(len) => write!(
+                f, "palette is {len} bytes, expected 192 (64 colours) or 1536 (512 colours)"
+            ),
+        }
+    }
+}
+
+impl std::error::Error for PaletteError {}
+
+impl From<std::io::Error> for PaletteError {
+    fn from(e: std::io::Error) -> Self {
+        PaletteError::Io(e)
+    }
+}
+
+#[derive(Clone, PartialEq, Eq)]
+pub struct Palette {
+    colors: Box<[Rgb; PALETTE_ENTRIES]>,
+}
+
+impl Default for Palette {
+    fn default() -> Self {
+        Palette::from_base_colors(&DEFAULT_COLORS)
+    }
+}
+
+impl fmt::Debug for Palette {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("Palette").field("colors", &self.colors.len()).finish()
+    }
+}
+
+impl Palette {
+    // .pal files are raw RGB triplets, either just the 64 base colours or
+    // all 512 with the emphasis variants already baked in
+    pub fn from_pal_bytes(data: &[u8]) -> Result<Self, PaletteError> {
+        let entries = match data.len() {
+            192 => 64,
+            1536 => PALETTE_ENTRIES,
+            len => return Err(PaletteError::InvalidLength(len)),
+        };
+
+        let mut colors = [[0u8; 3]; PALETTE_ENTRIES];
+        for (color, rgb) in colors.iter_mut().zip(data.chunks_exact(3)).take(entries) {
+            color.copy_from_slice(rgb);
+        }
+
+        if entries == 64 {
+            let mut base = [[0u8; 3]; 64];
+            base.copy_from_slice(&colors[..64]);
+            return Ok(Palette::from_base_colors(&base));
+        }
+
+        Ok(Palette { colors: Box::new(colors) })
+    }
+
+    pub fn load_pal_file<P: AsRef<Path>>(path: P) -> Result<Self, PaletteError> {
+        let data = fs::read(path)?;
+        Palette::from_pal_bytes(&data)
+    }
+
+    fn from_base_colors(base: &[Rgb; 64]) -> Self {
+        let mut colors = [[0u8; 3]; PALETTE_ENTRIES];
+
+        for emphasis in 0..8usize {
+            let red = emphasis & 0b001 != 0;
+            let green = emphasis & 0b010 != 0;
+            let blue = emphasis & 0b100 != 0;
+
+            for (index, color) in base.iter().enumerate() {
+                let mut rgb = *color;
+
+                // the black columns don't get touched by emphasis on real hardware
+                if index & 0x0e != 0x0e {
+                    if green || blue { rgb[0] = attenuate(rgb[0]); }
+                    if red || blue { rgb[1] = attenuate(rgb[1]); }
+                    if red || green { rgb[2] = attenuate(rgb[2]); }
+                }
+
+                colors[emphasis << 6 | index] = rgb;
+            }
+        }
+
+        Palette { colors: Box::new(colors) }
+    }
+
+    // index is the 6-bit value out of palette RAM, mask is whatever PPUMASK was when the pixel got drawn
+    pub fn rgb(&self, index: u8, mask: PPUMask) -> Rgb {
+        let mut mask = mask;
+
+        let mut index = index & 0x3f;
+        if mask.greyscale().get_raw() == 1 { index &= 0x30; }
+
+        self.entry((u16::from(mask.emphasis()) << 6) | u16::from(index))
+    }
+
+    // raw 9-bit lookup, emphasis in bits 6-8
+    pub fn entry(&self, entry: u16) -> Rgb {
+        self.colors[entry as usize % PALETTE_ENTRIES]
+    }
+
+    pub fn to_pal_bytes(&self) -> Vec<u8> {
+        self.colors.iter().flatten().copied().collect()
+    }
+}
+
+fn attenuate(channel: u8) -> u8 {
+    (f32::from(channel) * EMPHASIS_ATTENUATION) as u8
+}
@@ -0,0 +1,502 @@
+use bit_struct::*;
+use crate::core::cartridge::mapper::Mapper;
+use crate::core::ppu::registers::{PPUCtrl, PPUMask, PPUStatus};
+use crate::core::region::{Region, RegionTiming};
+use crate::core::state::snapshot::Snapshot;
+use crate::core::state::stream::{StateError, StateReader, StateWriter};
+
+pub const SCREEN_WIDTH: usize = 256;
+pub const SCREEN_HEIGHT: usize = 240;
+
+const DOTS_PER_SCANLINE: u16 = 341;
+
+// a sprite that made it through evaluation for the scanline being drawn
+#[derive(Copy, Clone)]
+struct LineSprite {
+    x: u8,
+    low: u8,
+    high: u8,
+    attributes: u8,
+    sprite_zero: bool,
+}
+
+pub struct PictureProcessor {
+    pub ctrl: PPUCtrl,
+    pub mask: PPUMask,
+    pub status: PPUStatus,
+    pub oam_addr: u8,
+    pub oam: [u8; 256],
+    vram: [u8; 0x1000],
+    palette_ram: [u8; 32],
+
+    // loopy's v/t/x/w scroll registers
+    v: u16,
+    t: u16,
+    fine_x: u8,
+    write_latch: bool,
+
+    read_buffer: u8,
+    io_latch: u8,
+
+    pub scanline: u16,
+    pub dot: u16,
+    pub frame: u64,
+    odd_frame: bool,
+
+    nmi_pending: bool,
+    pub frame_complete: bool,
+    sprite_zero_hit_dot: Option<u16>,
+
+    // 9-bit palette entries (emphasis in bits 6-8), ready for Palette::entry
+    framebuffer: Vec<u16>,
+    timing: &'static RegionTiming,
+}
+
+impl PictureProcessor {
+    pub fn new(region: Region) -> Self {
+        PictureProcessor {
+            ctrl: PPUCtrl::default(),
+            mask: PPUMask::default(),
+            status: PPUStatus::default(),
+            oam_addr: 0,
+            oam: [0; 256],
+            vram: [0; 0x1000],
+            palette_ram: [0; 32],
+            v: 0,
+            t: 0,
+            fine_x: 0,
+            write_latch: false,
+            read_buffer: 0,
+            io_latch: 0,
+            scanline: 0,
+            dot: 0,
+            frame: 0,
+            odd_frame: false,
+            nmi_pending: false,
+            frame_complete: false,
+            sprite_zero_hit_dot: None,
+            framebuffer: vec![0; SCREEN_WIDTH * SCREEN_HEIGHT],
+            timing: region.timing(),
+        }
+    }
+
+    // what the reset line does: registers go quiet, memory stays
+    pub fn reset(&mut self) {
+        self.ctrl = PPUCtrl::default();
+        self.mask = PPUMask::default();
+        self.write_latch = false;
+        self.read_buffer = 0;
+        self.fine_x = 0;
+        self.t = 0;
+    }
+
+    pub fn framebuffer(&self) -> &[u16] {
+        &self.framebuffer
+    }
+
+    pub fn poll_nmi(&mut self) -> bool {
+        let nmi = self.nmi_pending;
+        self.nmi_pending = false;
+        nmi
+    }
+
+    fn pre_render_scanline(&self) -> u16 {
+        self.timing.scanlines_per_frame - 1
+    }
+
+    fn palette_index(addr: u16) -> usize {
+        let index = addr as usize & 0x1f;
+
+        // $3F10/$3F14/$3F18/$3F1C are mirrors of the backdrop entries
+        if index >= 0x10 && index.is_multiple_of(4) { index - 0x10 } else { index }
+    }
+
+    fn vram_read(&mut self, addr: u16, mapper: &mut dyn Mapper) -> u8 {
+        let addr = addr & 0x3fff;
+
+        match addr {
+            0x0000..=0x1fff => mapper.ppu_read(addr),
+            0x2000..=0x3eff => self.vram[mapper.mirroring().nametable_offset(addr)],
+            _ => self.palette_ram[Self::palette_index(addr)],
+        }
+    }
+
+    fn vram_write(&mut self, addr: u16, data: u8, mapper: &mut dyn Mapper) {
+        let addr = addr & 0x3fff;
+
+        match addr {
+            0x0000..=0x1fff => mapper.ppu_write(addr, data),
+            0x2000..=0x3eff => self.vram[mapper.mirroring().nametable_offset(addr)] = data,
+            _ => self.palette_ram[Self::palette_index(addr)] = data & 0x3f,
+        }
+    }
+
+    fn increment_vram_addr(&mut self) {
+        self.v = self.v.wrapping_add(self.ctrl.vram_increment()) & 0x7fff;
+    }
+
+    pub fn read_register(&mut self, addr: u16, mapper: &mut dyn Mapper) -> u8 {
+        match addr & 0x7 {
+            2 => {
+                let mut status = self.status;
+                status.open_bus().set(u5::new(self.io_latch & 0x1f).unwrap());
+                self.status.vblank_started().set(u1!(0));
+                self.write_latch = false;
+                self.io_latch = status.raw();
+            }
+            4 => self.io_latch = self.oam[self.oam_addr as usize],
+            7 => {
+                let addr = self.v & 0x3fff;
+
+                if addr >= 0x3f00 {
+                    // palette reads skip the buffer, but the buffer still picks up the nametable underneath
+                    self.read_buffer = self.vram_read(addr - 0x1000, mapper);
+                    self.io_latch = (self.io_latch & 0xc0) | self.vram_read(addr, mapper);
+                } else {
+                    self.io_latch = self.read_buffer;
+                    self.read_buffer = self.vram_read(addr, mapper);
+                }
+
+                self.increment_vram_addr();
+            }
+            // the write-only ones just hand back whatever's left on the bus
+            _ => {}
+        }
+
+        self.io_latch
+    }
+
+    pub fn write_register(&mut self, addr: u16, data: u8, mapper: &mut dyn Mapper) {
+        self.io_latch = data;
+
+        match addr & 0x7 {
+            0 => {
+                let nmi_was_enabled = self.ctrl.generate_nmi().get_raw() == 1;
+                self.ctrl = PPUCtrl::from_bits(data);
+                self.t = (self.t & 0xf3ff) | (u16::from(data & 0b11) << 10);
+
+                // turning NMIs on in the middle of vblank fires one straight away
+                if !nmi_was_enabled
+                    && self.ctrl.generate_nmi().get_raw() == 1
+                    && self.status.vblank_started().get_raw() == 1 {
+                    self.nmi_pending = true;
+                }
+            }
+            1 => self.mask = PPUMask::from_bits(data),
+            3 => self.oam_addr = data,
+            4 => {
+                self.oam[self.oam_addr as usize] = data;
+                self.oam_addr = self.oam_addr.wrapping_add(1);
+            }
+            5 => {
+                if !self.write_latch {
+                    self.t = (self.t & !0x001f) | u16::from(data >> 3);
+                    self.fine_x = data & 0b111;
+                } else {
+                    self.t = (self.t & 0x8c1f)
+                        | (u16::from(data & 0b111) << 12)
+                        | (u16::from(data >> 3) << 5);
+                }
+                self.write_latch = !self.write_latch;
+            }
+            6 => {
+                if !self.write_latch {
+                    self.t = (self.t & 0x00ff) | (u16::from(data & 0x3f) << 8);
+                } else {
+                    self.t = (self.t & 0xff00) | u16::from(data);
+                    self.v = self.t;
+                }
+                self.write_latch = !self.write_latch;
+            }
+            7 => {
+                self.vram_write(self.v, data, mapper);
+                self.increment_vram_addr();
+            }
+            _ => {}
+        }
+    }
+
+    // $4014, the bus does the actual reading
+    pub fn write_oam_dma(&mut self, data: &[u8; 256]) {
+        for byte in data.iter() {
+            self.oam[self.oam_addr as usize] = *byte;
+            self.oam_addr = self.oam_addr.wrapping_add(1);
+        }
+    }
+
+    pub fn tick(&mut self, dots: u32, mapper: &mut dyn Mapper) {
+        for _ in 0..dots {
+            self.step_dot(mapper);
+        }
+    }
+
+    fn step_dot(&mut self, mapper: &mut dyn Mapper) {
+        let rendering = self.mask.rendering_enabled();
+        let visible = (self.scanline as usize) < SCREEN_HEIGHT;
+        let pre_render = self.scanline == self.pre_render_scanline();
+
+        if visible && self.dot == 1 {
+            self.render_scanline(mapper);
+        }
+
+        if let Some(hit_dot) = self.sprite_zero_hit_dot {
+            if self.dot >= hit_dot {
+                self.status.sprite_zero_hit().set(u1!(1));
+                self.sprite_zero_hit_dot = None;
+            }
+        }
+
+        if rendering && (visible || pre_render) {
+            match self.dot {
+                256 => self.increment_y(),
+                257 => self.v = (self.v & !0x041f) | (self.t & 0x041f),
+                260 => mapper.scanline(),
+                280..=304 if pre_render => self.v = (self.v & !0x7be0) | (self.t & 0x7be0),
+                _ => {}
+            }
+        }
+
+        if self.scanline == self.timing.vblank_scanline && self.dot == 1 {
+            self.status.vblank_started().set(u1!(1));
+            self.frame_complete = true;
+            if self.ctrl.generate_nmi().get_raw() == 1 { self.nmi_pending = true; }
+        }
+
+        if pre_render && self.dot == 1 {
+            self.status.vblank_started().set(u1!(0));
+            self.status.sprite_zero_hit().set(u1!(0));
+            self.status.sprite_overflow().set(u1!(0));
+        }
+
+        self.dot += 1;
+
+        // NTSC drops the last dot of the pre-render line on odd frames while rendering
+        if pre_render && self.dot == DOTS_PER_SCANLINE - 1
+            && self.odd_frame && rendering && self.timing.odd_frame_skip {
+            self.dot += 1;
+        }
+
+        if self.dot >= DOTS_PER_SCANLINE {
+            self.dot = 0;
+            self.scanline += 1;
+
+            if self.scanline >= self.timing.scanlines_per_frame {
+                self.scanline = 0;
+                self.frame += 1;
+                self.odd_frame = !self.odd_frame;
+            }
+        }
+    }
+
+    fn increment_y(&mut self) {
+        if self.v & 0x7000 != 0x7000 {
+            self.v += 0x1000;
+            return;
+        }
+
+        self.v &= !0x7000;
+        let mut coarse_y = (self.v & 0x03e0) >> 5;
+        if coarse_y == 29 {
+            coarse_y = 0;
+            self.v ^= 0x0800;
+        } else if coarse_y == 31 {
+            coarse_y = 0;
+        } else {
+            coarse_y += 1;
+        }
+        self.v = (self.v & !0x03e0) | (coarse_y << 5);
+    }
+
+    // draws the whole line in one go off the current scroll position.
+    // not dot-exact, but mid-frame scroll splits between lines still work
+    fn render_scanline(&mut self, mapper: &mut dyn Mapper) {
+        let y = self.scanline as usize;
+        let mut background = [0u8; SCREEN_WIDTH];
+
+        if self.mask.show_background().get_raw() == 1 {
+            self.render_background_line(&mut background, mapper);
+        }
+
+        let sprites = if self.mask.show_sprites().get_raw() == 1 { self.evaluate_sprites(mapper) }
+            else { Vec::new() };
+
+        let show_background_left = self.mask.show_background_left().get_raw() == 1;
+        let show_sprites_left = self.mask.show_sprites_left().get_raw() == 1;
+        let emphasis = u16::from(self.timing.normalize_emphasis(self.mask.emphasis())) << 6;
+        let greyscale = self.mask.greyscale().get_raw() == 1;
+
+        for (x, bg_pixel) in background.iter().enumerate() {
+            let mut bg_pixel = *bg_pixel;
+            if x < 8 && !show_background_left { bg_pixel = 0; }
+
+            let mut color = if bg_pixel & 0b11 != 0 { bg_pixel } else { 0 };
+
+            if x >= 8 || show_sprites_left {
+                if let Some((sprite_pixel, behind, sprite_zero)) = sprite_pixel_at(&sprites, x) {
+                    if sprite_zero && bg_pixel & 0b11 != 0 && x != 255 && self.sprite_zero_hit_dot.is_none()
+                        && self.status.sprite_zero_hit().get_raw() == 0 {
+                        self.sprite_zero_hit_dot = Some(x as u16 + 1);
+                    }
+
+                    if !behind || bg_pixel & 0b11 == 0 { color = sprite_pixel; }
+                }
+            }
+
+            let mut index = self.palette_ram[Self::palette_index(0x3f00 | u16::from(color))];
+            if greyscale { index &= 0x30; }
+
+            self.framebuffer[y * SCREEN_WIDTH + x] = emphasis | u16::from(index);
+        }
+    }
+
+    fn render_background_line(&mut self, line: &mut [u8; SCREEN_WIDTH], mapper: &mut dyn Mapper) {
+        let fine_y = (self.v >> 12) & 0b111;
+        let coarse_y = (self.v >> 5) & 0x1f;
+        let coarse_x = self.v & 0x1f;
+        let nametable = (self.v >> 10) & 0b11;
+        let pattern_table = self.ctrl.background_table();
+
+        // 33 tiles, since fine x can push the last one partly on screen
+        for tile in 0..33u16 {
+            let column = coarse_x + tile;
+            let nametable = nametable ^ (column / 32 % 2);
+            let column = column % 32;
+
+            let base = 0x2000 | (nametable << 10);
+            let tile_index = self.vram_read(base | (coarse_y << 5) | column, mapper);
+            let attribute = self.vram_read(base | 0x03c0 | ((coarse_y >> 2) << 3) | (column >> 2), mapper);
+            let shift = ((coarse_y & 0b10) << 1) | (column & 0b10);
+            let palette = ((attribute >> shift) & 0b11) << 2;
+
+            let pattern_addr = pattern_table + u16::from(tile_index) * 16 + fine_y;
+            let low = self.vram_read(pattern_addr, mapper);
+            let high = self.vram_read(pattern_addr + 8, mapper);
+
+            for bit in 0..8u16 {
+                let x = (tile * 8 + bit) as i32 - i32::from(self.fine_x);
+                if !(0..SCREEN_WIDTH as i32).contains(&x) { continue; }
+
+                let pixel = ((low >> (7 - bit)) & 1) | (((high >> (7 - bit)) & 1) << 1);
+                line[x as usize] = if pixel == 0 { 0 } else { palette | pixel };
+            }
+        }
+    }
+
+    fn evaluate_sprites(&mut self, mapper: &mut dyn Mapper) -> Vec<LineSprite> {
+        let height = self.ctrl.sprite_height();
+        let mut sprites = Vec::with_capacity(8);
+
+        for index in 0..64 {
+            let entry = &self.oam[index * 4 .. index * 4 + 4];
+            let (sprite_y, tile, attributes, x) = (u16::from(entry[0]), entry[1], entry[2], entry[3]);
+
+            // OAM holds the line above the sprite's first one
+            let row = self.scanline.wrapping_sub(sprite_y + 1);
+            if row >= height { continue; }
+
+            if sprites.len() == 8 {
+                self.status.sprite_overflow().set(u1!(1));
+                break;
+            }
+
+            let row = if attributes & 0x80 != 0 { height - 1 - row } else { row };
+            let pattern_addr = if height == 16 {
+                let table = u16::from(tile & 1) * 0x1000;
+                let tile = u16::from(tile & 0xfe) + row / 8;
+                table + tile * 16 + row % 8
+            } else {
+                self.ctrl.sprite_table() + u16::from(tile) * 16 + row
+            };
+
+            let mut low = self.vram_read(pattern_addr, mapper);
+            let mut high = self.vram_read(pattern_addr + 8, mapper);
+            if attributes & 0x40 != 0 {
+                low = low.reverse_bits();
+                high = high.reverse_bits();
+            }
+
+            sprites.push(LineSprite { x, low, high, attributes, sprite_zero: index == 0 });
+        }
+
+        sprites
+    }
+}
+
+// first opaque sprite pixel wins: (palette index into $3F10+, behind background, is sprite 0)
+fn sprite_pixel_at(sprites: &[LineSprite], x: usize) -> Option<(u8, bool, bool)> {
+    for sprite in sprites {
+        let offset = x.wrapping_sub(sprite.x as usize);
+        if offset >= 8 { continue; }
+
+        let bit = 7 - offset;
+        let pixel = ((sprite.low >> bit) & 1) | (((sprite.high >> bit) & 1) << 1);
+        if pixel == 0 { continue; }
+
+        let palette = 0x10 | ((sprite.attributes & 0b11) << 2);
+        return Some((palette | pixel, sprite.attributes & 0x20 != 0, sprite.sprite_zero));
+    }
+
+    None
+}
+
+impl Snapshot for PictureProcessor {
+    fn save_state(&self, state: &mut StateWriter) {
+        state.write_u8(self.ctrl.raw());
+        state.write_u8(self.mask.raw());
+        state.write_u8(self.status.raw());
+        state.write_u8(self.oam_addr);
+        state.write_bytes(&self.oam);
+        state.write_bytes(&self.vram);
+        state.write_bytes(&self.palette_ram);
+        state.write_u16(self.v);
+        state.write_u16(self.t);
+        state.write_u8(self.fine_x);
+        state.write_bool(self.write_latch);
+        state.write_u8(self.read_buffer);
+        state.write_u8(self.io_latch);
+        state.write_u16(self.scanline);
+        state.write_u16(self.dot);
+        state.write_u64(self.frame);
+        state.write_bool(self.odd_frame);
+        state.write_bool(self.nmi_pending);
+        state.write_bool(self.frame_complete);
+        state.write_u16(self.sprite_zero_hit_dot.unwrap_or(u16::MAX));
+
+        // so a state loaded mid-frame still has the top of the picture
+        let framebuffer: Vec<u8> = self.framebuffer.iter().flat_map(|entry| entry.to_le_bytes()).collect();
+        state.write_bytes(&framebuffer);
+    }
+
+    fn load_state(&mut self, state: &mut StateReader) -> Result<(), StateError> {
+        self.ctrl = PPUCtrl::from_bits(state.read_u8()?);
+        self.mask = PPUMask::from_bits(state.read_u8()?);
+        self.status = PPUStatus::from_bits(state.read_u8()?);
+        self.oam_addr = state.read_u8()?;
+        state.read_bytes_into(&mut self.oam)?;
+        state.read_bytes_into(&mut self.vram)?;
+        state.read_bytes_into(&mut self.palette_ram)?;
+        self.v = state.read_u16()? & 0x7fff;
+        self.t = state.read_u16()? & 0x7fff;
+        self.fine_x = state.read_u8()? & 0b111;
+        self.write_latch = state.read_bool()?;
+        self.read_buffer = state.read_u8()?;
+        self.io_latch = state.read_u8()?;
+        self.scanline = state.read_u16()?;
+        self.dot = state.read_u16()?;
+        if self.scanline >= self.timing.scanlines_per_frame || self.dot >= DOTS_PER_SCANLINE {
+            return Err(StateError::Corrupt("PPU position is off the end of the frame"));
+        }
+        self.frame = state.read_u64()?;
+        self.odd_frame = state.read_bool()?;
+        self.nmi_pending = state.read_bool()?;
+        self.frame_complete = state.read_bool()?;
+        self.sprite_zero_hit_dot = Some(state.read_u16()?).filter(|dot| *dot != u16::MAX);
+
+        let mut framebuffer = vec![0u8; self.framebuffer.len() * 2];
+        state.read_bytes_into(&mut framebuffer)?;
+        for (entry, bytes) in self.framebuffer.iter_mut().zip(framebuffer.chunks_exact(2)) {
+            *entry = u16::from_le_bytes([bytes[0], bytes[1]]);
+        }
+        Ok(())
+    }
+}
@@ -0,0 +1,100 @@
+use bit_struct::*;
+
+bit_struct! {
+    // $2001, bits 5-7 get swapped around on PAL machines (red/green)
+    pub struct PPUMask(u8) {
+        emphasize_blue: u1,
+        emphasize_green: u1,
+        emphasize_red: u1,
+        show_sprites: u1,
+        show_background: u1,
+        show_sprites_left: u1,
+        show_background_left: u1,
+        greyscale: u1
+    }
+}
+
+impl Default for PPUMask {
+    fn default() -> Self {
+        PPUMask::of_defaults()
+    }
+}
+
+impl PPUMask {
+    pub fn from_bits(data: u8) -> Self {
+        PPUMask::exact_from(data)
+    }
+
+    pub fn emphasis(&self) -> u8 {
+        self.raw() >> 5
+    }
+
+    pub fn rendering_enabled(&self) -> bool {
+        self.raw() & 0b0001_1000 != 0
+    }
+}
+
+
+bit_struct! {
+    // $2000
+    pub struct PPUCtrl(u8) {
+        generate_nmi: u1,
+        master_slave: u1,
+        sprite_size: u1,
+        background_pattern_addr: u1,
+        sprite_pattern_addr: u1,
+        vram_add_increment: u1,
+        nametable_high: u1,
+        nametable_low: u1
+    }
+}
+
+impl Default for PPUCtrl {
+    fn default() -> Self {
+        PPUCtrl::of_defaults()
+    }
+}
+
+impl PPUCtrl {
+    pub fn from_bits(data: u8) -> Self {
+        PPUCtrl::exact_from(data)
+    }
+
+    pub fn vram_increment(&mut self) -> u16 {
+        if self.vram_add_increment().get_raw() == 1 { 32 } else { 1 }
+    }
+
+    pub fn background_table(&mut self) -> u16 {
+        u16::from(self.background_pattern_addr().get_raw()) * 0x1000
+    }
+
+    pub fn sprite_table(&mut self) -> u16 {
+        u16::from(self.sprite_pattern_addr().get_raw()) * 0x1000
+    }
+
+    pub fn sprite_height(&mut self) -> u16 {
+        if self.sprite_size().get_raw() == 1 { 16 } else { 8 }
+    }
+}
+
+bit_struct! {
+    // $2002, the bottom 5 bits are whatever was last on the PPU's data bus
+    pub struct PPUStatus(u8) {
+        vblank_started: u1,
+        sprite_zero_hit: u1,
+        sprite_overflow: u1,
+        open_bus: u5
+    }
+}
+
+impl Default for PPUStatus {
+    fn default() -> Self {
+        PPUStatus::of_defaults()
+    }
+}
+
+impl PPUStatus {
+    pub fn from_bits(data: u8) -> Self {
+        PPUStatus::exact_from(data)
+    }
+}
@@ -0,0 +1,121 @@
+#[derive(Debug, Copy, Clone, Eq, PartialEq, Default)]
+pub enum Region {
+    #[default]
+    Ntsc,
+    Pal,
+    Dendy,
+}
+
+#[derive(Debug, Clone, Eq, PartialEq)]
+pub struct RegionTiming {
+    pub master_clock: u32,
+    pub cpu_divider: u32,
+    pub ppu_divider: u32,
+    pub scanlines_per_frame: u16,
+    // first scanline of vblank, the one the NMI fires on. it lasts up to the pre-render line
+    pub vblank_scanline: u16,
+    pub odd_frame_skip: bool,
+    // PAL style PPUs wire PPUMASK bits 5 and 6 the other way around
+    pub swapped_emphasis: bool,
+    // CPU cycles after the $4017 write where each frame counter step lands
+    pub frame_counter_4_step: [u32; 4],
+    pub frame_counter_5_step: [u32; 5],
+    pub dmc_periods: [u16; 16],
+    pub noise_periods: [u16; 16],
+}
+
+const NTSC_DMC_PERIODS: [u16; 16] = [
+    428, 380, 340, 320, 286, 254, 226, 214, 190, 160, 142, 128, 106, 84, 72, 54,
+];
+
+const NTSC_NOISE_PERIODS: [u16; 16] = [
+    4, 8, 16, 32, 64, 96, 128, 160, 202, 254, 380, 508, 762, 1016, 2034, 4068,
+];
+
+const NTSC_TIMING: RegionTiming = RegionTiming {
+    master_clock: 21_477_272,
+    cpu_divider: 12,
+    ppu_divider: 4,
+    scanlines_per_frame: 262,
+    vblank_scanline: 241,
+    odd_frame_skip: true,
+    swapped_emphasis: false,
+    frame_counter_4_step: [7457, 14913, 22371, 29829],
+    frame_counter_5_step: [7457, 14913, 22371, 29829, 37281],
+    dmc_periods: NTSC_DMC_PERIODS,
+    noise_periods: NTSC_NOISE_PERIODS,
+};
+
+const PAL_TIMING: RegionTiming = RegionTiming {
+    master_clock: 26_601_712,
+    cpu_divider: 16,
+    ppu_divider: 5,
+    scanlines_per_frame: 312,
+    vblank_scanline: 241,
+    odd_frame_skip: false,
+    swapped_emphasis: true,
+    frame_counter_4_step: [8313, 16627, 24939, 33253],
+    frame_counter_5_step: [8313, 16627, 24939, 33253, 41565],
+    dmc_periods: [
+        398, 354, 316, 298, 276, 236, 210, 198, 176, 148, 132, 118, 98, 78, 66, 50,
+    ],
+    noise_periods: [
+        4, 8, 14, 30, 60, 88, 118, 148, 188, 236, 354, 472, 708, 944, 1890, 3778,
+    ],
+};
+
+// the dendy clones run a PAL master clock with NTSC-ish CPU/APU behaviour,
+// and push vblank 50 lines later so the NMI handler still gets 20 lines
+const DENDY_TIMING: RegionTiming = RegionTiming {
+    master_clock: 26_601_712,
+    cpu_divider: 15,
+    ppu_divider: 5,
+    scanlines_per_frame: 312,
+    vblank_scanline: 291,
+    odd_frame_skip: false,
+    swapped_emphasis: true,
+    frame_counter_4_step: [7457, 14913, 22371, 29829],
+    frame_counter_5_step: [7457, 14913, 22371, 29829, 37281],
+    dmc_periods: NTSC_DMC_PERIODS,
+    noise_periods: NTSC_NOISE_PERIODS,
+};
+
+impl Region {
+    pub fn timing(&self) -> &'static RegionTiming {
+        match self {
+            Region::Ntsc => &NTSC_TIMING,
+            Region::Pal => &PAL_TIMING,
+            Region::Dendy => &DENDY_TIMING,
+        }
+    }
+}
+
+impl RegionTiming {
+    pub fn cpu_clock_rate(&self) -> f64 {
+        f64::from(self.master_clock) / f64::from(self.cpu_divider)
+    }
+
+    pub fn ppu_clock_rate(&self) -> f64 {
+        f64::from(self.master_clock) / f64::from(self.ppu_divider)
+    }
+
+    pub fn dots_per_frame(&self) -> u32 {
+        341 * u32::from(self.scanlines_per_frame)
+    }
+
+    pub fn frame_rate(&self) -> f64 {
+        self.ppu_clock_rate() / f64::from(self.dots_per_frame())
+    }
+
+    // 3 on NTSC and dendy, 3.2 on PAL, kept as a fraction so nothing drifts
+    pub fn ppu_dots_per_cpu_cycle(&self) -> (u32, u32) {
+        (self.cpu_divider, self.ppu_divider)
+    }
+
+    // red/green emphasis bits as the palette expects them (NTSC order)
+    pub fn normalize_emphasis(&self, emphasis: u8) -> u8 {
+        if !self.swapped_emphasis { return emphasis; }
+
+        (emphasis & 0b100) | ((emphasis & 0b001) << 1) | ((emphasis & 0b010) >> 1)
+    }
+}
@@ -0,0 +1,66 @@
+// states from one frame to the next are mostly the same bytes, so a delta is the XOR of the two
+// with the runs of zeroes squeezed out: pairs of (zeroes to skip, literal count) as LEB128, each
+// followed by that many literal XORed bytes
+
+fn write_varint(out: &mut Vec<u8>, mut value: usize) {
+    while value >= 0x80 {
+        out.push(value as u8 | 0x80);
+        value >>= 7;
+    }
+    out.push(value as u8);
+}
+
+fn read_varint(data: &[u8], position: &mut usize) -> Option<usize> {
+    let mut value = 0usize;
+    for shift in (0..usize::BITS).step_by(7) {
+        let byte = *data.get(*position)?;
+        *position += 1;
+        value |= usize::from(byte & 0x7f) << shift;
+        if byte & 0x80 == 0 { return Some(value); }
+    }
+    None
+}
+
+// what gets `from` to `to`, both the same length
+pub fn encode(from: &[u8], to: &[u8]) -> Vec<u8> {
+    assert_eq!(from.len(), to.len(), "deltas are between states of the same size");
+
+    let mut out = Vec::new();
+    let mut position = 0;
+    while position < to.len() {
+        let zeroes = from[position..].iter().zip(&to[position..]).take_while(|(a, b)| a == b).count();
+        position += zeroes;
+        if position == to.len() { break; }
+
+        // a literal run ends at the first few matching bytes, shorter gaps cost more to skip than to copy
+        let start = position;
+        while position < to.len() {
+            let gap = from[position..].iter().zip(&to[position..]).take(4).take_while(|(a, b)| a == b).count();
+            if gap == 4 || position + gap == to.len() { break; }
+            position += gap.max(1);
+        }
+
+        write_varint(&mut out, zeroes);
+        write_varint(&mut out, position - start);
+        out.extend(from[start..position].iter().zip(&to[start..position]).map(|(a, b)| a ^ b));
+    }
+    out
+}
+
+// `base` with a delta from encode() applied. None if the delta doesn't fit it
+pub fn apply(base: &[u8], delta: &[u8]) -> Option<Vec<u8>> {
+    let mut out = base.to_vec();
+    let (mut position, mut read) = (0, 0);
+
+    while read < delta.len() {
+        position += read_varint(delta, &mut read)?;
+        let len = read_varint(delta, &mut read)?;
+        let literal = delta.get(read..read + len)?;
+        for (byte, change) in out.get_mut(position..position + len)?.iter_mut().zip(literal) {
+            *byte ^= change;
+        }
+        position += len;
+        read += len;
+    }
+    Some(out)
+}
@@ -0,0 +1,58 @@
+use crate::core::region::Region;
+use crate::core::state::stream::{StateError, StateReader, StateWriter};
+
+const MAGIC: [u8; 4] = *b"NESS";
+// bump whenever any Snapshot impl changes what it writes
+pub const STATE_VERSION: u16 = 1;
+
+// what comes before the machine itself, enough to refuse a state that doesn't belong
+#[derive(Debug, Copy, Clone, Eq, PartialEq)]
+pub struct StateHeader {
+    pub rom_crc32: u32,
+    pub region: Region,
+    pub frame_number: u64,
+}
+
+impl StateHeader {
+    pub fn write(&self, state: &mut StateWriter) {
+        for byte in MAGIC { state.write_u8(byte); }
+        state.write_u16(STATE_VERSION);
+        state.write_u32(self.rom_crc32);
+        state.write_u8(match self.region {
+            Region::Ntsc => 0,
+            Region::Pal => 1,
+            Region::Dendy => 2,
+        });
+        state.write_u64(self.frame_number);
+    }
+
+    pub fn read(state: &mut StateReader) -> Result<StateHeader, StateError> {
+        for byte in MAGIC {
+            if state.read_u8().map_err(|_| StateError::NotAState)? != byte { return Err(StateError::NotAState); }
+        }
+
+        let version = state.read_u16()?;
+        if version != STATE_VERSION { return Err(StateError::UnsupportedVersion(version)); }
+
+        let rom_crc32 = state.read_u32()?;
+        let region = match state.read_u8()? {
+            0 => Region::Ntsc,
+            1 => Region::Pal,
+            2 => Region::Dendy,
+            _ => return Err(StateError::Corrupt("unknown region")),
+        };
+
+        Ok(StateHeader { rom_crc32, region, frame_number: state.read_u64()? })
+    }
+
+    // whether a console running `rom_crc32` in `region` can take this state
+    pub fn check(&self, rom_crc32: u32, region: Region) -> Result<(), StateError> {
+        if self.rom_crc32 != rom_crc32 {
+            return Err(StateError::RomMismatch { expected: rom_crc32, found: self.rom_crc32 });
+        }
+        if self.region != region {
+            return Err(StateError::RegionMismatch { expected: region, found: self.region });
+        }
+        Ok(())
+    }
+}
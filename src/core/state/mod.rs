@@ -0,0 +1,5 @@
+pub mod delta;
+pub mod header;
+pub mod rewind;
+pub mod snapshot;
+pub mod stream;
\ No newline at end of file
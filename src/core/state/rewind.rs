@@ -0,0 +1,137 @@
+use std::collections::VecDeque;
+use crate::core::state::delta;
+
+// where a step back has to start from: a snapshot, and the input for every frame after it up to
+// the one being stepped back to
+pub struct RewindPoint {
+    pub frame: u64,
+    pub state: Vec<u8>,
+    pub inputs: Vec<[u8; 4]>,
+}
+
+// a snapshot every `interval` frames plus what the pads were doing on every frame, so any frame
+// in between can be got back by re-running from the snapshot before it. the newest snapshot is
+// kept whole and each older one as a delta against the one after it, so the oldest can be
+// dropped whenever the budget runs out without touching the rest
+pub struct RewindBuffer {
+    interval: u32,
+    budget: usize,
+    newest: Option<(u64, Vec<u8>)>,
+    // oldest first
+    older: VecDeque<(u64, Vec<u8>)>,
+    older_bytes: usize,
+    // what the four players held on each frame from first_input_frame on
+    inputs: VecDeque<[u8; 4]>,
+    first_input_frame: u64,
+}
+
+impl RewindBuffer {
+    // `budget` in bytes, for snapshots and input together
+    pub fn new(interval: u32, budget: usize) -> Self {
+        RewindBuffer {
+            interval: interval.max(1),
+            budget,
+            newest: None,
+            older: VecDeque::new(),
+            older_bytes: 0,
+            inputs: VecDeque::new(),
+            first_input_frame: 0,
+        }
+    }
+
+    pub fn interval(&self) -> u32 {
+        self.interval
+    }
+
+    pub fn memory_used(&self) -> usize {
+        self.newest.as_ref().map_or(0, |(_, state)| state.len()) + self.older_bytes + self.inputs.len() * 4
+    }
+
+    pub fn snapshots(&self) -> usize {
+        self.older.len() + usize::from(self.newest.is_some())
+    }
+
+    // the earliest frame a step back can still reach
+    pub fn oldest_frame(&self) -> Option<u64> {
+        self.older.front().or(self.newest.as_ref()).map(|(frame, _)| *frame)
+    }
+
+    pub fn clear(&mut self) {
+        self.newest = None;
+        self.older.clear();
+        self.older_bytes = 0;
+        self.inputs.clear();
+    }
+
+    // the input `frame` is about to run with
+    pub fn push_input(&mut self, frame: u64, input: [u8; 4]) {
+        if self.newest.is_none() { return; }
+
+        self.inputs.truncate(frame.saturating_sub(self.first_input_frame) as usize);
+        self.inputs.push_back(input);
+        self.trim();
+    }
+
+    // whether the state as of `frame` is due to be snapshotted
+    pub fn wants_snapshot(&self, frame: u64) -> bool {
+        self.newest.as_ref().is_none_or(|(newest, _)| frame >= newest + u64::from(self.interval))
+    }
+
+    // the state after `frame` has run
+    pub fn push_snapshot(&mut self, frame: u64, state: Vec<u8>) {
+        match self.newest.take() {
+            Some((previous_frame, previous)) if previous.len() == state.len() => {
+                let delta = delta::encode(&state, &previous);
+                self.older_bytes += delta.len();
+                self.older.push_back((previous_frame, delta));
+            }
+            // a different size means a different machine, nothing before it is any use
+            _ => {
+                self.clear();
+                self.first_input_frame = frame + 1;
+            }
+        }
+
+        self.newest = Some((frame, state));
+        self.trim();
+    }
+
+    // drops everything after `target` and hands back the newest snapshot at or before it, with
+    // the input to re-run from there. None if `target` is older than anything that's left
+    pub fn rewind_to(&mut self, target: u64) -> Option<RewindPoint> {
+        if self.oldest_frame()? > target { return None; }
+
+        while let Some((frame, state)) = self.newest.take() {
+            if frame <= target {
+                self.newest = Some((frame, state));
+                break;
+            }
+
+            let (previous_frame, delta) = self.older.pop_back()?;
+            self.older_bytes -= delta.len();
+            self.newest = Some((previous_frame, delta::apply(&state, &delta)?));
+        }
+
+        let (frame, state) = self.newest.clone()?;
+        self.inputs.truncate((target + 1).saturating_sub(self.first_input_frame) as usize);
+        let inputs = self.inputs.iter()
+            .skip((frame + 1).saturating_sub(self.first_input_frame) as usize)
+            .copied()
+            .collect();
+
+        Some(RewindPoint { frame, state, inputs })
+    }
+
+    // over budget, the oldest snapshot goes along with the input that led up to the next one
+    fn trim(&mut self) {
+        while self.memory_used() > self.budget {
+            let Some((_, delta)) = self.older.pop_front() else { break };
+            self.older_bytes -= delta.len();
+
+            let oldest = self.oldest_frame().unwrap_or(self.first_input_frame);
+            let drop = (oldest + 1).saturating_sub(self.first_input_frame) as usize;
+            self.inputs.drain(..drop.min(self.inputs.len()));
+            self.first_input_frame = self.first_input_frame.max(oldest + 1);
+        }
+    }
+}
@@ -0,0 +1,8 @@
+use crate::core::state::stream::{StateError, StateReader, StateWriter};
+
+// anything with state that has to survive a save and load. load_state reads back exactly what
+// save_state wrote, in the same order, into something built for the same ROM and region
+pub trait Snapshot {
+    fn save_state(&self, state: &mut StateWriter);
+    fn load_state(&mut self, state: &mut StateReader) -> Result<(), StateError>;
+}
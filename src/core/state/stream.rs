@@ -0,0 +1,176 @@
+use std::fmt;
+use crate::core::region::Region;
+
+#[derive(Debug)]
+pub enum StateError {
+    Io(std::io::Error),
+    NotAState,
+    UnsupportedVersion(u16),
+    // the state was saved from a different ROM, both are CRC-32s of the PRG and CHR data
+    RomMismatch { expected: u32, found: u32 },
+    RegionMismatch { expected: Region, found: Region },
+    Truncated,
+    // something in the state doesn't fit the machine it's being loaded into
+    Corrupt(&'static str),
+}
+
+impl fmt::Display for StateError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            StateError::Io(e) => write!(f, "couldn't read save state: {e}"),
+            StateError::NotAState => write!(f, "not a save state"),
+            StateError::UnsupportedVersion(version) => write!(f, "save state version {version} isn't supported"),
+            StateError::RomMismatch { expected, found } => write!(
+                f, "save state is for ROM {found:08x}, this is {expected:08x}"
+            ),
+            StateError::RegionMismatch { expected, found } => write!(
+                f, "save state is from a {found:?} console, this one is {expected:?}"
+            ),
+            StateError::Truncated => write!(f, "save state is truncated"),
+            StateError::Corrupt(what) => write!(f, "save state is corrupt: {what}"),
+        }
+    }
+}
+
+impl std::error::Error for StateError {}
+
+impl From<std::io::Error> for StateError {
+    fn from(e: std::io::Error) -> Self {
+        StateError::Io(e)
+    }
+}
+
+// little-endian, fields back to back. the layout is whatever order the Snapshot impls write in
+#[derive(Debug, Clone, Default)]
+pub struct StateWriter {
+    data: Vec<u8>,
+}
+
+impl StateWriter {
+    pub fn new() -> Self {
+        StateWriter::default()
+    }
+
+    pub fn into_bytes(self) -> Vec<u8> {
+        self.data
+    }
+
+    pub fn write_u8(&mut self, value: u8) {
+        self.data.push(value);
+    }
+
+    pub fn write_bool(&mut self, value: bool) {
+        self.data.push(u8::from(value));
+    }
+
+    pub fn write_u16(&mut self, value: u16) {
+        self.data.extend_from_slice(&value.to_le_bytes());
+    }
+
+    pub fn write_u32(&mut self, value: u32) {
+        self.data.extend_from_slice(&value.to_le_bytes());
+    }
+
+    pub fn write_u64(&mut self, value: u64) {
+        self.data.extend_from_slice(&value.to_le_bytes());
+    }
+
+    // bank numbers and the like, always stored as 32 bits
+    pub fn write_usize(&mut self, value: usize) {
+        self.write_u32(value as u32);
+    }
+
+    pub fn write_f32(&mut self, value: f32) {
+        self.data.extend_from_slice(&value.to_le_bytes());
+    }
+
+    // a flag byte, then the value if there is one
+    pub fn write_option_u8(&mut self, value: Option<u8>) {
+        self.write_bool(value.is_some());
+        self.write_u8(value.unwrap_or(0));
+    }
+
+    // with the length in front, so loading into a differently sized buffer gets caught
+    pub fn write_bytes(&mut self, bytes: &[u8]) {
+        self.write_u32(bytes.len() as u32);
+        self.data.extend_from_slice(bytes);
+    }
+}
+
+pub struct StateReader<'a> {
+    data: &'a [u8],
+    position: usize,
+}
+
+impl<'a> StateReader<'a> {
+    pub fn new(data: &'a [u8]) -> Self {
+        StateReader { data, position: 0 }
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.position >= self.data.len()
+    }
+
+    fn take<const N: usize>(&mut self) -> Result<[u8; N], StateError> {
+        let bytes = self.data.get(self.position..self.position + N).ok_or(StateError::Truncated)?;
+        self.position += N;
+        Ok(bytes.try_into().unwrap())
+    }
+
+    pub fn read_u8(&mut self) -> Result<u8, StateError> {
+        Ok(self.take::<1>()?[0])
+    }
+
+    pub fn read_bool(&mut self) -> Result<bool, StateError> {
+        match self.read_u8()? {
+            0 => Ok(false),
+            1 => Ok(true),
+            _ => Err(StateError::Corrupt("flag that isn't 0 or 1")),
+        }
+    }
+
+    pub fn read_u16(&mut self) -> Result<u16, StateError> {
+        Ok(u16::from_le_bytes(self.take()?))
+    }
+
+    pub fn read_u32(&mut self) -> Result<u32, StateError> {
+        Ok(u32::from_le_bytes(self.take()?))
+    }
+
+    pub fn read_u64(&mut self) -> Result<u64, StateError> {
+        Ok(u64::from_le_bytes(self.take()?))
+    }
+
+    pub fn read_usize(&mut self) -> Result<usize, StateError> {
+        Ok(self.read_u32()? as usize)
+    }
+
+    pub fn read_f32(&mut self) -> Result<f32, StateError> {
+        Ok(f32::from_le_bytes(self.take()?))
+    }
+
+    pub fn read_option_u8(&mut self) -> Result<Option<u8>, StateError> {
+        let some = self.read_bool()?;
+        let value = self.read_u8()?;
+        Ok(some.then_some(value))
+    }
+
+    // the saved length has to match, the machine's memory sizes are fixed by the ROM
+    pub fn read_bytes_into(&mut self, bytes: &mut [u8]) -> Result<(), StateError> {
+        let len = self.read_u32()? as usize;
+        if len != bytes.len() { return Err(StateError::Corrupt("memory size doesn't match")); }
+
+        let data = self.data.get(self.position..self.position + len).ok_or(StateError::Truncated)?;
+        bytes.copy_from_slice(data);
+        self.position += len;
+        Ok(())
+    }
+
+    // for when it's the saved length that says how big it is
+    pub fn read_bytes(&mut self) -> Result<Vec<u8>, StateError> {
+        let len = self.read_u32()? as usize;
+        let data = self.data.get(self.position..self.position + len).ok_or(StateError::Truncated)?;
+        self.position += len;
+        Ok(data.to_vec())
+    }
+}
@@ -0,0 +1,99 @@
+mod core;
+#[cfg(test)]
+mod test;
+
+// the public surface. internals stay under `core`, this is what tools and tests get to build on
+
+pub mod cpu {
+    pub use crate::core::cpu::instructions::{OpCode, ProcessorAction, CPU_OPCODES};
+    pub use crate::core::cpu::processor::{AddressingMode, Processor};
+    pub use crate::core::cpu::status_flags::CPUStatusFlags;
+}
+
+pub mod apu {
+    pub use crate::core::apu::audio_processor::{AudioPipeline, AudioProcessor, DEFAULT_SAMPLE_RATE};
+    pub use crate::core::apu::dmc::Dmc;
+    pub use crate::core::apu::expansion::expansion_audio::ExpansionAudio;
+    pub use crate::core::apu::expansion::fds::FdsAudio;
+    pub use crate::core::apu::expansion::mmc5::Mmc5Audio;
+    pub use crate::core::apu::expansion::n163::N163Audio;
+    pub use crate::core::apu::expansion::sunsoft_5b::Sunsoft5bAudio;
+    pub use crate::core::apu::expansion::vrc6::Vrc6Audio;
+    pub use crate::core::apu::expansion::vrc7::Vrc7Audio;
+    pub use crate::core::apu::filters::{FilterChain, HighPass, LowPass};
+    pub use crate::core::apu::mixer::mix;
+    pub use crate::core::apu::noise::Noise;
+    pub use crate::core::apu::pulse::{Pulse, PulseChannel};
+    pub use crate::core::apu::resampler::Resampler;
+    pub use crate::core::apu::triangle::Triangle;
+}
+
+pub mod bus {
+    pub use crate::core::bus::{Bus, FlatMemory};
+    pub use crate::core::nes_bus::NesBus;
+}
+
+pub mod cartridge {
+    pub use crate::core::cartridge::mapper::{CartridgeData, Mapper};
+    pub use crate::core::cartridge::rom::{Cartridge, CartridgeError, Mirroring};
+}
+
+pub mod ppu {
+    pub use crate::core::ppu::palette::{Palette, PaletteError, Rgb, PALETTE_ENTRIES};
+    pub use crate::core::ppu::picture_processor::{PictureProcessor, SCREEN_HEIGHT, SCREEN_WIDTH};
+    pub use crate::core::ppu::registers::{PPUCtrl, PPUMask, PPUStatus};
+}
+
+pub mod console {
+    pub use crate::core::console::{Console, Frame, MovieStart};
+}
+
+pub mod export {
+    pub use crate::core::checksum::{crc32, md5};
+    pub use crate::core::export::png::{encode_png, write_png};
+    pub use crate::core::export::wav::{encode_wav, write_wav, WavRecorder};
+}
+
+pub mod input {
+    pub use crate::core::input::arkanoid::{ArkanoidVaus, VAUS_MAX_POSITION, VAUS_MIN_POSITION};
+    pub use crate::core::input::family_keyboard::{FamilyKeyboard, Key};
+    pub use crate::core::input::four_player::{FourPlayerAdapter, FourPlayerMode};
+    pub use crate::core::input::input_device::{InputDevice, Unplugged};
+    pub use crate::core::input::joypad::{Buttons, Joypad};
+    pub use crate::core::input::power_pad::PowerPad;
+    pub use crate::core::input::snes_mouse::SnesMouse;
+    pub use crate::core::input::zapper::Zapper;
+}
+
+pub mod movie {
+    pub use crate::core::movie::file::{Movie, MovieError, MovieFrame, COMMAND_POWER, COMMAND_RESET};
+}
+
+pub mod netplay {
+    pub use crate::core::netplay::packet::Packet;
+    pub use crate::core::netplay::session::{NetplayError, NetplaySession, DEFAULT_MAX_PREDICTION};
+    pub use crate::core::netplay::transport::Transport;
+    pub use crate::core::netplay::udp::UdpTransport;
+}
+
+pub mod nsf {
+    pub use crate::core::nsf::file::{ExpansionChips, Nsf, NsfError, TrackInfo};
+    pub use crate::core::nsf::mapper::NsfMapper;
+    pub use crate::core::nsf::player::{NsfPlayer, DEFAULT_FADE, DEFAULT_TRACK_LENGTH};
+}
+
+pub mod region {
+    pub use crate::core::region::{Region, RegionTiming};
+}
+
+pub mod state {
+    pub use crate::core::state::delta;
+    pub use crate::core::state::header::{StateHeader, STATE_VERSION};
+    pub use crate::core::state::rewind::{RewindBuffer, RewindPoint};
+    pub use crate::core::state::snapshot::Snapshot;
+    pub use crate::core::state::stream::{StateError, StateReader, StateWriter};
+}
+
+pub use crate::console::{Console, Frame};
+pub use crate::input::Buttons;
+pub use crate::region::Region;
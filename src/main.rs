@@ -1,6 +1,281 @@
-mod core;
-mod test;
+use std::env;
+use std::fs::{self, File};
+use std::io::{BufWriter, Write};
+use std::net::SocketAddr;
+use std::process;
+use std::thread;
+use std::time::Duration;
+use fucking_nes_emulator::cartridge::Cartridge;
+use fucking_nes_emulator::export::{crc32, write_png, write_wav};
+use fucking_nes_emulator::netplay::{NetplaySession, UdpTransport};
+use fucking_nes_emulator::nsf::{Nsf, NsfError, NsfPlayer, DEFAULT_FADE, DEFAULT_TRACK_LENGTH};
+use fucking_nes_emulator::ppu::{Palette, SCREEN_HEIGHT, SCREEN_WIDTH};
+use fucking_nes_emulator::{Buttons, Console, Frame, Region};
+
+const USAGE: &str = "usage: fucking_nes_emulator <rom.nes | tune.nsf | tune.nsfe> [options]
+
+  -f, --frames <n>       frames to run headless (default 60)
+  -r, --region <region>  ntsc, pal or dendy (default: whatever the ROM header says)
+      --palette <file>   .pal file to use instead of the built-in palette
+      --png <file>       dump the last frame as a PNG
+      --wav <file>       write the audio out as 16-bit WAV
+      --wav-frames <n>   only record the first n frames of audio (default: all of them)
+      --sample-rate <hz> audio output rate (default 44100)
+      --trace <file>     log every instruction the CPU runs, in nestest.log's format
+  -h, --help             this
+
+Netplay, against another instance of this with the same ROM:
+      --netplay-bind <addr>   address to listen on, like 127.0.0.1:7000
+      --netplay-peer <addr>   where the other instance is listening
+      --netplay-player <n>    which pad this side has, 1 or 2 (default 1)
+
+NSF/NSFe only, renders to the --wav file:
+  -t, --track <n>        track to render, from 1 (default: the file's starting track)
+      --all-tracks       render every track, to <wav>-01.wav, <wav>-02.wav and so on
+      --length <secs>    track length when the file doesn't give one (default 150)
+      --fade <secs>      fade out when the file doesn't give one (default 8)";
+
+#[derive(Default)]
+struct Options {
+    rom: String,
+    frames: u64,
+    region: Option<Region>,
+    palette: Option<String>,
+    png: Option<String>,
+    wav: Option<String>,
+    wav_frames: Option<u64>,
+    sample_rate: Option<u32>,
+    trace: Option<String>,
+    netplay_bind: Option<SocketAddr>,
+    netplay_peer: Option<SocketAddr>,
+    netplay_player: usize,
+    track: Option<u8>,
+    all_tracks: bool,
+    length: Option<u32>,
+    fade: Option<u32>,
+}
+
+impl Options {
+    fn parse(args: &[String]) -> Result<Options, String> {
+        let mut options = Options { frames: 60, ..Options::default() };
+        let mut rom = None;
+
+        let mut args = args.iter();
+        while let Some(arg) = args.next() {
+            let mut value = || args.next().cloned().ok_or(format!("{arg} needs a value"));
+
+            match arg.as_str() {
+                "-h" | "--help" => return Err(String::new()),
+                "-f" | "--frames" => {
+                    let frames = value()?;
+                    options.frames = frames.parse().map_err(|_| format!("bad frame count: {frames}"))?;
+                }
+                "-r" | "--region" => options.region = Some(parse_region(&value()?)?),
+                "--palette" => options.palette = Some(value()?),
+                "--png" => options.png = Some(value()?),
+                "--wav" => options.wav = Some(value()?),
+                "--wav-frames" => {
+                    let frames = value()?;
+                    options.wav_frames = Some(frames.parse().map_err(|_| format!("bad frame count: {frames}"))?);
+                }
+                "--sample-rate" => {
+                    let rate = value()?;
+                    options.sample_rate = Some(rate.parse().ok().filter(|rate| *rate > 0)
+                        .ok_or(format!("bad sample rate: {rate}"))?);
+                }
+                "--trace" => options.trace = Some(value()?),
+                "--netplay-bind" => options.netplay_bind = Some(parse_address(&value()?)?),
+                "--netplay-peer" => options.netplay_peer = Some(parse_address(&value()?)?),
+                "--netplay-player" => {
+                    let player = value()?;
+                    options.netplay_player = match player.as_str() {
+                        "1" => 0,
+                        "2" => 1,
+                        _ => return Err(format!("bad netplay player: {player}")),
+                    };
+                }
+                "-t" | "--track" => {
+                    let track = value()?;
+                    options.track = Some(track.parse().ok().filter(|track| *track > 0)
+                        .ok_or(format!("bad track number: {track}"))?);
+                }
+                "--all-tracks" => options.all_tracks = true,
+                "--length" => options.length = Some(parse_milliseconds(&value()?)?),
+                "--fade" => options.fade = Some(parse_milliseconds(&value()?)?),
+                _ if arg.starts_with('-') => return Err(format!("unknown option: {arg}")),
+                _ if rom.is_none() => rom = Some(arg.clone()),
+                _ => return Err(format!("unexpected argument: {arg}")),
+            }
+        }
+
+        options.rom = rom.ok_or("no ROM given")?;
+        if options.netplay_bind.is_some() != options.netplay_peer.is_some() {
+            return Err("--netplay-bind and --netplay-peer go together".to_string());
+        }
+        Ok(options)
+    }
+}
+
+fn parse_region(region: &str) -> Result<Region, String> {
+    match region.to_ascii_lowercase().as_str() {
+        "ntsc" => Ok(Region::Ntsc),
+        "pal" => Ok(Region::Pal),
+        "dendy" => Ok(Region::Dendy),
+        _ => Err(format!("unknown region: {region}")),
+    }
+}
+
+fn parse_address(address: &str) -> Result<SocketAddr, String> {
+    address.parse().map_err(|_| format!("bad address: {address}"))
+}
+
+fn parse_milliseconds(seconds: &str) -> Result<u32, String> {
+    match seconds.parse::<f64>() {
+        Ok(seconds) if (0.0..4_000_000.0).contains(&seconds) => Ok((seconds * 1000.0).round() as u32),
+        _ => Err(format!("bad number of seconds: {seconds}")),
+    }
+}
+
+fn fail(message: String) -> ! {
+    eprintln!("{message}");
+    process::exit(1);
+}
 
 fn main() {
-    println!("lmaolol");
+    let args: Vec<String> = env::args().skip(1).collect();
+    let options = Options::parse(&args).unwrap_or_else(|e| {
+        if e.is_empty() {
+            println!("{USAGE}");
+            process::exit(0);
+        }
+        fail(format!("{e}\n\n{USAGE}"));
+    });
+
+    let raw = fs::read(&options.rom).unwrap_or_else(|e| fail(format!("{}: {e}", options.rom)));
+    match Nsf::from_bytes(&raw) {
+        Ok(nsf) => return play_nsf(&options, nsf),
+        Err(NsfError::NotNsf) => {}
+        Err(e) => fail(format!("{}: {e}", options.rom)),
+    }
+
+    let cartridge = Cartridge::from_ines(&raw)
+        .unwrap_or_else(|e| fail(format!("{}: {e}", options.rom)));
+    let region = options.region.or(cartridge.region).unwrap_or_default();
+    let mut console = Console::with_region(cartridge, region);
+
+    if let Some(path) = &options.palette {
+        let palette = Palette::load_pal_file(path).unwrap_or_else(|e| fail(format!("{path}: {e}")));
+        console.set_palette(palette);
+    }
+
+    if let Some(sample_rate) = options.sample_rate {
+        console.set_sample_rate(sample_rate);
+    }
+
+    if let Some(path) = &options.wav {
+        console.start_recording(path, options.wav_frames).unwrap_or_else(|e| fail(format!("{path}: {e}")));
+    }
+
+    let mut trace = options.trace.as_ref().map(|path| {
+        BufWriter::new(File::create(path).unwrap_or_else(|e| fail(format!("{path}: {e}"))))
+    });
+
+    let mut last_frame = None;
+    if let (Some(bind), Some(peer)) = (options.netplay_bind, options.netplay_peer) {
+        last_frame = run_netplay(&options, &mut console, bind, peer);
+    }
+    while console.frame_number() < options.frames {
+        let frame = console.step_frame_with_callback(|cpu| {
+            if let Some(trace) = trace.as_mut() {
+                writeln!(trace, "{}", cpu.trace_line()).unwrap_or_else(|e| fail(format!("trace: {e}")));
+            }
+        });
+
+        last_frame = Some(frame);
+    }
+
+    if let Some(trace) = trace.as_mut() {
+        trace.flush().unwrap_or_else(|e| fail(format!("trace: {e}")));
+    }
+
+    if let Some(path) = &options.png {
+        let pixels = last_frame.map_or_else(|| console.framebuffer(), |frame| frame.pixels);
+        write_png(path, SCREEN_WIDTH as u32, SCREEN_HEIGHT as u32, &pixels)
+            .unwrap_or_else(|e| fail(format!("{path}: {e}")));
+    }
+
+    if let Some(path) = &options.wav {
+        console.stop_recording().unwrap_or_else(|e| fail(format!("{path}: {e}")));
+    }
+
+    println!("ran {} frames ({} CPU cycles, {region:?})", console.frame_number(), console.cpu.cycles);
+}
+
+// runs the frames against the other instance, nobody touching the pads, and prints what the
+// state came out as so the two can be compared
+fn run_netplay(options: &Options, console: &mut Console, bind: SocketAddr, peer: SocketAddr) -> Option<Frame> {
+    let transport = UdpTransport::bind(bind, peer).unwrap_or_else(|e| fail(format!("{bind}: {e}")));
+    let mut session = NetplaySession::new(transport, options.netplay_player);
+    let wait = || thread::sleep(Duration::from_millis(1));
+
+    let mut last_frame = None;
+    while session.frame() < options.frames {
+        match session.advance_frame(console, Buttons::default()) {
+            Ok(Some(frame)) => last_frame = Some(frame),
+            Ok(None) => wait(),
+            Err(e) => fail(format!("netplay: {e}")),
+        }
+    }
+
+    // the other side's last few frames might still be guesses
+    while session.confirmed_frames() < options.frames {
+        session.poll(console).unwrap_or_else(|e| fail(format!("netplay: {e}")));
+        wait();
+    }
+
+    println!("netplay: {} rollbacks, state CRC-32 {:08x}", session.rollbacks(), crc32(&console.save_state()));
+    last_frame
+}
+
+fn play_nsf(options: &Options, nsf: Nsf) {
+    // there's no sound output, so the WAV is the only place the tune could go
+    let Some(path) = &options.wav else {
+        fail(format!("{}: NSFs need --wav <file> to render to\n\n{USAGE}", options.rom));
+    };
+    println!("{} - {} ({}), {} tracks", nsf.title, nsf.artist, nsf.copyright, nsf.songs);
+
+    let region = options.region.unwrap_or(nsf.region());
+    let tracks: Vec<u8> = if options.all_tracks {
+        if nsf.playlist.is_empty() { (0..nsf.songs).collect() } else { nsf.playlist.clone() }
+    } else {
+        vec![options.track.map_or(nsf.starting_song, |track| track - 1)]
+    };
+    if let Some(track) = tracks.iter().find(|track| **track >= nsf.songs) {
+        fail(format!("there's no track {}, only {}", track + 1, nsf.songs));
+    }
+
+    let mut player = NsfPlayer::with_region(nsf, region);
+    if let Some(sample_rate) = options.sample_rate {
+        player.set_sample_rate(sample_rate);
+    }
+
+    for track in tracks {
+        let info = player.nsf().track(track);
+        let length = info.length.or(options.length).unwrap_or(DEFAULT_TRACK_LENGTH);
+        let fade = info.fade.or(options.fade).unwrap_or(DEFAULT_FADE);
+
+        player.start_track(track);
+        let samples = player.render(length, fade);
+
+        let path = if options.all_tracks {
+            let stem = path.strip_suffix(".wav").unwrap_or(path);
+            format!("{stem}-{:02}.wav", track + 1)
+        } else {
+            path.clone()
+        };
+        write_wav(&path, player.sample_rate(), &samples).unwrap_or_else(|e| fail(format!("{path}: {e}")));
+
+        let title = info.title.unwrap_or_else(|| format!("track {}", track + 1));
+        println!("{path}: {title}, {:.1}s", f64::from(length) / 1000.0);
+    }
 }
@@ -1,6 +1,51 @@
-#[cfg(test)]
-
+use crate::core::apu::audio_processor::AudioProcessor;
+use crate::core::apu::dmc::Dmc;
+use crate::core::apu::expansion::expansion_audio::ExpansionAudio;
+use crate::core::apu::expansion::fds::FdsAudio;
+use crate::core::apu::expansion::n163::N163Audio;
+use crate::core::apu::expansion::sunsoft_5b::Sunsoft5bAudio;
+use crate::core::apu::expansion::vrc7::Vrc7Audio;
+use crate::core::apu::frame_counter::{FrameClock, FrameCounter, FrameCounterMode};
+use crate::core::apu::mixer::mix;
+use crate::core::apu::noise::Noise;
+use crate::core::apu::pulse::{Pulse, PulseChannel};
+use crate::core::apu::resampler::Resampler;
+use crate::core::apu::triangle::Triangle;
+use crate::core::cartridge::rom::{Cartridge, CartridgeError};
+use crate::core::checksum::{adler32, crc32, md5};
+use crate::core::cartridge::mapper::Mapper;
+use crate::core::console::{Console, Frame, MovieStart};
+use crate::core::export::png::encode_png;
+use crate::core::export::wav::{encode_wav, WavRecorder};
 use crate::core::cpu::processor::Processor;
+use crate::core::input::arkanoid::ArkanoidVaus;
+use crate::core::input::family_keyboard::{FamilyKeyboard, Key};
+use crate::core::input::input_device::InputDevice;
+use crate::core::input::power_pad::PowerPad;
+use crate::core::input::snes_mouse::SnesMouse;
+use crate::core::input::four_player::{FourPlayerAdapter, FourPlayerMode};
+use crate::core::input::joypad::{Buttons, Joypad};
+use crate::core::input::zapper::Zapper;
+use crate::core::movie::file::{Movie, MovieError};
+use crate::core::netplay::packet::Packet;
+use crate::core::netplay::session::{NetplayError, NetplaySession};
+use crate::core::netplay::transport::Transport;
+use crate::core::netplay::udp::UdpTransport;
+use crate::core::nsf::file::{Nsf, NsfError};
+use crate::core::nsf::mapper::NsfMapper;
+use crate::core::nsf::player::NsfPlayer;
+use crate::core::ppu::palette::{Palette, PaletteError};
+use crate::core::ppu::picture_processor::PictureProcessor;
+use crate::core::ppu::registers::PPUMask;
+use crate::core::region::Region;
+use crate::core::state::delta;
+use crate::core::state::snapshot::Snapshot;
+use crate::core::state::stream::{StateError, StateReader, StateWriter};
+use std::cell::{Cell, RefCell};
+use std::collections::VecDeque;
+use std::io::Cursor;
+use std::net::UdpSocket;
+use std::rc::Rc;
 
 #[test]
 fn test_adc() {
@@ -73,7 +118,36 @@ fn test_bit() {
     let mut cpu = Processor::new();
     cpu.mem_write(0x10, 0x01);
     cpu.load_and_run(vec![0xa9, 0xff, 0x24, 0x10, 0x00]);
-    assert_eq!(cpu.register_a, 0x01);
+    assert_eq!(cpu.register_a, 0xff);
+    assert_eq!(cpu.status.zero().get_raw(), 0);
+}
+
+#[test]
+fn test_bit_flags_from_memory() {
+    // N and V are copied from memory even when the AND comes out zero
+    let mut cpu = Processor::new();
+    cpu.mem_write(0x10, 0b1100_0000);
+    cpu.load_and_run(vec![0xa9, 0x00, 0x24, 0x10, 0x00]);
+    assert_eq!(cpu.register_a, 0x00);
+    assert_eq!(cpu.status.zero().get_raw(), 1);
+    assert_eq!(cpu.status.negative().get_raw(), 1);
+    assert_eq!(cpu.status.overflow().get_raw(), 1);
+}
+
+#[test]
+fn test_program_counter_wraps() {
+    // LDA $1234 and STA $1234 sitting at the very top of memory, their operands run past $FFFF
+    let mut cpu = Processor::new();
+    cpu.mem_write(0x1234, 0x42);
+    for (opcode, program_counter) in [(0xad, 0xfffe), (0x8d, 0xfffd)] {
+        cpu.mem_write(program_counter, opcode);
+        cpu.mem_write(program_counter.wrapping_add(1), 0x34);
+        cpu.mem_write(program_counter.wrapping_add(2), 0x12);
+        cpu.program_counter = program_counter;
+        cpu.step();
+        assert_eq!(cpu.program_counter, program_counter.wrapping_add(3));
+    }
+    assert_eq!(cpu.register_a, 0x42);
 }
 
 #[test]
@@ -92,6 +166,40 @@ fn test_bit_negative() {
     assert_eq!(cpu.status.negative().get_raw(), 1);
 }
 
+#[test]
+fn test_trace_log() {
+    let mut cpu = Processor::new();
+    cpu.mem_write(0x11, 0x7f);
+    cpu.mem_write_u16(0x20, 0x0300);
+    cpu.mem_write(0x02ff, 0x10);
+    cpu.mem_write(0x0200, 0x80);
+    let lines = Rc::new(RefCell::new(Vec::new()));
+    let log = Rc::clone(&lines);
+    cpu.set_tracer(move |line| log.borrow_mut().push(line.to_string()));
+
+    cpu.load_and_run(vec![
+        0xa2, 0x01, 0xb5, 0x10, 0x91, 0x20, 0xd0, 0x02, 0x00, 0x00,
+        0x4a, 0x6c, 0xff, 0x02, 0x00, 0x00, 0x00,
+    ]);
+
+    let lines = lines.borrow();
+    assert_eq!(lines.len(), 7);
+    assert_eq!(lines[0], "8000  A2 01     LDX #$01                        A:00 X:00 Y:00 P:24 SP:FD CYC:7");
+    assert_eq!(lines[1], "8002  B5 10     LDA $10,X @ 11 = 7F             A:00 X:01 Y:00 P:24 SP:FD CYC:9");
+    assert_eq!(lines[2], "8004  91 20     STA ($20),Y = 0300 @ 0300 = 00  A:7F X:01 Y:00 P:24 SP:FD CYC:13");
+    assert_eq!(lines[3], "8006  D0 02     BNE $800A                       A:7F X:01 Y:00 P:24 SP:FD CYC:19");
+    assert_eq!(lines[4], "800A  4A        LSR A                           A:7F X:01 Y:00 P:24 SP:FD CYC:22");
+    // JMP ($xxFF) takes its high byte from the start of the same page
+    assert_eq!(lines[5], "800B  6C FF 02  JMP ($02FF) = 8010              A:3F X:01 Y:00 P:25 SP:FD CYC:24");
+
+    // on the console the PPU's position goes in too, and tracing reads nothing for real
+    let mut console = Console::new(Cartridge::from_ines(&test_rom(&[0xad, 0x02, 0x20, 0x4c, 0x00, 0x80], &[0x40])).unwrap());
+    console.cpu.bus.ppu.scanline = 241;
+    let line = console.cpu.trace_line();
+    assert_eq!(line, "8000  AD 02 20  LDA $2002 = FF                  A:00 X:00 Y:00 P:24 SP:FD PPU:241,  0 CYC:7");
+    assert_eq!(console.cpu.trace_line(), line);
+}
+
 #[test]
 fn test_lda_immediate_load_data() {
     let mut cpu = Processor::new();
@@ -155,3 +263,1304 @@ fn test_inx_overflow() {
 
     assert_eq!(cpu.register_x, 1)
 }
+
+#[test]
+fn test_palette_default_lookup() {
+    let palette = Palette::default();
+    assert_eq!(palette.rgb(0x20, PPUMask::default()), [0xff, 0xff, 0xff]);
+    assert_eq!(palette.rgb(0x0f, PPUMask::default()), [0x05, 0x05, 0x05]);
+}
+
+#[test]
+fn test_palette_greyscale() {
+    let palette = Palette::default();
+    let mask = PPUMask::from_bits(0b0000_0001);
+    assert_eq!(palette.rgb(0x16, mask), palette.rgb(0x10, PPUMask::default()));
+}
+
+#[test]
+fn test_palette_emphasis() {
+    let palette = Palette::default();
+    let red = palette.rgb(0x20, PPUMask::from_bits(0b0010_0000));
+    assert_eq!(red[0], 0xff);
+    assert!(red[1] < 0xff && red[2] < 0xff);
+}
+
+#[test]
+fn test_palette_pal_file() {
+    let mut data = vec![0u8; 192];
+    data[3..6].copy_from_slice(&[1, 2, 3]);
+    let palette = Palette::from_pal_bytes(&data).unwrap();
+    assert_eq!(palette.entry(0x01), [1, 2, 3]);
+    assert_eq!(palette.to_pal_bytes().len(), 1536);
+
+    let full = Palette::from_pal_bytes(&palette.to_pal_bytes()).unwrap();
+    assert_eq!(full, palette);
+
+    assert!(matches!(Palette::from_pal_bytes(&[0; 100]), Err(PaletteError::InvalidLength(100))));
+}
+
+#[test]
+fn test_region_clocks() {
+    let ntsc = Region::Ntsc.timing();
+    assert_eq!(ntsc.cpu_clock_rate().round(), 1_789_773.0);
+    assert_eq!(ntsc.ppu_dots_per_cpu_cycle(), (12, 4));
+
+    let pal = Region::Pal.timing();
+    assert_eq!(pal.cpu_clock_rate().round(), 1_662_607.0);
+    assert_eq!(pal.scanlines_per_frame, 312);
+    assert!(!pal.odd_frame_skip);
+    assert_eq!(pal.normalize_emphasis(0b001), 0b010);
+
+    assert_eq!(Region::Dendy.timing().vblank_scanline, 291);
+}
+
+#[test]
+fn test_cycle_counter() {
+    let mut cpu = Processor::with_region(Region::Pal);
+    cpu.load_and_run(vec![0xa9, 0x05, 0x85, 0x10, 0x00]);
+    // reset + LDA #imm + STA zp + BRK
+    assert_eq!(cpu.cycles, 7 + 2 + 3 + 7);
+    assert_eq!(cpu.clock_rate().round(), 1_662_607.0);
+}
+
+// NROM with 16K of PRG at $C000 (mirrored at $8000) and CHR RAM. reset goes to $8000, NMI to $8100
+fn test_rom(program: &[u8], nmi_handler: &[u8]) -> Vec<u8> {
+    let mut rom = vec![0x4e, 0x45, 0x53, 0x1a, 0x01, 0x00, 0x00, 0x00];
+    rom.resize(16, 0);
+
+    let mut prg = vec![0xea; 0x4000];
+    prg[..program.len()].copy_from_slice(program);
+    prg[0x100..0x100 + nmi_handler.len()].copy_from_slice(nmi_handler);
+    prg[0x3ffa..].copy_from_slice(&[0x00, 0x81, 0x00, 0x80, 0x00, 0x80]);
+
+    rom.extend(prg);
+    rom
+}
+
+#[test]
+fn test_cartridge_errors() {
+    assert!(matches!(Cartridge::from_ines(&[0; 16]), Err(CartridgeError::NotINes)));
+
+    let mut rom = test_rom(&[], &[]);
+    rom.truncate(1000);
+    assert!(matches!(Cartridge::from_ines(&rom), Err(CartridgeError::Truncated { .. })));
+
+    let mut rom = test_rom(&[], &[]);
+    rom[4] = 0;
+    assert!(matches!(Cartridge::from_ines(&rom), Err(CartridgeError::NoPrgRom)));
+
+    let mut rom = test_rom(&[], &[]);
+    rom[6] = 0xf0;
+    assert!(matches!(Cartridge::from_ines(&rom), Err(CartridgeError::UnsupportedMapper(15))));
+}
+
+#[test]
+fn test_console_nmi_every_frame() {
+    // enable NMIs and spin, the handler bumps $10
+    let program = [0xa9, 0x80, 0x8d, 0x00, 0x20, 0x4c, 0x05, 0x80];
+    let nmi_handler = [0xe6, 0x10, 0x40];
+    let mut console = Console::new(Cartridge::from_ines(&test_rom(&program, &nmi_handler)).unwrap());
+
+    for _ in 0..3 { console.step_frame(); }
+    console.step_instruction();
+
+    assert_eq!(console.frame_number(), 3);
+    assert_eq!(console.cpu.mem_read(0x10), 3);
+}
+
+#[test]
+fn test_console_backdrop_colour() {
+    // write $21 into the backdrop palette entry and turn the background on
+    let program = [
+        0xa9, 0x3f, 0x8d, 0x06, 0x20, 0xa9, 0x00, 0x8d, 0x06, 0x20,
+        0xa9, 0x21, 0x8d, 0x07, 0x20, 0xa9, 0x08, 0x8d, 0x01, 0x20,
+        0x4c, 0x14, 0x80,
+    ];
+    let mut console = Console::new(Cartridge::from_ines(&test_rom(&program, &[0x40])).unwrap());
+
+    console.step_frame();
+    let frame = console.step_frame();
+
+    assert_eq!(frame.pixels.len(), 256 * 240 * 3);
+    assert_eq!(frame.pixels[..3], Palette::default().entry(0x21));
+}
+
+#[test]
+fn test_console_run_cycles() {
+    let mut console = Console::with_region(
+        Cartridge::from_ines(&test_rom(&[0x4c, 0x00, 0x80], &[0x40])).unwrap(), Region::Pal
+    );
+
+    let ran = console.run_cycles(1000);
+    assert!((1000..1003).contains(&ran));
+
+    let start = console.cpu.cycles;
+    console.step_frame();
+    console.step_frame();
+    let start_of_frame = console.cpu.cycles;
+    console.step_frame();
+
+    // 312 * 341 dots at 3.2 dots per cycle
+    assert!((33_245..33_250).contains(&(console.cpu.cycles - start_of_frame)));
+    assert!(start_of_frame > start);
+}
+
+#[test]
+fn test_jsr_rts() {
+    let mut cpu = Processor::new();
+    // JSR $8005, INX, BRK, then the subroutine: LDX #$41, RTS
+    cpu.load_and_run(vec![0x20, 0x05, 0x80, 0xe8, 0x00, 0xa2, 0x41, 0x60]);
+    assert_eq!(cpu.register_x, 0x42);
+    assert_eq!(cpu.register_s, 0xfd - 3);
+}
+
+#[test]
+fn test_branch_not_taken() {
+    let mut cpu = Processor::new();
+    cpu.load_and_run(vec![0xa9, 0x01, 0xf0, 0x02, 0xa9, 0x05, 0x00]);
+    assert_eq!(cpu.register_a, 0x05);
+}
+
+#[test]
+fn test_checksums() {
+    assert_eq!(crc32(b"123456789"), 0xcbf4_3926);
+    assert_eq!(adler32(b"Wikipedia"), 0x11e6_0398);
+
+    let hex = |digest: [u8; 16]| digest.iter().map(|byte| format!("{byte:02x}")).collect::<String>();
+    assert_eq!(hex(md5(b"")), "d41d8cd98f00b204e9800998ecf8427e");
+    assert_eq!(hex(md5(b"The quick brown fox jumps over the lazy dog")), "9e107d9d372bb6826bd81d3542a419d6");
+}
+
+#[test]
+fn test_png_encoding() {
+    let png = encode_png(2, 1, &[0xff, 0, 0, 0, 0xff, 0]);
+    assert_eq!(png[..8], [0x89, 0x50, 0x4e, 0x47, 0x0d, 0x0a, 0x1a, 0x0a]);
+    assert_eq!(&png[12..16], b"IHDR");
+    assert_eq!(&png[png.len() - 8 .. png.len() - 4], b"IEND");
+}
+
+#[test]
+fn test_wav_encoding() {
+    let wav = encode_wav(44_100, &[0.0, 1.0, -2.0]);
+    assert_eq!(wav.len(), 44 + 6);
+    assert_eq!(&wav[..4], b"RIFF");
+    assert_eq!(wav[44..], [0x00, 0x00, 0xff, 0x7f, 0x01, 0x80]);
+}
+
+#[test]
+fn test_wav_recorder_matches_encoder() {
+    let mut recorder = WavRecorder::new(Cursor::new(Vec::new()), 48_000).unwrap();
+    recorder.write_samples(&[0.0, 1.0]).unwrap();
+    recorder.write_samples(&[-2.0]).unwrap();
+    assert_eq!(recorder.samples_written(), 3);
+
+    let wav = recorder.finish().unwrap().into_inner();
+    assert_eq!(wav, encode_wav(48_000, &[0.0, 1.0, -2.0]));
+}
+
+#[test]
+fn test_pulse_output_and_status() {
+    let mut apu = AudioProcessor::new(Region::Ntsc);
+    apu.write_register(0x4015, 0b01);
+    apu.write_register(0x4000, 0b1011_1111);
+    apu.write_register(0x4002, 0x40);
+    apu.write_register(0x4003, 0x08);
+
+    assert_eq!(apu.read_status(), 0b01);
+
+    let mut outputs = Vec::new();
+    for _ in 0..2000 {
+        apu.tick(1, &mut |_| 0);
+        outputs.push(apu.pulse_1.output());
+    }
+    assert!(outputs.contains(&15) && outputs.contains(&0));
+
+    apu.write_register(0x4015, 0);
+    assert_eq!(apu.read_status(), 0);
+}
+
+#[test]
+fn test_pulse_sweep_negate() {
+    let mut pulse_1 = Pulse::new(PulseChannel::One);
+    let mut pulse_2 = Pulse::new(PulseChannel::Two);
+
+    for pulse in [&mut pulse_1, &mut pulse_2] {
+        pulse.write_register(2, 0x00);
+        pulse.write_register(3, 0x01);
+        pulse.write_register(1, 0b1000_1001);
+        pulse.clock_half_frame();
+    }
+
+    assert_eq!(pulse_1.timer_period, 0x7f);
+    assert_eq!(pulse_2.timer_period, 0x80);
+}
+
+#[test]
+fn test_pulse_length_and_muting() {
+    let mut pulse = Pulse::new(PulseChannel::One);
+    pulse.length_counter.set_enabled(true);
+    pulse.write_register(0, 0b1101_1111);
+    pulse.write_register(2, 0x05);
+    pulse.write_register(3, 0b0001_1000);
+
+    // timer period under 8 mutes it no matter what
+    assert!((0..8).all(|_| { pulse.clock_timer(); pulse.output() == 0 }));
+
+    assert!(pulse.length_counter.active());
+    pulse.clock_half_frame();
+    pulse.clock_half_frame();
+    assert!(!pulse.length_counter.active());
+}
+
+#[test]
+fn test_triangle_needs_linear_counter() {
+    let mut triangle = Triangle::default();
+    triangle.length_counter.set_enabled(true);
+    triangle.write_register(0, 0x10);
+    triangle.write_register(2, 0x00);
+    triangle.write_register(3, 0x08);
+
+    for _ in 0..10 { triangle.clock_timer(); }
+    assert_eq!(triangle.output(), 15);
+
+    triangle.clock_quarter_frame();
+    for _ in 0..3 { triangle.clock_timer(); }
+    assert_eq!(triangle.output(), 12);
+}
+
+#[test]
+fn test_noise_lfsr_periods() {
+    let mut noise = Noise::new(&Region::Pal.timing().noise_periods);
+    noise.length_counter.set_enabled(true);
+    noise.write_register(0, 0b0011_1111);
+    noise.write_register(3, 0x08);
+
+    let mut sequence = Vec::new();
+    for _ in 0..32767 * 4 {
+        noise.clock_timer();
+        sequence.push(noise.output());
+    }
+    // period index 0 on PAL is still 4 CPU cycles, so the 15-bit loop repeats every 32767 * 4
+    assert_eq!(sequence[..4 * 100], noise_steps(&mut noise, 4 * 100)[..]);
+
+    noise.write_register(2, 0x82);
+    let first = noise_steps(&mut noise, 14 * 93);
+    let second = noise_steps(&mut noise, 14 * 93);
+    assert_eq!(first, second);
+}
+
+fn noise_steps(noise: &mut Noise, cycles: usize) -> Vec<u8> {
+    (0..cycles).map(|_| { noise.clock_timer(); noise.output() }).collect()
+}
+
+#[test]
+fn test_dmc_fetch_and_irq() {
+    let mut dmc = Dmc::new(&Region::Ntsc.timing().dmc_periods);
+    dmc.write_register(0, 0x8f);
+    dmc.write_register(2, 0x01);
+    dmc.write_register(3, 0x00);
+    dmc.set_enabled(true);
+
+    let mut reads = Vec::new();
+    dmc.clock(&mut |addr| { reads.push(addr); 0xff });
+
+    assert_eq!(reads, [0xc040]);
+    assert_eq!(dmc.take_stall_cycles(), 4);
+    assert!(dmc.irq_flag && !dmc.active());
+
+    // the byte of all 1s walks the output up 2 at a time once it's shifted in
+    for _ in 0..54 * 9 { dmc.clock(&mut |_| 0xff); }
+    assert!(dmc.output() >= 2);
+
+    dmc.write_register(1, 0x40);
+    assert_eq!(dmc.output(), 0x40);
+
+    dmc.set_enabled(false);
+    assert!(!dmc.irq_flag);
+}
+
+#[test]
+fn test_dmc_looping_sample() {
+    let mut dmc = Dmc::new(&Region::Ntsc.timing().dmc_periods);
+    dmc.write_register(0, 0xcf);
+    dmc.write_register(3, 0x00);
+    dmc.set_enabled(true);
+
+    for _ in 0..54 * 8 * 4 { dmc.clock(&mut |_| 0x00); }
+    assert!(dmc.active() && !dmc.irq_flag);
+}
+
+#[test]
+fn test_dmc_stalls_cpu() {
+    // LDA #$0F, STA $4010, LDA #$10, STA $4015
+    let program = [0xa9, 0x0f, 0x8d, 0x10, 0x40, 0xa9, 0x10, 0x8d, 0x15, 0x40, 0x4c, 0x0a, 0x80];
+    let mut console = Console::new(Cartridge::from_ines(&test_rom(&program, &[0x40])).unwrap());
+
+    for _ in 0..3 { console.step_instruction(); }
+    // the first sample fetch happens straight away and the CPU pays for it
+    assert_eq!(console.step_instruction(), 4 + 4);
+}
+
+#[test]
+fn test_frame_counter_irq_timing() {
+    let mut frame_counter = FrameCounter::new(Region::Ntsc.timing());
+    frame_counter.write(0x00, false);
+
+    let mut cycles = 0;
+    while !frame_counter.irq_flag {
+        frame_counter.clock();
+        cycles += 1;
+    }
+    // 3 cycles of write delay, then the flag goes up one cycle before the last step
+    assert_eq!(cycles, 3 + 29828);
+
+    frame_counter.write(0x40, true);
+    assert!(!frame_counter.irq_flag);
+    for _ in 0..40_000 { frame_counter.clock(); }
+    assert!(!frame_counter.irq_flag);
+}
+
+#[test]
+fn test_frame_counter_five_step() {
+    let mut frame_counter = FrameCounter::new(Region::Pal.timing());
+    frame_counter.write(0x80, true);
+
+    let clocks: Vec<FrameClock> = (0..4).map(|_| frame_counter.clock()).collect();
+    assert_eq!(clocks[3], FrameClock { quarter: true, half: true });
+    assert_eq!(frame_counter.mode, FrameCounterMode::FiveStep);
+
+    let halves = (0..41_566).filter(|_| frame_counter.clock().half).count();
+    assert_eq!(halves, 2);
+    assert!(!frame_counter.irq_flag);
+}
+
+#[test]
+fn test_frame_irq_status_read() {
+    let mut apu = AudioProcessor::new(Region::Ntsc);
+    apu.tick(30_000, &mut |_| 0);
+
+    assert!(apu.irq());
+    assert_eq!(apu.read_status() & 0x40, 0x40);
+    assert!(!apu.irq());
+    assert_eq!(apu.read_status() & 0x40, 0);
+}
+
+#[test]
+fn test_mixer_levels() {
+    assert_eq!(mix(0, 0, 0, 0, 0), 0.0);
+    assert!((mix(15, 15, 15, 15, 127) - 1.0).abs() < 0.01);
+    // nonlinear: two pulses at full volume are quieter than twice one of them
+    assert!(mix(15, 15, 0, 0, 0) < 2.0 * mix(15, 0, 0, 0, 0));
+}
+
+#[test]
+fn test_resampler_rejects_ultrasonics() {
+    let clock_rate = Region::Ntsc.timing().cpu_clock_rate();
+    let mut resampler = Resampler::new(clock_rate, 44_100);
+
+    // a square wave at ~112kHz, way past Nyquist: all that should come out is its DC level
+    let mut level = 0.0;
+    for clock in 0..29_780 {
+        if clock % 8 == 0 {
+            let next = if level == 0.0 { 0.5 } else { 0.0 };
+            resampler.add_delta(clock, next - level);
+            level = next;
+        }
+    }
+    let samples = resampler.end_frame(29_780);
+
+    assert_eq!(samples.len(), 733);
+    for sample in &samples[32..] {
+        assert!((sample - 0.25).abs() < 0.01, "{sample}");
+    }
+}
+
+#[test]
+fn test_frame_samples() {
+    let program = [0x4c, 0x00, 0x80];
+    let mut console = Console::new(Cartridge::from_ines(&test_rom(&program, &[0x40])).unwrap());
+    console.step_frame();
+
+    let seconds = 60.0 / Region::Ntsc.timing().frame_rate();
+    for sample_rate in [44_100, 48_000] {
+        console.set_sample_rate(sample_rate);
+        let total: usize = (0..60).map(|_| console.step_frame().samples.len()).sum();
+        let expected = f64::from(sample_rate) * seconds;
+        assert!((total as f64 - expected).abs() < 2.0, "{total} vs {expected}");
+    }
+}
+
+#[test]
+fn test_console_recording_frame_limit() {
+    let program = [0x4c, 0x00, 0x80];
+    let mut console = Console::new(Cartridge::from_ines(&test_rom(&program, &[0x40])).unwrap());
+    let path = std::env::temp_dir().join(format!("fucking_nes_emulator_{}.wav", std::process::id()));
+
+    console.start_recording(&path, Some(3)).unwrap();
+    let recorded: usize = (0..3).map(|_| console.step_frame().samples.len()).sum();
+    assert!(!console.is_recording());
+    console.step_frame();
+    console.stop_recording().unwrap();
+
+    let wav = std::fs::read(&path).unwrap();
+    std::fs::remove_file(&path).unwrap();
+    assert_eq!(wav.len(), 44 + recorded * 2);
+    assert_eq!(u32::from_le_bytes(wav[40..44].try_into().unwrap()) as usize, recorded * 2);
+}
+
+#[test]
+fn test_vrc6_cartridge_audio() {
+    // digitized mode at full volume on pulse 1, then spin
+    let program = [0xa9, 0x8f, 0x8d, 0x00, 0x90, 0xa9, 0x80, 0x8d, 0x02, 0x90, 0x4c, 0x0a, 0x80];
+    let mut rom = test_rom(&program, &[0x40]);
+    rom[6] = 0x80;
+    rom[7] = 0x10;
+
+    let mut console = Console::new(Cartridge::from_ines(&rom).unwrap());
+    assert_eq!(console.cpu.bus.cartridge.mapper_id, 24);
+    console.run_cycles(100);
+
+    let expansion = console.cpu.bus.cartridge.mapper.audio_output();
+    assert!((expansion - 15.0 * 0.00996).abs() < 0.0001);
+    // the triangle idles partway up its ramp, so it's never quite silent
+    let apu = &console.cpu.bus.apu;
+    assert_eq!(apu.output(), mix(0, 0, apu.triangle.output(), 0, 0) + expansion);
+}
+
+#[test]
+fn test_sunsoft_5b_tone_period() {
+    let mut chip = Sunsoft5bAudio::new();
+    for (register, value) in [(0x00, 100), (0x01, 0), (0x07, 0b11_1110), (0x08, 0x0f)] {
+        chip.write_register(0xc000, register);
+        chip.write_register(0xe000, value);
+    }
+
+    // a square at 32 CPU cycles per period step
+    let mut edges = 0;
+    let mut previous = chip.output();
+    for _ in 0..32 * 100 * 10 {
+        chip.clock();
+        if chip.output() > 0.0 && previous == 0.0 { edges += 1; }
+        previous = chip.output();
+    }
+    assert_eq!(edges, 10);
+}
+
+#[test]
+fn test_n163_wavetable() {
+    let mut chip = N163Audio::new();
+    // a two-sample wave of 15 then 0 at RAM address 0, played by channel 7 at full volume
+    chip.write_register(0xf800, 0x00);
+    chip.write_register(0x4800, 0x0f);
+    chip.write_register(0xf800, 0x80 | 0x78);
+    for data in [0x00, 0x00, 0x00, 0x00, 0xfc, 0x00, 0x00, 0x0f] {
+        chip.write_register(0x4800, data);
+    }
+    // the auto-increment carried on into reading back
+    chip.write_register(0xf800, 0x80 | 0x7f);
+    assert_eq!(chip.read_register(0x4800), Some(0x0f));
+
+    for _ in 0..15 { chip.clock(); }
+    assert!((chip.output() - 7.0 * 15.0 * 0.0025).abs() < 0.0001);
+}
+
+#[test]
+fn test_vrc7_key_on() {
+    let mut chip = Vrc7Audio::new();
+    for (register, value) in [(0x10, 0xac), (0x30, 0x10), (0x20, 0x18)] {
+        chip.write_register(0x9010, register);
+        chip.write_register(0x9030, value);
+    }
+
+    let peak = (0..36 * 2000).fold(0.0f32, |peak, _| {
+        chip.clock();
+        peak.max(chip.output().abs())
+    });
+    assert!(peak > 0.01, "{peak}");
+
+    // and nothing at all with the key up
+    let mut chip = Vrc7Audio::new();
+    for _ in 0..36 * 2000 { chip.clock(); }
+    assert_eq!(chip.output(), 0.0);
+}
+
+#[test]
+fn test_fds_wave() {
+    let mut chip = FdsAudio::new();
+    chip.write_register(0x4089, 0x80);
+    for addr in 0x4040..0x4080 {
+        chip.write_register(addr, 0x3f);
+    }
+    assert_eq!(chip.read_register(0x4040), Some(0x7f));
+
+    // direct gain of 32, master volume 2/2
+    chip.write_register(0x4089, 0x00);
+    chip.write_register(0x4080, 0x80 | 0x20);
+    chip.write_register(0x4082, 0x00);
+    chip.write_register(0x4083, 0x01);
+    for _ in 0..10_000 { chip.clock(); }
+
+    assert!((chip.output() - 63.0 * 32.0 * 0.0003).abs() < 0.01);
+    assert_eq!(chip.read_register(0x4090), Some(0x60));
+}
+
+// an NSF loading `data` at $8000, INIT at $8000 and PLAY at $8010
+fn test_nsf(data: &[u8], bank_init: [u8; 8]) -> Vec<u8> {
+    let mut nsf = b"NESM\x1a\x01\x03\x02".to_vec();
+    nsf.extend_from_slice(&[0x00, 0x80, 0x00, 0x80, 0x10, 0x80]);
+    nsf.resize(0x80, 0);
+    nsf[0x0e..0x13].copy_from_slice(b"Tune!");
+    nsf[0x6e..0x70].copy_from_slice(&16_639u16.to_le_bytes());
+    nsf[0x70..0x78].copy_from_slice(&bank_init);
+    nsf.extend_from_slice(data);
+    nsf
+}
+
+#[test]
+fn test_nsf_header() {
+    let nsf = Nsf::from_bytes(&test_nsf(&[0x60], [0; 8])).unwrap();
+    assert_eq!(nsf.title, "Tune!");
+    assert_eq!((nsf.songs, nsf.starting_song), (3, 1));
+    assert_eq!((nsf.load_address, nsf.init_address, nsf.play_address), (0x8000, 0x8000, 0x8010));
+    assert_eq!(nsf.region(), Region::Ntsc);
+    assert!(!nsf.bankswitched());
+    assert_eq!(nsf.data, [0x60]);
+
+    assert!(matches!(Nsf::from_bytes(b"NESM"), Err(NsfError::NotNsf)));
+    assert!(matches!(Nsf::from_bytes(b"NESM\x1a\x01"), Err(NsfError::Truncated)));
+}
+
+#[test]
+fn test_nsfe_metadata() {
+    let chunk = |id: &[u8], data: &[u8]| {
+        let mut chunk = (data.len() as u32).to_le_bytes().to_vec();
+        chunk.extend_from_slice(id);
+        chunk.extend_from_slice(data);
+        chunk
+    };
+
+    let mut nsfe = b"NSFE".to_vec();
+    nsfe.extend(chunk(b"INFO", &[0x00, 0x80, 0x00, 0x80, 0x10, 0x80, 0x00, 0x01, 0x02, 0x01]));
+    nsfe.extend(chunk(b"DATA", &[0x60]));
+    nsfe.extend(chunk(b"auth", b"Song\0Someone\0\0Ripper\0"));
+    nsfe.extend(chunk(b"time", &[&90_000i32.to_le_bytes()[..], &(-1i32).to_le_bytes()].concat()));
+    nsfe.extend(chunk(b"fade", &2_000i32.to_le_bytes()));
+    nsfe.extend(chunk(b"tlbl", b"Intro\0Boss\0"));
+    nsfe.extend(chunk(b"NEND", &[]));
+
+    let nsf = Nsf::from_bytes(&nsfe).unwrap();
+    assert_eq!((nsf.title.as_str(), nsf.artist.as_str(), nsf.ripper.as_str()), ("Song", "Someone", "Ripper"));
+    assert_eq!((nsf.songs, nsf.starting_song), (2, 1));
+    assert!(nsf.expansion.vrc6());
+    assert_eq!(nsf.track(0).title.as_deref(), Some("Intro"));
+    assert_eq!((nsf.track(0).length, nsf.track(0).fade), (Some(90_000), Some(2_000)));
+    assert_eq!((nsf.track(1).length, nsf.track(1).fade), (None, None));
+
+    let mut unknown = nsfe.clone();
+    unknown.truncate(unknown.len() - 8);
+    unknown.extend(chunk(b"WHAT", &[]));
+    assert!(matches!(Nsf::from_bytes(&unknown), Err(NsfError::UnknownChunk(id)) if id == "WHAT"));
+}
+
+#[test]
+fn test_nsf_bankswitching() {
+    let mut data = vec![0x11; 0x1000];
+    data.extend(vec![0x22; 0x1000]);
+    let mut raw = test_nsf(&data, [1, 0, 0, 0, 0, 0, 0, 0]);
+    // loading at $8010 pads the first bank by $10
+    raw[0x08] = 0x10;
+
+    let mut mapper = NsfMapper::new(&Nsf::from_bytes(&raw).unwrap());
+    assert_eq!(mapper.cpu_read(0x8000), Some(0x11));
+    assert_eq!(mapper.cpu_read(0x8010), Some(0x22));
+    assert_eq!(mapper.cpu_read(0x9010), Some(0x11));
+
+    mapper.cpu_write(0x5ff8, 0);
+    assert_eq!(mapper.cpu_read(0x8000), Some(0x00));
+    assert_eq!(mapper.cpu_read(0x8010), Some(0x11));
+}
+
+#[test]
+fn test_nsf_player_calls() {
+    let mut data = vec![0xea; 0x20];
+    // INIT: store the track number at $00 and turn pulse 1 on, PLAY: count calls in $01
+    data[..5].copy_from_slice(&[0x85, 0x00, 0x86, 0x02, 0x60]);
+    data[0x10..0x13].copy_from_slice(&[0xe6, 0x01, 0x60]);
+
+    let mut player = NsfPlayer::new(Nsf::from_bytes(&test_nsf(&data, [0; 8])).unwrap());
+    assert_eq!(player.track(), 1);
+
+    player.start_track(2);
+    let samples = player.render(1000, 0);
+    assert_eq!(samples.len(), 44_100);
+    assert_eq!(player.cpu.mem_read(0x00), 2);
+    assert_eq!(player.cpu.mem_read(0x02), 0);
+
+    // 60.1 PLAYs a second, give or take the partial frame at the end
+    let calls = player.cpu.mem_read(0x01);
+    assert!((59..=62).contains(&calls), "{calls}");
+}
+
+#[test]
+fn test_nsf_fade() {
+    let mut data = vec![0x60; 0x20];
+    // INIT: pulse 1 at a constant volume, so there's something to fade
+    data[..16].copy_from_slice(&[
+        0xa9, 0xbf, 0x8d, 0x00, 0x40, 0xa9, 0x80, 0x8d, 0x02, 0x40, 0xa9, 0x08, 0x8d, 0x03, 0x40, 0x60,
+    ]);
+    let mut player = NsfPlayer::new(Nsf::from_bytes(&test_nsf(&data, [0; 8])).unwrap());
+
+    player.start_track(0);
+    let plain = player.render(200, 0);
+    player.start_track(0);
+    let faded = player.render(200, 100);
+
+    assert_eq!(plain.len(), 8_820);
+    assert!(plain.iter().any(|sample| sample.abs() > 0.01));
+    assert_eq!(plain[..4_410], faded[..4_410]);
+    for i in 4_410..8_820 {
+        let gain = 1.0 - (i - 4_410) as f32 / 4_410.0;
+        assert!((faded[i] - plain[i] * gain).abs() < 1e-6);
+    }
+}
+
+#[test]
+fn test_joypad_shift_register() {
+    let mut joypad = Joypad::default();
+    joypad.set_buttons(Buttons { a: true, start: true, right: true, ..Buttons::default() });
+    assert_eq!(Buttons::from_byte(joypad.buttons().to_byte()), joypad.buttons());
+
+    // held strobe keeps giving A
+    joypad.write_strobe(1);
+    assert_eq!([joypad.read_bit(), joypad.read_bit()], [1, 1]);
+
+    joypad.write_strobe(0);
+    let bits: Vec<u8> = (0..10).map(|_| joypad.read_bit()).collect();
+    assert_eq!(bits, [1, 0, 0, 1, 0, 0, 0, 1, 1, 1]);
+
+    // buttons changing mid-read don't show up until the next strobe
+    joypad.write_strobe(1);
+    joypad.write_strobe(0);
+    joypad.set_buttons(Buttons::default());
+    assert_eq!(joypad.read_bit(), 1);
+}
+
+#[test]
+fn test_controller_ports() {
+    // strobe, then LDA $4016 / STA $10, LDA $4016 / STA $11, LDA $4017 / STA $12
+    let program = [
+        0xa9, 0x01, 0x8d, 0x16, 0x40, 0xa9, 0x00, 0x8d, 0x16, 0x40,
+        0xad, 0x16, 0x40, 0x85, 0x10, 0xad, 0x16, 0x40, 0x85, 0x11, 0xad, 0x17, 0x40, 0x85, 0x12,
+        0x4c, 0x19, 0x80,
+    ];
+    let mut console = Console::new(Cartridge::from_ines(&test_rom(&program, &[0x40])).unwrap());
+    console.set_buttons(0, Buttons { a: true, ..Buttons::default() });
+    console.set_buttons(1, Buttons { a: true, ..Buttons::default() });
+    console.run_cycles(100);
+
+    // the top bits are the $40 left on the bus by the address's high byte
+    assert_eq!(console.cpu.mem_read(0x10), 0x41);
+    assert_eq!(console.cpu.mem_read(0x11), 0x40);
+    assert_eq!(console.cpu.mem_read(0x12), 0x41);
+}
+
+#[test]
+fn test_zapper_light_and_trigger() {
+    // backdrop white ($30) from the start, rendering on, then poll $4017 into $10 forever
+    let program = [
+        0xa9, 0x3f, 0x8d, 0x06, 0x20, 0xa9, 0x00, 0x8d, 0x06, 0x20, 0xa9, 0x30, 0x8d, 0x07, 0x20,
+        0xa9, 0x0a, 0x8d, 0x01, 0x20,
+        0xad, 0x17, 0x40, 0x85, 0x10, 0x4c, 0x14, 0x80,
+    ];
+    let mut console = Console::new(Cartridge::from_ines(&test_rom(&program, &[0x40])).unwrap());
+    console.connect(1, Box::new(Zapper::new()));
+    assert!(console.device_mut::<Joypad>(1).is_none());
+
+    let zapper = console.device_mut::<Zapper>(1).unwrap();
+    zapper.aim = Some((128, 100));
+    zapper.trigger = true;
+
+    console.step_frame();
+    console.step_frame();
+    // vblank, nowhere near the aim point: dark, but the trigger's held
+    assert_eq!(console.cpu.mem_read(0x10) & 0x18, 0x18);
+
+    while console.cpu.bus.ppu.scanline != 105 { console.step_instruction(); }
+    assert_eq!(console.cpu.mem_read(0x10) & 0x18, 0x10);
+
+    // pointing off screen never sees anything
+    console.device_mut::<Zapper>(1).unwrap().aim = None;
+    for _ in 0..6 { console.step_instruction(); }
+    assert_eq!(console.cpu.mem_read(0x10) & 0x08, 0x08);
+}
+
+#[test]
+fn test_four_score_signature() {
+    let mut console = Console::new(Cartridge::from_ines(&test_rom(&[0x4c, 0x00, 0x80], &[0x40])).unwrap());
+    console.connect_four_player(FourPlayerMode::FourScore);
+    console.set_buttons(0, Buttons { a: true, ..Buttons::default() });
+    console.set_buttons(1, Buttons { b: true, ..Buttons::default() });
+    console.set_buttons(2, Buttons { start: true, ..Buttons::default() });
+    console.set_buttons(3, Buttons { right: true, ..Buttons::default() });
+
+    console.cpu.mem_write(0x4016, 1);
+    console.cpu.mem_write(0x4016, 0);
+    let mut read = |addr: u16| -> u32 {
+        (0..24).fold(0, |bits, i| bits | u32::from(console.cpu.mem_read(addr) & 1) << i)
+    };
+
+    // pad, the pad behind it, then the signature
+    assert_eq!(read(0x4016), 0x01 | 0x08 << 8 | 0x08 << 16);
+    assert_eq!(read(0x4017), 0x02 | 0x80 << 8 | 0x04 << 16);
+    assert_eq!(console.cpu.mem_read(0x4016) & 1, 1);
+}
+
+#[test]
+fn test_famicom_four_player() {
+    let mut adapter = FourPlayerAdapter::new(FourPlayerMode::Famicom, 0);
+    adapter.set_buttons(0, Buttons { a: true, ..Buttons::default() });
+    adapter.set_buttons(1, Buttons { b: true, ..Buttons::default() });
+    assert_eq!(adapter.mode(), FourPlayerMode::Famicom);
+
+    let ppu = PictureProcessor::new(Region::Ntsc);
+    adapter.write(1);
+    adapter.write(0);
+    let reads: Vec<u8> = (0..3).map(|_| adapter.read(&ppu)).collect();
+    assert_eq!(reads, [0b01, 0b10, 0b00]);
+}
+
+#[test]
+fn test_port_peripherals() {
+    let ppu = PictureProcessor::new(Region::Ntsc);
+
+    // the knob comes out inverted, MSB first on bit 4
+    let mut vaus = ArkanoidVaus::new();
+    vaus.position = 0xa5;
+    vaus.button = true;
+    vaus.write(1);
+    vaus.write(0);
+    let knob = (0..8).fold(0u8, |bits, _| bits << 1 | vaus.read(&ppu) >> 4 & 1);
+    assert_eq!(knob, !0xa5);
+    assert_eq!(vaus.read(&ppu) & 0x08, 0x08);
+
+    let mut pad = PowerPad::new();
+    pad.buttons[1] = true;
+    pad.buttons[2] = true;
+    pad.write(1);
+    pad.write(0);
+    let reads: Vec<u8> = (0..9).map(|_| pad.read(&ppu) >> 3).collect();
+    // button 2 first on bit 3, button 3 second on bit 4, then 1s once each runs out
+    assert_eq!(reads, [0b01, 0b10, 0b00, 0b00, 0b10, 0b10, 0b10, 0b10, 0b11]);
+
+    let mut mouse = SnesMouse::new();
+    mouse.left = true;
+    mouse.move_by(-3, 200);
+    mouse.write(1);
+    mouse.write(0);
+    let report = (0..32).fold(0u32, |bits, _| bits << 1 | u32::from(mouse.read(&ppu)));
+    assert_eq!(report, 0x0041_7f83);
+    assert_eq!(mouse.read(&ppu), 1);
+
+    // clocking it while strobed steps the sensitivity
+    mouse.write(1);
+    mouse.read(&ppu);
+    assert_eq!(mouse.sensitivity(), 1);
+}
+
+#[test]
+fn test_expansion_port() {
+    let mut console = Console::new(Cartridge::from_ines(&test_rom(&[0x4c, 0x00, 0x80], &[0x40])).unwrap());
+    let mut keyboard = FamilyKeyboard::new();
+    keyboard.set_key(Key::Return, true);
+    keyboard.set_key(Key::Space, true);
+    assert!(keyboard.is_pressed(Key::Space));
+    console.connect_expansion(Box::new(keyboard));
+
+    // row 0, column 0: ] [ RETURN F8, with RETURN held
+    console.cpu.mem_write(0x4016, 0b101);
+    assert_eq!(console.cpu.mem_read(0x4017) & 0x1e, 0b10110);
+
+    // walk down to row 8's second column: INS DEL SPACE DOWN
+    for _ in 0..8 {
+        console.cpu.mem_write(0x4016, 0b110);
+        console.cpu.mem_write(0x4016, 0b100);
+    }
+    console.cpu.mem_write(0x4016, 0b110);
+    assert_eq!(console.cpu.mem_read(0x4017) & 0x1e, 0b10110);
+
+    // switched off, it lets go of the lines
+    console.cpu.mem_write(0x4016, 0);
+    assert_eq!(console.cpu.mem_read(0x4017) & 0x1e, 0);
+
+    // the Famicom Vaus puts its button on $4016 bit 1, next to the first pad
+    console.connect_expansion(Box::new(ArkanoidVaus::new()));
+    console.expansion_device_mut::<ArkanoidVaus>().unwrap().button = true;
+    console.set_buttons(0, Buttons { a: true, ..Buttons::default() });
+    console.cpu.mem_write(0x4016, 1);
+    assert_eq!(console.cpu.mem_read(0x4016) & 0x1f, 0b11);
+}
+
+// turns on NMIs, rendering and a pulse tone, then counts in PRG RAM forever
+const BUSY_PROGRAM: [u8; 36] = [
+    0xa9, 0x80, 0x8d, 0x00, 0x20, 0xa9, 0x1e, 0x8d, 0x01, 0x20,
+    0xa9, 0x01, 0x8d, 0x15, 0x40, 0xa9, 0xbf, 0x8d, 0x00, 0x40,
+    0xa9, 0x40, 0x8d, 0x02, 0x40, 0xa9, 0x00, 0x8d, 0x03, 0x40,
+    0xee, 0x00, 0x60, 0x4c, 0x1e, 0x80,
+];
+
+#[test]
+fn test_save_state_round_trip() {
+    let rom = test_rom(&BUSY_PROGRAM, &[0xe6, 0x10, 0x40]);
+    let mut console = Console::new(Cartridge::from_ines(&rom).unwrap());
+    for _ in 0..3 { console.step_frame(); }
+
+    let state = console.save_state();
+    let frames: Vec<Vec<u8>> = (0..2).map(|_| console.step_frame().pixels).collect();
+    let after = console.save_state();
+    assert_ne!(state, after);
+
+    console.load_state(&state).unwrap();
+    assert_eq!(console.frame_number(), 3);
+    assert_eq!(console.save_state(), state);
+
+    // replaying from the state has to land in exactly the same place
+    let replayed: Vec<Vec<u8>> = (0..2).map(|_| console.step_frame().pixels).collect();
+    assert_eq!(replayed, frames);
+    assert_eq!(console.save_state(), after);
+    assert_eq!(console.cpu.mem_read(0x10), 4);
+}
+
+#[test]
+fn test_save_state_rejects() {
+    let rom = test_rom(&BUSY_PROGRAM, &[0xe6, 0x10, 0x40]);
+    let mut console = Console::new(Cartridge::from_ines(&rom).unwrap());
+    console.step_frame();
+    let state = console.save_state();
+    console.step_frame();
+    let before = console.save_state();
+
+    let mut other = Console::new(Cartridge::from_ines(&test_rom(&[0x4c, 0x00, 0x80], &[0x40])).unwrap());
+    assert!(matches!(other.load_state(&state), Err(StateError::RomMismatch { .. })));
+
+    let mut pal = Console::with_region(Cartridge::from_ines(&rom).unwrap(), Region::Pal);
+    assert!(matches!(pal.load_state(&state), Err(StateError::RegionMismatch { .. })));
+
+    assert!(matches!(console.load_state(b"not a state"), Err(StateError::NotAState)));
+
+    let mut version = state.clone();
+    version[4] = 0xff;
+    assert!(matches!(console.load_state(&version), Err(StateError::UnsupportedVersion(_))));
+
+    // failing partway through doesn't leave anything half loaded
+    assert!(matches!(console.load_state(&state[..state.len() / 2]), Err(StateError::Truncated)));
+    assert_eq!(console.save_state(), before);
+}
+
+#[test]
+fn test_save_state_rejects_counters_out_of_range() {
+    // each of these would otherwise overflow or never wrap on the next clock
+    fn rejects<S: Snapshot>(mut snapshot: S, corrupt: impl Fn(&mut Vec<u8>)) -> bool {
+        let mut state = StateWriter::new();
+        snapshot.save_state(&mut state);
+        let mut state = state.into_bytes();
+        corrupt(&mut state);
+        matches!(snapshot.load_state(&mut StateReader::new(&state)), Err(StateError::Corrupt(_)))
+    }
+
+    let timing = Region::Ntsc.timing();
+    // bits_remaining comes after the two flags, rate, timer, level and shift register
+    assert!(rejects(Dmc::new(&timing.dmc_periods), |state| state[7] = 0));
+    assert!(rejects(Dmc::new(&timing.dmc_periods), |state| state[7] = 9));
+    assert!(rejects(FrameCounter::new(timing), |state| state[0..4].copy_from_slice(&u32::MAX.to_le_bytes())));
+    // the sample clock is the last byte before the 32 bit LFO clock
+    assert!(rejects(Vrc7Audio::new(), |state| {
+        let at = state.len() - 5;
+        state[at] = 255;
+    }));
+    // and N163's comes after its RAM, address and two flags
+    assert!(rejects(N163Audio::new(), |state| {
+        let at = 4 + u32::from_le_bytes(state[0..4].try_into().unwrap()) as usize + 3;
+        state[at] = 255;
+    }));
+}
+
+#[test]
+fn test_state_deltas() {
+    let from: Vec<u8> = (0..=255).cycle().take(5000).collect();
+    let mut to = from.clone();
+    to[3] ^= 0xff;
+    to[4] = 7;
+    to[2000..2010].fill(0xaa);
+    to[4999] = 1;
+
+    let patch = delta::encode(&from, &to);
+    assert!(patch.len() < 40);
+    assert_eq!(delta::apply(&from, &patch).unwrap(), to);
+    assert!(delta::encode(&from, &from).is_empty());
+    assert_eq!(delta::apply(&from[..100], &patch), None);
+}
+
+#[test]
+fn test_rewind() {
+    // the NMI adds up how many frames A was held for in $11
+    let nmi_handler = [
+        0xa9, 0x01, 0x8d, 0x16, 0x40, 0xa9, 0x00, 0x8d, 0x16, 0x40,
+        0xad, 0x16, 0x40, 0x29, 0x01, 0x18, 0x65, 0x11, 0x85, 0x11, 0x40,
+    ];
+    let rom = test_rom(&BUSY_PROGRAM, &nmi_handler);
+    let mut console = Console::new(Cartridge::from_ines(&rom).unwrap());
+    console.enable_rewind(5, 1 << 20);
+
+    let mut states = vec![console.save_state()];
+    for frame in 0..20 {
+        console.set_buttons(0, Buttons { a: frame % 3 == 0, ..Buttons::default() });
+        console.step_frame();
+        states.push(console.save_state());
+    }
+    assert_eq!(console.rewind_buffer().unwrap().snapshots(), 5);
+
+    // every step back lands on exactly what that frame was the first time round
+    for frame in (0..20).rev() {
+        let shown = console.step_back().unwrap();
+        assert_eq!(shown.number, frame as u64);
+        assert!(shown.samples.is_empty());
+        assert_eq!(console.save_state(), states[frame]);
+    }
+    assert!(console.step_back().is_none());
+
+    // and playing on from there records history all over again
+    console.set_buttons(0, Buttons::default());
+    for _ in 0..7 { console.step_frame(); }
+    console.step_back().unwrap();
+    assert_eq!(console.frame_number(), 6);
+}
+
+#[test]
+fn test_rewind_budget() {
+    let rom = test_rom(&BUSY_PROGRAM, &[0xe6, 0x10, 0x40]);
+    let mut console = Console::new(Cartridge::from_ines(&rom).unwrap());
+    let state_size = console.save_state().len();
+    console.enable_rewind(1, state_size + 4096);
+
+    for _ in 0..60 { console.step_frame(); }
+    let rewind = console.rewind_buffer().unwrap();
+    assert!(rewind.memory_used() <= state_size + 4096);
+    assert!(rewind.snapshots() > 1);
+
+    // only back as far as what's left
+    let oldest = rewind.oldest_frame().unwrap();
+    assert!(oldest > 0);
+    let mut steps = 0;
+    while console.step_back().is_some() { steps += 1; }
+    assert_eq!(steps, 60 - oldest);
+    assert_eq!(console.frame_number(), oldest);
+}
+
+#[test]
+fn test_run_ahead() {
+    // the NMI counts the frames A was held for in $11 and shows the count as the backdrop colour
+    let nmi_handler = [
+        0xa9, 0x01, 0x8d, 0x16, 0x40, 0xa9, 0x00, 0x8d, 0x16, 0x40,
+        0xad, 0x16, 0x40, 0x29, 0x01, 0x18, 0x65, 0x11, 0x85, 0x11,
+        0xa9, 0x3f, 0x8d, 0x06, 0x20, 0xa9, 0x00, 0x8d, 0x06, 0x20,
+        0xa5, 0x11, 0x29, 0x3f, 0x8d, 0x07, 0x20, 0xa9, 0x00, 0x8d,
+        0x06, 0x20, 0x8d, 0x06, 0x20, 0x40,
+    ];
+    let rom = test_rom(&BUSY_PROGRAM, &nmi_handler);
+    let held = Buttons { a: true, ..Buttons::default() };
+
+    let mut reference = Console::new(Cartridge::from_ines(&rom).unwrap());
+    reference.set_buttons(0, held);
+    let frames: Vec<(Frame, Vec<u8>)> = (0..16).map(|_| (reference.step_frame(), reference.save_state())).collect();
+    assert_ne!(frames[4].0.pixels, frames[6].0.pixels);
+
+    for second_instance in [false, true] {
+        let mut console = Console::new(Cartridge::from_ines(&rom).unwrap());
+        if second_instance {
+            console.enable_run_ahead_second_instance(2, Cartridge::from_ines(&rom).unwrap()).unwrap();
+        } else {
+            console.enable_run_ahead(2);
+        }
+        assert_eq!(console.run_ahead_frames(), 2);
+        console.set_buttons(0, held);
+
+        // each frame shows the picture from two frames on, but the machine itself only moves one,
+        // and the sound is the real frame's
+        for frame in 0..14 {
+            let shown = console.step_frame();
+            assert_eq!(shown.number, frame as u64 + 1);
+            assert!(shown.pixels == frames[frame + 2].0.pixels);
+            assert!(console.save_state() == frames[frame].1);
+            assert_eq!(shown.samples, frames[frame].0.samples);
+        }
+    }
+
+    let mut console = Console::new(Cartridge::from_ines(&rom).unwrap());
+    let other = test_rom(&[0x4c, 0x00, 0x80], &[0x40]);
+    assert!(matches!(
+        console.enable_run_ahead_second_instance(1, Cartridge::from_ines(&other).unwrap()),
+        Err(StateError::RomMismatch { .. })
+    ));
+    assert_eq!(console.run_ahead_frames(), 0);
+}
+
+// the NMI adds up how many frames A was held on pad 1 in $11 and on pad 2 in $12
+const TWO_PAD_NMI: [u8; 31] = [
+    0xa9, 0x01, 0x8d, 0x16, 0x40, 0xa9, 0x00, 0x8d, 0x16, 0x40,
+    0xad, 0x16, 0x40, 0x29, 0x01, 0x18, 0x65, 0x11, 0x85, 0x11,
+    0xad, 0x17, 0x40, 0x29, 0x01, 0x18, 0x65, 0x12, 0x85, 0x12, 0x40,
+];
+
+// packets on their way, with the tick each one arrives at
+type InFlight = Rc<RefCell<VecDeque<(u64, Vec<u8>)>>>;
+
+// one end of a link that holds every packet back for `latency` ticks of a shared clock
+struct LaggyLink {
+    clock: Rc<Cell<u64>>,
+    latency: u64,
+    outgoing: InFlight,
+    incoming: InFlight,
+}
+
+impl LaggyLink {
+    fn pair(clock: &Rc<Cell<u64>>, latency: u64) -> (LaggyLink, LaggyLink) {
+        let (there, back) = (Rc::default(), Rc::default());
+        let end = |outgoing: &Rc<_>, incoming: &Rc<_>| LaggyLink {
+            clock: clock.clone(), latency, outgoing: Rc::clone(outgoing), incoming: Rc::clone(incoming),
+        };
+        (end(&there, &back), end(&back, &there))
+    }
+}
+
+impl Transport for LaggyLink {
+    fn send(&mut self, packet: &[u8]) -> std::io::Result<()> {
+        self.outgoing.borrow_mut().push_back((self.clock.get() + self.latency, packet.to_vec()));
+        Ok(())
+    }
+
+    fn receive(&mut self) -> std::io::Result<Option<Vec<u8>>> {
+        let mut incoming = self.incoming.borrow_mut();
+        match incoming.front() {
+            Some((arrives, _)) if *arrives <= self.clock.get() => Ok(incoming.pop_front().map(|(_, packet)| packet)),
+            _ => Ok(None),
+        }
+    }
+}
+
+#[test]
+fn test_netplay_packet() {
+    let packet = Packet { ack: 12, first_frame: 9, inputs: vec![1, 0, 0x81], hashes: vec![(8, 0xdead_beef)] };
+    assert_eq!(Packet::decode(&packet.encode()).unwrap(), packet);
+    assert!(Packet::decode(b"NESN").is_err());
+    assert!(Packet::decode(&[0; 32]).is_err());
+}
+
+#[test]
+fn test_netplay_rollback() {
+    let rom = test_rom(&BUSY_PROGRAM, &TWO_PAD_NMI);
+    let pad = |player: usize, frame: u64| Buttons { a: frame % (5 + player as u64 * 2) < 2 + player as u64, ..Buttons::default() };
+
+    for delay in [0, 2] {
+        let clock = Rc::new(Cell::new(0));
+        let (link_a, link_b) = LaggyLink::pair(&clock, 3);
+        let mut sessions = [NetplaySession::new(link_a, 0), NetplaySession::new(link_b, 1)];
+        let mut consoles = [0, 1].map(|_| Console::new(Cartridge::from_ines(&rom).unwrap()));
+        for session in sessions.iter_mut() { session.set_input_delay(delay); }
+
+        // both sides run ahead on guesses, the packets turn up three ticks late
+        while sessions.iter().any(|session| session.frame() < 20) {
+            clock.set(clock.get() + 1);
+            for (player, (session, console)) in sessions.iter_mut().zip(consoles.iter_mut()).enumerate() {
+                if session.frame() < 20 {
+                    session.advance_frame(console, pad(player, session.frame())).unwrap();
+                }
+            }
+        }
+        while sessions.iter().any(|session| session.confirmed_frames() < 20) {
+            clock.set(clock.get() + 1);
+            for (session, console) in sessions.iter_mut().zip(consoles.iter_mut()) {
+                session.poll(console).unwrap();
+            }
+        }
+        assert!(sessions.iter().all(|session| session.rollbacks() > 0));
+
+        // and end up exactly where one console with both pads would
+        let mut reference = Console::new(Cartridge::from_ines(&rom).unwrap());
+        for frame in 0..20u64 {
+            for player in 0..2 {
+                let buttons = frame.checked_sub(delay).map_or(Buttons::default(), |held| pad(player, held));
+                reference.set_buttons(player, buttons);
+            }
+            reference.step_frame();
+        }
+        assert!(consoles[0].save_state() == reference.save_state());
+        assert!(consoles[1].save_state() == reference.save_state());
+    }
+}
+
+#[test]
+fn test_netplay_stalls_and_desyncs() {
+    let rom = test_rom(&BUSY_PROGRAM, &TWO_PAD_NMI);
+    let clock = Rc::new(Cell::new(0));
+    let (link_a, link_b) = LaggyLink::pair(&clock, 1);
+    let mut a = NetplaySession::new(link_a, 0);
+    let mut b = NetplaySession::new(link_b, 1);
+    a.set_max_prediction(4);
+    let mut console_a = Console::new(Cartridge::from_ines(&rom).unwrap());
+    let mut console_b = Console::new(Cartridge::from_ines(&rom).unwrap());
+
+    // nothing from the other side yet, so only four frames of guessing
+    for _ in 0..4 { assert!(a.advance_frame(&mut console_a, Buttons::default()).unwrap().is_some()); }
+    assert!(a.advance_frame(&mut console_a, Buttons::default()).unwrap().is_none());
+    assert_eq!(console_a.frame_number(), 4);
+
+    // two machines that don't match get caught at the first frame they both hash
+    console_b.cpu.register_x = 0x55;
+    let mut desync = None;
+    for _ in 0..20 {
+        clock.set(clock.get() + 1);
+        for (session, console) in [(&mut a, &mut console_a), (&mut b, &mut console_b)] {
+            if let Err(NetplayError::Desync { frame }) = session.advance_frame(console, Buttons::default()) {
+                desync = Some(frame);
+            }
+        }
+    }
+    assert_eq!(desync, Some(0));
+}
+
+#[test]
+fn test_netplay_over_udp() {
+    let rom = test_rom(&BUSY_PROGRAM, &TWO_PAD_NMI);
+    let sockets = [0, 1].map(|_| UdpSocket::bind("127.0.0.1:0").unwrap());
+    let addresses = [0, 1].map(|side| sockets[side].local_addr().unwrap());
+    let mut sessions = sockets.into_iter().enumerate()
+        .map(|(side, socket)| NetplaySession::new(UdpTransport::new(socket, addresses[1 - side]).unwrap(), side))
+        .collect::<Vec<_>>();
+    let mut consoles = [0, 1].map(|_| Console::new(Cartridge::from_ines(&rom).unwrap()));
+
+    for _ in 0..2000 {
+        if sessions.iter().all(|session| session.frame() >= 20 && session.confirmed_frames() >= 20) { break; }
+        for (side, (session, console)) in sessions.iter_mut().zip(consoles.iter_mut()).enumerate() {
+            if session.frame() < 20 {
+                let buttons = Buttons { a: (session.frame() + side as u64).is_multiple_of(3), ..Buttons::default() };
+                session.advance_frame(console, buttons).unwrap();
+            } else {
+                session.poll(console).unwrap();
+            }
+        }
+        std::thread::sleep(std::time::Duration::from_millis(1));
+    }
+
+    assert!(sessions.iter().all(|session| session.confirmed_frames() >= 20));
+    assert_eq!(consoles[0].frame_number(), 20);
+    assert!(consoles[0].save_state() == consoles[1].save_state());
+}
+
+#[test]
+fn test_fm2_round_trip() {
+    let text = "version 3\nemuVersion 22020\nrerecordCount 7\npalFlag 0\nromFilename smb\n\
+        romChecksum base64:1B2M2Y8AsgTpgAmY7PhCfg==\nguid 452DE2C3-EF43-2FA9-77AC-0677FC51543B\n\
+        fourscore 1\nmicrophone 0\nport0 0\nport1 0\nport2 0\nFDS 0\nNewPPU 0\ncomment author someone\n\
+        |0|R......A|........|....T...|.L......||\n|1|........|.......A|........|........||\n";
+    let movie = Movie::from_fm2(text).unwrap();
+    assert_eq!(movie.rerecord_count, 7);
+    assert_eq!(movie.rom_md5, Some(md5(b"")));
+    assert!(movie.four_score);
+    assert_eq!(movie.comments, vec!["author someone".to_string()]);
+    assert_eq!(movie.len(), 2);
+    assert_eq!(movie.frames[0].pads[0], Buttons { right: true, a: true, ..Buttons::default() });
+    assert_eq!(movie.frames[0].pads[2], Buttons { start: true, ..Buttons::default() });
+    assert_eq!(movie.frames[0].pads[3], Buttons { left: true, ..Buttons::default() });
+    assert!(movie.frames[1].reset());
+
+    let again = Movie::from_fm2(&movie.to_fm2()).unwrap();
+    assert_eq!(again.frames, movie.frames);
+    assert_eq!(again.guid, movie.guid);
+    assert_eq!(again.rom_md5, movie.rom_md5);
+
+    assert!(matches!(Movie::from_fm2("rerecordCount 1\n"), Err(MovieError::NotFm2)));
+    assert!(matches!(Movie::from_fm2("version 2\n"), Err(MovieError::Unsupported(_))));
+    assert!(matches!(Movie::from_fm2("version 3\n|0|bad|\n"), Err(MovieError::Malformed { line: 2, .. })));
+    assert!(matches!(Movie::from_fm2("version 3\nport0 2\n"), Err(MovieError::Unsupported(_))));
+}
+
+#[test]
+fn test_movie_playback() {
+    // the NMI adds up how many frames A was held for in $11
+    let nmi_handler = [
+        0xa9, 0x01, 0x8d, 0x16, 0x40, 0xa9, 0x00, 0x8d, 0x16, 0x40,
+        0xad, 0x16, 0x40, 0x29, 0x01, 0x18, 0x65, 0x11, 0x85, 0x11, 0x40,
+    ];
+    let rom = test_rom(&BUSY_PROGRAM, &nmi_handler);
+
+    let mut console = Console::new(Cartridge::from_ines(&rom).unwrap());
+    console.start_movie_recording(MovieStart::PowerOn).unwrap();
+    let mut recorded = Vec::new();
+    for frame in 0..30 {
+        if frame == 12 { console.reset(); }
+        console.set_buttons(0, Buttons { a: frame % 4 != 0, right: frame > 20, ..Buttons::default() });
+        recorded.push(console.step_frame().pixels);
+    }
+    let final_state = console.save_state();
+    let movie = console.stop_movie().unwrap();
+    assert_eq!(movie.len(), 30);
+    assert!(movie.frames[12].reset());
+    assert_eq!(movie.rom_md5, Some(Cartridge::from_ines(&rom).unwrap().md5));
+
+    // through FM2 and back, then twice over: the same frames and the same machine every time
+    let movie = Movie::from_fm2(&movie.to_fm2()).unwrap();
+    for _ in 0..2 {
+        let mut replay = Console::new(Cartridge::from_ines(&rom).unwrap());
+        replay.play_movie(movie.clone()).unwrap();
+        for pixels in recorded.iter() {
+            // the host's input is ignored while a movie plays
+            replay.set_buttons(0, Buttons::default());
+            assert_eq!(&replay.step_frame().pixels, pixels);
+        }
+        assert!(!replay.is_playing_movie());
+        assert_eq!(replay.save_state(), final_state);
+    }
+
+    // a power-on movie can't start part way through
+    let mut late = Console::new(Cartridge::from_ines(&rom).unwrap());
+    late.step_frame();
+    assert!(matches!(late.play_movie(movie.clone()), Err(MovieError::NotAtPowerOn)));
+    assert!(matches!(late.start_movie_recording(MovieStart::PowerOn), Err(MovieError::NotAtPowerOn)));
+}
+
+#[test]
+fn test_movie_from_save_state() {
+    let nmi_handler = [
+        0xa9, 0x01, 0x8d, 0x16, 0x40, 0xa9, 0x00, 0x8d, 0x16, 0x40,
+        0xad, 0x16, 0x40, 0x29, 0x01, 0x18, 0x65, 0x11, 0x85, 0x11, 0x40,
+    ];
+    let rom = test_rom(&BUSY_PROGRAM, &nmi_handler);
+
+    let mut console = Console::new(Cartridge::from_ines(&rom).unwrap());
+    for _ in 0..5 { console.step_frame(); }
+    console.start_movie_recording(MovieStart::SaveState).unwrap();
+    let mut checkpoint = Vec::new();
+    for frame in 0..10 {
+        if frame == 3 { checkpoint = console.save_state(); }
+        console.set_buttons(0, Buttons { a: frame % 2 == 0, ..Buttons::default() });
+        console.step_frame();
+    }
+
+    // loading a state from during the recording is a rerecord, and drops what came after it
+    console.load_state(&checkpoint).unwrap();
+    for _ in 0..4 {
+        console.set_buttons(0, Buttons { a: true, ..Buttons::default() });
+        console.step_frame();
+    }
+    let final_state = console.save_state();
+    let movie = console.stop_movie().unwrap();
+    assert_eq!(movie.rerecord_count, 1);
+    assert_eq!(movie.len(), 7);
+
+    let movie = Movie::from_fm2(&movie.to_fm2()).unwrap();
+    let mut replay = Console::new(Cartridge::from_ines(&rom).unwrap());
+    replay.play_movie(movie).unwrap();
+    for _ in 0..7 { replay.step_frame(); }
+    assert_eq!(replay.save_state(), final_state);
+}
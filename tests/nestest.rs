@@ -0,0 +1,53 @@
+use std::fs;
+use std::path::Path;
+use fucking_nes_emulator::bus::Bus;
+use fucking_nes_emulator::cartridge::Cartridge;
+use fucking_nes_emulator::Console;
+
+// nestest.nes and its nestest.log aren't ours to ship, so this one only runs when asked for:
+// put them in tests/roms and run cargo test -- --ignored
+const ROM_DIR: &str = "tests/roms";
+
+// the log goes on into the unofficial opcodes, marked with a * before the mnemonic. we stop there
+fn official_lines(log: &str) -> Vec<&str> {
+    log.lines()
+        .map(str::trim_end)
+        .take_while(|line| line.as_bytes().get(15) != Some(&b'*'))
+        .collect()
+}
+
+fn mismatch(number: usize, previous: Option<&str>, expected: &str, actual: &str) -> String {
+    let column = expected.bytes().zip(actual.bytes()).position(|(e, a)| e != a)
+        .unwrap_or(expected.len().min(actual.len()));
+    format!(
+        "nestest.log line {number}, column {}:\n  previous: {}\n  expected: {expected}\n  actual:   {actual}\n  {}^",
+        column + 1, previous.unwrap_or("(none, it's the first line)"), " ".repeat(column + 10)
+    )
+}
+
+#[test]
+#[ignore = "needs nestest.nes and nestest.log in tests/roms"]
+fn test_nestest_log() {
+    let dir = Path::new(env!("CARGO_MANIFEST_DIR")).join(ROM_DIR);
+    let rom = fs::read(dir.join("nestest.nes")).unwrap_or_else(|e| panic!("no nestest.nes in {}: {e}", dir.display()));
+    let log = fs::read_to_string(dir.join("nestest.log"))
+        .unwrap_or_else(|e| panic!("no nestest.log in {}: {e}", dir.display()));
+
+    let mut console = Console::new(Cartridge::from_ines(&rom).unwrap());
+    // automation mode starts at $C000. the log has the reset's 7 cycles already on the PPU
+    console.cpu.program_counter = 0xc000;
+    console.cpu.bus.tick(7);
+
+    let expected = official_lines(&log);
+    for (index, expected_line) in expected.iter().enumerate() {
+        let actual = console.cpu.trace_line();
+        if actual != *expected_line {
+            let previous = index.checked_sub(1).map(|previous| expected[previous]);
+            panic!("{}", mismatch(index + 1, previous, expected_line, &actual));
+        }
+        console.cpu.step();
+    }
+
+    // where nestest leaves the number of the first official opcode test that failed
+    assert_eq!(console.cpu.bus.peek(0x02), 0, "nestest reported a failure in $02");
+}
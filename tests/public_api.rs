@@ -0,0 +1,16 @@
+use fucking_nes_emulator::bus::{Bus, FlatMemory};
+use fucking_nes_emulator::cpu::Processor;
+
+#[test]
+fn test_processor_on_own_bus() {
+    let mut memory = FlatMemory::default();
+    memory.load(0x0600, &[0xa9, 0x42, 0x85, 0x10, 0x00]);
+    memory.mem_write(0xfffc, 0x00);
+    memory.mem_write(0xfffd, 0x06);
+
+    let mut cpu = Processor::with_bus(memory);
+    cpu.reset();
+    cpu.run();
+
+    assert_eq!(cpu.bus.mem_read(0x10), 0x42);
+}
@@ -0,0 +1,516 @@
+use std::any::Any;
+use std::collections::BTreeMap;
+use std::fs;
+use std::panic::{self, AssertUnwindSafe};
+use std::path::Path;
+use fucking_nes_emulator::bus::Bus;
+use fucking_nes_emulator::cpu::{AddressingMode, CPUStatusFlags, OpCode, Processor, ProcessorAction, CPU_OPCODES};
+
+// the nes6502 set from SingleStepTests/ProcessorTests (one 00.json to ff.json per opcode, 10000
+// cases each, decimal mode left out like on the 2A03) isn't ours to ship, so this one only runs
+// when asked for: put the files in tests/roms/nes6502 and run cargo test -- --ignored
+const TEST_DIR: &str = "tests/roms/nes6502";
+
+// just enough JSON for the test files: no serde here
+mod json {
+    #[derive(Debug, Clone, PartialEq)]
+    pub enum Value {
+        Null,
+        Bool(bool),
+        Number(f64),
+        String(String),
+        Array(Vec<Value>),
+        Object(Vec<(String, Value)>),
+    }
+
+    impl Value {
+        pub fn get(&self, key: &str) -> Option<&Value> {
+            match self {
+                Value::Object(fields) => fields.iter().find(|(name, _)| name == key).map(|(_, value)| value),
+                _ => None,
+            }
+        }
+
+        pub fn as_array(&self) -> Option<&[Value]> {
+            match self {
+                Value::Array(items) => Some(items),
+                _ => None,
+            }
+        }
+
+        pub fn as_str(&self) -> Option<&str> {
+            match self {
+                Value::String(text) => Some(text),
+                _ => None,
+            }
+        }
+
+        pub fn as_u64(&self) -> Option<u64> {
+            match self {
+                Value::Number(number) if *number >= 0.0 && number.fract() == 0.0 => Some(*number as u64),
+                _ => None,
+            }
+        }
+    }
+
+    pub fn parse(text: &str) -> Result<Value, String> {
+        let mut parser = Parser { bytes: text.as_bytes(), position: 0 };
+        let value = parser.value()?;
+        parser.skip_whitespace();
+        if parser.position != parser.bytes.len() { return Err(parser.error("trailing characters")); }
+        Ok(value)
+    }
+
+    struct Parser<'a> {
+        bytes: &'a [u8],
+        position: usize,
+    }
+
+    impl Parser<'_> {
+        fn error(&self, what: &str) -> String {
+            format!("{what} at byte {}", self.position)
+        }
+
+        fn skip_whitespace(&mut self) {
+            while self.bytes.get(self.position).is_some_and(|byte| byte.is_ascii_whitespace()) {
+                self.position += 1;
+            }
+        }
+
+        fn peek(&mut self) -> Option<u8> {
+            self.skip_whitespace();
+            self.bytes.get(self.position).copied()
+        }
+
+        fn expect(&mut self, byte: u8) -> Result<(), String> {
+            if self.peek() != Some(byte) { return Err(self.error(&format!("expected '{}'", char::from(byte)))); }
+            self.position += 1;
+            Ok(())
+        }
+
+        fn literal(&mut self, word: &str, value: Value) -> Result<Value, String> {
+            if !self.bytes[self.position..].starts_with(word.as_bytes()) { return Err(self.error("unknown literal")); }
+            self.position += word.len();
+            Ok(value)
+        }
+
+        fn value(&mut self) -> Result<Value, String> {
+            match self.peek() {
+                Some(b'{') => self.object(),
+                Some(b'[') => self.array(),
+                Some(b'"') => Ok(Value::String(self.string()?)),
+                Some(b't') => self.literal("true", Value::Bool(true)),
+                Some(b'f') => self.literal("false", Value::Bool(false)),
+                Some(b'n') => self.literal("null", Value::Null),
+                Some(b'-' | b'0'..=b'9') => self.number(),
+                Some(_) => Err(self.error("unexpected character")),
+                None => Err(self.error("unexpected end")),
+            }
+        }
+
+        fn object(&mut self) -> Result<Value, String> {
+            self.expect(b'{')?;
+            let mut fields = Vec::new();
+            if self.peek() == Some(b'}') {
+                self.position += 1;
+                return Ok(Value::Object(fields));
+            }
+
+            loop {
+                self.skip_whitespace();
+                let key = self.string()?;
+                self.expect(b':')?;
+                fields.push((key, self.value()?));
+                match self.peek() {
+                    Some(b',') => self.position += 1,
+                    Some(b'}') => {
+                        self.position += 1;
+                        return Ok(Value::Object(fields));
+                    }
+                    _ => return Err(self.error("expected ',' or '}'")),
+                }
+            }
+        }
+
+        fn array(&mut self) -> Result<Value, String> {
+            self.expect(b'[')?;
+            let mut items = Vec::new();
+            if self.peek() == Some(b']') {
+                self.position += 1;
+                return Ok(Value::Array(items));
+            }
+
+            loop {
+                items.push(self.value()?);
+                match self.peek() {
+                    Some(b',') => self.position += 1,
+                    Some(b']') => {
+                        self.position += 1;
+                        return Ok(Value::Array(items));
+                    }
+                    _ => return Err(self.error("expected ',' or ']'")),
+                }
+            }
+        }
+
+        fn string(&mut self) -> Result<String, String> {
+            if self.bytes.get(self.position) != Some(&b'"') { return Err(self.error("expected a string")); }
+            self.position += 1;
+
+            let mut text = Vec::new();
+            loop {
+                let Some(&byte) = self.bytes.get(self.position) else { return Err(self.error("unterminated string")) };
+                self.position += 1;
+                match byte {
+                    b'"' => return String::from_utf8(text).map_err(|_| self.error("bad UTF-8")),
+                    b'\\' => {
+                        let Some(&escape) = self.bytes.get(self.position) else { return Err(self.error("unterminated string")) };
+                        self.position += 1;
+                        let unescaped = match escape {
+                            b'"' => '"',
+                            b'\\' => '\\',
+                            b'/' => '/',
+                            b'b' => '\u{8}',
+                            b'f' => '\u{c}',
+                            b'n' => '\n',
+                            b'r' => '\r',
+                            b't' => '\t',
+                            // surrogate pairs don't turn up in these files, they come out as U+FFFD
+                            b'u' => {
+                                let hex = self.bytes.get(self.position..self.position + 4)
+                                    .and_then(|hex| std::str::from_utf8(hex).ok())
+                                    .and_then(|hex| u32::from_str_radix(hex, 16).ok())
+                                    .ok_or_else(|| self.error("bad \\u escape"))?;
+                                self.position += 4;
+                                char::from_u32(hex).unwrap_or(char::REPLACEMENT_CHARACTER)
+                            }
+                            _ => return Err(self.error("bad escape")),
+                        };
+                        text.extend_from_slice(unescaped.encode_utf8(&mut [0; 4]).as_bytes());
+                    }
+                    _ => text.push(byte),
+                }
+            }
+        }
+
+        fn number(&mut self) -> Result<Value, String> {
+            let start = self.position;
+            while self.bytes.get(self.position)
+                .is_some_and(|byte| matches!(byte, b'-' | b'+' | b'.' | b'e' | b'E' | b'0'..=b'9')) {
+                self.position += 1;
+            }
+
+            std::str::from_utf8(&self.bytes[start..self.position]).ok()
+                .and_then(|number| number.parse().ok())
+                .map(Value::Number)
+                .ok_or_else(|| self.error("bad number"))
+        }
+    }
+}
+
+#[derive(Debug, Copy, Clone, Eq, PartialEq)]
+enum Access {
+    Read,
+    Write,
+}
+
+// flat 64K that writes down every access, for comparing with the cycle lists
+struct RecordingBus {
+    memory: Box<[u8; 0x10000]>,
+    activity: Vec<(u16, u8, Access)>,
+}
+
+impl RecordingBus {
+    fn new() -> Self {
+        RecordingBus { memory: Box::new([0; 0x10000]), activity: Vec::new() }
+    }
+}
+
+impl Bus for RecordingBus {
+    fn mem_read(&mut self, addr: u16) -> u8 {
+        let data = self.memory[addr as usize];
+        self.activity.push((addr, data, Access::Read));
+        data
+    }
+
+    fn mem_write(&mut self, addr: u16, data: u8) {
+        self.memory[addr as usize] = data;
+        self.activity.push((addr, data, Access::Write));
+    }
+
+    fn peek(&self, addr: u16) -> u8 {
+        self.memory[addr as usize]
+    }
+}
+
+struct CpuState {
+    pc: u16,
+    s: u8,
+    a: u8,
+    x: u8,
+    y: u8,
+    p: u8,
+    ram: Vec<(u16, u8)>,
+}
+
+struct TestCase {
+    name: String,
+    initial: CpuState,
+    expected: CpuState,
+    cycles: Vec<(u16, u8, Access)>,
+}
+
+fn number<T: TryFrom<u64>>(value: &json::Value, key: &str) -> Result<T, String> {
+    value.get(key).and_then(json::Value::as_u64).and_then(|number| T::try_from(number).ok())
+        .ok_or(format!("missing or bad \"{key}\""))
+}
+
+fn pair(value: &json::Value) -> Option<(u16, u8)> {
+    let pair = value.as_array()?;
+    Some((u16::try_from(pair.first()?.as_u64()?).ok()?, u8::try_from(pair.get(1)?.as_u64()?).ok()?))
+}
+
+fn cpu_state(value: &json::Value) -> Result<CpuState, String> {
+    let ram = value.get("ram").and_then(json::Value::as_array).ok_or("missing \"ram\"")?
+        .iter().map(|entry| pair(entry).ok_or("bad ram entry".to_string()))
+        .collect::<Result<_, _>>()?;
+    Ok(CpuState {
+        pc: number(value, "pc")?,
+        s: number(value, "s")?,
+        a: number(value, "a")?,
+        x: number(value, "x")?,
+        y: number(value, "y")?,
+        p: number(value, "p")?,
+        ram,
+    })
+}
+
+fn test_case(value: &json::Value) -> Result<TestCase, String> {
+    let cycles = value.get("cycles").and_then(json::Value::as_array).ok_or("missing \"cycles\"")?
+        .iter().map(|cycle| {
+            let (addr, data) = pair(cycle).ok_or("bad cycle")?;
+            let access = match cycle.as_array().and_then(|cycle| cycle.get(2)).and_then(json::Value::as_str) {
+                Some("read") => Access::Read,
+                Some("write") => Access::Write,
+                _ => return Err("bad cycle type".to_string()),
+            };
+            Ok((addr, data, access))
+        })
+        .collect::<Result<_, _>>()?;
+
+    Ok(TestCase {
+        name: value.get("name").and_then(json::Value::as_str).unwrap_or("?").to_string(),
+        initial: cpu_state(value.get("initial").ok_or("missing \"initial\"")?)?,
+        expected: cpu_state(value.get("final").ok_or("missing \"final\"")?)?,
+        cycles,
+    })
+}
+
+#[derive(Default)]
+struct Results {
+    cases: usize,
+    state: usize,
+    cycles: usize,
+    // the same accesses in the same order, give or take the dummy ones
+    bus: usize,
+    // every cycle's access, dummies and all
+    exact_bus: usize,
+    first_failure: Option<String>,
+}
+
+// B and bit 5 only exist on the stack, so they don't count in P
+const FLAG_MASK: u8 = !0x30;
+
+// the CPU doesn't do the 6502's dummy accesses, the reads it makes on cycles it's busy working
+// something out: the byte after a one byte opcode, the stack before a pull, the address an
+// indexed access lands on before the carry into the high byte gets fixed up, and so on. this is
+// where each of those lands for `opcode`, given the memory and registers it starts from
+fn dummy_reads(opcode: &OpCode, memory: &[u8; 0x10000], initial: &CpuState, final_pc: u16) -> Vec<u16> {
+    let pc = initial.pc;
+    let byte = |addr: u16| memory[addr as usize];
+    let word = |lo: u16, hi: u16| u16::from(byte(lo)) | u16::from(byte(hi)) << 8;
+    let operand = byte(pc.wrapping_add(1));
+    let stack = 0x0100 | u16::from(initial.s);
+    // where base + index points before the carry out of the low byte is added to the high one
+    let unfixed = |base: u16, index: u8| (base & 0xff00) | (base.wrapping_add(u16::from(index)) & 0x00ff);
+
+    let mut reads = Vec::new();
+    if opcode.bytes == 1 { reads.push(pc.wrapping_add(1)); }
+    match opcode.action {
+        ProcessorAction::PLA | ProcessorAction::PLP | ProcessorAction::RTI | ProcessorAction::JSR => reads.push(stack),
+        // and after pulling the return address, a read from it before stepping past
+        ProcessorAction::RTS => reads.extend([stack, final_pc.wrapping_sub(1)]),
+        ProcessorAction::BCC | ProcessorAction::BCS | ProcessorAction::BEQ | ProcessorAction::BMI
+        | ProcessorAction::BNE | ProcessorAction::BPL | ProcessorAction::BVC | ProcessorAction::BVS => {
+            // a taken branch reads the next opcode, and again from the wrong page if it crosses one
+            let next = pc.wrapping_add(2);
+            reads.extend([next, (next & 0xff00) | (final_pc & 0x00ff)]);
+        }
+        _ => {}
+    }
+
+    match opcode.mode {
+        AddressingMode::ZeroPage_X | AddressingMode::ZeroPage_Y | AddressingMode::Indirect_X => reads.push(u16::from(operand)),
+        AddressingMode::Absolute_X => {
+            reads.push(unfixed(word(pc.wrapping_add(1), pc.wrapping_add(2)), initial.x));
+        }
+        AddressingMode::Absolute_Y => {
+            reads.push(unfixed(word(pc.wrapping_add(1), pc.wrapping_add(2)), initial.y));
+        }
+        AddressingMode::Indirect_Y => {
+            reads.push(unfixed(word(u16::from(operand), u16::from(operand.wrapping_add(1))), initial.y));
+        }
+        _ => {}
+    }
+    reads
+}
+
+// every access but those dummy reads has to be there, in order, with nothing extra. the other
+// dummy is the write of the old value read-modify-write instructions do before the new one. none
+// of them reach anything on a flat 64K with no registers on it
+fn matches_without_dummies(
+    actual: &[(u16, u8, Access)],
+    expected: &[(u16, u8, Access)],
+    dummy_reads: &[u16],
+    read_modify_write: bool,
+) -> bool {
+    let mut actual = actual.iter().peekable();
+    for (index, access) in expected.iter().enumerate() {
+        if actual.peek() == Some(&access) {
+            actual.next();
+            continue;
+        }
+
+        let dummy = match access.2 {
+            Access::Read => dummy_reads.contains(&access.0),
+            Access::Write => read_modify_write
+                && expected.get(index + 1).is_some_and(|next| next.0 == access.0 && next.2 == Access::Write),
+        };
+        if !dummy { return false; }
+    }
+    actual.next().is_none()
+}
+
+fn bus_trace(accesses: &[(u16, u8, Access)]) -> String {
+    let accesses: Vec<String> = accesses.iter().map(|(addr, data, access)| match access {
+        Access::Read => format!("r ${addr:04X}={data:02X}"),
+        Access::Write => format!("w ${addr:04X}={data:02X}"),
+    }).collect();
+    accesses.join(" ")
+}
+
+fn panic_message(payload: &(dyn Any + Send)) -> &str {
+    payload.downcast_ref::<&str>().copied()
+        .or_else(|| payload.downcast_ref::<String>().map(String::as_str))
+        .unwrap_or("panicked")
+}
+
+fn run_case(opcode: &OpCode, case: &TestCase, results: &mut Results) {
+    let mut cpu = Processor::with_bus(RecordingBus::new());
+    let initial = &case.initial;
+    for (addr, data) in initial.ram.iter() {
+        cpu.bus.memory[*addr as usize] = *data;
+    }
+    cpu.program_counter = initial.pc;
+    cpu.register_s = initial.s;
+    cpu.register_a = initial.a;
+    cpu.register_x = initial.x;
+    cpu.register_y = initial.y;
+    cpu.status = CPUStatusFlags::from_stack(initial.p);
+    let dummy_reads = dummy_reads(opcode, &cpu.bus.memory, initial, case.expected.pc);
+    let read_modify_write = matches!(
+        opcode.action,
+        ProcessorAction::ASL | ProcessorAction::LSR | ProcessorAction::ROL | ProcessorAction::ROR
+        | ProcessorAction::INC | ProcessorAction::DEC
+    );
+
+    results.cases += 1;
+    // one case blowing up shouldn't take the rest of the report with it
+    if let Err(payload) = panic::catch_unwind(AssertUnwindSafe(|| { cpu.step(); })) {
+        if results.first_failure.is_none() {
+            results.first_failure = Some(format!("\"{}\": panicked: {}", case.name, panic_message(payload.as_ref())));
+        }
+        return;
+    }
+
+    let expected = &case.expected;
+    let mut wrong = Vec::new();
+    let registers = [
+        ("PC", u32::from(cpu.program_counter), u32::from(expected.pc)),
+        ("S", u32::from(cpu.register_s), u32::from(expected.s)),
+        ("A", u32::from(cpu.register_a), u32::from(expected.a)),
+        ("X", u32::from(cpu.register_x), u32::from(expected.x)),
+        ("Y", u32::from(cpu.register_y), u32::from(expected.y)),
+        ("P", u32::from(cpu.status.raw() & FLAG_MASK), u32::from(expected.p & FLAG_MASK)),
+    ];
+    for (name, actual, expected) in registers {
+        if actual != expected { wrong.push(format!("{name} {actual:02X}, expected {expected:02X}")); }
+    }
+    for (addr, data) in expected.ram.iter() {
+        let actual = cpu.bus.memory[*addr as usize];
+        if actual != *data { wrong.push(format!("${addr:04X} {actual:02X}, expected {data:02X}")); }
+    }
+    if wrong.is_empty() { results.state += 1; }
+
+    if cpu.cycles == case.cycles.len() as u64 { results.cycles += 1; }
+    else { wrong.push(format!("{} cycles, expected {}", cpu.cycles, case.cycles.len())); }
+
+    if cpu.bus.activity == case.cycles { results.exact_bus += 1; }
+    if matches_without_dummies(&cpu.bus.activity, &case.cycles, &dummy_reads, read_modify_write) { results.bus += 1; }
+    else { wrong.push(format!("bus {}, expected {}", bus_trace(&cpu.bus.activity), bus_trace(&case.cycles))); }
+
+    if !wrong.is_empty() && results.first_failure.is_none() {
+        results.first_failure = Some(format!("\"{}\": {}", case.name, wrong.join(", ")));
+    }
+}
+
+#[test]
+#[ignore = "needs the nes6502 JSON files in tests/roms/nes6502"]
+fn test_single_step() {
+    let dir = Path::new(env!("CARGO_MANIFEST_DIR")).join(TEST_DIR);
+    let entries = fs::read_dir(&dir).unwrap_or_else(|e| panic!("no single step tests in {}: {e}", dir.display()));
+
+    let mut files: BTreeMap<u8, _> = BTreeMap::new();
+    for entry in entries.flatten() {
+        let path = entry.path();
+        let opcode = path.file_stem().and_then(|stem| stem.to_str()).and_then(|stem| u8::from_str_radix(stem, 16).ok());
+        if let (Some(opcode), Some("json")) = (opcode, path.extension().and_then(|extension| extension.to_str())) {
+            files.insert(opcode, path);
+        }
+    }
+    assert!(!files.is_empty(), "no 00.json to ff.json in {}", dir.display());
+
+    let mut failures = Vec::new();
+    for (hex, path) in files {
+        let Some(opcode) = CPU_OPCODES.iter().find(|opcode| opcode.hex == hex) else {
+            println!("{hex:02x}  unofficial, skipped");
+            continue;
+        };
+
+        let text = fs::read_to_string(&path).unwrap_or_else(|e| panic!("{}: {e}", path.display()));
+        let cases = json::parse(&text).and_then(|value| {
+            value.as_array().ok_or("not a list of tests".to_string())?.iter().map(test_case).collect::<Result<Vec<_>, _>>()
+        }).unwrap_or_else(|e| panic!("{}: {e}", path.display()));
+
+        // the panics get reported with the case they came from, not printed as they happen
+        let mut results = Results::default();
+        let hook = panic::take_hook();
+        panic::set_hook(Box::new(|_| {}));
+        for case in cases.iter() {
+            run_case(opcode, case, &mut results);
+        }
+        panic::set_hook(hook);
+
+        let percent = |passed: usize| 100.0 * passed as f64 / results.cases.max(1) as f64;
+        println!(
+            "{hex:02x}  {:<4} {:>6.2}% state  {:>6.2}% cycles  {:>6.2}% bus  {:>6.2}% exact bus   ({} cases)",
+            format!("{:?}", opcode.action), percent(results.state), percent(results.cycles), percent(results.bus),
+            percent(results.exact_bus), results.cases
+        );
+        if let Some(failure) = results.first_failure {
+            failures.push(format!("{hex:02x} {:?} {failure}", opcode.action));
+        }
+    }
+
+    assert!(failures.is_empty(), "{} opcodes failed, first case of each:\n{}", failures.len(), failures.join("\n"));
+}